@@ -0,0 +1,119 @@
+use crate::AppError;
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, TempFile};
+use clap::ValueEnum;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Past this many seconds, animations get too big to be worth it
+const LONG_CLIP: f64 = 60f64;
+
+/// Formats short clips can be turned into.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Webp,
+}
+
+impl Display for AnimationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Webp => "webp",
+        })
+    }
+}
+
+/// How a clip is turned into an animation.
+#[derive(Debug, Clone, Copy)]
+pub struct Animation {
+    pub format: AnimationFormat,
+    pub fps: u32,
+    // Narrower videos aren't scaled up
+    pub width: u32,
+    // Only GIF has a palette
+    pub colors: u16,
+}
+
+impl Animation {
+    /// ffmpeg's arguments for the animation. GIFs get a palette made for
+    /// the clip in a first pass over it, and WebP is lossy.
+    fn args(&self) -> Vec<String> {
+        let Animation {
+            fps, width, colors, ..
+        } = self;
+        let scale = format!("fps={fps},scale='min({width},iw)':-2:flags=lanczos");
+        match self.format {
+            AnimationFormat::Gif => vec![
+                "-vf".to_string(),
+                format!(
+                    "{scale},split[a][b];[a]palettegen=max_colors={colors}:stats_mode=diff[p];\
+                    [b][p]paletteuse=dither=bayer:bayer_scale=5:diff_mode=rectangle"
+                ),
+                "-loop".to_string(),
+                "0".to_string(),
+            ],
+            AnimationFormat::Webp => [
+                "-vf",
+                &scale,
+                "-c:v",
+                "libwebp",
+                "-lossless",
+                "0",
+                "-q:v",
+                "75",
+                "-loop",
+                "0",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+        }
+    }
+}
+
+/// Turns the video at `path`, `duration` seconds long, into `animation`
+/// next to it, without sound, and deletes it unless `keep_original` is set.
+/// Returns where the animation was saved, or `None` when an existing file
+/// was kept under `policy`.
+pub fn convert(
+    path: &Path,
+    animation: Animation,
+    duration: Option<f64>,
+    keep_original: bool,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    if let Some(duration) = duration.filter(|duration| *duration > LONG_CLIP) {
+        eprintln!(
+            "\r\x1b[2K{} is {duration:.0}s long, which makes for a large {}",
+            path.display(),
+            animation.format
+        );
+    }
+    let extension = animation.format.to_string();
+    let target = path.with_extension(&extension);
+    // ffmpeg picks the format from the extension, so the part file gets one
+    // after the final name
+    let staged =
+        TempFile::new(download::part_path(&target).with_extension(format!("tmp.{extension}")));
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(path)
+        .args(["-map", "0:v:0", "-an"])
+        .args(animation.args())
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} turning {} into {extension}",
+            path.display()
+        )));
+    }
+    let placed = conflict::place(staged.path(), &target, policy)?;
+    if !keep_original {
+        std::fs::remove_file(path).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    }
+    Ok(placed)
+}
@@ -0,0 +1,74 @@
+use crate::{AppError, FileDetails};
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+static ARCHIVE: OnceLock<Archive> = OnceLock::new();
+
+/// Items downloaded before, kept in a file in the format of yt-dlp's
+/// `--download-archive`: one `<extractor> <id>` line per item, so either
+/// program can pick up where the other left off.
+#[derive(Debug)]
+struct Archive {
+    path: PathBuf,
+    entries: Mutex<HashSet<String>>,
+}
+
+/// The archive line for an item, e.g. `youtube dQw4w9WgXcQ`
+fn key(file_details: &FileDetails) -> String {
+    format!(
+        "{} {}",
+        file_details.extractor_key.to_lowercase(),
+        file_details.id
+    )
+}
+
+/// Loads the archive at `path` for the rest of the run. A missing file is
+/// an empty archive, created once something is downloaded.
+pub fn open(path: &str) -> Result<(), AppError> {
+    let path = PathBuf::from(path);
+    let entries = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(AppError::Io(format!("{}: {e}", path.display()))),
+    };
+    ARCHIVE.get_or_init(|| Archive {
+        path,
+        entries: Mutex::new(entries),
+    });
+    Ok(())
+}
+
+/// Whether the item is in the archive, if one is open
+pub fn contains(file_details: &FileDetails) -> bool {
+    ARCHIVE
+        .get()
+        .is_some_and(|archive| archive.entries.lock().unwrap().contains(&key(file_details)))
+}
+
+/// Adds the item to the archive, if one is open and it isn't there yet
+pub fn record(file_details: &FileDetails) -> Result<(), AppError> {
+    let Some(archive) = ARCHIVE.get() else {
+        return Ok(());
+    };
+    let key = key(file_details);
+    let mut entries = archive.entries.lock().unwrap();
+    if entries.contains(&key) {
+        return Ok(());
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&archive.path)
+        .and_then(|mut file| writeln!(file, "{key}"))
+        .map_err(|e| AppError::Io(format!("{}: {e}", archive.path.display())))?;
+    entries.insert(key);
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, TempFile};
+use crate::{AppError, FileEncoding, FileFormat};
+use clap::ValueEnum;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Approximate bitrates in kbit/s of LAME's VBR presets `-V 0` to `-V 9`,
+/// used for the codecs that only take a bitrate
+const VBR_BITRATES: [u32; 10] = [245, 225, 190, 175, 165, 130, 115, 100, 85, 65];
+
+/// Formats audio can be extracted into.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Mp3,
+    M4a,
+    Opus,
+    Flac,
+}
+
+impl AudioFormat {
+    /// The format of a file at `path`, going by its extension
+    pub fn of(path: &Path) -> Option<AudioFormat> {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        AudioFormat::from_str(&extension, true).ok()
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Flac => "flac",
+        }
+    }
+
+    /// Whether a stream in `codec` is already in this format, so it only
+    /// needs copying out of its container
+    fn holds(self, codec: &str) -> bool {
+        extension_for(codec) == self.extension()
+    }
+
+    /// ffmpeg's arguments for encoding into this format
+    pub fn encoder_args(self, quality: AudioQuality) -> Vec<String> {
+        let bitrate = format!("{}k", quality.bitrate());
+        let (encoder, quality_args) = match (self, quality) {
+            (AudioFormat::Mp3, AudioQuality::Vbr(level)) => {
+                ("libmp3lame", vec!["-q:a".to_string(), level.to_string()])
+            }
+            (AudioFormat::Mp3, _) => ("libmp3lame", vec!["-b:a".to_string(), bitrate]),
+            (AudioFormat::M4a, _) => ("aac", vec!["-b:a".to_string(), bitrate]),
+            (AudioFormat::Opus, _) => ("libopus", vec!["-b:a".to_string(), bitrate]),
+            // Lossless, so there's no quality to choose
+            (AudioFormat::Flac, _) => ("flac", vec![]),
+        };
+        [vec!["-c:a".to_string(), encoder.to_string()], quality_args].concat()
+    }
+}
+
+impl Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// How good converted audio should be: a VBR level from 0 (best) to 9, as
+/// with LAME, or a bitrate in kbit/s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioQuality {
+    Vbr(u8),
+    Bitrate(u32),
+}
+
+impl Default for AudioQuality {
+    fn default() -> Self {
+        AudioQuality::Vbr(5)
+    }
+}
+
+impl AudioQuality {
+    /// Parses a VBR level like `2`, or a bitrate like `192K`
+    pub fn parse(value: &str) -> Result<AudioQuality, String> {
+        let value = value.trim();
+        if let Some(kbps) = value.strip_suffix(['k', 'K']) {
+            return kbps
+                .parse()
+                .ok()
+                .filter(|kbps| *kbps > 0)
+                .map(AudioQuality::Bitrate)
+                .ok_or_else(|| format!("invalid bitrate `{value}`"));
+        }
+        value
+            .parse()
+            .ok()
+            .filter(|level| *level <= 9)
+            .map(AudioQuality::Vbr)
+            .ok_or_else(|| format!("`{value}` is neither a VBR level from 0 to 9 nor a bitrate"))
+    }
+
+    fn bitrate(self) -> u32 {
+        match self {
+            AudioQuality::Vbr(level) => VBR_BITRATES[usize::from(level)],
+            AudioQuality::Bitrate(kbps) => kbps,
+        }
+    }
+}
+
+/// The extension for audio in `codec` kept as it is, in the container
+/// usual for it, or Matroska audio for codecs without one
+fn extension_for(codec: &str) -> &'static str {
+    match codec.split('.').next().unwrap_or_default() {
+        "mp4a" | "aac" | "alac" => "m4a",
+        "mp3" => "mp3",
+        "opus" => "opus",
+        "vorbis" => "ogg",
+        "flac" => "flac",
+        _ => "mka",
+    }
+}
+
+/// Where the audio of `path`, downloaded in `source`, is saved: next to it,
+/// in `format` or in the format the stream already has
+pub fn extracted_path(path: &Path, format: Option<AudioFormat>, source: &FileFormat) -> PathBuf {
+    let extension = match format {
+        Some(format) => format.extension(),
+        None => extension_for(&source.acodec),
+    };
+    path.with_extension(extension)
+}
+
+/// Extracts the audio of the file at `path`, downloaded in `source`, into
+/// `format` at `quality`, and deletes the original once it's done. Audio
+/// already in the format is copied rather than re-encoded. Returns where it was saved, or
+/// `None` when an existing file was kept under `policy`.
+pub fn extract(
+    path: &Path,
+    source: &FileFormat,
+    format: Option<AudioFormat>,
+    quality: AudioQuality,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    let target = extracted_path(path, format, source);
+    let is_audio_only = source.file_encoding == FileEncoding::AudioOnly;
+    // Nothing to take out or convert
+    if target == path && is_audio_only {
+        return Ok(Some(target));
+    }
+
+    let extension = target.extension().unwrap_or_default().to_string_lossy();
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let staged =
+        TempFile::new(download::part_path(&target).with_extension(format!("tmp.{extension}")));
+    let encoder_args = match format {
+        Some(format) if !format.holds(&source.acodec) => format.encoder_args(quality),
+        _ => vec!["-c:a".to_string(), "copy".to_string()],
+    };
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(path)
+        .args(["-vn", "-map", "0:a:0"])
+        .args(&encoder_args)
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} extracting the audio of {}",
+            path.display()
+        )));
+    }
+    // The download itself is replaced when the audio keeps its name
+    if target == path {
+        return conflict::place(staged.path(), &target, ConflictPolicy::Overwrite);
+    }
+    std::fs::remove_file(path).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    conflict::place(staged.path(), &target, policy)
+}
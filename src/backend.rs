@@ -0,0 +1,215 @@
+use crate::AppError;
+use crate::download::{self, ChildPause, FetchOptions, Progress};
+use crate::interrupt;
+use crate::retry::RetryPolicy;
+use crate::timeout::Timeouts;
+use regex::Regex;
+use serde::Deserialize;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::LazyLock;
+
+/// aria2c's console readout, e.g. `[#2089b0 400.0KiB/33.2MiB(1%) CN:1 DL:115.7KiB ETA:4m51s]`
+static ARIA2C_READOUT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[#\w+ ([\d.]+)(\w+)/([\d.]+)(\w+)\(\d+%\)(?: CN:\d+)?(?: SD:\d+)?(?: DL:([\d.]+)(\w+))?(?: ETA:(\w+))?\]")
+        .unwrap()
+});
+
+/// Which program fetches plain HTTP(S) files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Downloader {
+    #[default]
+    Native,
+    Aria2c,
+}
+
+impl Downloader {
+    pub fn backend(self) -> &'static dyn DownloadBackend {
+        match self {
+            Downloader::Native => &Native,
+            Downloader::Aria2c => &Aria2c,
+        }
+    }
+}
+
+/// Something that can fetch a file over HTTP(S).
+pub trait DownloadBackend: Sync {
+    /// Downloads `url` to `path`, sending `headers` with the request, and
+    /// returns the number of bytes in the finished file.
+    fn fetch(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        path: &Path,
+        options: &FetchOptions,
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<u64, AppError>;
+}
+
+/// The built-in engine.
+struct Native;
+
+impl DownloadBackend for Native {
+    fn fetch(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        path: &Path,
+        options: &FetchOptions,
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<u64, AppError> {
+        download::fetch(url, headers, path, options, on_progress)
+    }
+}
+
+/// Hands the file to aria2c, mapping our connection, rate and retry settings
+/// onto its options.
+struct Aria2c;
+
+fn bytes(number: &str, unit: &str) -> Option<f64> {
+    let multiplier = match unit {
+        "B" => 1f64,
+        "KiB" => 1024f64,
+        "MiB" => 1024f64.powi(2),
+        "GiB" => 1024f64.powi(3),
+        _ => return None,
+    };
+    number.parse::<f64>().ok().map(|number| number * multiplier)
+}
+
+/// `1h2m3s`-style durations to seconds
+fn seconds(eta: &str) -> Option<f64> {
+    let mut total = 0f64;
+    let mut number = String::new();
+    for c in eta.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' | 's' => {
+                let value: f64 = number.parse().ok()?;
+                total += value
+                    * match c {
+                        'h' => 3600f64,
+                        'm' => 60f64,
+                        _ => 1f64,
+                    };
+                number.clear();
+            }
+            _ => return None,
+        }
+    }
+    Some(total)
+}
+
+fn parse_readout(line: &str) -> Option<Progress> {
+    let captures = ARIA2C_READOUT_REGEX.captures(line)?;
+    let group = |index: usize| captures.get(index).map(|group| group.as_str());
+    Some(Progress {
+        downloaded_bytes: bytes(group(1)?, group(2)?)? as u64,
+        total_bytes: bytes(group(3)?, group(4)?).map(|total| total as u64),
+        speed: group(5)
+            .zip(group(6))
+            .and_then(|(speed, unit)| bytes(speed, unit)),
+        average_speed: None,
+        eta: group(7).and_then(seconds),
+    })
+}
+
+impl DownloadBackend for Aria2c {
+    fn fetch(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        path: &Path,
+        options: &FetchOptions,
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<u64, AppError> {
+        // aria2c keeps its own `.aria2` control file next to the `.part`
+        // file, and continues both when run again
+        let part_path = download::part_path(path);
+        let dir = part_path.parent().unwrap_or(Path::new("."));
+        let out = part_path.file_name().unwrap_or_default();
+        let connections = options.connections.clamp(1, 16).to_string();
+        let retries = RetryPolicy::current();
+        let timeouts = Timeouts::current();
+
+        let mut command = Command::new("aria2c");
+        command
+            .arg("--dir")
+            .arg(dir)
+            .arg("--out")
+            .arg(out)
+            .args(["--continue=true", "--auto-file-renaming=false"])
+            .args(["--allow-overwrite=true", "--summary-interval=0"])
+            .args(["--console-log-level=error", "--download-result=hide"])
+            .args(["--max-connection-per-server", &connections])
+            .args(["--split", &connections])
+            .args(["--max-tries", &(retries.retries + 1).to_string()])
+            .args(["--retry-wait", &retries.delay.as_secs().max(1).to_string()])
+            .args([
+                "--connect-timeout",
+                &timeouts.connect.as_secs().max(1).to_string(),
+            ])
+            .args(["--timeout", &timeouts.stall.as_secs().max(1).to_string()]);
+        if let Some(rate_limit) = options.rate_limit {
+            command.arg(format!("--max-overall-download-limit={rate_limit}"));
+        }
+        for (name, value) in headers {
+            command.arg(format!("--header={name}: {value}"));
+        }
+        let mut child = command
+            .arg(url)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| AppError::CommandFailed(format!("failed to execute aria2c: {e}")))?;
+        let _pause = ChildPause::new(&child, &options.pause);
+        let _guard = interrupt::ChildGuard::new(&child);
+
+        // The readout is redrawn with carriage returns rather than newlines
+        if let Some(stdout) = child.stdout.take() {
+            let mut line = Vec::new();
+            for byte in BufReader::new(stdout).bytes().map_while(Result::ok) {
+                if byte == b'\r' || byte == b'\n' {
+                    if let Some(progress) = parse_readout(&String::from_utf8_lossy(&line)) {
+                        on_progress(&progress);
+                    }
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| AppError::CommandFailed(format!("failed to execute aria2c: {e}")))?;
+        // Exit codes from aria2c's manual
+        match status.code() {
+            Some(0) => {}
+            _ if interrupt::is_interrupted() => return Err(AppError::Interrupted),
+            Some(3) => {
+                return Err(AppError::HttpStatus(
+                    404,
+                    format!("aria2c couldn't find {url}"),
+                ));
+            }
+            Some(2 | 6 | 19) => {
+                return Err(AppError::Http(format!(
+                    "aria2c hit a network error fetching {url}"
+                )));
+            }
+            _ => {
+                return Err(AppError::CommandFailed(format!(
+                    "aria2c exited with {status} for {url}"
+                )));
+            }
+        }
+
+        std::fs::rename(&part_path, path).map_err(|e| AppError::Io(e.to_string()))?;
+        std::fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .map_err(|e| AppError::Io(e.to_string()))
+    }
+}
@@ -0,0 +1,105 @@
+use crate::config::cache_dir;
+use crate::{checksum, download};
+use aws_lc_rs::digest::{SHA256, digest};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static POLICY: OnceLock<CachePolicy> = OnceLock::new();
+
+/// How long fetched details are reused when neither the command line nor
+/// the config says. Format URLs some sites hand out expire after a few
+/// hours, so this stays well short of that.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Whether cached details are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Reuse details fetched within the TTL, and keep new ones
+    Use,
+    /// Fetch everything again, keeping what was fetched
+    Refresh,
+    /// Neither read nor write the cache
+    Off,
+}
+
+/// How yt-dlp's descriptions of URLs are cached on disk, so fetching the
+/// same URL again soon after doesn't run yt-dlp, which takes seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct CachePolicy {
+    pub mode: CacheMode,
+    pub ttl: Duration,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy {
+            mode: CacheMode::Use,
+            ttl: DEFAULT_TTL,
+        }
+    }
+}
+
+impl CachePolicy {
+    /// Sets the policy for every fetch of the run.
+    pub fn install(self) {
+        POLICY.get_or_init(|| self);
+    }
+
+    fn current() -> CachePolicy {
+        POLICY.get().copied().unwrap_or_default()
+    }
+}
+
+/// A URL's details as saved in the cache
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    url: String,
+    // Unix timestamp
+    fetched_at: i64,
+    json: Value,
+}
+
+/// The cache file for `url`, named after its hash to keep it a valid name
+fn path_for(url: &str) -> Option<PathBuf> {
+    let name = checksum::hex(digest(&SHA256, url.as_bytes()).as_ref());
+    cache_dir().map(|dir| dir.join("metadata").join(format!("{name}.json")))
+}
+
+/// yt-dlp's description of `url`, when it was fetched within the TTL
+pub fn get(url: &str) -> Option<Value> {
+    let policy = CachePolicy::current();
+    if policy.mode != CacheMode::Use {
+        return None;
+    }
+    let contents = std::fs::read_to_string(path_for(url)?).ok()?;
+    let cached: Cached = serde_json::from_str(&contents).ok()?;
+    let age = Local::now().timestamp().saturating_sub(cached.fetched_at);
+    // A hash collision would hand back another URL's details
+    (cached.url == url && u64::try_from(age).is_ok_and(|age| age < policy.ttl.as_secs()))
+        .then_some(cached.json)
+}
+
+/// Keeps yt-dlp's description of `url` for later runs. The cache is only a
+/// shortcut, so failing to write it is ignored.
+pub fn put(url: &str, json: &Value) {
+    if CachePolicy::current().mode == CacheMode::Off {
+        return;
+    }
+    let Some(path) = path_for(url) else {
+        return;
+    };
+    let cached = Cached {
+        url: url.to_string(),
+        fetched_at: Local::now().timestamp(),
+        json: json.clone(),
+    };
+    if let (Some(dir), Ok(contents)) = (path.parent(), serde_json::to_vec(&cached))
+        && std::fs::create_dir_all(dir).is_ok()
+    {
+        let _ = download::write_file(&path, contents);
+    }
+}
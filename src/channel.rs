@@ -0,0 +1,124 @@
+use crate::cli::ListingArgs;
+use crate::playlist::PlaylistEntry;
+use crate::{AppError, FileDetails, ytdlp};
+
+/// An upload as returned by `--flat-playlist`, before its full metadata is fetched.
+#[derive(Debug)]
+pub struct ChannelEntry {
+    pub id: String,
+    pub url: String,
+    /// Private or deleted, though still listed
+    pub unavailable: bool,
+}
+
+/// Channel root URLs list tabs (Videos, Shorts, Live) rather than uploads, so
+/// point them at the Videos tab unless a tab was given explicitly.
+fn uploads_url(channel_url: &str) -> String {
+    let trimmed = channel_url.trim_end_matches('/');
+    let has_tab = ["/videos", "/shorts", "/streams"]
+        .iter()
+        .any(|tab| trimmed.ends_with(tab));
+    if has_tab {
+        trimmed.to_string()
+    } else {
+        format!("{trimmed}/videos")
+    }
+}
+
+/// Lists a channel's uploads, newest first, without fetching per-video metadata.
+pub fn list_uploads(
+    channel_url: &str,
+    latest: Option<usize>,
+) -> Result<Vec<ChannelEntry>, AppError> {
+    list_entries(&uploads_url(channel_url), latest)
+}
+
+/// Lists the items of a playlist, profile or channel tab in its own order,
+/// up to the first `first` when given, without fetching their metadata.
+pub fn list_entries(url: &str, first: Option<usize>) -> Result<Vec<ChannelEntry>, AppError> {
+    let playlist_end = first.map(|n| n.to_string());
+    let mut args = vec!["--flat-playlist"];
+    if let Some(playlist_end) = &playlist_end {
+        args.extend(["--playlist-end", playlist_end]);
+    }
+
+    let json = ytdlp::fetch_json(url, &args)?;
+    let entries = json
+        .get("entries")
+        .and_then(|v| v.as_array())
+        .ok_or(AppError::MissingField("entries"))?;
+
+    let mut uploads = vec![];
+    for entry in entries {
+        let (Some(id), Some(url)) = (
+            entry.get("id").and_then(|v| v.as_str()),
+            entry.get("url").and_then(|v| v.as_str()),
+        ) else {
+            continue;
+        };
+        // Playlists keep private and deleted videos, under a placeholder
+        // title. Members-only and sign-in ones can still be had with cookies.
+        let unavailable = entry.get("availability").and_then(|v| v.as_str()) == Some("private")
+            || entry
+                .get("title")
+                .and_then(|v| v.as_str())
+                .is_some_and(|title| matches!(title, "[Private video]" | "[Deleted video]"));
+        uploads.push(ChannelEntry {
+            id: id.to_string(),
+            url: url.to_string(),
+            unavailable,
+        });
+    }
+
+    Ok(uploads)
+}
+
+/// Fetches full metadata for the uploads selected by `listing`, numbered
+/// from the newest.
+///
+/// Uploads that can't be fetched (private, members-only, removed) are reported
+/// and skipped rather than failing the whole channel.
+pub fn fetch_uploads(
+    channel_url: &str,
+    listing: &ListingArgs,
+) -> Result<Vec<PlaylistEntry>, AppError> {
+    let entries = list_uploads(channel_url, listing.latest)?;
+    let mut uploads = vec![];
+
+    for (position, entry) in entries.into_iter().enumerate() {
+        if entry.unavailable {
+            eprintln!("Skipping {}, which is private or deleted", entry.id);
+            continue;
+        }
+        let json = match ytdlp::fetch_json(&entry.url, &[]) {
+            Ok(json) => json,
+            Err(error) => {
+                eprintln!("Skipping {}: {error}", entry.id);
+                continue;
+            }
+        };
+
+        let file_details: FileDetails = serde_json::from_value(json.clone())
+            .map_err(|e| AppError::InvalidJson(e.to_string()))?;
+
+        if let Some(since) = &listing.since {
+            // Uploads are listed newest first, so the first older one ends the scan
+            if file_details
+                .upload_date
+                .as_ref()
+                .is_some_and(|date| date < since)
+            {
+                break;
+            }
+        }
+        if file_details.matches_duration(listing) {
+            uploads.push(PlaylistEntry {
+                index: position + 1,
+                file_details,
+                json,
+            });
+        }
+    }
+
+    Ok(uploads)
+}
@@ -0,0 +1,116 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, TempFile};
+use crate::{AppError, filename, format_duration};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A titled section of a video, from yt-dlp's `chapters` array.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Chapter {
+    pub title: String,
+    // Seconds from the start of the video
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+impl Chapter {
+    pub fn duration(&self) -> f64 {
+        self.end_time - self.start_time
+    }
+}
+
+impl Display for Chapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - {} ({}) {}",
+            format_duration(self.start_time),
+            format_duration(self.end_time),
+            format_duration(self.duration()),
+            self.title
+        )
+    }
+}
+
+/// Parses the `chapters` array, skipping malformed entries. Full albums are
+/// often uploaded as one video whose chapters are the tracks.
+pub fn from_json(value: &Value) -> Vec<Chapter> {
+    value
+        .get("chapters")
+        .and_then(Value::as_array)
+        .map(|chapters| {
+            chapters
+                .iter()
+                .filter_map(|chapter| serde_json::from_value(chapter.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Cuts the file at `media` into one file per chapter next to it, named like
+/// `01 - Chapter Title.mp4` and tagged with the chapter's title and track
+/// number, with `album` as the album, on top of the file's own tags. Streams
+/// are copied, which cuts at the nearest keyframes, and only re-encoded when
+/// that fails. Returns where they were saved, leaving out chapters whose
+/// existing files were kept under `policy`.
+pub fn split(
+    chapters: &[Chapter],
+    media: &Path,
+    album: &str,
+    policy: ConflictPolicy,
+) -> Result<Vec<PathBuf>, AppError> {
+    let extension = media.extension().unwrap_or_default().to_string_lossy();
+    let directory = media.parent().unwrap_or(Path::new("."));
+    let mut paths = vec![];
+    for (index, chapter) in chapters.iter().enumerate() {
+        let number = index + 1;
+        let name = format!("{number:02} - {}", filename::sanitize(&chapter.title));
+        let path = directory.join(filename::fit(&format!("{name}.{extension}")));
+        // ffmpeg picks the container from the extension, so the part file
+        // gets one after the final name
+        let staged =
+            TempFile::new(download::part_path(&path).with_extension(format!("tmp.{extension}")));
+        let cut = |copy: bool| {
+            let mut command = Command::new("ffmpeg");
+            command
+                .args(["-y", "-loglevel", "error", "-ss"])
+                .arg(chapter.start_time.to_string())
+                .arg("-t")
+                .arg(chapter.duration().to_string())
+                .arg("-i")
+                .arg(media)
+                .args(["-map", "0", "-map_chapters", "-1"]);
+            if copy {
+                command.args(["-c", "copy"]);
+            }
+            command
+                .arg("-metadata")
+                .arg(format!("title={}", chapter.title))
+                .arg("-metadata")
+                .arg(format!("track={number}/{}", chapters.len()))
+                .arg("-metadata")
+                .arg(format!("album={album}"))
+                .arg(staged.path())
+                .stdin(Stdio::null())
+                .status()
+                .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))
+        };
+        let mut status = cut(true)?;
+        if !status.success() {
+            status = cut(false)?;
+        }
+        if !status.success() {
+            return Err(AppError::CommandFailed(format!(
+                "ffmpeg exited with {status} cutting chapter {number} out of {}",
+                media.display()
+            )));
+        }
+        if let Some(path) = conflict::place(staged.path(), &path, policy)? {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
@@ -0,0 +1,175 @@
+use crate::{AppError, download};
+use aws_lc_rs::digest::{Context, SHA256};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use md5::{Digest, Md5};
+use reqwest::StatusCode;
+use reqwest::blocking::Response;
+use reqwest::header::{ETAG, HeaderName};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+static CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Feeds the contents of `path` to `update` a buffer at a time
+fn read_chunks(path: &Path, mut update: impl FnMut(&[u8])) -> Result<(), AppError> {
+    let mut file =
+        File::open(path).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+        if read == 0 {
+            return Ok(());
+        }
+        update(&buffer[..read]);
+    }
+}
+
+/// Lowercase hex SHA-256 of a file
+pub fn sha256(path: &Path) -> Result<String, AppError> {
+    let mut context = Context::new(&SHA256);
+    read_chunks(path, |chunk| context.update(chunk))?;
+    Ok(hex(context.finish().as_ref()))
+}
+
+/// Lowercase hex MD5 of a file
+pub fn md5(path: &Path) -> Result<String, AppError> {
+    let mut hasher = Md5::new();
+    read_chunks(path, |chunk| hasher.update(chunk))?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// The MD5 a response vouches for, as lowercase hex: its `Content-MD5`, or
+/// an `ETag` that is a plain MD5, as S3 and many static servers send.
+///
+/// `Content-MD5` only covers the body it came with, so it's ignored on
+/// partial responses. Weak and multipart ETags aren't hashes of the file.
+pub fn expected_md5(response: &Response) -> Option<String> {
+    let header = |name: &HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    if response.status() == StatusCode::OK
+        && let Some(digest) = header(&CONTENT_MD5)
+            .and_then(|value| STANDARD.decode(value).ok())
+            .filter(|digest| digest.len() == 16)
+    {
+        return Some(hex(&digest));
+    }
+    header(&ETAG)
+        .and_then(|etag| etag.strip_prefix('"')?.strip_suffix('"'))
+        .filter(|etag| etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+}
+
+/// Fails if the MD5 of the file at `path` isn't `expected`
+pub fn verify_md5(path: &Path, expected: &str) -> Result<(), AppError> {
+    let actual = md5(path)?;
+    if actual != expected {
+        return Err(AppError::ChecksumMismatch(format!(
+            "{} has MD5 {actual} but the server sent {expected}",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// `<file>.sha256` next to the file
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".sha256");
+    path.with_file_name(file_name)
+}
+
+/// Hashes a finished download and saves the checksum next to it, in the
+/// format `sha256sum -c` reads. Returns the checksum.
+pub fn write_sidecar(path: &Path) -> Result<String, AppError> {
+    let digest = sha256(path)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    download::write_file(&sidecar_path(path), format!("{digest}  {file_name}\n"))?;
+    Ok(digest)
+}
+
+/// Every `.sha256` sidecar under `dir`, in a stable order
+fn find_sidecars(dir: &Path, sidecars: &mut Vec<PathBuf>) -> Result<(), AppError> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| AppError::Io(format!("{}: {e}", dir.display())))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            find_sidecars(&path, sidecars)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "sha256")
+        {
+            sidecars.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Re-hashes every file with a checksum sidecar under `paths`, which may be
+/// directories or sidecars themselves, and reports each one.
+pub fn verify(paths: &[String]) -> Result<(), AppError> {
+    let mut sidecars = vec![];
+    for path in paths.iter().map(Path::new) {
+        if path.is_dir() {
+            find_sidecars(path, &mut sidecars)?;
+        } else {
+            sidecars.push(path.to_path_buf());
+        }
+    }
+    if sidecars.is_empty() {
+        println!("No checksums found");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for sidecar in &sidecars {
+        let contents = std::fs::read_to_string(sidecar)
+            .map_err(|e| AppError::Io(format!("{}: {e}", sidecar.display())))?;
+        let Some((expected, file_name)) = contents.trim_end().split_once("  ") else {
+            failed += 1;
+            println!("Unreadable\t{}", sidecar.display());
+            continue;
+        };
+        let path = sidecar.with_file_name(file_name);
+        match sha256(&path) {
+            Ok(actual) if actual == expected.to_lowercase() => {
+                println!("OK\t{}", path.display())
+            }
+            Ok(_) => {
+                failed += 1;
+                println!("Changed\t{}", path.display());
+            }
+            Err(_) if !path.exists() => {
+                failed += 1;
+                println!("Missing\t{}", path.display());
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    if failed > 0 {
+        return Err(AppError::ChecksumMismatch(format!(
+            "{failed} of {} files don't match",
+            sidecars.len()
+        )));
+    }
+    Ok(())
+}
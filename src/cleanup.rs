@@ -0,0 +1,197 @@
+use crate::cli::CleanupArgs;
+use crate::config::Config;
+use crate::{AppError, FileSize, collection, history};
+use chrono::Local;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Downloads tagged with this, as with `history tag ID watched`, go first
+/// when a folder is over its quota
+pub const WATCHED_TAG: &str = "watched";
+
+/// Limits on what's kept of the downloads in a folder, as a `[[cleanup]]`
+/// table of the config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Retention {
+    pub dir: PathBuf,
+    /// Delete downloads, watched then oldest first, while the folder holds more
+    pub max_size: Option<FileSize>,
+    /// Delete downloads finished longer ago than this
+    pub max_age_days: Option<f64>,
+}
+
+/// A download in a folder being cleaned up, with the files saved next to it
+struct Download {
+    path: PathBuf,
+    files: Vec<PathBuf>,
+    bytes: u64,
+    // When it was first downloaded, as a Unix timestamp
+    finished_at: i64,
+    watched: bool,
+}
+
+/// Why a download is deleted
+enum Reason {
+    Expired { days: i64 },
+    OverQuota,
+}
+
+/// Every file under `dir`, not following links, added up
+fn folder_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.path().symlink_metadata().ok()?;
+            Some(if metadata.is_dir() {
+                folder_size(&entry.path())
+            } else if metadata.is_file() {
+                metadata.len()
+            } else {
+                0
+            })
+        })
+        .sum()
+}
+
+/// The history's downloads still under `dir`, oldest first
+fn downloads_in(dir: &Path) -> Result<Vec<Download>, AppError> {
+    let mut downloads: Vec<Download> = vec![];
+    for entry in history::downloads()? {
+        let Some(path) = entry.path.as_deref().map(PathBuf::from) else {
+            continue;
+        };
+        if !path.starts_with(dir) || !path.is_file() {
+            continue;
+        }
+        let watched = entry.tags.iter().any(|tag| tag == WATCHED_TAG);
+        // Jobs that downloaded the same file again share its path
+        if let Some(download) = downloads.iter_mut().find(|other| other.path == path) {
+            download.watched |= watched;
+            continue;
+        }
+        let files = collection::with_companions(&path);
+        let bytes = files
+            .iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        downloads.push(Download {
+            path,
+            files,
+            bytes,
+            finished_at: entry.finished_at,
+            watched,
+        });
+    }
+    Ok(downloads)
+}
+
+/// What has to go for the folder to keep to `retention`
+fn plan(retention: &Retention, dir: &Path) -> Result<Vec<(Download, Reason)>, AppError> {
+    let now = Local::now().timestamp();
+    let mut kept = downloads_in(dir)?;
+    let mut removals = vec![];
+    if let Some(max_age_days) = retention.max_age_days {
+        let (expired, rest) = kept
+            .into_iter()
+            .partition(|download| (now - download.finished_at) as f64 > max_age_days * 86400.0);
+        kept = rest;
+        removals.extend(expired.into_iter().map(|download: Download| {
+            let days = (now - download.finished_at) / 86400;
+            (download, Reason::Expired { days })
+        }));
+    }
+    if let Some(max_size) = &retention.max_size {
+        let mut size = folder_size(dir)
+            .saturating_sub(removals.iter().map(|(download, _)| download.bytes).sum());
+        // Watched ones first, then oldest first
+        kept.sort_by_key(|download| (!download.watched, download.finished_at));
+        for download in kept {
+            if size <= max_size.bytes {
+                break;
+            }
+            size = size.saturating_sub(download.bytes);
+            removals.push((download, Reason::OverQuota));
+        }
+    }
+    Ok(removals)
+}
+
+/// Deletes what's over the limits of `retention`, or only lists it when
+/// `dry_run` is set
+pub fn enforce(retention: &Retention, dry_run: bool) -> Result<(), AppError> {
+    if retention.max_size.is_none() && retention.max_age_days.is_none() {
+        return Err(AppError::InvalidConfig(format!(
+            "no max_size or max_age_days to clean up {} by",
+            retention.dir.display()
+        )));
+    }
+    let dir = std::path::absolute(&retention.dir).unwrap_or_else(|_| retention.dir.clone());
+    if !dir.is_dir() {
+        return Err(AppError::Io(format!("{} isn't a folder", dir.display())));
+    }
+    let removals = plan(retention, &dir)?;
+    let mut freed = 0;
+    for (download, reason) in &removals {
+        let reason = match reason {
+            Reason::Expired { days } => format!("finished {days} days ago"),
+            Reason::OverQuota if download.watched => "watched, over the quota".to_string(),
+            Reason::OverQuota => "over the quota".to_string(),
+        };
+        let size = FileSize::new(download.bytes as f64);
+        if dry_run {
+            println!(
+                "Would delete {} ({size}, {reason})",
+                download.path.display()
+            );
+            freed += download.bytes;
+            continue;
+        }
+        let mut deleted = true;
+        for file in &download.files {
+            if let Err(error) = std::fs::remove_file(file) {
+                eprintln!("Couldn't delete {}: {error}", file.display());
+                deleted = false;
+            }
+        }
+        if deleted {
+            println!("Deleted {} ({size}, {reason})", download.path.display());
+            freed += download.bytes;
+        }
+    }
+    let (verb, left) = if dry_run {
+        ("Would free", folder_size(&dir).saturating_sub(freed))
+    } else {
+        ("Freed", folder_size(&dir))
+    };
+    println!(
+        "{verb} {} in {}, leaving {}",
+        FileSize::new(freed as f64),
+        dir.display(),
+        FileSize::new(left as f64)
+    );
+    Ok(())
+}
+
+/// Enforces the rules of the config, or the one given on the command line
+pub fn run(args: &CleanupArgs, config: &Config) -> Result<(), AppError> {
+    let retentions = match &args.dir {
+        Some(dir) => vec![Retention {
+            dir: dir.clone(),
+            max_size: args.max_size.clone(),
+            max_age_days: args.max_age,
+        }],
+        None => config.cleanup.clone(),
+    };
+    if retentions.is_empty() {
+        println!("No folders to clean up, as the config has no [[cleanup]] rules");
+    }
+    for retention in &retentions {
+        enforce(retention, args.dry_run)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,976 @@
+use crate::animation::AnimationFormat;
+use crate::audio::{AudioFormat, AudioQuality};
+use crate::backend::Downloader;
+use crate::conflict::ConflictPolicy;
+use crate::failed::FailureKind;
+use crate::fit::{AspectRatio, FitMode};
+use crate::history::ExportFormat;
+use crate::hwaccel::HwAccel;
+use crate::library;
+use crate::postprocess::Step;
+use crate::preview::Grid;
+use crate::queue::Priority;
+use crate::recode::VideoContainer;
+use crate::schedule::{self, Window};
+use crate::section::Section;
+use crate::sponsorblock::{SegmentCategory, SponsorBlockMode};
+use crate::subtitle::SubtitleFormat;
+use crate::{FileEncoding, FileSize, QualityPreference, Resolution, SortField};
+use chrono::{DateTime, Local};
+use clap::ArgGroup;
+use clap::builder::Resettable;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "downloader",
+    version,
+    about = "Download videos from YouTube, Instagram, and more",
+    // Options given after a profile's override its own
+    args_override_self = true
+)]
+pub struct Cli {
+    // Only missing with --check-config, as main makes sure
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// Show sizes in SI units (KB = 1000 bytes) instead of binary (KiB = 1024)
+    #[arg(long, global = true)]
+    pub si: bool,
+
+    /// Retry failed requests and downloads up to N times
+    #[arg(long, global = true, value_name = "N", default_value_t = 3)]
+    pub retries: u32,
+
+    /// Seconds to wait before the first retry, doubling for each one after it
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 1.0)]
+    pub retry_delay: f64,
+
+    /// Seconds to wait for yt-dlp to fetch a URL's details before retrying
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 120.0)]
+    pub metadata_timeout: f64,
+
+    /// Seconds to wait for a connection to a server before retrying
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 30.0)]
+    pub connect_timeout: f64,
+
+    /// Seconds a transfer may go without receiving anything before it's
+    /// aborted and retried
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 60.0)]
+    pub stall_timeout: f64,
+
+    /// Seconds to reuse a URL's details, cached from fetching it before, for
+    /// [default: 3600]
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub cache_ttl: Option<f64>,
+
+    /// Neither use nor keep cached details of URLs
+    #[arg(long, global = true, conflicts_with = "refresh")]
+    pub no_cache: bool,
+
+    /// Fetch the details of URLs again instead of using cached ones
+    #[arg(long, global = true)]
+    pub refresh: bool,
+
+    /// Check the config file and the saved state for problems, then exit
+    #[arg(long, exclusive = true)]
+    pub check_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Print the available formats for a URL
+    Info(InfoArgs),
+    /// Download media from a URL
+    Download(DownloadArgs),
+    /// Record a live stream until it ends
+    Record(RecordArgs),
+    /// Download the best audio of YouTube Music tracks or albums, one file per track
+    Music(MusicArgs),
+    /// Download episodes from a podcast RSS or Atom feed
+    Podcast(PodcastArgs),
+    /// Queue URLs to download later with `download --from-queue`, or manage queued ones
+    #[command(subcommand)]
+    Queue(QueueCommand),
+    /// Look through past downloads, kept in a database in the data directory
+    #[command(subcommand)]
+    History(HistoryCommand),
+    /// List downloads that failed even after retrying, and retry them
+    #[command(subcommand)]
+    Failed(FailedCommand),
+    /// Sum up the history: bytes per site and month, speeds, failure rates
+    /// and the most downloaded uploaders
+    Stats(StatsArgs),
+    /// Delete downloads, with the files saved next to them, that are older
+    /// or take more room than the `[[cleanup]]` rules of the config allow
+    Cleanup(CleanupArgs),
+    /// Move downloads into folders by site, uploader and year, or another
+    /// layout, keeping the history up to date
+    Organize(OrganizeArgs),
+    /// Follow channels, profiles and playlists, downloading what they add
+    #[command(subcommand)]
+    Subscriptions(SubscriptionCommand),
+    /// Re-hash downloaded files and compare them with their saved SHA-256 checksums
+    Verify(VerifyArgs),
+    /// Copy downloaded files into another container without re-encoding them
+    Remux(RemuxArgs),
+    /// Keep downloading queued URLs in the background, taking new ones from
+    /// `queue add` as they come, until stopped
+    #[cfg(unix)]
+    Daemon(DaemonArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    pub url: String,
+
+    /// List the available thumbnails instead of the formats
+    #[arg(long)]
+    pub list_thumbnails: bool,
+
+    /// List the available subtitles and automatic captions instead of the formats
+    #[arg(long)]
+    pub list_subs: bool,
+
+    #[command(flatten)]
+    pub listing: ListingArgs,
+
+    #[command(flatten)]
+    pub formats: FormatArgs,
+}
+
+#[derive(Args, Debug)]
+// URLs are optional here, as the daemon's jobs come from the queue
+#[command(mut_arg("urls", |arg| arg.required_unless_present(Resettable::Reset)))]
+#[command(mut_arg("from_queue", |arg| arg.hide(true)))]
+pub struct DaemonArgs {
+    /// Ask the running daemon what it's doing
+    #[arg(long, conflicts_with = "stop")]
+    pub status: bool,
+
+    /// Tell the running daemon to finish its current jobs and exit
+    #[arg(long)]
+    pub stop: bool,
+
+    /// Enforce the `[[cleanup]]` rules of the config on start and every hour
+    #[arg(long, conflicts_with_all = ["status", "stop"])]
+    pub cleanup: bool,
+
+    /// Queue the links of `.txt` and `.url` files dropped into DIR, instead
+    /// of the config's `watch_dir`, and move them into `DIR/done`
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["status", "stop"])]
+    pub watch: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub download: DownloadArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+// Options that recode the download, which --keep-video needs one of
+#[command(group(ArgGroup::new("recoding").multiple(true)))]
+pub struct DownloadArgs {
+    /// One or more URLs to download
+    #[arg(required_unless_present = "from_queue", value_name = "URL")]
+    pub urls: Vec<String>,
+
+    /// Download the queued URLs, highest priority first, instead of URLs given here
+    #[arg(long, conflicts_with = "urls")]
+    pub from_queue: bool,
+
+    /// Use the options of this profile in the config file, which options
+    /// given here override
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Tag what's downloaded with NAME in the history, to list it with
+    /// `history list --tag` and gather it with `history collect`
+    #[arg(long = "tag", value_name = "NAME", value_parser = parse_tag)]
+    pub tags: Vec<String>,
+
+    /// How many URLs to download at once
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Split large direct downloads across N ranged connections
+    #[arg(short = 'N', long, value_name = "N", default_value_t = 1)]
+    pub connections: usize,
+
+    /// Cap the combined download speed, in bytes per second (e.g. 500K, 2M)
+    #[arg(long, value_name = "RATE", value_parser = FileSize::parse)]
+    pub limit_rate: Option<FileSize>,
+
+    /// Program to fetch plain HTTP(S) files with
+    #[arg(long, value_name = "DOWNLOADER")]
+    pub downloader: Option<Downloader>,
+
+    /// Only transfer during this daily window of local time, e.g. 01:00-07:00,
+    /// holding downloads while it's closed
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = Window::parse)]
+    pub schedule: Option<Window>,
+
+    /// What to do when a file to save already exists, for media, thumbnails,
+    /// and subtitles alike
+    #[arg(long, value_name = "POLICY", default_value = "skip")]
+    pub on_conflict: ConflictPolicy,
+
+    /// Leave out HLS and DASH fragments that still fail after retrying, and
+    /// report the gaps, instead of failing the download
+    #[arg(long)]
+    pub skip_unavailable_fragments: bool,
+
+    /// Don't check finished downloads with ffprobe for missing streams, a
+    /// short duration, or container errors
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// Skip items listed in FILE and add each one downloaded, in the format
+    /// of yt-dlp's --download-archive
+    #[arg(long, value_name = "FILE")]
+    pub download_archive: Option<String>,
+
+    /// Download items the history has as downloaded before, under this URL
+    /// or another, instead of skipping them, and don't warn about files with
+    /// the same contents as earlier ones
+    #[arg(long)]
+    pub allow_duplicates: bool,
+
+    /// When the chosen format still fails after retrying, try up to N of the
+    /// next best ones instead
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub max_fallbacks: usize,
+
+    /// Keep file names to ASCII letters, digits and -_.()[], spelling
+    /// accented letters without their accents
+    #[arg(long)]
+    pub restrict_filenames: bool,
+
+    /// Directory to save downloads into
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    pub output_dir: String,
+
+    /// File name template, in yt-dlp's format
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "%(title)s [%(id)s].%(ext)s"
+    )]
+    pub template: String,
+
+    /// Mux an audio track for each of --audio-lang into one file
+    #[arg(long)]
+    pub multi_audio: bool,
+
+    /// Keep only the audio, from the best audio stream or taken out of a
+    /// muxed format when there's none, converted with ffmpeg
+    #[arg(short = 'x', long, conflicts_with_all = ["multi_audio", "max_total_size"])]
+    pub extract_audio: bool,
+
+    /// Format to convert extracted audio into, instead of keeping the stream's own
+    #[arg(long, value_name = "FORMAT", requires = "extract_audio")]
+    pub audio_format: Option<AudioFormat>,
+
+    /// Quality of converted audio: a VBR level from 0 (best) to 9, or a
+    /// bitrate like 192K
+    #[arg(
+        long,
+        value_name = "QUALITY",
+        value_parser = AudioQuality::parse,
+        default_value = "5"
+    )]
+    pub audio_quality: AudioQuality,
+
+    /// Normalize the loudness of extracted audio with two EBU R128 passes
+    #[arg(long, requires = "extract_audio")]
+    pub normalize_audio: bool,
+
+    /// Integrated loudness --normalize-audio aims for, in LUFS
+    #[arg(
+        long,
+        value_name = "LUFS",
+        allow_hyphen_values = true,
+        default_value_t = -16f64
+    )]
+    pub loudness_target: f64,
+
+    /// Recode downloaded videos into this container, copying the streams it
+    /// can hold and transcoding the rest
+    #[arg(
+        long,
+        value_name = "CONTAINER",
+        conflicts_with = "extract_audio",
+        group = "recoding"
+    )]
+    pub recode_video: Option<VideoContainer>,
+
+    /// Render subtitles in this language into the video's frames, for
+    /// players that can't show subtitle tracks. Transcodes the video.
+    #[arg(
+        long,
+        value_name = "LANG",
+        conflicts_with = "extract_audio",
+        group = "recoding"
+    )]
+    pub burn_subs: Option<String>,
+
+    /// Give portrait videos this shape, like 16:9, so they fill TVs the way
+    /// they should. Videos already as wide are left alone; the rest are
+    /// transcoded.
+    #[arg(
+        long,
+        value_name = "RATIO",
+        value_parser = AspectRatio::parse,
+        conflicts_with_all = ["extract_audio", "to_gif"],
+        group = "recoding"
+    )]
+    pub fit: Option<AspectRatio>,
+
+    /// How --fit reshapes videos
+    #[arg(long, value_name = "MODE", default_value = "pad", requires = "fit")]
+    pub fit_mode: FitMode,
+
+    /// Transcode video for --recode-video, --burn-subs and --fit on this
+    /// hardware encoder, or in software when it doesn't work here
+    #[arg(long, value_name = "ENCODER")]
+    pub hwaccel: Option<HwAccel>,
+
+    /// Turn downloaded clips into animated GIFs, or WebPs with --gif-format
+    #[arg(
+        long,
+        group = "recoding",
+        conflicts_with_all = [
+            "extract_audio",
+            "recode_video",
+            "embed_metadata",
+            "embed_subs",
+            "embed_thumbnail",
+            "split_chapters",
+        ]
+    )]
+    pub to_gif: bool,
+
+    /// Format --to-gif makes
+    #[arg(long, value_name = "FORMAT", default_value = "gif")]
+    pub gif_format: AnimationFormat,
+
+    /// Frames per second of --to-gif animations
+    #[arg(long, value_name = "FPS", default_value_t = 15, value_parser = clap::value_parser!(u32).range(1..=60))]
+    pub gif_fps: u32,
+
+    /// Width --to-gif scales clips down to, keeping their aspect ratio
+    #[arg(long, value_name = "PIXELS", default_value_t = 480, value_parser = clap::value_parser!(u32).range(16..))]
+    pub gif_width: u32,
+
+    /// Colors in the palette of --to-gif GIFs, fewer making smaller files
+    #[arg(long, value_name = "COLORS", default_value_t = 256, value_parser = clap::value_parser!(u16).range(2..=256))]
+    pub gif_colors: u16,
+
+    /// Keep the original download next to the recoded video or animation
+    #[arg(short, long, requires = "recoding")]
+    pub keep_video: bool,
+
+    /// Mark the segments of YouTube videos SponsorBlock users submitted as
+    /// chapters, or remove them
+    #[arg(long, value_name = "MODE")]
+    pub sponsorblock: Option<SponsorBlockMode>,
+
+    /// SponsorBlock categories to mark or remove (comma-separated)
+    #[arg(
+        long,
+        value_name = "CATEGORIES",
+        value_delimiter = ',',
+        default_value = "sponsor,selfpromo"
+    )]
+    pub sb_categories: Vec<SegmentCategory>,
+
+    /// Only download this part of the video, like 00:05:00-00:12:30. Can be
+    /// given more than once, saving each part to a file of its own.
+    #[arg(
+        long = "sections",
+        value_name = "START-END",
+        value_parser = Section::parse,
+        conflicts_with_all = ["multi_audio", "sponsorblock", "split_chapters"]
+    )]
+    pub sections: Vec<Section>,
+
+    /// Save the best thumbnail next to the downloaded file
+    #[arg(long)]
+    pub write_thumbnail: bool,
+
+    /// Embed the best thumbnail as cover art, which --extract-audio does
+    /// unless --no-embed-thumbnail is given
+    #[arg(long, overrides_with = "no_embed_thumbnail")]
+    pub embed_thumbnail: bool,
+
+    /// Don't embed a thumbnail, even with --extract-audio
+    #[arg(long, overrides_with = "embed_thumbnail")]
+    pub no_embed_thumbnail: bool,
+
+    /// Write the title, uploader, upload date, description and source URL
+    /// into the file's tags
+    #[arg(long)]
+    pub embed_metadata: bool,
+
+    /// Also cut the downloaded file into one file per chapter, named like
+    /// `01 - Chapter Title.mp4`
+    #[arg(long)]
+    pub split_chapters: bool,
+
+    /// Save subtitles next to the downloaded file, falling back to automatic captions
+    #[arg(long)]
+    pub write_subs: bool,
+
+    /// Save the item's details next to the downloaded file as
+    /// <name>.info.json
+    #[arg(long)]
+    pub write_info_json: bool,
+
+    /// With --write-info-json, also save yt-dlp's own JSON for the item as
+    /// <name>.raw.info.json
+    #[arg(long, requires = "write_info_json")]
+    pub raw_info_json: bool,
+
+    /// Save a .nfo file next to the downloaded file, so media servers like
+    /// Kodi and Jellyfin pick up its title, description, date and uploader
+    #[arg(long)]
+    pub write_nfo: bool,
+
+    /// Save a contact sheet of frames from each downloaded video next to it
+    /// as <name>.preview.jpg
+    #[arg(long)]
+    pub write_preview: bool,
+
+    /// Columns and rows of frames in --write-preview sheets
+    #[arg(long, value_name = "GRID", default_value = "4x4", value_parser = Grid::parse)]
+    pub preview_grid: Grid,
+
+    /// Mux subtitles in the --sub-langs languages into the file as
+    /// selectable tracks
+    #[arg(long)]
+    pub embed_subs: bool,
+
+    /// Subtitle languages to save or embed (comma-separated)
+    #[arg(
+        long,
+        value_name = "LANGS",
+        value_delimiter = ',',
+        default_value = "en"
+    )]
+    pub sub_langs: Vec<String>,
+
+    /// Convert saved subtitles to this format
+    #[arg(long, value_name = "FORMAT")]
+    pub convert_subs: Option<SubtitleFormat>,
+
+    /// Post-processing steps to run, in order (comma-separated); steps left
+    /// out don't run even when asked for [default: the order listed]
+    #[arg(long, value_name = "STEPS", value_delimiter = ',')]
+    pub post_processing: Option<Vec<Step>>,
+
+    #[command(flatten)]
+    pub hooks: HookArgs,
+
+    #[command(flatten)]
+    pub listing: ListingArgs,
+
+    #[command(flatten)]
+    pub formats: FormatArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RecordArgs {
+    pub url: String,
+
+    /// Record from the start of the stream rather than from now, where supported
+    #[arg(long)]
+    pub from_start: bool,
+
+    /// Wait for a scheduled stream to go live, retrying every N seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub wait: Option<u64>,
+
+    /// Output path template, in yt-dlp's format
+    #[arg(short, long, value_name = "TEMPLATE")]
+    pub output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+// The audio is always extracted, and split unless --no-split is given
+#[command(mut_arg("extract_audio", |arg| arg.hide(true)))]
+#[command(mut_arg("split_chapters", |arg| arg.hide(true)))]
+pub struct MusicArgs {
+    /// Keep albums uploaded as a single video in one file instead of
+    /// splitting them into tracks by chapter
+    #[arg(long)]
+    pub no_split: bool,
+
+    #[command(flatten)]
+    pub download: DownloadArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct PodcastArgs {
+    /// URL of the RSS or Atom feed
+    pub url: String,
+
+    /// Only download the latest N episodes
+    #[arg(long, value_name = "N")]
+    pub latest: Option<usize>,
+
+    /// Directory to save episodes into
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    pub output_dir: String,
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// Print the summary as JSON, for dashboards and scripts
+    #[arg(long)]
+    pub json: bool,
+
+    /// How many uploaders to list
+    #[arg(long, value_name = "COUNT", default_value_t = 10)]
+    pub top: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct CleanupArgs {
+    /// List what would be deleted, without deleting anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Clean up this folder instead of those in the config
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+
+    /// Delete downloads in DIR, watched then oldest first, while it holds
+    /// more than SIZE (e.g. 200G)
+    #[arg(long, value_name = "SIZE", value_parser = FileSize::parse, requires = "dir")]
+    pub max_size: Option<FileSize>,
+
+    /// Delete downloads in DIR finished more than DAYS days ago
+    #[arg(long, value_name = "DAYS", requires = "dir")]
+    pub max_age: Option<f64>,
+}
+
+#[derive(Args, Debug)]
+pub struct OrganizeArgs {
+    /// Library folder to move downloads into
+    pub dir: PathBuf,
+
+    /// Folders under DIR to move each download into, made of %(extractor)s,
+    /// %(uploader)s, %(year)s, %(month)s, %(id)s and %(title)s
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = library::DEFAULT_LAYOUT,
+        value_parser = library::parse_layout
+    )]
+    pub layout: String,
+
+    /// Leave a symbolic link where each download was
+    #[arg(long)]
+    pub link: bool,
+
+    /// List what would be moved, without moving anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Directories to search for `.sha256` files, or the files themselves
+    #[arg(value_name = "PATH", default_value = ".")]
+    pub paths: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct RemuxArgs {
+    /// Files to remux
+    #[arg(required = true, value_name = "FILE")]
+    pub paths: Vec<PathBuf>,
+
+    /// Container to copy the streams into
+    #[arg(long, value_name = "CONTAINER")]
+    pub to: VideoContainer,
+
+    /// Keep the original files next to the remuxed ones
+    #[arg(short, long)]
+    pub keep_original: bool,
+
+    /// What to do when a remuxed file already exists
+    #[arg(long, value_name = "POLICY", default_value = "skip")]
+    pub on_conflict: ConflictPolicy,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum QueueCommand {
+    /// Add URLs to the end of the queue for their priority
+    Add {
+        #[arg(required = true, value_name = "URL")]
+        urls: Vec<String>,
+
+        #[arg(long, value_name = "PRIORITY", default_value_t = Priority::Normal)]
+        priority: Priority,
+
+        /// Don't start before this local time: HH:MM for its next occurrence,
+        /// or YYYY-MM-DD HH:MM
+        #[arg(long, value_name = "TIME", value_parser = schedule::parse_start)]
+        at: Option<DateTime<Local>>,
+    },
+    /// List pending jobs in the order they'll be downloaded
+    List,
+    /// Change a job's priority or move it to the front of its priority
+    Move {
+        id: u64,
+
+        /// Run the job at this priority instead
+        #[arg(long, value_name = "PRIORITY")]
+        priority: Option<Priority>,
+
+        /// Run the job before the others at its priority
+        #[arg(long)]
+        first: bool,
+    },
+    /// Suspend jobs, keeping what they've downloaded, or hold them back from
+    /// the next `download --from-queue`
+    Pause {
+        #[arg(required = true, value_name = "ID")]
+        ids: Vec<u64>,
+    },
+    /// Continue paused jobs
+    Resume {
+        #[arg(required = true, value_name = "ID")]
+        ids: Vec<u64>,
+    },
+    /// Remove jobs from the queue
+    Remove {
+        #[arg(required = true, value_name = "ID")]
+        ids: Vec<u64>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FailedCommand {
+    /// List failed downloads with their errors, oldest first
+    List,
+    /// Download failed URLs again, taking them off the list as they succeed
+    Retry(Box<RetryArgs>),
+    /// Take failed downloads off the list without retrying them
+    Remove {
+        #[arg(required = true, value_name = "ID")]
+        ids: Vec<u64>,
+    },
+}
+
+#[derive(Args, Debug)]
+// The URLs come from the failed list
+#[command(mut_arg("urls", |arg| arg.required_unless_present(Resettable::Reset).hide(true)))]
+#[command(mut_arg("from_queue", |arg| arg.hide(true)))]
+pub struct RetryArgs {
+    /// Only retry the failed download with this ID; can be given more than once
+    #[arg(long = "id", value_name = "ID")]
+    pub ids: Vec<u64>,
+
+    /// Only retry failures of this kind
+    #[arg(long, value_name = "KIND", conflicts_with = "ids")]
+    pub only: Option<FailureKind>,
+
+    #[command(flatten)]
+    pub download: DownloadArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// List past downloads, newest first
+    List {
+        /// Show at most this many
+        #[arg(long, value_name = "COUNT", default_value_t = 20)]
+        limit: u32,
+
+        /// Only show downloads that failed
+        #[arg(long)]
+        failed: bool,
+
+        /// Only show downloads with this tag
+        #[arg(long, value_name = "NAME")]
+        tag: Option<String>,
+    },
+    /// List past downloads whose URL, title or path contains TEXT
+    Search {
+        text: String,
+
+        /// Show at most this many
+        #[arg(long, value_name = "COUNT", default_value_t = 20)]
+        limit: u32,
+    },
+    /// Print everything recorded about a past download
+    Show { id: i64 },
+    /// Add tags to a past download, or take them off
+    Tag {
+        id: i64,
+
+        #[arg(required = true, value_name = "NAME", value_parser = parse_tag)]
+        tags: Vec<String>,
+
+        /// Take the tags off instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Gather tagged downloads into a folder per tag under DIR, as symbolic
+    /// links to where they are
+    Collect {
+        dir: PathBuf,
+
+        /// Only gather downloads with this tag
+        #[arg(long, value_name = "NAME")]
+        tag: Option<String>,
+
+        /// Move each download, with the files saved next to it, into the
+        /// folder of its first tag instead, linking to it from the others
+        #[arg(long = "move")]
+        move_files: bool,
+    },
+    /// Print the whole history, oldest first, with times as Unix timestamps
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+    /// Add the items in a yt-dlp --download-archive file to the history, so
+    /// they're skipped as downloaded before
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubscriptionCommand {
+    /// Subscribe to a YouTube channel, Instagram profile or playlist, so
+    /// items it adds from now on are downloaded on sync, or to a batch file
+    /// of URLs, so each one added to it is
+    Add {
+        #[arg(required_unless_present = "batch_file")]
+        url: Option<String>,
+
+        /// Subscribe to a file of URLs, one per line, instead of a URL
+        #[arg(long, value_name = "FILE", conflicts_with = "url")]
+        batch_file: Option<PathBuf>,
+
+        /// Download the items there now on the next sync too, as is always
+        /// done for batch files
+        #[arg(long)]
+        include_existing: bool,
+
+        /// Sync it on this schedule, like `every 6h` or `daily at 03:00`,
+        /// while the daemon runs or on `sync --due`
+        #[arg(long, value_name = "SCHEDULE", value_parser = schedule::Recurrence::parse)]
+        schedule: Option<schedule::Recurrence>,
+
+        /// Download options for its items, like `--max-res 720p`, after `--`
+        #[arg(last = true, value_name = "OPTIONS")]
+        options: Vec<String>,
+    },
+    /// List subscriptions and when they were last synced
+    List,
+    /// Unsubscribe, keeping what was downloaded
+    Remove { id: u64 },
+    /// Download what subscriptions added since they were last synced
+    Sync {
+        /// Only sync these subscriptions
+        #[arg(value_name = "ID")]
+        ids: Vec<u64>,
+
+        /// Only sync scheduled subscriptions whose time has come, as the
+        /// daemon does, for running from cron
+        #[arg(long, conflicts_with = "ids")]
+        due: bool,
+    },
+}
+
+/// Shell commands run as jobs reach a stage, told about the job through
+/// `DOWNLOADER_*` environment variables and as JSON on their stdin
+#[derive(Args, Debug, Default, Clone)]
+pub struct HookArgs {
+    /// Run CMD once an item's details are fetched, before it's downloaded
+    #[arg(long, value_name = "CMD")]
+    pub on_metadata: Option<String>,
+
+    /// Run CMD with the path of each completed download
+    #[arg(long, value_name = "CMD")]
+    pub on_download_complete: Option<String>,
+
+    /// Run CMD with the error when a job fails
+    #[arg(long, value_name = "CMD")]
+    pub on_error: Option<String>,
+}
+
+/// Options for choosing between the formats of a single item
+#[derive(Args, Debug, Default, Clone)]
+pub struct FormatArgs {
+    /// Only show the best format for each resolution and encoding
+    #[arg(long)]
+    pub best: bool,
+
+    /// Order formats by these keys (res, fps, size, abr, ext), largest first;
+    /// prefix a key with + for smallest first, e.g. --sort-by=res,+size
+    #[arg(
+        long,
+        value_name = "KEYS",
+        value_delimiter = ',',
+        value_parser = SortField::parse,
+        allow_hyphen_values = true,
+        default_value = "res,fps,size"
+    )]
+    pub sort_by: Vec<SortField>,
+
+    /// Only consider these extensions (comma-separated, e.g. mp4,m4a)
+    #[arg(long = "ext", value_name = "EXTS", value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// Only consider these encodings (comma-separated)
+    #[arg(long = "encoding", value_name = "ENCODINGS", value_delimiter = ',')]
+    pub encodings: Vec<FileEncoding>,
+
+    /// Exclude formats larger than this (e.g. 500K, 200MB, 1.5GiB)
+    #[arg(long, value_name = "SIZE", value_parser = FileSize::parse)]
+    pub max_size: Option<FileSize>,
+
+    /// Exclude formats smaller than this
+    #[arg(long, value_name = "SIZE", value_parser = FileSize::parse)]
+    pub min_size: Option<FileSize>,
+
+    /// Pick the best format, or video and audio pair, whose combined size
+    /// fits in this budget (e.g. 200MB)
+    #[arg(long, value_name = "SIZE", value_parser = FileSize::parse)]
+    pub max_total_size: Option<FileSize>,
+
+    /// Exclude video formats below this resolution (e.g. 720 or 720p)
+    #[arg(long = "min-res", value_name = "RES", value_parser = Resolution::parse)]
+    pub min_resolution: Option<Resolution>,
+
+    /// Exclude video formats above this resolution
+    #[arg(long = "max-res", value_name = "RES", value_parser = Resolution::parse)]
+    pub max_resolution: Option<Resolution>,
+
+    /// Exclude video formats below this frame rate
+    #[arg(long, value_name = "FPS")]
+    pub min_fps: Option<f64>,
+
+    /// Video codecs in order of preference, for formats that otherwise tie
+    #[arg(
+        long,
+        value_name = "CODECS",
+        value_delimiter = ',',
+        default_value = "av01,vp9,h264"
+    )]
+    pub video_codecs: Vec<String>,
+
+    /// Audio codecs in order of preference, for formats that otherwise tie
+    #[arg(
+        long,
+        value_name = "CODECS",
+        value_delimiter = ',',
+        default_value = "opus,aac"
+    )]
+    pub audio_codecs: Vec<String>,
+
+    /// How to choose between formats of the same resolution
+    #[arg(long, value_name = "MODE", value_enum, default_value_t)]
+    pub prefer: QualityPreference,
+
+    /// Prefer HDR video over SDR of the same resolution
+    #[arg(long, conflicts_with = "no_hdr")]
+    pub prefer_hdr: bool,
+
+    /// Exclude HDR video formats
+    #[arg(long)]
+    pub no_hdr: bool,
+
+    /// Exclude video and images that aren't taller than they are wide
+    #[arg(long, conflicts_with = "landscape_only")]
+    pub portrait_only: bool,
+
+    /// Exclude video and images that aren't wider than they are tall
+    #[arg(long)]
+    pub landscape_only: bool,
+
+    /// Only consider audio tracks in these languages (comma-separated, e.g. de,en)
+    #[arg(long = "audio-lang", value_name = "LANGS", value_delimiter = ',')]
+    pub audio_langs: Vec<String>,
+
+    /// Exclude formats only available as HLS (m3u8) streams
+    #[arg(long)]
+    pub no_hls: bool,
+
+    /// Only consider formats served as plain HTTP(S) files
+    #[arg(long)]
+    pub direct_only: bool,
+
+    /// List storyboard (seek preview) formats, which are hidden by default
+    #[arg(long)]
+    pub include_storyboards: bool,
+}
+
+/// Options for URLs that expand to many items, such as YouTube channels or
+/// Instagram carousels
+#[derive(Args, Debug, Default, Clone)]
+pub struct ListingArgs {
+    /// Only include these items of a carousel or playlist (1-based, comma-separated)
+    #[arg(long, value_name = "INDICES", value_delimiter = ',')]
+    pub items: Vec<usize>,
+
+    /// Only consider the latest N uploads
+    #[arg(long, value_name = "N")]
+    pub latest: Option<usize>,
+
+    /// Only consider uploads made on or after this date (YYYYMMDD or YYYY-MM-DD)
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    pub since: Option<String>,
+
+    /// Skip items shorter than this (seconds, M:SS or H:MM:SS)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub min_duration: Option<f64>,
+
+    /// Skip items longer than this (seconds, M:SS or H:MM:SS)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub max_duration: Option<f64>,
+}
+
+/// Normalizes a date to yt-dlp's `upload_date` format (YYYYMMDD), so it can be
+/// compared directly against the metadata.
+fn parse_date(value: &str) -> Result<String, String> {
+    let digits = value.replace('-', "");
+    if digits.len() != 8 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "invalid date `{value}`, expected YYYYMMDD or YYYY-MM-DD"
+        ));
+    }
+    Ok(digits)
+}
+
+/// Tags name folders in `history collect`, so they can't hold path separators.
+fn parse_tag(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() || value == "." || value == ".." {
+        return Err(format!("invalid tag `{value}`"));
+    }
+    if value.contains(['/', '\\', '\n']) {
+        return Err(format!(
+            "invalid tag `{value}`, which can't hold slashes or line breaks"
+        ));
+    }
+    Ok(value.to_string())
+}
+
+/// Parses `90`, `1:30` or `1:01:30` into seconds.
+fn parse_duration(value: &str) -> Result<f64, String> {
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() > 3 {
+        return Err(format!("invalid duration `{value}`"));
+    }
+    parts.iter().try_fold(0f64, |total, part| {
+        part.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|n| *n >= 0f64)
+            .map(|n| total * 60f64 + n)
+            .ok_or_else(|| format!("invalid duration `{value}`, expected seconds, M:SS or H:MM:SS"))
+    })
+}
@@ -0,0 +1,175 @@
+use crate::AppError;
+use crate::history::Entry;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+fn io_error(path: &Path, e: std::io::Error) -> AppError {
+    AppError::Io(format!("{}: {e}", path.display()))
+}
+
+/// The file at `path` and those saved next to it under the same name, like
+/// its thumbnail, subtitles and checksum
+pub fn with_companions(path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf()];
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return paths;
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return paths;
+    };
+    let mut companions: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .filter(|other| other != path && other.is_file())
+        .collect();
+    companions.sort();
+    paths.extend(companions);
+    paths
+}
+
+/// Renames `from`, copying it instead when `to` is on another filesystem
+fn rename(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::rename(from, to)
+        .or_else(|_| std::fs::copy(from, to).and_then(|_| std::fs::remove_file(from)))
+}
+
+/// Moves the file at `path` and its companions into `dir`, returning where
+/// the file is now. Nothing is moved when any of them would replace a file.
+pub fn move_into(path: &Path, dir: &Path) -> Result<PathBuf, AppError> {
+    if path.parent() == Some(dir) {
+        return Ok(path.to_path_buf());
+    }
+    std::fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+    let moves: Vec<(PathBuf, PathBuf)> = with_companions(path)
+        .into_iter()
+        .map(|from| {
+            let to = dir.join(from.file_name().unwrap_or_default());
+            (from, to)
+        })
+        .collect();
+    if let Some((_, to)) = moves
+        .iter()
+        .find(|(from, to)| to.symlink_metadata().is_ok() && !links_to(to, from))
+    {
+        return Err(AppError::Io(format!("{} already exists", to.display())));
+    }
+    for (from, to) in &moves {
+        // A link to the file, as `collect` makes, gives way to it
+        if links_to(to, from) {
+            std::fs::remove_file(to).map_err(|e| io_error(to, e))?;
+        }
+        rename(from, to).map_err(|e| io_error(from, e))?;
+    }
+    Ok(moves[0].1.clone())
+}
+
+/// Whether `link` is a symbolic link to `path`
+fn links_to(link: &Path, path: &Path) -> bool {
+    std::fs::read_link(link).is_ok_and(|target| target == path)
+}
+
+#[cfg(unix)]
+pub fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+pub fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+/// Links to the file at `path` from `dir`, under its own name, replacing
+/// a link to a file that's gone. Returns whether a link was made, rather
+/// than already there.
+pub fn link_into(path: &Path, dir: &Path) -> Result<bool, AppError> {
+    let link = dir.join(path.file_name().unwrap_or_default());
+    if links_to(&link, path) {
+        return Ok(false);
+    }
+    let dangling = link.is_symlink() && !link.exists();
+    if dangling {
+        std::fs::remove_file(&link).map_err(|e| io_error(&link, e))?;
+    } else if link.symlink_metadata().is_ok() {
+        return Err(AppError::Io(format!("{} already exists", link.display())));
+    }
+    std::fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+    symlink(path, &link).map_err(|e| io_error(&link, e))?;
+    Ok(true)
+}
+
+/// Gathers the downloads of `entries` into a folder per tag under `dir`,
+/// only for the `only` tag when given. Returns where the files that were
+/// moved were, with where they are now.
+pub fn collect(
+    entries: &[Entry],
+    dir: &Path,
+    only: Option<&str>,
+    move_files: bool,
+) -> Vec<(PathBuf, PathBuf)> {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    // Jobs that downloaded the same file again share its path, and their tags
+    let mut files: Vec<(PathBuf, BTreeSet<&str>)> = vec![];
+    for entry in entries {
+        let Some(path) = entry.path.as_deref().map(PathBuf::from) else {
+            continue;
+        };
+        let tags = entry
+            .tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| only.is_none_or(|only| *tag == only));
+        match files.iter_mut().find(|(other, _)| *other == path) {
+            Some((_, file_tags)) => file_tags.extend(tags),
+            None => files.push((path, tags.collect())),
+        }
+    }
+
+    let mut moved = vec![];
+    let (mut linked, mut failed) = (0, 0);
+    for (mut path, tags) in files {
+        if !path.exists() {
+            eprintln!("Skipping {}, which is gone", path.display());
+            continue;
+        }
+        let tags: Vec<&str> = tags.into_iter().collect();
+        let mut links = tags.as_slice();
+        if move_files && let Some((first, rest)) = tags.split_first() {
+            match move_into(&path, &dir.join(first)) {
+                Ok(new_path) => {
+                    if new_path != path {
+                        moved.push((path, new_path.clone()));
+                    }
+                    path = new_path;
+                    links = rest;
+                }
+                Err(error) => {
+                    eprintln!("Couldn't move {}: {error}", path.display());
+                    failed += 1;
+                    continue;
+                }
+            }
+        }
+        for tag in links {
+            match link_into(&path, &dir.join(tag)) {
+                Ok(made) => linked += usize::from(made),
+                Err(error) => {
+                    eprintln!("Couldn't link to {}: {error}", path.display());
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!(
+        "Moved {} and linked {linked} download(s) into {}{}",
+        moved.len(),
+        dir.display(),
+        if failed > 0 {
+            format!(", {failed} failed")
+        } else {
+            String::new()
+        }
+    );
+    moved
+}
@@ -0,0 +1,132 @@
+use crate::AppError;
+use crate::backend::Downloader;
+use crate::cleanup::Retention;
+use crate::hwaccel::HwAccel;
+use crate::postprocess::Step;
+use crate::schedule::Window;
+use crate::schema::Migration;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Changes to the config's keys, each bringing a config of the version
+/// before it up to the next. Configs without a `version` key are version 1.
+const MIGRATIONS: &[Migration<toml::Table>] = &[];
+
+/// The version of the config's keys this version of downloader reads
+const VERSION: i64 = MIGRATIONS.len() as i64 + 1;
+
+/// Settings read from `config.toml` in the user's config directory. Every key
+/// is optional, and command-line flags take precedence over them. A
+/// `version` key says which version of the keys the file is written for,
+/// and older ones are brought up to date as they're read.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Show sizes in SI units (KB = 1000 bytes) instead of binary (KiB = 1024)
+    pub si_units: bool,
+    /// Program to fetch plain HTTP(S) files with: `native` or `aria2c`
+    pub downloader: Option<Downloader>,
+    /// Only transfer during this daily window of local time, e.g. `"01:00-07:00"`
+    pub schedule: Option<Window>,
+    /// File listing downloaded items, kept as yt-dlp's `--download-archive` is
+    pub download_archive: Option<String>,
+    /// Post-processing steps to run, in order, e.g.
+    /// `["extract-audio", "embed-metadata", "embed-thumbnail"]`. Steps left
+    /// out don't run even when asked for.
+    pub post_processing: Option<Vec<Step>>,
+    /// Seconds to reuse a URL's cached details for before fetching them again
+    pub metadata_cache_ttl: Option<f64>,
+    /// Hardware encoder to transcode videos with: `auto`, `nvenc`, `vaapi`
+    /// or `videotoolbox`
+    pub hwaccel: Option<HwAccel>,
+    /// Shell command run once an item's details are fetched
+    pub on_metadata: Option<String>,
+    /// Shell command run with the path of each completed download
+    pub on_download_complete: Option<String>,
+    /// Shell command run when a job fails
+    pub on_error: Option<String>,
+    /// Sets of download options chosen with `--profile`, each a table like
+    /// `[profile.phone]` of option names and values, e.g. `max-res = "720p"`
+    pub profile: HashMap<String, toml::Table>,
+    /// Limits on what's kept in download folders, each a `[[cleanup]]` table
+    /// like `dir = "/media/videos"`, `max_size = "200G"`, `max_age_days = 30`,
+    /// enforced by `cleanup` and `daemon --cleanup`
+    pub cleanup: Vec<Retention>,
+    /// Folder the daemon queues the links of dropped `.txt` and `.url` files
+    /// from, moving each file into a `done` folder inside it afterwards
+    pub watch_dir: Option<PathBuf>,
+}
+
+/// `$XDG_CONFIG_HOME/downloader`, falling back to `~/.config/downloader`, or
+/// `%APPDATA%\downloader` on Windows
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("downloader"))
+}
+
+/// `$XDG_DATA_HOME/downloader`, falling back to `~/.local/share/downloader`,
+/// or `%LOCALAPPDATA%\downloader` on Windows
+pub fn data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join("downloader"))
+}
+
+/// `$XDG_CACHE_HOME/downloader`, falling back to `~/.cache/downloader`, or
+/// a `cache` folder in the data directory on Windows
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|base| base.join("downloader"))
+        .or_else(|| data_dir().map(|dir| dir.join("cache")))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+impl Config {
+    /// Loads the config file, or the defaults when there isn't one.
+    pub fn load() -> Result<Config, AppError> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(AppError::Io(format!("{}: {e}", path.display()))),
+        };
+        let invalid =
+            |e: toml::de::Error| AppError::InvalidConfig(format!("{}: {e}", path.display()));
+        let mut table: toml::Table = toml::from_str(&contents).map_err(invalid)?;
+        let version = match table.remove("version") {
+            None => 1,
+            Some(toml::Value::Integer(version)) => version,
+            Some(_) => {
+                return Err(AppError::InvalidConfig(format!(
+                    "{}: version isn't a number",
+                    path.display()
+                )));
+            }
+        };
+        if version > VERSION {
+            return Err(AppError::NewerFormat(format!(
+                "{} is written for version {version} of the config, newer than {VERSION}",
+                path.display()
+            )));
+        }
+        for migration in &MIGRATIONS[(version.max(1) - 1) as usize..] {
+            migration(&mut table);
+        }
+        table.try_into().map_err(invalid)
+    }
+}
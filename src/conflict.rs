@@ -0,0 +1,109 @@
+use crate::AppError;
+use clap::ValueEnum;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What to do when a download's file already exists.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the existing file and don't download
+    #[default]
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Save next to it as `name (1).ext`, `name (2).ext`, and so on
+    Rename,
+    /// Ask which of the others to do for each file
+    Ask,
+}
+
+/// Held while prompting, so concurrent downloads ask one at a time
+static PROMPT: Mutex<()> = Mutex::new(());
+
+/// Asks on the terminal what to do about `path`. Skips when there's no
+/// answer, e.g. when stdin isn't interactive.
+fn ask(path: &Path) -> ConflictPolicy {
+    let _prompt = PROMPT.lock().unwrap();
+    loop {
+        eprint!(
+            "\r\x1b[2K{} already exists. [s]kip, [o]verwrite, or [r]ename? ",
+            path.display()
+        );
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        match io::stdin().lock().read_line(&mut answer) {
+            Ok(0) | Err(_) => return ConflictPolicy::Skip,
+            Ok(_) => {}
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "s" | "skip" => return ConflictPolicy::Skip,
+            "o" | "overwrite" => return ConflictPolicy::Overwrite,
+            "r" | "rename" => return ConflictPolicy::Rename,
+            _ => {}
+        }
+    }
+}
+
+/// The first of `name (1).ext`, `name (2).ext`, ... that doesn't exist yet
+pub fn numbered(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    (1..)
+        .map(|n| {
+            let file_name = match &extension {
+                Some(extension) => format!("{stem} ({n}).{extension}"),
+                None => format!("{stem} ({n})"),
+            };
+            path.with_file_name(file_name)
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Where a file meant for `path` should be saved under `policy`: `path`
+/// itself, a numbered name next to it, or `None` to keep what's there.
+pub fn resolve(path: &Path, policy: ConflictPolicy) -> Option<PathBuf> {
+    if !path.exists() {
+        return Some(path.to_path_buf());
+    }
+    let policy = match policy {
+        ConflictPolicy::Ask => ask(path),
+        policy => policy,
+    };
+    match policy {
+        ConflictPolicy::Skip | ConflictPolicy::Ask => None,
+        ConflictPolicy::Overwrite => Some(path.to_path_buf()),
+        ConflictPolicy::Rename => Some(numbered(path)),
+    }
+}
+
+/// Whether to write a thumbnail or subtitle file at `path`. Sidecars are
+/// named after their media file, which was already renamed if it needed to
+/// be, so an existing one is replaced rather than numbered.
+pub fn should_write_sidecar(path: &Path, policy: ConflictPolicy) -> bool {
+    let policy = match policy {
+        ConflictPolicy::Ask if path.exists() => ask(path),
+        policy => policy,
+    };
+    policy != ConflictPolicy::Skip || !path.exists()
+}
+
+/// Moves a finished file from `staged` to `path`, or where `policy` puts it
+/// instead. A file that isn't kept is deleted. Returns where it was saved.
+pub fn place(
+    staged: &Path,
+    path: &Path,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    let Some(destination) = resolve(path, policy) else {
+        let _ = std::fs::remove_file(staged);
+        return Ok(None);
+    };
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    std::fs::rename(staged, &destination)
+        .map_err(|e| AppError::Io(format!("{}: {e}", destination.display())))?;
+    Ok(Some(destination))
+}
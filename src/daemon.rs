@@ -0,0 +1,346 @@
+use crate::cli::{DaemonArgs, DownloadArgs};
+use crate::config::{Config, data_dir};
+use crate::download::{self, DownloadObserver};
+use crate::failed::DeadLetters;
+use crate::queue::{self, Job, Priority, Queue};
+use crate::{
+    AppError, TerminalObserver, cleanup, download_url, failed, interrupt, observers, resolve_url,
+    schedule, subscription, watch,
+};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, ScopedJoinHandle};
+use std::time::Duration;
+
+/// How often the daemon looks at the saved queue for changes made without it
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often `--cleanup` enforces the config's cleanup rules
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// What a client asks of the running daemon, sent as one line of JSON.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "request", rename_all = "lowercase")]
+pub enum Request {
+    Add {
+        urls: Vec<String>,
+        priority: Priority,
+        start_at: Option<i64>,
+    },
+    Status,
+    Stop,
+}
+
+fn socket_path() -> Result<PathBuf, AppError> {
+    data_dir()
+        .map(|dir| dir.join("daemon.sock"))
+        .ok_or_else(|| AppError::Io("no data directory to keep the daemon's socket in".to_string()))
+}
+
+/// Sends `request` to the running daemon and returns its reply, or `None`
+/// when no daemon is running.
+pub fn send(request: &Request) -> Result<Option<String>, AppError> {
+    let Ok(mut stream) = UnixStream::connect(socket_path()?) else {
+        return Ok(None);
+    };
+    let json = serde_json::to_string(request).map_err(|e| AppError::InvalidJson(e.to_string()))?;
+    let mut reply = String::new();
+    writeln!(stream, "{json}")
+        .and_then(|_| stream.shutdown(Shutdown::Write))
+        .and_then(|_| stream.read_to_string(&mut reply))
+        .map_err(|e| AppError::Io(format!("talking to the daemon: {e}")))?;
+    Ok(Some(reply))
+}
+
+/// Hands each request that reaches the socket to the daemon's loop, and
+/// its answer back to the client.
+fn listen(listener: UnixListener, requests: Sender<(Request, Sender<String>)>) {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        let mut line = String::new();
+        if BufReader::new(&stream).read_line(&mut line).is_err() {
+            continue;
+        }
+        let reply = match serde_json::from_str(&line) {
+            // Answered before stopping, as the daemon may exit right after
+            Ok(Request::Stop) => {
+                let _ = stream.write_all(b"Stopping once running jobs wind down\n");
+                interrupt::stop();
+                continue;
+            }
+            Ok(request) => {
+                let (reply, answer) = mpsc::channel();
+                if requests.send((request, reply)).is_err() {
+                    return;
+                }
+                answer.recv().unwrap_or_default()
+            }
+            Err(e) => format!("Error: invalid request: {e}\n"),
+        };
+        let _ = stream.write_all(reply.as_bytes());
+    }
+}
+
+/// Runs the daemon, or with `--status` or `--stop` asks the running one.
+///
+/// The daemon downloads queued jobs as they come, `--jobs` at a time, until
+/// it's stopped. New jobs arrive through `queue add`, which hands them over
+/// on a Unix socket, and other queue changes are picked up from the saved
+/// queue. Finished jobs leave the queue, and failed ones move to the failed
+/// list for `failed retry`. Jobs a daemon that was stopped or killed didn't
+/// finish are resumed first. Subscriptions with a schedule are
+/// synced alongside the jobs when their time comes, and links dropped into
+/// the watched folder are queued.
+pub fn run(args: DaemonArgs, config: &Config) -> Result<(), AppError> {
+    if args.status || args.stop {
+        let request = if args.stop {
+            Request::Stop
+        } else {
+            Request::Status
+        };
+        match send(&request)? {
+            Some(reply) => print!("{reply}"),
+            None => println!("No daemon is running"),
+        }
+        return Ok(());
+    }
+    if send(&Request::Status)?.is_some() {
+        return Err(AppError::CommandFailed(
+            "a daemon is already running".to_string(),
+        ));
+    }
+
+    if args.cleanup && config.cleanup.is_empty() {
+        return Err(AppError::InvalidConfig(
+            "--cleanup needs [[cleanup]] rules in the config".to_string(),
+        ));
+    }
+    let cleanup = args.cleanup;
+    let watch = args.watch.or_else(|| config.watch_dir.clone());
+    if let Some(dir) = &watch
+        && !dir.is_dir()
+    {
+        return Err(AppError::Io(format!(
+            "{} isn't a folder to watch",
+            dir.display()
+        )));
+    }
+    let mut args = args.download;
+    // URLs given here are queued like any others
+    if !args.urls.is_empty() {
+        let report =
+            Queue::update(|queue| Ok(queue::add_all(queue, &args.urls, Priority::Normal, None)))?;
+        print!("{report}");
+        args.urls.clear();
+    }
+    for job in Queue::load()?.pending().iter().filter(|job| job.started) {
+        println!(
+            "Resuming {}, which the last run didn't finish: {}",
+            job.id, job.url
+        );
+    }
+    crate::start_run(&args)?;
+
+    let path = socket_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    // Left behind by a daemon that didn't get to clean up
+    let _ = std::fs::remove_file(&path);
+    let listener =
+        UnixListener::bind(&path).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || listen(listener, sender));
+    println!("Daemon listening on {}", path.display());
+    if let Some(dir) = &watch {
+        println!("Watching {} for dropped links", dir.display());
+    }
+
+    serve(&args, cleanup, watch.as_deref(), config, receiver);
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+/// Starts queued jobs and scheduled syncs, cleans up when `cleanup` is set,
+/// queues links dropped into `watch`, and answers requests, until stopped,
+/// then waits for the running ones to wind down.
+fn serve(
+    args: &DownloadArgs,
+    cleanup: bool,
+    watch: Option<&Path>,
+    config: &Config,
+    requests: Receiver<(Request, Sender<String>)>,
+) {
+    let observer = observers(
+        TerminalObserver {
+            live_progress: false,
+        },
+        args,
+    );
+    let observer: &dyn DownloadObserver = &observer;
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        if let Some(window) = &args.schedule {
+            scope.spawn(|| schedule::hold_outside(window, &done));
+        }
+        let mut running: Vec<(Job, ScopedJoinHandle<_>)> = vec![];
+        let mut syncing: Option<ScopedJoinHandle<_>> = None;
+        // Failed jobs the failed list couldn't take, which stay queued for
+        // the next start rather than being retried straight away
+        let mut held: Vec<u64> = vec![];
+        let mut next_cleanup = cleanup.then(|| Local::now().timestamp());
+        loop {
+            let (finished, still_running): (Vec<_>, Vec<_>) = running
+                .into_iter()
+                .partition(|(_, handle)| handle.is_finished());
+            running = still_running;
+            for (job, handle) in finished {
+                let result = handle.join().unwrap_or_else(|_| {
+                    Err(AppError::CommandFailed("download job panicked".to_string()))
+                });
+                match &result {
+                    Ok(_) => eprintln!("\r\x1b[2KDone {}: {}", job.id, job.url),
+                    // Left queued to resume on the next start
+                    Err(AppError::Interrupted) => continue,
+                    Err(error) => {
+                        eprintln!("\r\x1b[2KFailed {}: {}: {error}", job.id, job.url)
+                    }
+                }
+                // Failed jobs go to the failed list, or stay held when it
+                // can't take them
+                let recorded = failed::settle(&[(job.url.as_str(), &result)]);
+                if let Err(error) = &recorded {
+                    eprintln!("Error: {error}");
+                }
+                if result.is_err() && recorded.is_err() {
+                    held.push(job.id);
+                } else if let Err(error) = Queue::complete(&[job.id]) {
+                    eprintln!("Error: {error}");
+                }
+            }
+
+            let stopping = interrupt::is_interrupted();
+            let synced = syncing.as_ref().is_none_or(|handle| handle.is_finished());
+            if stopping && running.is_empty() && synced {
+                break;
+            }
+            if !stopping
+                && let Some(dir) = watch
+                && let Err(error) = watch::ingest(dir)
+            {
+                eprintln!("\r\x1b[2KError: {error}");
+            }
+            // The file is replaced whole, so a failed read is a real problem
+            let pending = match Queue::load() {
+                Ok(queue) => queue.pending(),
+                Err(error) => {
+                    eprintln!("Error: {error}");
+                    vec![]
+                }
+            };
+            for (job, _) in &running {
+                if let Some(saved) = pending.iter().find(|saved| saved.id == job.id) {
+                    download::pause_for(job.id).set(saved.paused);
+                }
+            }
+            let now = Local::now().timestamp();
+            let open = args.schedule.is_none_or(|window| window.is_open());
+            for job in pending {
+                if stopping || !open || running.len() >= args.jobs.max(1) {
+                    break;
+                }
+                if job.paused
+                    || job.start_at.is_some_and(|start| start > now)
+                    || running.iter().any(|(started, _)| started.id == job.id)
+                    || held.contains(&job.id)
+                {
+                    continue;
+                }
+                eprintln!("\r\x1b[2KStarting {}: {}", job.id, job.url);
+                if let Err(error) = Queue::start(&[job.id]) {
+                    eprintln!("Error: {error}");
+                }
+                let (id, url) = (job.id, job.url.clone());
+                let handle = scope
+                    .spawn(move || download::as_job(id, || download_url(&url, args, observer)));
+                running.push((job, handle));
+            }
+            // One sync at a time, which goes through the due subscriptions
+            if !stopping && open && synced && subscription::any_due() {
+                syncing = Some(scope.spawn(|| {
+                    if let Err(error) = subscription::sync_due(config) {
+                        eprintln!("\r\x1b[2KError: {error}");
+                    }
+                }));
+            }
+            if !stopping && next_cleanup.is_some_and(|at| at <= now) {
+                for retention in &config.cleanup {
+                    if let Err(error) = cleanup::enforce(retention, false) {
+                        eprintln!("\r\x1b[2KError: {error}");
+                    }
+                }
+                next_cleanup = Some(now + CLEANUP_INTERVAL.as_secs() as i64);
+            }
+
+            match requests.recv_timeout(POLL_INTERVAL) {
+                Ok((request, reply)) => {
+                    let _ = reply.send(answer(request, &running));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // Without the socket only the saved queue brings new jobs
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(POLL_INTERVAL),
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+}
+
+/// The reply to a client's request
+fn answer<T>(request: Request, running: &[(Job, T)]) -> String {
+    match request {
+        Request::Add {
+            urls,
+            priority,
+            start_at,
+        } => {
+            let urls: Vec<String> = urls.iter().map(|url| resolve_url(url)).collect();
+            Queue::update(|queue| Ok(queue::add_all(queue, &urls, priority, start_at)))
+                .unwrap_or_else(|error| format!("Error: {error}\n"))
+        }
+        Request::Status => {
+            let mut reply = String::new();
+            for (job, _) in running {
+                reply.push_str(&format!("Running {}: {}\n", job.id, job.url));
+            }
+            let waiting = Queue::load()
+                .map(|queue| queue.pending())
+                .unwrap_or_default();
+            for job in waiting
+                .iter()
+                .filter(|job| !running.iter().any(|(started, _)| started.id == job.id))
+            {
+                let state = if job.paused { "Paused" } else { "Queued" };
+                reply.push_str(&format!("{state} {}: {}\n", job.id, job.url));
+            }
+            if reply.is_empty() {
+                reply.push_str("Idle\n");
+            }
+            let failed = DeadLetters::load()
+                .map(|dead_letters| dead_letters.jobs().len())
+                .unwrap_or_default();
+            if failed > 0 {
+                reply.push_str(&format!("{failed} failed, listed by `failed list`\n"));
+            }
+            reply
+        }
+        Request::Stop => unreachable!("the listener stops the daemon itself"),
+    }
+}
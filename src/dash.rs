@@ -0,0 +1,290 @@
+use crate::download::{self, FetchOptions, Progress};
+use crate::fragments::{self, Fragment, FragmentedStream};
+use crate::{AppError, http};
+use regex::{Captures, Regex};
+use reqwest::Url;
+use roxmltree::{Document, Node};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// ISO 8601 durations as MPDs use them, e.g. `PT1H2M3.5S`
+static DURATION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^P(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:([\d.]+)S)?)?$").unwrap()
+});
+
+/// `$Identifier$` and `$Identifier%0Nd$` in segment templates
+static TEMPLATE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$(RepresentationID|Number|Time|Bandwidth)?(?:%0(\d+)d)?\$").unwrap()
+});
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn children<'a>(node: Node<'a, 'a>, name: &'a str) -> impl Iterator<Item = Node<'a, 'a>> {
+    node.children().filter(move |n| n.tag_name().name() == name)
+}
+
+fn parse_duration(value: &str) -> Option<f64> {
+    let captures = DURATION_REGEX.captures(value.trim())?;
+    let part = |index: usize, seconds: f64| {
+        captures
+            .get(index)
+            .and_then(|part| part.as_str().parse::<f64>().ok())
+            .map_or(0f64, |value| value * seconds)
+    };
+    Some(part(1, 86400f64) + part(2, 3600f64) + part(3, 60f64) + part(4, 1f64))
+}
+
+/// Manifest features the engine doesn't handle, which yt-dlp is left to
+fn unsupported(url: &str, feature: &str) -> AppError {
+    AppError::UnsupportedUrl(format!("{url} (DASH {feature})"))
+}
+
+/// Fills in a segment template's `$…$` identifiers
+fn expand(template: &str, representation: &str, bandwidth: &str, number: u64, time: u64) -> String {
+    TEMPLATE_REGEX
+        .replace_all(template, |captures: &Captures| {
+            let value = match captures.get(1).map(|name| name.as_str()) {
+                Some("RepresentationID") => return representation.to_string(),
+                Some("Bandwidth") => return bandwidth.to_string(),
+                Some("Number") => number,
+                Some("Time") => time,
+                _ => return "$".to_string(),
+            };
+            let width = captures
+                .get(2)
+                .and_then(|width| width.as_str().parse().ok())
+                .unwrap_or(0);
+            format!("{value:0width$}")
+        })
+        .into_owned()
+}
+
+/// The representation's fragments as listed by its manifest.
+struct Representation<'a> {
+    node: Node<'a, 'a>,
+    // Ancestors that segment information can be inherited from, nearest first
+    parents: [Node<'a, 'a>; 2],
+    base: Url,
+    // Seconds
+    period_duration: Option<f64>,
+}
+
+impl<'a> Representation<'a> {
+    /// The nearest `SegmentTemplate` attribute, from the representation up
+    fn template_attribute(&self, name: &str) -> Option<&'a str> {
+        std::iter::once(self.node)
+            .chain(self.parents)
+            .filter_map(|node| child(node, "SegmentTemplate"))
+            .find_map(|template| template.attribute(name))
+    }
+
+    fn template_timeline(&self) -> Option<Node<'a, 'a>> {
+        std::iter::once(self.node)
+            .chain(self.parents)
+            .filter_map(|node| child(node, "SegmentTemplate"))
+            .find_map(|template| child(template, "SegmentTimeline"))
+    }
+
+    fn resolve(&self, reference: &str) -> Result<String, AppError> {
+        self.base
+            .join(reference)
+            .map(String::from)
+            .map_err(|e| AppError::InvalidFeed(format!("bad URL `{reference}`: {e}")))
+    }
+
+    fn stream(&self) -> Result<FragmentedStream, AppError> {
+        let url = self.base.as_str();
+        if let Some(media) = self.template_attribute("media") {
+            return self.templated_stream(media);
+        }
+        if let Some(list) = std::iter::once(self.node)
+            .chain(self.parents)
+            .find_map(|node| child(node, "SegmentList"))
+        {
+            let init_url = child(list, "Initialization")
+                .and_then(|init| init.attribute("sourceURL"))
+                .map(|source| self.resolve(source))
+                .transpose()?;
+            let fragments = children(list, "SegmentURL")
+                .filter_map(|segment| segment.attribute("media"))
+                .enumerate()
+                .map(|(sequence, media)| {
+                    Ok(Fragment {
+                        url: self.resolve(media)?,
+                        sequence: sequence as u64,
+                        key: None,
+                    })
+                })
+                .collect::<Result<Vec<_>, AppError>>()?;
+            if fragments.is_empty() {
+                return Err(unsupported(url, "byte-range segment list"));
+            }
+            return Ok(FragmentedStream {
+                init_url,
+                fragments,
+            });
+        }
+        // Otherwise the whole representation is the file at its base URL
+        Ok(FragmentedStream {
+            init_url: None,
+            fragments: vec![Fragment {
+                url: url.to_string(),
+                sequence: 0,
+                key: None,
+            }],
+        })
+    }
+
+    fn templated_stream(&self, media: &str) -> Result<FragmentedStream, AppError> {
+        let url = self.base.as_str();
+        let id = self.node.attribute("id").unwrap_or_default();
+        let bandwidth = self.node.attribute("bandwidth").unwrap_or_default();
+        let number = |name: &str, default: u64| {
+            self.template_attribute(name)
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        let start_number = number("startNumber", 1);
+        let timescale = number("timescale", 1).max(1);
+
+        // (number, time) of each segment
+        let mut segments = vec![];
+        if let Some(timeline) = self.template_timeline() {
+            let end = self
+                .period_duration
+                .map(|duration| (duration * timescale as f64) as u64);
+            let mut time = 0;
+            for s in children(timeline, "S") {
+                let attribute = |name: &str| s.attribute(name).and_then(|v| v.parse::<i64>().ok());
+                time = attribute("t").map_or(time, |t| t as u64);
+                let duration = attribute("d").unwrap_or(0).max(0) as u64;
+                if duration == 0 {
+                    return Err(AppError::InvalidFeed(format!(
+                        "segment without duration in {url}"
+                    )));
+                }
+                let repeats = match attribute("r").unwrap_or(0) {
+                    // Repeats until the end of the period
+                    r if r < 0 => match end {
+                        Some(end) => end
+                            .saturating_sub(time)
+                            .div_ceil(duration)
+                            .saturating_sub(1),
+                        None => return Err(unsupported(url, "open-ended timeline")),
+                    },
+                    r => r as u64,
+                };
+                for _ in 0..=repeats {
+                    segments.push((start_number + segments.len() as u64, time));
+                    time += duration;
+                }
+            }
+        } else {
+            let duration = number("duration", 0);
+            let Some(period_duration) = self.period_duration.filter(|_| duration > 0) else {
+                return Err(unsupported(url, "template without a segment count"));
+            };
+            let count = (period_duration * timescale as f64 / duration as f64).ceil() as u64;
+            segments = (0..count)
+                .map(|index| (start_number + index, index * duration))
+                .collect();
+        }
+
+        let init_url = self
+            .template_attribute("initialization")
+            .map(|init| self.resolve(&expand(init, id, bandwidth, start_number, 0)))
+            .transpose()?;
+        let fragments = segments
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, (number, time))| {
+                Ok(Fragment {
+                    url: self.resolve(&expand(media, id, bandwidth, number, time))?,
+                    sequence: sequence as u64,
+                    key: None,
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        Ok(FragmentedStream {
+            init_url,
+            fragments,
+        })
+    }
+}
+
+/// Whether a yt-dlp format ID names this representation; generic MPD
+/// formats are prefixed with the manifest's ID, like `dash-video=1000`
+fn is_representation(format_id: &str, id: &str) -> bool {
+    format_id == id
+        || format_id
+            .strip_suffix(id)
+            .is_some_and(|prefix| prefix.ends_with('-'))
+}
+
+/// Finds the representation for `format_id` in the manifest at `url` and
+/// lists its fragments
+fn parse_manifest(url: &str, text: &str, format_id: &str) -> Result<FragmentedStream, AppError> {
+    let document =
+        Document::parse(text).map_err(|e| AppError::InvalidFeed(format!("{url}: {e}")))?;
+    let mpd = document.root_element();
+    if mpd.attribute("type") == Some("dynamic") {
+        return Err(unsupported(url, "live manifest"));
+    }
+    let periods: Vec<_> = children(mpd, "Period").collect();
+    let [period] = periods.as_slice() else {
+        return Err(unsupported(url, "multi-period manifest"));
+    };
+
+    let (adaptation_set, node) = children(*period, "AdaptationSet")
+        .flat_map(|set| children(set, "Representation").map(move |node| (set, node)))
+        .find(|(_, node)| {
+            node.attribute("id")
+                .is_some_and(|id| is_representation(format_id, id))
+        })
+        .ok_or_else(|| {
+            AppError::NoMatchingFormat(format!("no representation {format_id} in {url}"))
+        })?;
+
+    // Base URLs nest from the manifest down to the representation
+    let mut base = Url::parse(url).map_err(|_| AppError::UnsupportedUrl(url.to_string()))?;
+    for node in [mpd, *period, adaptation_set, node] {
+        if let Some(base_url) = child(node, "BaseURL").and_then(|n| n.text()) {
+            base = base
+                .join(base_url.trim())
+                .map_err(|e| AppError::InvalidFeed(format!("bad BaseURL in {url}: {e}")))?;
+        }
+    }
+    let period_duration = period
+        .attribute("duration")
+        .or_else(|| mpd.attribute("mediaPresentationDuration"))
+        .and_then(parse_duration);
+
+    Representation {
+        node,
+        parents: [adaptation_set, *period],
+        base,
+        period_duration,
+    }
+    .stream()
+}
+
+/// Downloads the representation `format_id` of the DASH manifest at `url`
+/// to `path`, fetching `options.connections` fragments at a time, and
+/// returns where the file was saved. Manifests the engine can't handle fail
+/// with `UnsupportedUrl`, so the caller can hand them to yt-dlp instead.
+pub fn download(
+    url: &str,
+    format_id: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    options: &FetchOptions,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<PathBuf, AppError> {
+    let text = String::from_utf8_lossy(&http::fetch_bytes(url, headers)?).into_owned();
+    let stream = parse_manifest(url, &text, format_id)?;
+    fragments::download(&stream, headers, path, options, on_progress)?;
+    std::fs::rename(download::part_path(path), path).map_err(|e| AppError::Io(e.to_string()))?;
+    Ok(path.to_path_buf())
+}
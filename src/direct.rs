@@ -0,0 +1,159 @@
+use crate::download::{FetchOptions, Progress};
+use crate::{
+    AppError, FileDetails, FileEncoding, FileFormat, FileSize, Protocol, default_codec, filename,
+    http, retry,
+};
+use regex::Regex;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use std::path::Path;
+use std::sync::LazyLock;
+
+pub static DIRECT_MEDIA_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^https?://[^?#]+\.(mp4|m4v|mov|webm|mkv|mp3|m4a|aac|ogg|opus|wav|flac|jpe?g|png|gif|webp)([?#].*)?$",
+    )
+    .unwrap()
+});
+
+/// A file served directly over HTTP, described from its response headers.
+#[derive(Debug)]
+pub struct DirectMedia {
+    pub url: String,
+    pub file_details: FileDetails,
+}
+
+impl DirectMedia {
+    pub fn file_name(&self) -> String {
+        filename::fit(&format!(
+            "{}.{}",
+            filename::sanitize(&self.file_details.title),
+            self.file_details.ext
+        ))
+    }
+}
+
+/// The last path segment of the URL without its extension
+fn title_from(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let file_name = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(path);
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    if stem.is_empty() {
+        "download".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+/// Requests the headers of `url` and builds the format it serves, failing if
+/// the response isn't audio, video, or an image.
+pub fn probe(url: &str) -> Result<DirectMedia, AppError> {
+    let response = retry::with_retries(|| {
+        http::client()
+            .head(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(http::error)
+    })?;
+    let headers = response.headers();
+
+    let mime_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    // Servers often send a generic type for media, so fall back to the URL's extension
+    let file_encoding = match FileEncoding::from_mime_type(&mime_type) {
+        FileEncoding::Unknown if DIRECT_MEDIA_REGEX.is_match(url) => encoding_from_extension(url),
+        FileEncoding::Unknown => return Err(AppError::UnsupportedUrl(url.to_string())),
+        file_encoding => file_encoding,
+    };
+    let file_size = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok())
+        .map(FileSize::new);
+
+    let extension = http::file_extension(url, &mime_type);
+    let file_details = FileDetails {
+        id: url.to_string(),
+        title: title_from(url),
+        duration: None,
+        is_live: false,
+        ext: extension.clone(),
+        extractor: "direct".to_string(),
+        extractor_key: "Direct".to_string(),
+        uploader: None,
+        channel: None,
+        upload_date: None,
+        view_count: None,
+        like_count: None,
+        description: None,
+        webpage_url: Some(url.to_string()),
+        original_url: Some(url.to_string()),
+        availability: None,
+        thumbnails: vec![],
+        subtitles: vec![],
+        chapters: vec![],
+        formats: vec![FileFormat {
+            id: "direct".to_string(),
+            extension,
+            resolution: None,
+            width: None,
+            height: None,
+            fps: None,
+            vcodec: default_codec(),
+            acodec: default_codec(),
+            dynamic_range: None,
+            tbr: None,
+            abr: None,
+            language: None,
+            protocol: Some(Protocol::from_url(url)),
+            format_note: None,
+            container: None,
+            file_size,
+            has_drm: false,
+            url: Some(url.to_string()),
+            manifest_url: None,
+            http_headers: vec![],
+            file_encoding,
+        }],
+    };
+
+    Ok(DirectMedia {
+        url: url.to_string(),
+        file_details,
+    })
+}
+
+fn encoding_from_extension(url: &str) -> FileEncoding {
+    match http::file_extension(url, "").as_str() {
+        "mp3" | "m4a" | "aac" | "ogg" | "opus" | "wav" | "flac" => FileEncoding::AudioOnly,
+        "jpg" | "jpeg" | "png" | "gif" | "webp" => FileEncoding::Image,
+        _ => FileEncoding::VideoAndAudio,
+    }
+}
+
+/// Downloads the media to `path`, creating its directory if needed.
+pub fn download(
+    media: &DirectMedia,
+    path: &Path,
+    options: &FetchOptions,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<(), AppError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    options
+        .downloader
+        .backend()
+        .fetch(&media.url, &[], path, options, on_progress)?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use crate::{AppError, FileSize};
+use std::path::Path;
+use std::process::Command;
+
+/// Bytes free to unprivileged users on the filesystem holding `dir`, from
+/// `df`. `None` when that can't be found, e.g. where there's no `df`.
+pub fn free_space(dir: &Path) -> Option<u64> {
+    // The output directory is only created once the download starts
+    let existing = dir
+        .ancestors()
+        .find(|ancestor| ancestor.as_os_str().is_empty() || ancestor.exists())?;
+    let existing = if existing.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing
+    };
+
+    let output = Command::new("df").arg("-Pk").arg(existing).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // The mount point may contain spaces, so the available column is found
+    // from the capacity percentage after it
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.lines().nth(1)?.split_whitespace().collect();
+    let capacity = fields.iter().position(|field| field.ends_with('%'))?;
+    let kilobytes: u64 = fields.get(capacity.checked_sub(1)?)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Fails when `needed` is more than is free where `dir` is, unless the size
+/// is only an estimate, which just gets a warning. Nothing is checked when
+/// the free space is unknown.
+pub fn check_space(needed: &FileSize, dir: &Path) -> Result<(), AppError> {
+    let Some(available) = free_space(dir) else {
+        return Ok(());
+    };
+    if needed.bytes <= available {
+        return Ok(());
+    }
+    if needed.estimated {
+        eprintln!(
+            "Warning: the download may need {needed} but only {} is free in {}",
+            FileSize::new(available as f64),
+            dir.display()
+        );
+        return Ok(());
+    }
+    Err(AppError::InsufficientSpace {
+        needed: needed.bytes,
+        available,
+        dir: dir.display().to_string(),
+    })
+}
@@ -0,0 +1,867 @@
+use crate::backend::Downloader;
+use crate::{AppError, FileSize, checksum, http, interrupt, retry};
+use reqwest::StatusCode;
+use reqwest::blocking::Response;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A progress update for the file being downloaded.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub downloaded_bytes: u64,
+    // Estimated for fragmented formats
+    pub total_bytes: Option<u64>,
+    // Bytes per second, lately and since the transfer started
+    pub speed: Option<f64>,
+    pub average_speed: Option<f64>,
+    // Seconds
+    pub eta: Option<f64>,
+}
+
+impl Progress {
+    /// Share of the file downloaded so far, from 0 to 1
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_bytes
+            .filter(|total| *total > 0)
+            .map(|total| (self.downloaded_bytes as f64 / total as f64).min(1f64))
+    }
+}
+
+/// How a file is fetched.
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    // Ranged connections to split large files across
+    pub connections: usize,
+    // Bytes per second, for backends that can't share the native limiter
+    pub rate_limit: Option<u64>,
+    pub downloader: Downloader,
+    pub pause: Pause,
+    // Leave out fragments of HLS and DASH streams that keep failing
+    pub skip_unavailable_fragments: bool,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            connections: 1,
+            rate_limit: None,
+            downloader: Downloader::Native,
+            pause: Pause::default(),
+            skip_unavailable_fragments: false,
+        }
+    }
+}
+
+/// Something that happened to a download, for frontends to render.
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    Started,
+    /// The item's details were fetched and what to download was chosen
+    MetadataFetched {
+        title: String,
+        /// yt-dlp's name for the site, like `Youtube`
+        extractor: String,
+        /// The site's ID for the item
+        id: String,
+        /// Who uploaded the item, or the channel it's on
+        uploader: Option<String>,
+        /// When the item was published, as YYYYMMDD
+        upload_date: Option<String>,
+        /// The chosen format IDs, as yt-dlp takes them with `-f`
+        format: String,
+        selection: String,
+    },
+    Progress(Progress),
+    /// The chosen formats failed even after retrying, so the next best are
+    /// being tried instead
+    FallingBack {
+        failed: String,
+        error: String,
+        format: String,
+        selection: String,
+    },
+    /// Only a section of the video is being fetched, which ffmpeg doesn't
+    /// report progress for
+    DownloadingSection {
+        section: String,
+    },
+    /// Downloaded streams are being muxed into one file
+    Merging,
+    /// The audio is being taken out of the download and converted
+    ExtractingAudio,
+    /// The loudness of the audio is being measured and evened out
+    NormalizingAudio,
+    /// Something, like the thumbnail, is being added into the file
+    Embedding {
+        what: String,
+    },
+    /// Segments SponsorBlock users marked are being cut out of the file
+    RemovingSegments {
+        count: usize,
+    },
+    /// The download is being copied into another container as it is
+    Remuxing {
+        container: String,
+    },
+    /// The download is being re-encoded to fit another container, with how
+    /// much of it is done when its duration is known
+    Transcoding {
+        container: String,
+        fraction: Option<f64>,
+    },
+    /// The transfer was suspended, keeping what it had downloaded
+    Paused,
+    Resumed,
+    Completed {
+        path: PathBuf,
+        /// Lowercase hex SHA-256 of the file
+        sha256: String,
+    },
+    /// The file was already there and kept, so nothing was downloaded
+    Skipped {
+        path: PathBuf,
+    },
+    /// The item is in the download archive, so it was passed over
+    Archived {
+        title: String,
+    },
+    /// The history has the item as downloaded before, so it was passed over
+    Duplicate {
+        title: String,
+        /// The earlier download's ID in the history
+        previous: i64,
+        /// Where the earlier download was saved
+        path: Option<PathBuf>,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Receives the events of each download, identified by its URL. Concurrent
+/// downloads send events from their own threads.
+pub trait DownloadObserver: Sync {
+    fn on_event(&self, url: &str, event: &DownloadEvent);
+}
+
+/// Forwards events to a channel, for frontends that render on their own thread
+impl DownloadObserver for Sender<(String, DownloadEvent)> {
+    fn on_event(&self, url: &str, event: &DownloadEvent) {
+        // A frontend that stopped listening just misses the rest
+        let _ = self.send((url.to_string(), event.clone()));
+    }
+}
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lets another thread suspend a download and pick it up again where it
+/// stopped. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Pause(Arc<AtomicBool>);
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        self.0.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed) || HOLD_ALL.load(Ordering::Relaxed)
+    }
+
+    /// Blocks for as long as the download is paused, or until Ctrl-C
+    pub fn wait(&self) {
+        while self.is_paused() && !interrupt::is_interrupted() {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Pauses every download at once on top of their own controls, e.g. outside
+/// a download window
+static HOLD_ALL: AtomicBool = AtomicBool::new(false);
+
+pub fn hold_all(held: bool) {
+    HOLD_ALL.store(held, Ordering::Relaxed);
+}
+
+/// Pause controls of each queued job, by its ID, as the same URL may be
+/// queued more than once
+static PAUSES: LazyLock<Mutex<HashMap<u64, Pause>>> = LazyLock::new(Default::default);
+
+thread_local! {
+    /// The pause control of the job this thread is downloading
+    static JOB_PAUSE: RefCell<Option<Pause>> = const { RefCell::new(None) };
+}
+
+/// The pause control of the queued job with ID `job`
+pub fn pause_for(job: u64) -> Pause {
+    PAUSES.lock().unwrap().entry(job).or_default().clone()
+}
+
+/// Runs `work`, which downloads the queued job with ID `job` on this
+/// thread, with what it downloads paused along with the job
+pub fn as_job<T>(job: u64, work: impl FnOnce() -> T) -> T {
+    JOB_PAUSE.with_borrow_mut(|pause| *pause = Some(pause_for(job)));
+    let result = work();
+    JOB_PAUSE.with_borrow_mut(|pause| *pause = None);
+    result
+}
+
+/// The pause control of what this thread downloads: its job's, or one of
+/// its own for URLs that weren't queued
+pub fn current_pause() -> Pause {
+    JOB_PAUSE.with_borrow(|pause| pause.clone().unwrap_or_default())
+}
+
+/// Sends SIGSTOP to a child process while its download is paused and
+/// SIGCONT once it's resumed, until dropped.
+pub struct ChildPause {
+    done: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl ChildPause {
+    pub fn new(child: &Child, pause: &Pause) -> ChildPause {
+        let done = Arc::new(AtomicBool::new(false));
+        let pid = child.id().to_string();
+        let watcher = {
+            let done = Arc::clone(&done);
+            let pause = pause.clone();
+            thread::spawn(move || {
+                let mut stopped = false;
+                while !done.load(Ordering::Relaxed) {
+                    if pause.is_paused() != stopped {
+                        stopped = !stopped;
+                        signal(&pid, if stopped { "-STOP" } else { "-CONT" });
+                    }
+                    thread::sleep(PAUSE_POLL_INTERVAL);
+                }
+                if stopped {
+                    signal(&pid, "-CONT");
+                }
+            })
+        };
+        ChildPause {
+            done,
+            watcher: Some(watcher),
+        }
+    }
+}
+
+impl Drop for ChildPause {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
+
+/// Signals a process with `kill`, where there is one. Without it pausing
+/// can't stop the child, nor a timeout end it.
+pub fn signal(pid: &str, signal: &str) {
+    let _ = Command::new("kill")
+        .args([signal, pid])
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Shared by every native transfer, so concurrent jobs and connections stay
+/// under one combined cap
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// A token bucket refilled at a fixed rate. Transfers take tokens for the
+/// bytes they read and sleep off any debt, which spreads the rate across
+/// however many are running.
+#[derive(Debug)]
+struct RateLimiter {
+    bytes_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Blocks until `bytes` fit in the rate
+    fn take(&self, bytes: usize) {
+        let debt = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * self.bytes_per_second;
+            // At most a second's worth can build up while nothing is reading
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_second) - bytes as f64;
+            bucket.updated = now;
+            -bucket.tokens
+        };
+        if debt > 0f64 {
+            thread::sleep(Duration::from_secs_f64(debt / self.bytes_per_second));
+        }
+    }
+}
+
+/// Caps the combined speed of native downloads for the rest of the run.
+pub fn limit_rate(bytes_per_second: u64) {
+    RATE_LIMITER.get_or_init(|| RateLimiter {
+        bytes_per_second: bytes_per_second.max(1) as f64,
+        bucket: Mutex::new(Bucket {
+            tokens: 0f64,
+            updated: Instant::now(),
+        }),
+    });
+}
+
+/// Waits until `bytes` more fit under `--limit-rate`, if one is set.
+pub fn throttle(bytes: usize) {
+    if let Some(limiter) = RATE_LIMITER.get() {
+        limiter.take(bytes);
+    }
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// Smaller segments cost more in requests than they gain in throughput
+const MIN_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Where a file is written until it's complete: `.<name>.part.tmp` in the
+/// same directory, so it's hidden, never mistaken for a finished file, and
+/// moved into place with an atomic rename
+pub fn part_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    if file_name.starts_with('.') {
+        path.with_file_name(format!("{file_name}.part.tmp"))
+    } else {
+        path.with_file_name(format!(".{file_name}.part.tmp"))
+    }
+}
+
+/// Deletes a part file when dropped unless it was moved into place, for
+/// writes that can't be continued, so failed and interrupted ones don't
+/// leave it behind
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(path: PathBuf) -> TempFile {
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Waits for other processes holding the lock file at `path`, and keeps
+/// them waiting until the returned file is dropped
+pub fn lock(path: &Path) -> Result<File, AppError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    file.lock()
+        .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    Ok(file)
+}
+
+/// Writes a small file whole, through its part file so it never exists
+/// half-written
+pub fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), AppError> {
+    let part_path = TempFile::new(part_path(path));
+    let part_path = part_path.path();
+    std::fs::write(part_path, contents)
+        .and_then(|_| std::fs::rename(part_path, path))
+        .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))
+}
+
+/// Writes a small file whole like `write_file`, and only returns once it's
+/// on disk, so a crash or power loss right after leaves it intact
+pub fn write_file_synced(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), AppError> {
+    let part_path = TempFile::new(part_path(path));
+    let part_path = part_path.path();
+    File::create(part_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(part_path, path))
+        .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    // The rename is only kept for sure once the directory is synced too,
+    // which Windows doesn't allow or need
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| AppError::Io(format!("{}: {e}", dir.display())))?;
+    }
+    Ok(())
+}
+
+fn get(
+    url: &str,
+    headers: &[(String, String)],
+    range: Option<String>,
+) -> Result<Response, AppError> {
+    let mut request = http::client().get(url);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    if let Some(range) = range {
+        request = request.header(RANGE, range);
+    }
+    request
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(http::error)
+}
+
+/// The full length of the file from a `Content-Range` header
+fn range_total(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn progress(
+    downloaded_bytes: u64,
+    offset: u64,
+    total_bytes: Option<u64>,
+    started: Instant,
+) -> Progress {
+    let elapsed = started.elapsed().as_secs_f64();
+    let speed = (elapsed > 0f64).then(|| (downloaded_bytes - offset) as f64 / elapsed);
+    let eta = total_bytes
+        .zip(speed)
+        .filter(|(_, speed)| *speed > 0f64)
+        .map(|(total, speed)| total.saturating_sub(downloaded_bytes) as f64 / speed);
+    Progress {
+        downloaded_bytes,
+        total_bytes,
+        speed,
+        average_speed: speed,
+        eta,
+    }
+}
+
+/// Where the progress of an unfinished download is kept next to its `.part`
+/// file, so a later run can tell whether and how to continue it
+fn state_path(path: &Path) -> PathBuf {
+    let mut file_name = part_path(path)
+        .file_name()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push(".json");
+    path.with_file_name(file_name)
+}
+
+/// A range of the file fetched over its own connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Segment {
+    start: u64,
+    end: u64,
+    downloaded: u64,
+}
+
+/// What's known about a `.part` file, saved in its sidecar.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PartState {
+    total_bytes: Option<u64>,
+    // Empty for single-stream downloads, whose progress is the file's length
+    segments: Vec<Segment>,
+}
+
+impl PartState {
+    fn load(path: &Path) -> Option<PartState> {
+        let json = std::fs::read_to_string(state_path(path)).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), AppError> {
+        let json = serde_json::to_string(self).map_err(|e| AppError::Io(e.to_string()))?;
+        std::fs::write(state_path(path), json).map_err(|e| AppError::Io(e.to_string()))
+    }
+}
+
+/// Removes an unfinished download so it starts over
+fn discard_part(path: &Path) {
+    let _ = std::fs::remove_file(part_path(path));
+    let _ = std::fs::remove_file(state_path(path));
+}
+
+/// Moves a finished download into place, once it matches the MD5 the server
+/// sent, if any. A mismatched one is discarded so the next try starts over.
+fn finish(path: &Path, md5: Option<&str>) -> Result<(), AppError> {
+    if let Some(md5) = md5
+        && let Err(error) = checksum::verify_md5(&part_path(path), md5)
+    {
+        discard_part(path);
+        return Err(error);
+    }
+    std::fs::rename(part_path(path), path).map_err(|e| AppError::Io(e.to_string()))?;
+    let _ = std::fs::remove_file(state_path(path));
+    Ok(())
+}
+
+/// Downloads `url` to `path` over plain HTTP(S), sending `headers` with the
+/// request, and returns the number of bytes in the finished file.
+///
+/// Data goes to a `.part` file that is renamed into place once its length
+/// matches the server's `Content-Length`, and its MD5 any `Content-MD5` or
+/// `ETag` the server sent, with its progress kept in a sidecar
+/// next to it. An unfinished download left by an earlier run is continued
+/// with range requests when the server supports them and still reports the
+/// same length, and started over otherwise. Large files are split across
+/// `options.connections` ranged requests when the server accepts ranges, and
+/// fetched as one stream otherwise. Failed attempts are retried, continuing
+/// from what they saved.
+pub fn fetch(
+    url: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    options: &FetchOptions,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<u64, AppError> {
+    retry::with_retries(|| fetch_once(url, headers, path, options, on_progress))
+}
+
+fn fetch_once(
+    url: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    options: &FetchOptions,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<u64, AppError> {
+    let state = part_path(path)
+        .exists()
+        .then(|| PartState::load(path).unwrap_or_default());
+
+    match state {
+        // Segmented parts have holes, so only the recorded segments say what's missing
+        Some(state) if !state.segments.is_empty() => {
+            if let Some(ranged) = ranged_file(url, headers)
+                && state.total_bytes == Some(ranged.total_bytes)
+            {
+                eprintln!("Resuming {}", path.display());
+                return fetch_segmented(
+                    url,
+                    headers,
+                    path,
+                    &options.pause,
+                    &ranged,
+                    state.segments,
+                    on_progress,
+                );
+            }
+            discard_part(path);
+        }
+        Some(state) => {
+            return fetch_stream(
+                url,
+                headers,
+                path,
+                &options.pause,
+                state.total_bytes,
+                on_progress,
+            );
+        }
+        None => {}
+    }
+
+    if options.connections > 1
+        && let Some(ranged) = ranged_file(url, headers)
+        && ranged.total_bytes >= 2 * MIN_SEGMENT_SIZE
+    {
+        let total_bytes = ranged.total_bytes;
+        let count = options
+            .connections
+            .min((total_bytes / MIN_SEGMENT_SIZE) as usize) as u64;
+        let segment_size = total_bytes.div_ceil(count);
+        let segments = (0..count)
+            .map(|segment| Segment {
+                start: segment * segment_size,
+                end: ((segment + 1) * segment_size).min(total_bytes),
+                downloaded: 0,
+            })
+            .collect();
+        File::create(part_path(path))
+            .and_then(|file| file.set_len(total_bytes))
+            .map_err(|e| AppError::Io(e.to_string()))?;
+        return fetch_segmented(
+            url,
+            headers,
+            path,
+            &options.pause,
+            &ranged,
+            segments,
+            on_progress,
+        );
+    }
+    fetch_stream(url, headers, path, &options.pause, None, on_progress)
+}
+
+/// A file served in ranges
+struct RangedFile {
+    total_bytes: u64,
+    md5: Option<String>,
+}
+
+/// The file's length, and any MD5 its ETag gives, when the server answers
+/// range requests, found by asking for its first byte
+fn ranged_file(url: &str, headers: &[(String, String)]) -> Option<RangedFile> {
+    let response = get(url, headers, Some("bytes=0-0".to_string())).ok()?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return None;
+    }
+    Some(RangedFile {
+        total_bytes: range_total(&response)?,
+        md5: checksum::expected_md5(&response),
+    })
+}
+
+/// Fetches the file as one stream, continuing the `.part` file from its end
+/// when it exists and `expected_total` (if recorded) still matches
+fn fetch_stream(
+    url: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    pause: &Pause,
+    expected_total: Option<u64>,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<u64, AppError> {
+    let part_path = part_path(path);
+    let existing = std::fs::metadata(&part_path).map_or(0, |metadata| metadata.len());
+    let range = (existing > 0).then(|| format!("bytes={existing}-"));
+    let mut response = get(url, headers, range)?;
+
+    // Servers that ignore the range send the whole file again with a 200
+    let resumed = existing > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    if resumed && expected_total.is_some() && range_total(&response) != expected_total {
+        // The file changed since the part was saved
+        drop(response);
+        discard_part(path);
+        return fetch_stream(url, headers, path, pause, None, on_progress);
+    }
+    if resumed {
+        eprintln!(
+            "Resuming {} at {}",
+            path.display(),
+            FileSize::new(existing as f64)
+        );
+    }
+    let offset = if resumed { existing } else { 0 };
+    let md5 = checksum::expected_md5(&response);
+    let total_bytes = match range_total(&response) {
+        Some(total) if resumed => Some(total),
+        _ => response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<u64>().ok())
+            .map(|length| length + offset),
+    };
+
+    let mut file = if resumed {
+        OpenOptions::new().append(true).open(&part_path)
+    } else {
+        File::create(&part_path)
+    }
+    .map_err(|e| AppError::Io(e.to_string()))?;
+    PartState {
+        total_bytes,
+        segments: vec![],
+    }
+    .save(path)?;
+
+    let started = Instant::now();
+    let mut downloaded_bytes = offset;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        pause.wait();
+        // What's saved so far is kept to continue from
+        interrupt::check()?;
+        let read = response.read(&mut buffer).map_err(http::read_error)?;
+        if read == 0 {
+            break;
+        }
+        throttle(read);
+        file.write_all(&buffer[..read])
+            .map_err(|e| AppError::Io(e.to_string()))?;
+        downloaded_bytes += read as u64;
+        on_progress(&progress(downloaded_bytes, offset, total_bytes, started));
+    }
+    file.flush().map_err(|e| AppError::Io(e.to_string()))?;
+
+    if let Some(total_bytes) = total_bytes
+        && downloaded_bytes != total_bytes
+    {
+        return Err(AppError::Http(format!(
+            "{url} ended after {downloaded_bytes} of {total_bytes} bytes"
+        )));
+    }
+    finish(path, md5.as_deref())?;
+    Ok(downloaded_bytes)
+}
+
+/// Fetches what's missing from each of `segments` in parallel, each written
+/// at its own offset in the preallocated `.part` file. Progress is saved to
+/// the sidecar as it goes, so a failed segment leaves a download that a later
+/// run can continue.
+fn fetch_segmented(
+    url: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    pause: &Pause,
+    file: &RangedFile,
+    segments: Vec<Segment>,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<u64, AppError> {
+    let total_bytes = file.total_bytes;
+    let part_path = part_path(path);
+    let counters: Vec<AtomicU64> = segments
+        .iter()
+        .map(|segment| AtomicU64::new(segment.downloaded))
+        .collect();
+    let state = |counters: &[AtomicU64]| PartState {
+        total_bytes: Some(total_bytes),
+        segments: segments
+            .iter()
+            .zip(counters)
+            .map(|(segment, downloaded)| Segment {
+                downloaded: downloaded.load(Ordering::Relaxed),
+                ..segment.clone()
+            })
+            .collect(),
+    };
+    let downloaded_bytes = |counters: &[AtomicU64]| -> u64 {
+        counters
+            .iter()
+            .map(|downloaded| downloaded.load(Ordering::Relaxed))
+            .sum()
+    };
+    let offset = downloaded_bytes(&counters);
+    let started = Instant::now();
+
+    let result = thread::scope(|scope| {
+        let handles: Vec<_> = segments
+            .iter()
+            .zip(&counters)
+            .filter(|(segment, _)| segment.downloaded < segment.end - segment.start)
+            .map(|(segment, downloaded)| {
+                let part_path = &part_path;
+                scope.spawn(move || {
+                    fetch_segment(url, headers, part_path, pause, segment, downloaded)
+                })
+            })
+            .collect();
+        // Progress is reported and saved from here since the callback can't
+        // be shared across threads
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            let _ = state(&counters).save(path);
+            // Leave the paused notice on screen
+            if !pause.is_paused() {
+                on_progress(&progress(
+                    downloaded_bytes(&counters),
+                    offset,
+                    Some(total_bytes),
+                    started,
+                ));
+            }
+            thread::sleep(PROGRESS_INTERVAL);
+        }
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(AppError::Http(format!("a segment of {url} panicked"))))
+            })
+            .collect::<Result<Vec<()>, AppError>>()
+    });
+    state(&counters).save(path)?;
+    result?;
+
+    on_progress(&progress(total_bytes, offset, Some(total_bytes), started));
+    finish(path, file.md5.as_deref())?;
+    Ok(total_bytes)
+}
+
+/// Fetches the rest of `segment` into the same range of the file at
+/// `part_path`, counting bytes in `downloaded` once they're written
+fn fetch_segment(
+    url: &str,
+    headers: &[(String, String)],
+    part_path: &Path,
+    pause: &Pause,
+    segment: &Segment,
+    downloaded: &AtomicU64,
+) -> Result<(), AppError> {
+    let start = segment.start + downloaded.load(Ordering::Relaxed);
+    let end = segment.end;
+    let mut response = get(url, headers, Some(format!("bytes={start}-{}", end - 1)))?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(AppError::Http(format!(
+            "{url} stopped answering range requests"
+        )));
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    let mut written = 0;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        pause.wait();
+        // What's saved so far is kept to continue from
+        interrupt::check()?;
+        let read = response.read(&mut buffer).map_err(http::read_error)?;
+        if read == 0 {
+            break;
+        }
+        throttle(read);
+        file.write_all(&buffer[..read])
+            .map_err(|e| AppError::Io(e.to_string()))?;
+        written += read as u64;
+        downloaded.fetch_add(read as u64, Ordering::Relaxed);
+    }
+
+    if written != end - start {
+        return Err(AppError::Http(format!(
+            "{url} sent {written} of {} bytes for range {start}-{}",
+            end - start,
+            end - 1
+        )));
+    }
+    Ok(())
+}
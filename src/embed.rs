@@ -0,0 +1,239 @@
+use crate::chapter::Chapter;
+use crate::download::{self, TempFile};
+use crate::subtitle::{self, SubtitleTrack};
+use crate::thumbnail::{self, Thumbnail};
+use crate::{AppError, FileDetails, http};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Containers ffmpeg can put cover art in
+const COVER_ART_CONTAINERS: &[&str] = &["mp4", "m4a", "m4v", "mov", "mp3", "flac", "mkv", "mka"];
+
+/// Rewrites `media` in place through ffmpeg, copying its streams, with
+/// `configure` adding the inputs and options between it and the output
+fn rewrite(media: &Path, configure: impl FnOnce(&mut Command)) -> Result<(), AppError> {
+    let extension = media.extension().unwrap_or_default().to_string_lossy();
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let staged =
+        TempFile::new(download::part_path(media).with_extension(format!("tmp.{extension}")));
+    let mut command = Command::new("ffmpeg");
+    command.args(["-y", "-loglevel", "error", "-i"]).arg(media);
+    configure(&mut command);
+    if extension == "mp3" {
+        // ID3v2.4, ffmpeg's default, isn't read by every player
+        command.args(["-id3v2_version", "3"]);
+    }
+    let status = command
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} embedding into {}",
+            media.display()
+        )));
+    }
+    std::fs::rename(staged.path(), media).map_err(|e| AppError::Io(e.to_string()))
+}
+
+/// Embeds the best of `thumbnails` into `media` as cover art, converted to
+/// JPEG when it's in a format containers don't take. Containers without
+/// cover art, like WebM and Ogg, are left as they are with a warning.
+pub fn thumbnail(thumbnails: &[Thumbnail], media: &Path, has_video: bool) -> Result<(), AppError> {
+    let container = media
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if !COVER_ART_CONTAINERS.contains(&container.as_str()) {
+        eprintln!("\r\x1b[2KCan't embed a thumbnail in .{container} files");
+        return Ok(());
+    }
+    let Some(best) = thumbnail::best(thumbnails) else {
+        eprintln!("\r\x1b[2KNo thumbnail to embed in {}", media.display());
+        return Ok(());
+    };
+
+    let extension = http::file_extension(&best.url, "image/jpeg");
+    let temp_path = |extension: &str| {
+        TempFile::new(download::part_path(media).with_extension(format!("cover.{extension}")))
+    };
+    let downloaded = temp_path(&extension);
+    http::download_to(&best.url, downloaded.path())?;
+    let cover = match extension.as_str() {
+        "jpg" | "jpeg" | "png" => downloaded,
+        _ => {
+            let converted = temp_path("jpg");
+            let status = Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-i"])
+                .arg(downloaded.path())
+                .arg(converted.path())
+                .stdin(Stdio::null())
+                .status()
+                .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+            if !status.success() {
+                return Err(AppError::CommandFailed(format!(
+                    "ffmpeg exited with {status} converting the thumbnail {}",
+                    best.url
+                )));
+            }
+            converted
+        }
+    };
+    let is_png = cover.path().extension().is_some_and(|ext| ext == "png");
+
+    rewrite(media, |command| match container.as_str() {
+        // Matroska keeps cover art as an attachment
+        "mkv" | "mka" => {
+            let (mime_type, file_name) = if is_png {
+                ("image/png", "cover.png")
+            } else {
+                ("image/jpeg", "cover.jpg")
+            };
+            command
+                .args(["-map", "0", "-c", "copy", "-attach"])
+                .arg(cover.path())
+                .arg("-metadata:s:t")
+                .arg(format!("mimetype={mime_type}"))
+                .arg("-metadata:s:t")
+                .arg(format!("filename={file_name}"));
+        }
+        _ => {
+            // The cover comes after the video stream, when there is one
+            let cover_stream = format!("-disposition:v:{}", usize::from(has_video));
+            command
+                .arg("-i")
+                .arg(cover.path())
+                .args(["-map", "0", "-map", "1", "-c", "copy"])
+                .args([cover_stream.as_str(), "attached_pic"]);
+        }
+    })
+}
+
+/// Writes `details`' title, uploader, upload date, description and source
+/// URL into the tags of `media`, under the names ffmpeg maps to each
+/// container's own (ID3, MP4 atoms or Matroska tags)
+pub fn metadata(details: &FileDetails, media: &Path) -> Result<(), AppError> {
+    let date = details.iso_upload_date();
+    let source = details
+        .webpage_url
+        .as_ref()
+        .or(details.original_url.as_ref());
+    let tags = [
+        ("title", Some(&details.title)),
+        (
+            "artist",
+            details.uploader.as_ref().or(details.channel.as_ref()),
+        ),
+        ("date", date.as_ref()),
+        ("description", details.description.as_ref()),
+        ("synopsis", details.description.as_ref()),
+        ("comment", source),
+        ("purl", source),
+    ];
+    rewrite(media, |command| {
+        command.args(["-map", "0", "-c", "copy"]);
+        for (tag, value) in tags {
+            if let Some(value) = value {
+                command.arg("-metadata").arg(format!("{tag}={value}"));
+            }
+        }
+    })
+}
+
+/// Muxes a subtitle track for each of `languages` into `media` as selectable
+/// streams, replacing any it already has. WebVTT is converted to SubRip for
+/// Matroska and MP4, which also gets every track as the `mov_text` it takes,
+/// and WebM only takes WebVTT. Languages without a track the container can
+/// take are reported and skipped.
+pub fn subtitles(
+    tracks: &[SubtitleTrack],
+    languages: &[String],
+    media: &Path,
+) -> Result<(), AppError> {
+    let container = media
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let codec = match container.as_str() {
+        "mkv" => "copy",
+        "mp4" | "m4v" | "mov" => "mov_text",
+        "webm" => "webvtt",
+        _ => {
+            eprintln!("\r\x1b[2KCan't embed subtitles in .{container} files");
+            return Ok(());
+        }
+    };
+
+    let formats = match codec {
+        "webvtt" => &["vtt"][..],
+        _ => subtitle::FFMPEG_FORMATS,
+    };
+    let mut files = vec![];
+    for language in languages {
+        if let Some(file) = subtitle::fetch_for_ffmpeg(tracks, language, formats, media)? {
+            files.push(file);
+        }
+    }
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    rewrite(media, |command| {
+        for (_, file) in &files {
+            command.arg("-i").arg(file.path());
+        }
+        // Leaving out the file's own subtitles numbers the new ones from 0
+        command.args(["-map", "0", "-map", "-0:s"]);
+        for input in 1..=files.len() {
+            command.arg("-map").arg(input.to_string());
+        }
+        command.args(["-c", "copy", "-c:s", codec]);
+        for (index, (track, _)) in files.iter().enumerate() {
+            command
+                .arg(format!("-metadata:s:s:{index}"))
+                .arg(format!("language={}", track.language));
+            if let Some(name) = &track.name {
+                command
+                    .arg(format!("-metadata:s:s:{index}"))
+                    .arg(format!("title={name}"));
+            }
+        }
+    })
+}
+
+/// Replaces the chapters of `media` with `chapters`
+pub fn chapters(chapters: &[Chapter], media: &Path) -> Result<(), AppError> {
+    // Chapters go in through an ffmetadata file, whose special characters
+    // are escaped with backslashes
+    let escape = |title: &str| {
+        let mut escaped = String::new();
+        for c in title.chars() {
+            if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    let mut metadata = ";FFMETADATA1\n".to_string();
+    for chapter in chapters {
+        metadata.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            (chapter.start_time * 1000f64).round() as u64,
+            (chapter.end_time * 1000f64).round() as u64,
+            escape(&chapter.title)
+        ));
+    }
+    let file = TempFile::new(download::part_path(media).with_extension("chapters.txt"));
+    std::fs::write(file.path(), metadata).map_err(|e| AppError::Io(e.to_string()))?;
+    rewrite(media, |command| {
+        command
+            .arg("-i")
+            .arg(file.path())
+            .args(["-map", "0", "-map_chapters", "1", "-c", "copy"]);
+    })
+}
@@ -0,0 +1,221 @@
+use crate::cli::FailedCommand;
+use crate::config::{Config, data_dir};
+use crate::schema::Schema;
+use crate::{AppError, apply_config, download, history};
+use chrono::Local;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::path::PathBuf;
+
+/// What kind of failure a job ended with, for retrying some kinds only.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureKind {
+    /// Dropped connections, timeouts, and server-side or rate-limit errors
+    Network,
+    /// Missing, protected or unsupported items, and ones with no format to get
+    Unavailable,
+    /// Files that couldn't be written, or a full disk
+    Storage,
+    Other,
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // pad() so the kind can be aligned in the list
+        f.pad(match self {
+            FailureKind::Network => "network",
+            FailureKind::Unavailable => "unavailable",
+            FailureKind::Storage => "storage",
+            FailureKind::Other => "other",
+        })
+    }
+}
+
+/// A URL whose download failed even after retrying.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailedJob {
+    pub id: u64,
+    pub url: String,
+    pub kind: FailureKind,
+    pub error: String,
+    /// Unix timestamp of the last failure
+    pub failed_at: i64,
+    /// How many runs it failed in
+    pub failures: u32,
+}
+
+/// Downloads that failed, kept in `failed.json` in the user's data
+/// directory until they succeed or are removed, so they can be retried
+/// without giving their URLs again.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DeadLetters {
+    next_id: u64,
+    jobs: Vec<FailedJob>,
+}
+
+/// The versions of the failed jobs' format, which the file is brought through
+pub const SCHEMA: Schema = Schema { migrations: &[] };
+
+pub fn dead_letters_path() -> Result<PathBuf, AppError> {
+    data_dir()
+        .map(|dir| dir.join("failed.json"))
+        .ok_or_else(|| AppError::Io("no data directory to keep failed jobs in".to_string()))
+}
+
+/// Waits for other processes to finish changing the failed jobs, and keeps
+/// them from changing them until the returned file is dropped
+fn lock() -> Result<File, AppError> {
+    download::lock(&dead_letters_path()?.with_extension("lock"))
+}
+
+impl DeadLetters {
+    /// Loads the failed jobs, or none when nothing failed yet.
+    pub fn load() -> Result<DeadLetters, AppError> {
+        let path = dead_letters_path()?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(DeadLetters::default());
+            }
+            Err(e) => return Err(AppError::Io(format!("{}: {e}", path.display()))),
+        };
+        SCHEMA.parse(&path, &contents)
+    }
+
+    fn save(&self) -> Result<(), AppError> {
+        let path = dead_letters_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+        }
+        let json = SCHEMA.to_json(self)?;
+        download::write_file_synced(&path, json)
+    }
+
+    /// Loads the failed jobs, has `change` make changes to them and saves
+    /// them, with other processes held off in between
+    pub fn update<T>(
+        change: impl FnOnce(&mut DeadLetters) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let _lock = lock()?;
+        let mut dead_letters = DeadLetters::load()?;
+        let value = change(&mut dead_letters)?;
+        dead_letters.save()?;
+        Ok(value)
+    }
+
+    pub fn jobs(&self) -> &[FailedJob] {
+        &self.jobs
+    }
+
+    /// Adds a failure of `url`, or updates the one it already has
+    fn add(&mut self, url: &str, error: &AppError) {
+        let (kind, error, failed_at) = (error.failure_kind(), error.to_string(), Local::now());
+        if let Some(job) = self.jobs.iter_mut().find(|job| job.url == url) {
+            job.kind = kind;
+            job.error = error;
+            job.failed_at = failed_at.timestamp();
+            job.failures += 1;
+            return;
+        }
+        self.next_id += 1;
+        self.jobs.push(FailedJob {
+            id: self.next_id,
+            url: url.to_string(),
+            kind,
+            error,
+            failed_at: failed_at.timestamp(),
+            failures: 1,
+        });
+    }
+}
+
+/// Keeps the URLs of `results` that failed, other than by being
+/// interrupted, and lets go of those that succeeded this time
+pub fn settle<T>(results: &[(&str, &Result<T, AppError>)]) -> Result<(), AppError> {
+    let failed = results
+        .iter()
+        .any(|(_, result)| matches!(result, Err(error) if !matches!(error, AppError::Interrupted)));
+    // Nothing to note, and no need for the file
+    if !failed && !dead_letters_path().is_ok_and(|path| path.exists()) {
+        return Ok(());
+    }
+    DeadLetters::update(|dead_letters| {
+        for (url, result) in results {
+            match result {
+                Ok(_) => dead_letters.jobs.retain(|job| job.url != *url),
+                Err(AppError::Interrupted) => {}
+                Err(error) => dead_letters.add(url, error),
+            }
+        }
+        Ok(())
+    })
+}
+
+pub fn run(command: FailedCommand, config: &Config) -> Result<(), AppError> {
+    match command {
+        FailedCommand::List => {
+            let jobs = DeadLetters::load()?.jobs;
+            if jobs.is_empty() {
+                println!("No failed jobs");
+            }
+            for job in jobs {
+                let failures = if job.failures > 1 {
+                    format!(" ({} times)", job.failures)
+                } else {
+                    String::new()
+                };
+                println!(
+                    "{:>4}  {}  {:<11}  {}{failures}\n      {}",
+                    job.id,
+                    history::format_time(job.failed_at),
+                    job.kind,
+                    job.url,
+                    job.error
+                );
+            }
+        }
+        FailedCommand::Retry(mut args) => {
+            let jobs: Vec<FailedJob> = DeadLetters::load()?
+                .jobs
+                .into_iter()
+                .filter(|job| args.ids.is_empty() || args.ids.contains(&job.id))
+                .filter(|job| args.only.is_none_or(|kind| job.kind == kind))
+                .collect();
+            if let Some(id) = args
+                .ids
+                .iter()
+                .find(|id| !jobs.iter().any(|job| job.id == **id))
+            {
+                return Err(AppError::UnknownFailedJob(*id));
+            }
+            if jobs.is_empty() {
+                println!("No failed jobs to retry");
+                return Ok(());
+            }
+            args.download.urls = jobs.into_iter().map(|job| job.url).collect();
+            apply_config(&mut args.download, config);
+            crate::download(args.download)?;
+        }
+        FailedCommand::Remove { ids } => {
+            let removed = DeadLetters::update(|dead_letters| {
+                let mut removed = vec![];
+                for id in ids {
+                    let index = dead_letters
+                        .jobs
+                        .iter()
+                        .position(|job| job.id == id)
+                        .ok_or(AppError::UnknownFailedJob(id))?;
+                    removed.push(dead_letters.jobs.remove(index));
+                }
+                Ok(removed)
+            })?;
+            for job in removed {
+                println!("Removed {}: {}", job.id, job.url);
+            }
+        }
+    }
+    Ok(())
+}
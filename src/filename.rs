@@ -0,0 +1,186 @@
+use std::sync::OnceLock;
+
+/// Set once at startup from `--restrict-filenames`
+static RESTRICTED: OnceLock<bool> = OnceLock::new();
+
+// ext4, APFS and NTFS all cap a name at 255 bytes (NTFS at 255 UTF-16
+// units, which never take more room than UTF-8 bytes do)
+const MAX_NAME_BYTES: usize = 255;
+// Kept free for suffixes added to names on the way, like `.f137.webm`,
+// `.part` and `.tmp.mkv`
+const SUFFIX_ROOM: usize = 40;
+
+/// Names Windows reserves for devices, whatever extension follows them
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// ASCII spellings of common accented letters and typographic marks
+const TRANSLITERATIONS: &[(&str, &str)] = &[
+    ("àáâãäåāăą", "a"),
+    ("ÀÁÂÃÄÅĀĂĄ", "A"),
+    ("æ", "ae"),
+    ("Æ", "AE"),
+    ("çćĉċč", "c"),
+    ("ÇĆĈĊČ", "C"),
+    ("ďđð", "d"),
+    ("ĎĐÐ", "D"),
+    ("èéêëēĕėęě", "e"),
+    ("ÈÉÊËĒĔĖĘĚ", "E"),
+    ("ĝğġģ", "g"),
+    ("ĜĞĠĢ", "G"),
+    ("ìíîïĩīĭįı", "i"),
+    ("ÌÍÎÏĨĪĬĮİ", "I"),
+    ("ķ", "k"),
+    ("Ķ", "K"),
+    ("ĺļľŀł", "l"),
+    ("ĹĻĽĿŁ", "L"),
+    ("ñńņňŉ", "n"),
+    ("ÑŃŅŇ", "N"),
+    ("òóôõöøōŏő", "o"),
+    ("ÒÓÔÕÖØŌŎŐ", "O"),
+    ("œ", "oe"),
+    ("Œ", "OE"),
+    ("ŕŗř", "r"),
+    ("ŔŖŘ", "R"),
+    ("śŝşšș", "s"),
+    ("ŚŜŞŠȘ", "S"),
+    ("ß", "ss"),
+    ("ţťŧț", "t"),
+    ("ŢŤŦȚ", "T"),
+    ("þ", "th"),
+    ("Þ", "TH"),
+    ("ùúûüũūŭůűų", "u"),
+    ("ÙÚÛÜŨŪŬŮŰŲ", "U"),
+    ("ŵ", "w"),
+    ("Ŵ", "W"),
+    ("ýÿŷ", "y"),
+    ("ÝŸŶ", "Y"),
+    ("źżž", "z"),
+    ("ŹŻŽ", "Z"),
+    ("‘’‚′", "'"),
+    ("–—―", "-"),
+    ("…", "..."),
+];
+
+/// Limits every name made from now on to ASCII letters, digits and `-_.()[]`,
+/// spelling accented letters without their accents
+pub fn restrict() {
+    RESTRICTED.get_or_init(|| true);
+}
+
+fn is_restricted() -> bool {
+    RESTRICTED.get().copied().unwrap_or(false)
+}
+
+/// The ASCII spelling of `c`, when it has one
+fn transliterate(c: char) -> Option<&'static str> {
+    TRANSLITERATIONS
+        .iter()
+        .find(|(letters, _)| letters.contains(c))
+        .map(|(_, ascii)| *ascii)
+}
+
+/// `name` cut to at most `max` bytes, on a character boundary
+fn truncate(name: &str, max: usize) -> &str {
+    let mut end = max.min(name.len());
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// Makes `name` safe to use as (part of) a file name on Windows, macOS and
+/// Linux alike: characters they don't allow become `_`, trailing dots and
+/// spaces, which Windows drops, are removed, and names Windows reserves
+/// for devices get a leading `_`. With `restrict`, non-ASCII characters are
+/// transliterated or replaced too.
+pub fn sanitize(name: &str) -> String {
+    let restricted = is_restricted();
+    let mut sanitized = String::new();
+    for c in name.trim().trim_end_matches(['.', ' ']).chars() {
+        match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => sanitized.push('_'),
+            c if c.is_control() => sanitized.push('_'),
+            c if !restricted => sanitized.push(c),
+            c if c.is_ascii_alphanumeric() || "-_.()[]".contains(c) => sanitized.push(c),
+            c => match transliterate(c) {
+                Some(ascii) => sanitized.push_str(ascii),
+                None => sanitized.push('_'),
+            },
+        }
+    }
+    if restricted {
+        // Runs of replaced characters and spaces read as one
+        while sanitized.contains("__") {
+            sanitized = sanitized.replace("__", "_");
+        }
+    }
+    let sanitized = truncate(sanitized.trim(), MAX_NAME_BYTES - SUFFIX_ROOM)
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if sanitized.is_empty() {
+        "_".to_string()
+    } else if RESERVED_NAMES.contains(&stem.to_uppercase().as_str()) {
+        format!("_{sanitized}")
+    } else {
+        sanitized
+    }
+}
+
+/// `path`, relative and with `/` between its directories, with each name in
+/// it cut to fit the filesystem, keeping the last one's extension
+pub fn fit(path: &str) -> String {
+    let limit = MAX_NAME_BYTES - SUFFIX_ROOM;
+    let mut names: Vec<String> = path
+        .split('/')
+        .map(|name| truncate(name, limit).to_string())
+        .collect();
+    if let Some(last) = names.last_mut()
+        && let Some((stem, extension)) =
+            path.rsplit('/').next().unwrap_or_default().rsplit_once('.')
+    {
+        let stem = truncate(stem, limit.saturating_sub(extension.len() + 1));
+        *last = format!("{}.{extension}", stem.trim_end_matches(['.', ' ']));
+    }
+    names.join("/")
+}
+
+/// The options that make yt-dlp name files the same way
+pub fn ytdlp_args() -> Vec<String> {
+    // yt-dlp trims to a number of characters, which take up to three bytes
+    // each in CJK titles
+    let max_chars = (MAX_NAME_BYTES - SUFFIX_ROOM) / 3;
+    let mut args = vec![
+        "--windows-filenames".to_string(),
+        "--trim-filenames".to_string(),
+        max_chars.to_string(),
+    ];
+    if is_restricted() {
+        args.push("--restrict-filenames".to_string());
+    }
+    args
+}
+
+/// Renders the part of yt-dlp's output template syntax that plain
+/// `%(field)s` placeholders cover, looking fields up in `fields` and using
+/// `NA` for missing values as yt-dlp does. Returns `None` for templates using
+/// anything more (unknown fields, format specs, nesting), which are best left
+/// to yt-dlp.
+pub fn render_template(template: &str, fields: &[(&str, Option<&str>)]) -> Option<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("%(") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find(")s")?;
+        let name = &after[..end];
+        let (_, value) = fields.iter().find(|(field, _)| *field == name)?;
+        rendered.push_str(&sanitize(value.unwrap_or("NA")));
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    Some(fit(&rendered))
+}
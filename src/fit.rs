@@ -0,0 +1,139 @@
+use crate::AppError;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Shapes this close to the target are left as they are
+const RATIO_SLACK: f64 = 0.01;
+
+/// A shape for videos to fit, like 16:9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    /// Parses `WIDTH:HEIGHT`, like `16:9`
+    pub fn parse(value: &str) -> Result<AspectRatio, String> {
+        let (width, height) = value
+            .split_once(':')
+            .ok_or_else(|| format!("`{value}` isn't a ratio like 16:9"))?;
+        match (width.trim().parse(), height.trim().parse()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(AspectRatio { width, height }),
+            _ => Err(format!("`{value}` isn't a ratio like 16:9")),
+        }
+    }
+
+    fn value(self) -> f64 {
+        f64::from(self.width) / f64::from(self.height)
+    }
+}
+
+impl Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+/// How videos narrower than the ratio are made to fit it.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitMode {
+    /// Add black bars at the sides
+    Pad,
+    /// Cut off the top and bottom, keeping the center
+    Crop,
+}
+
+/// What shape to give videos.
+#[derive(Debug, Clone, Copy)]
+pub struct Fit {
+    pub ratio: AspectRatio,
+    pub mode: FitMode,
+}
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<Stream>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    width: Option<u32>,
+    height: Option<u32>,
+    // Phones record sideways and tag the video to be turned when played
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+#[derive(Deserialize)]
+struct SideData {
+    rotation: Option<i32>,
+}
+
+/// The size the video at `path` plays at, turned as its rotation tag says
+fn dimensions(path: &Path) -> Result<Option<(u32, u32)>, AppError> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-of", "json", "-select_streams", "v:0"])
+        .args([
+            "-show_entries",
+            "stream=width,height:stream_side_data=rotation",
+        ])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffprobe: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffprobe exited with {} reading {}: {}",
+            output.status,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let probe: Probe = serde_json::from_slice(&output.stdout)
+        .map_err(|e| AppError::InvalidJson(format!("ffprobe output: {e}")))?;
+    Ok(probe.streams.first().and_then(|stream| {
+        let (width, height) = stream.width.zip(stream.height)?;
+        let rotation = stream
+            .side_data_list
+            .iter()
+            .find_map(|side_data| side_data.rotation)
+            .unwrap_or(0);
+        Some(match rotation.rem_euclid(180) {
+            90 => (height, width),
+            _ => (width, height),
+        })
+    }))
+}
+
+impl Fit {
+    /// The video filter giving the video at `path` the ratio, or `None`
+    /// when it's already at least as wide
+    pub fn filter(self, path: &Path) -> Result<Option<String>, AppError> {
+        let Some((width, height)) = dimensions(path)? else {
+            return Ok(None);
+        };
+        let shape = f64::from(width) / f64::from(height.max(1));
+        if shape >= self.ratio.value() * (1f64 - RATIO_SLACK) {
+            return Ok(None);
+        }
+        let AspectRatio {
+            width: ratio_width,
+            height: ratio_height,
+        } = self.ratio;
+        // Encoders need even sizes
+        let filter = match self.mode {
+            FitMode::Pad => format!(
+                "pad=w=ceil(ih*{ratio_width}/{ratio_height}/2)*2:h=ih:x=(ow-iw)/2:y=0:color=black"
+            ),
+            FitMode::Crop => {
+                format!("crop=w=iw:h=floor(iw*{ratio_height}/{ratio_width}/2)*2:x=0:y=(ih-oh)/2")
+            }
+        };
+        Ok(Some(format!("{filter},setsar=1")))
+    }
+}
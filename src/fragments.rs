@@ -0,0 +1,347 @@
+use crate::download::{self, FetchOptions, Progress};
+use crate::{AppError, http, interrupt, retry, scheduler};
+use aws_lc_rs::cipher::{AES_128, DecryptionContext, PaddedBlockDecryptingKey, UnboundCipherKey};
+use aws_lc_rs::iv::FixedLength;
+use reqwest::header::CONTENT_LENGTH;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+/// An AES-128 key for a fragment.
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub url: String,
+    // Defaults to the fragment's sequence number
+    pub iv: Option<[u8; 16]>,
+}
+
+/// One piece of a fragmented stream.
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    pub url: String,
+    pub sequence: u64,
+    pub key: Option<Key>,
+}
+
+/// A stream served as fragments, as listed by an HLS playlist or a DASH
+/// manifest.
+#[derive(Debug)]
+pub struct FragmentedStream {
+    // Initialization section the fragments are appended to, for fMP4
+    pub init_url: Option<String>,
+    pub fragments: Vec<Fragment>,
+}
+
+fn decrypt(data: &mut Vec<u8>, key: &[u8], iv: [u8; 16]) -> Option<()> {
+    let key = UnboundCipherKey::new(&AES_128, key)
+        .and_then(PaddedBlockDecryptingKey::cbc_pkcs7)
+        .ok()?;
+    let length = key
+        .decrypt(data, DecryptionContext::Iv128(FixedLength::from(iv)))
+        .ok()?
+        .len();
+    data.truncate(length);
+    Some(())
+}
+
+/// Where a fragment is kept until the fragments are joined
+fn fragment_path(path: &Path, sequence: u64) -> PathBuf {
+    let mut file_name = download::part_path(path)
+        .file_name()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push(format!("-Frag{sequence}"));
+    path.with_file_name(file_name)
+}
+
+/// Why a fragment's data can't be what the server meant to send, if it
+/// can't: it's empty, or cut short of its `Content-Length`, or breaks the
+/// framing of MPEG-TS packets or MP4 boxes. Other containers only get the
+/// length checks.
+fn check_fragment(data: &[u8], expected_length: Option<u64>) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("it's empty".to_string());
+    }
+    if let Some(expected) = expected_length
+        && data.len() as u64 != expected
+    {
+        return Err(format!("got {} of {expected} bytes", data.len()));
+    }
+    if data[0] == TS_SYNC_BYTE {
+        if !data.len().is_multiple_of(TS_PACKET_SIZE)
+            || data
+                .chunks(TS_PACKET_SIZE)
+                .any(|packet| packet[0] != TS_SYNC_BYTE)
+        {
+            return Err("its MPEG-TS packets are out of step".to_string());
+        }
+        return Ok(());
+    }
+    // A run of ISO BMFF boxes, each starting with its size and a type like `moof`
+    let is_box = |offset: usize| {
+        data.get(offset + 4..offset + 8)
+            .is_some_and(|kind| kind.iter().all(u8::is_ascii_alphanumeric))
+    };
+    if !is_box(0) {
+        return Ok(());
+    }
+    let mut offset = 0;
+    while offset < data.len() {
+        let size = data
+            .get(offset..offset + 4)
+            .map(|size| u32::from_be_bytes(size.try_into().unwrap()) as usize)
+            .filter(|size| *size >= 8 && is_box(offset))
+            .ok_or_else(|| format!("its MP4 boxes break off at byte {offset}"))?;
+        offset += size;
+    }
+    if offset != data.len() {
+        return Err("its last MP4 box is cut short".to_string());
+    }
+    Ok(())
+}
+
+/// Downloads, checks, and decrypts one fragment into its own file, skipping
+/// it when an earlier run already finished it. Returns its size.
+fn fetch_fragment(
+    fragment: &Fragment,
+    headers: &[(String, String)],
+    keys: &HashMap<String, Vec<u8>>,
+    path: &Path,
+) -> Result<u64, AppError> {
+    let fragment_path = fragment_path(path, fragment.sequence);
+    if let Ok(metadata) = std::fs::metadata(&fragment_path) {
+        return Ok(metadata.len());
+    }
+
+    let mut request = http::client().get(&fragment.url);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(http::error)?;
+    let expected_length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    let mut data = response.bytes().map_err(http::error)?.to_vec();
+    download::throttle(data.len());
+    if fragment.key.is_none() {
+        check_fragment(&data, expected_length).map_err(|problem| {
+            AppError::CorruptFragment(format!("fragment {}: {problem}", fragment.sequence))
+        })?;
+    }
+    if let Some(key) = &fragment.key {
+        let iv = key
+            .iv
+            .unwrap_or_else(|| u128::from(fragment.sequence).to_be_bytes());
+        decrypt(&mut data, &keys[&key.url], iv).ok_or_else(|| {
+            AppError::InvalidFeed(format!("can't decrypt fragment {}", fragment.url))
+        })?;
+        // Only the plaintext has a framing to check
+        check_fragment(&data, None).map_err(|problem| {
+            AppError::CorruptFragment(format!("fragment {}: {problem}", fragment.sequence))
+        })?;
+    }
+
+    // Fragments only get their final name once complete, so a later run can
+    // trust any it finds
+    let part_path = download::part_path(&fragment_path);
+    std::fs::write(&part_path, &data)
+        .and_then(|_| std::fs::rename(&part_path, &fragment_path))
+        .map_err(|e| AppError::Io(e.to_string()))?;
+    Ok(data.len() as u64)
+}
+
+/// Progress with the total extrapolated from the fragments finished so far
+fn progress(downloaded_bytes: u64, finished: usize, count: usize, started: Instant) -> Progress {
+    let total_bytes = (finished > 0).then(|| downloaded_bytes * count as u64 / finished as u64);
+    let elapsed = started.elapsed().as_secs_f64();
+    let speed = (elapsed > 0f64).then(|| downloaded_bytes as f64 / elapsed);
+    let eta = total_bytes
+        .zip(speed)
+        .filter(|(_, speed)| *speed > 0f64)
+        .map(|(total, speed)| total.saturating_sub(downloaded_bytes) as f64 / speed);
+    Progress {
+        downloaded_bytes,
+        total_bytes,
+        speed,
+        average_speed: speed,
+        eta,
+    }
+}
+
+/// Sequence numbers as a list of ranges, like `3, 7-9`
+fn ranges(sequences: &[u64]) -> String {
+    let mut sequences = sequences.to_vec();
+    sequences.sort_unstable();
+    let mut ranges: Vec<(u64, u64)> = vec![];
+    for sequence in sequences {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == sequence => *end = sequence,
+            _ => ranges.push((sequence, sequence)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Downloads every fragment of `stream`, `options.connections` at a time,
+/// and joins them in order into `path`'s `.part` file, which is left for the
+/// caller to move into place.
+///
+/// Each fragment is saved to its own file first, so an interrupted download
+/// picks up from the fragments it finished. Fragments are checked and
+/// retried on their own, and the ones that needed fetching again are
+/// reported. One that still fails fails the download, unless
+/// `options.skip_unavailable_fragments` is set, which leaves it out and
+/// reports the gap.
+pub fn download(
+    stream: &FragmentedStream,
+    headers: &[(String, String)],
+    path: &Path,
+    options: &FetchOptions,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<(), AppError> {
+    let mut keys = HashMap::new();
+    for fragment in &stream.fragments {
+        if let Some(key) = &fragment.key
+            && !keys.contains_key(&key.url)
+        {
+            keys.insert(key.url.clone(), http::fetch_bytes(&key.url, headers)?);
+        }
+    }
+
+    let downloaded = AtomicU64::new(0);
+    let finished = AtomicUsize::new(0);
+    let refetched = Mutex::new(vec![]);
+    let skipped = Mutex::new(vec![]);
+    let count = stream.fragments.len();
+    let started = Instant::now();
+    let results = thread::scope(|scope| {
+        let handle = scope.spawn(|| {
+            scheduler::run(
+                &stream.fragments,
+                options.connections,
+                |fragment| {
+                    options.pause.wait();
+                    let mut attempts = 0;
+                    let result = retry::with_retries(|| {
+                        attempts += 1;
+                        fetch_fragment(fragment, headers, &keys, path)
+                    });
+                    let size = match result {
+                        Ok(size) => size,
+                        Err(error)
+                            if options.skip_unavailable_fragments
+                                && !interrupt::is_interrupted() =>
+                        {
+                            eprintln!(
+                                "\r\x1b[2KGiving up on fragment {}: {error}",
+                                fragment.sequence
+                            );
+                            skipped.lock().unwrap().push(fragment.sequence);
+                            0
+                        }
+                        Err(error) => return Err(error),
+                    };
+                    if attempts > 1 {
+                        refetched.lock().unwrap().push(fragment.sequence);
+                    }
+                    downloaded.fetch_add(size, Ordering::Relaxed);
+                    finished.fetch_add(1, Ordering::Relaxed);
+                    Ok(size)
+                },
+                |_, _| {},
+            )
+        });
+        // Progress is reported from here since the callback can't be shared
+        // across threads
+        while !handle.is_finished() {
+            if !options.pause.is_paused() {
+                on_progress(&progress(
+                    downloaded.load(Ordering::Relaxed),
+                    finished.load(Ordering::Relaxed),
+                    count,
+                    started,
+                ));
+            }
+            thread::sleep(PROGRESS_INTERVAL);
+        }
+        handle.join().unwrap_or_default()
+    });
+    let refetched = refetched.into_inner().unwrap();
+    if !refetched.is_empty() {
+        eprintln!(
+            "\r\x1b[2KFetched fragments {} again after failed attempts",
+            ranges(&refetched)
+        );
+    }
+    let sizes = results
+        .into_iter()
+        .collect::<Result<Vec<u64>, AppError>>()?;
+    on_progress(&progress(sizes.iter().sum(), count, count, started));
+
+    let skipped: HashSet<u64> = skipped.into_inner().unwrap().into_iter().collect();
+    if !skipped.is_empty() {
+        eprintln!(
+            "\r\x1b[2KWarning: {} left out {} of {count} fragments that couldn't be downloaded: {}",
+            path.display(),
+            skipped.len(),
+            ranges(&skipped.iter().copied().collect::<Vec<_>>())
+        );
+    }
+    join(stream, headers, path, &skipped)
+}
+
+/// Writes the init section and every fragment but the `skipped` ones, in
+/// order, into the `.part` file, removing the fragment files once they're
+/// all in
+fn join(
+    stream: &FragmentedStream,
+    headers: &[(String, String)],
+    path: &Path,
+    skipped: &HashSet<u64>,
+) -> Result<(), AppError> {
+    let mut file =
+        File::create(download::part_path(path)).map_err(|e| AppError::Io(e.to_string()))?;
+    if let Some(init_url) = &stream.init_url {
+        file.write_all(&http::fetch_bytes(init_url, headers)?)
+            .map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    for fragment in stream
+        .fragments
+        .iter()
+        .filter(|fragment| !skipped.contains(&fragment.sequence))
+    {
+        let mut fragment_file = File::open(fragment_path(path, fragment.sequence))
+            .map_err(|e| AppError::Io(e.to_string()))?;
+        io::copy(&mut fragment_file, &mut file).map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    file.flush().map_err(|e| AppError::Io(e.to_string()))?;
+    for fragment in &stream.fragments {
+        let _ = std::fs::remove_file(fragment_path(path, fragment.sequence));
+    }
+    Ok(())
+}
@@ -0,0 +1,895 @@
+use crate::cli::{HistoryCommand, StatsArgs};
+use crate::config::data_dir;
+use crate::download::{DownloadEvent, DownloadObserver};
+use crate::{AppError, FileSize, collection};
+use chrono::{Local, TimeZone};
+use clap::ValueEnum;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Row, params};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long a write waits for another run holding the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bumped, with a step in `migrate`, whenever the schema changes
+pub const SCHEMA_VERSION: i64 = 6;
+
+/// How a job ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Completed,
+    /// The file was already there and kept
+    Skipped,
+    /// The item was in the download archive
+    Archived,
+    /// The item was downloaded before, as the history says
+    Duplicate,
+    Failed,
+    /// The item came from a yt-dlp download archive
+    Imported,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Completed => "completed",
+            Status::Skipped => "skipped",
+            Status::Archived => "archived",
+            Status::Duplicate => "duplicate",
+            Status::Failed => "failed",
+            Status::Imported => "imported",
+        }
+    }
+}
+
+/// How `history export` writes the history out.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A header row, then one row per job
+    Csv,
+    /// An array of one object per job
+    Json,
+}
+
+/// A finished job, as kept in the history.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub id: i64,
+    url: String,
+    pub title: Option<String>,
+    pub extractor: Option<String>,
+    // The site's ID for the item
+    pub item_id: Option<String>,
+    pub uploader: Option<String>,
+    // YYYYMMDD
+    pub upload_date: Option<String>,
+    format: Option<String>,
+    status: String,
+    pub path: Option<String>,
+    // SQLite's integers are signed
+    size: Option<i64>,
+    sha256: Option<String>,
+    error: Option<String>,
+    // Unix timestamps
+    started_at: i64,
+    pub finished_at: i64,
+    pub tags: Vec<String>,
+}
+
+const COLUMNS: &str = "id, url, title, extractor, format, status, path, size, sha256, error, \
+    started_at, finished_at, item_id, uploader, upload_date,
+    (SELECT group_concat(tag, char(10)) FROM tags WHERE job_id = jobs.id)";
+
+impl Entry {
+    fn from_row(row: &Row) -> rusqlite::Result<Entry> {
+        Ok(Entry {
+            id: row.get(0)?,
+            url: row.get(1)?,
+            title: row.get(2)?,
+            extractor: row.get(3)?,
+            format: row.get(4)?,
+            status: row.get(5)?,
+            path: row.get(6)?,
+            size: row.get(7)?,
+            sha256: row.get(8)?,
+            error: row.get(9)?,
+            started_at: row.get(10)?,
+            finished_at: row.get(11)?,
+            item_id: row.get(12)?,
+            uploader: row.get(13)?,
+            upload_date: row.get(14)?,
+            tags: {
+                let tags: Option<String> = row.get(15)?;
+                let mut tags: Vec<String> = tags
+                    .iter()
+                    .flat_map(|tags| tags.lines())
+                    .map(String::from)
+                    .collect();
+                tags.sort();
+                tags
+            },
+        })
+    }
+}
+
+fn database_error(e: rusqlite::Error) -> AppError {
+    AppError::Database(e.to_string())
+}
+
+pub fn history_path() -> Result<PathBuf, AppError> {
+    data_dir()
+        .map(|dir| dir.join("history.sqlite3"))
+        .ok_or_else(|| AppError::Io("no data directory to keep the history in".to_string()))
+}
+
+/// Opens the history in the user's data directory, creating it, or
+/// bringing an older one up to date, as needed.
+fn open() -> Result<Connection, AppError> {
+    let path = history_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| AppError::Io(format!("{}: {e}", dir.display())))?;
+    }
+    let mut connection = Connection::open(&path)
+        .map_err(|e| AppError::Database(format!("{}: {e}", path.display())))?;
+    connection
+        .busy_timeout(BUSY_TIMEOUT)
+        .map_err(database_error)?;
+    let version = user_version(&connection).map_err(database_error)?;
+    if version > SCHEMA_VERSION {
+        return Err(newer_version(&path, version));
+    }
+    migrate(&mut connection).map_err(database_error)?;
+    Ok(connection)
+}
+
+fn user_version(connection: &Connection) -> rusqlite::Result<i64> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+pub fn newer_version(path: &Path, version: i64) -> AppError {
+    AppError::NewerFormat(format!(
+        "{} is at version {version} of its schema, newer than {SCHEMA_VERSION}",
+        path.display()
+    ))
+}
+
+/// The schema version of the history, without changing it, or `None` when
+/// there's no history yet
+pub fn stored_version() -> Result<Option<i64>, AppError> {
+    let path = history_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let connection = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| AppError::Database(format!("{}: {e}", path.display())))?;
+    user_version(&connection).map(Some).map_err(database_error)
+}
+
+/// Brings a history written by an older version up to date
+pub fn migrate_existing() -> Result<(), AppError> {
+    if stored_version()?.is_some_and(|version| version < SCHEMA_VERSION) {
+        eprintln!(
+            "Updating {} to version {SCHEMA_VERSION} of its schema",
+            history_path()?.display()
+        );
+        open()?;
+    }
+    Ok(())
+}
+
+/// Brings the schema up from whichever version the file is at
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version = user_version(connection)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    let transaction = connection.transaction()?;
+    if version < 1 {
+        transaction.execute_batch(
+            "CREATE TABLE jobs (
+                id INTEGER PRIMARY KEY,
+                url TEXT NOT NULL,
+                title TEXT,
+                extractor TEXT,
+                format TEXT,
+                status TEXT NOT NULL,
+                path TEXT,
+                size INTEGER,
+                sha256 TEXT,
+                error TEXT,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER NOT NULL
+            );
+            CREATE INDEX jobs_url ON jobs (url);
+            CREATE INDEX jobs_sha256 ON jobs (sha256);",
+        )?;
+    }
+    if version < 2 {
+        transaction.execute_batch(
+            "ALTER TABLE jobs ADD COLUMN item_id TEXT;
+            CREATE INDEX jobs_item ON jobs (extractor, item_id);",
+        )?;
+    }
+    // Download archives name extractors in lowercase
+    if version < 3 {
+        transaction.execute_batch(
+            "DROP INDEX jobs_item;
+            CREATE INDEX jobs_item ON jobs (extractor COLLATE NOCASE, item_id);",
+        )?;
+    }
+    if version < 4 {
+        transaction.execute_batch("ALTER TABLE jobs ADD COLUMN uploader TEXT;")?;
+    }
+    if version < 5 {
+        transaction.execute_batch(
+            "CREATE TABLE tags (
+                job_id INTEGER NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (job_id, tag)
+            );
+            CREATE INDEX tags_tag ON tags (tag);",
+        )?;
+    }
+    if version < 6 {
+        transaction.execute_batch("ALTER TABLE jobs ADD COLUMN upload_date TEXT;")?;
+    }
+    transaction.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    transaction.commit()
+}
+
+/// What's known of an item being downloaded, until its job ends.
+#[derive(Debug, Clone)]
+struct Pending {
+    started_at: i64,
+    title: Option<String>,
+    extractor: Option<String>,
+    item_id: Option<String>,
+    uploader: Option<String>,
+    upload_date: Option<String>,
+    format: Option<String>,
+}
+
+impl Pending {
+    fn new() -> Pending {
+        Pending {
+            started_at: Local::now().timestamp(),
+            title: None,
+            extractor: None,
+            item_id: None,
+            uploader: None,
+            upload_date: None,
+            format: None,
+        }
+    }
+}
+
+/// Passes events on to another observer, recording each item that
+/// completes, is skipped or fails in the download history. The history
+/// failing to save is reported and otherwise ignored.
+pub struct Recorded<'a, O> {
+    observer: O,
+    // Given to every job of the run
+    tags: &'a [String],
+    // `None` when the history couldn't be opened
+    connection: Option<Mutex<Connection>>,
+    // Items being downloaded, by the URL of their job
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl<'a, O> Recorded<'a, O> {
+    pub fn new(observer: O, tags: &'a [String]) -> Recorded<'a, O> {
+        let connection = match open() {
+            Ok(connection) => Some(Mutex::new(connection)),
+            Err(error) => {
+                eprintln!("Downloads won't be kept in the history: {error}");
+                None
+            }
+        };
+        Recorded {
+            observer,
+            tags,
+            connection,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn insert(
+        &self,
+        url: &str,
+        status: Status,
+        path: Option<&Path>,
+        sha256: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        let Some(connection) = &self.connection else {
+            return Ok(());
+        };
+        // A playlist's later items start when the one before ends
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), Pending::new())
+            .unwrap_or_else(Pending::new);
+        let size = path
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|metadata| i64::try_from(metadata.len()).ok());
+        let mut connection = connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(database_error)?;
+        transaction
+            .execute(
+                "INSERT INTO jobs (url, title, extractor, item_id, uploader, upload_date, format, \
+                status, path, size, sha256, error, started_at, finished_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    url,
+                    pending.title,
+                    pending.extractor,
+                    pending.item_id,
+                    pending.uploader,
+                    pending.upload_date,
+                    pending.format,
+                    status.as_str(),
+                    path.map(|path| std::path::absolute(path)
+                        .unwrap_or_else(|_| path.to_path_buf())
+                        .display()
+                        .to_string()),
+                    size,
+                    sha256,
+                    error,
+                    pending.started_at,
+                    Local::now().timestamp(),
+                ],
+            )
+            .map_err(database_error)?;
+        let job_id = transaction.last_insert_rowid();
+        for tag in self.tags {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO tags (job_id, tag) VALUES (?1, ?2)",
+                    params![job_id, tag],
+                )
+                .map_err(database_error)?;
+        }
+        transaction.commit().map_err(database_error)
+    }
+}
+
+impl<O: DownloadObserver> DownloadObserver for Recorded<'_, O> {
+    fn on_event(&self, url: &str, event: &DownloadEvent) {
+        self.observer.on_event(url, event);
+        let result = match event {
+            DownloadEvent::Started => {
+                self.pending
+                    .lock()
+                    .unwrap()
+                    .insert(url.to_string(), Pending::new());
+                Ok(())
+            }
+            DownloadEvent::MetadataFetched {
+                title,
+                extractor,
+                id,
+                uploader,
+                upload_date,
+                format,
+                ..
+            } => {
+                let mut pending = self.pending.lock().unwrap();
+                let pending = pending.entry(url.to_string()).or_insert_with(Pending::new);
+                pending.title = Some(title.clone());
+                pending.extractor = Some(extractor.clone());
+                pending.item_id = Some(id.clone());
+                pending.uploader = uploader.clone();
+                pending.upload_date = upload_date.clone();
+                pending.format = Some(format.clone());
+                Ok(())
+            }
+            DownloadEvent::FallingBack { format, .. } => {
+                if let Some(pending) = self.pending.lock().unwrap().get_mut(url) {
+                    pending.format = Some(format.clone());
+                }
+                Ok(())
+            }
+            DownloadEvent::Completed { path, sha256 } => {
+                self.insert(url, Status::Completed, Some(path), Some(sha256), None)
+            }
+            DownloadEvent::Skipped { path } => {
+                self.insert(url, Status::Skipped, Some(path), None, None)
+            }
+            DownloadEvent::Archived { title } => {
+                if let Some(pending) = self.pending.lock().unwrap().get_mut(url) {
+                    pending.title = Some(title.clone());
+                }
+                self.insert(url, Status::Archived, None, None, None)
+            }
+            DownloadEvent::Duplicate { title, .. } => {
+                if let Some(pending) = self.pending.lock().unwrap().get_mut(url) {
+                    pending.title = Some(title.clone());
+                }
+                self.insert(url, Status::Duplicate, None, None, None)
+            }
+            DownloadEvent::Failed { error } => {
+                let result = self.insert(url, Status::Failed, None, None, Some(error));
+                self.pending.lock().unwrap().remove(url);
+                result
+            }
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            eprintln!("\r\x1b[2KCouldn't save {url} in the history: {error}");
+        }
+    }
+}
+
+/// The newest job `matching` the given values, or `None` when there's none
+/// or the history can't be read
+fn find(matching: &str, values: &[&dyn rusqlite::ToSql]) -> Option<Entry> {
+    let connection = open().ok()?;
+    connection
+        .query_row(
+            &format!("SELECT {COLUMNS} FROM jobs WHERE {matching} ORDER BY id DESC LIMIT 1"),
+            values,
+            Entry::from_row,
+        )
+        .ok()
+}
+
+/// The last time the item with `id` on the `extractor`'s site was
+/// downloaded, or imported from a download archive, if it was
+pub fn previous_download(extractor: &str, id: &str) -> Option<Entry> {
+    find(
+        "status IN ('completed', 'imported') AND extractor = ?1 COLLATE NOCASE
+        AND item_id = ?2",
+        &[&extractor, &id],
+    )
+}
+
+/// An earlier download of a file with the `sha256` checksum, saved
+/// somewhere other than `path`
+pub fn same_contents(sha256: &str, path: &Path) -> Option<Entry> {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    find(
+        "status = 'completed' AND sha256 = ?1 AND path IS NOT ?2",
+        &[&sha256, &path.display().to_string()],
+    )
+}
+
+pub fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Prints one line per entry, newest first
+fn print_list(entries: &[Entry]) {
+    if entries.is_empty() {
+        println!("Nothing in the history");
+    }
+    for entry in entries {
+        println!(
+            "{:>5}  {}  {:<9}  {}",
+            entry.id,
+            format_time(entry.finished_at),
+            entry.status,
+            entry.title.as_deref().unwrap_or(&entry.url)
+        );
+    }
+}
+
+fn print_entry(entry: &Entry) {
+    let fields = [
+        ("URL", Some(entry.url.clone())),
+        ("Title", entry.title.clone()),
+        ("Extractor", entry.extractor.clone()),
+        ("Item ID", entry.item_id.clone()),
+        ("Uploader", entry.uploader.clone()),
+        ("Uploaded", entry.upload_date.clone()),
+        ("Format", entry.format.clone()),
+        ("Status", Some(entry.status.clone())),
+        ("Path", entry.path.clone()),
+        (
+            "Size",
+            entry
+                .size
+                .map(|size| FileSize::new(size as f64).to_string()),
+        ),
+        ("SHA-256", entry.sha256.clone()),
+        ("Error", entry.error.clone()),
+        (
+            "Tags",
+            (!entry.tags.is_empty()).then(|| entry.tags.join(", ")),
+        ),
+        ("Started", Some(format_time(entry.started_at))),
+        ("Finished", Some(format_time(entry.finished_at))),
+    ];
+    println!("ID: {}", entry.id);
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+}
+
+/// A CSV field, quoted when it holds a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn print_csv(entries: &[Entry]) {
+    println!(
+        "id,url,title,extractor,item_id,uploader,upload_date,format,status,path,size,sha256,error,started_at,\
+        finished_at,tags"
+    );
+    for entry in entries {
+        let text = |value: &Option<String>| value.as_deref().map(csv_field).unwrap_or_default();
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            entry.id,
+            csv_field(&entry.url),
+            text(&entry.title),
+            text(&entry.extractor),
+            text(&entry.item_id),
+            text(&entry.uploader),
+            text(&entry.upload_date),
+            text(&entry.format),
+            csv_field(&entry.status),
+            text(&entry.path),
+            entry.size.map(|size| size.to_string()).unwrap_or_default(),
+            text(&entry.sha256),
+            text(&entry.error),
+            entry.started_at,
+            entry.finished_at,
+            csv_field(&entry.tags.join(";"))
+        );
+    }
+}
+
+/// Records each `<extractor> <id>` line of a yt-dlp download archive that
+/// isn't in the history yet. Having no URL, the items keep their archive
+/// line in its place.
+fn import(connection: &mut Connection, file: &Path) -> Result<(), AppError> {
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| AppError::Io(format!("{}: {e}", file.display())))?;
+    let now = Local::now().timestamp();
+    let (mut imported, mut known) = (0, 0);
+    let transaction = connection.transaction().map_err(database_error)?;
+    for line in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let Some((extractor, id)) = line.split_once(' ') else {
+            eprintln!("Skipping `{line}`, which isn't an archive line");
+            continue;
+        };
+        let exists: bool = transaction
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM jobs WHERE extractor = ?1 COLLATE NOCASE
+                AND item_id = ?2 AND status IN ('completed', 'imported'))",
+                [extractor, id],
+                |row| row.get(0),
+            )
+            .map_err(database_error)?;
+        if exists {
+            known += 1;
+            continue;
+        }
+        transaction
+            .execute(
+                "INSERT INTO jobs (url, extractor, item_id, status, started_at, finished_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+                params![line, extractor, id, Status::Imported.as_str(), now],
+            )
+            .map_err(database_error)?;
+        imported += 1;
+    }
+    transaction.commit().map_err(database_error)?;
+    println!("Imported {imported} item(s), {known} already in the history");
+    Ok(())
+}
+
+/// Every download that saved a file, oldest first
+pub fn downloads() -> Result<Vec<Entry>, AppError> {
+    let connection = open()?;
+    let mut statement = connection
+        .prepare(&format!(
+            "SELECT {COLUMNS} FROM jobs
+            WHERE status IN ('completed', 'skipped') AND path IS NOT NULL ORDER BY id"
+        ))
+        .map_err(database_error)?;
+    let rows = statement
+        .query_map([], Entry::from_row)
+        .map_err(database_error)?;
+    rows.collect::<rusqlite::Result<_>>()
+        .map_err(database_error)
+}
+
+/// Records where files were moved to, for every job that saved them
+pub fn record_moves(moved: &[(PathBuf, PathBuf)]) -> Result<(), AppError> {
+    if moved.is_empty() {
+        return Ok(());
+    }
+    set_paths(&mut open()?, moved)
+}
+
+fn set_paths(connection: &mut Connection, moved: &[(PathBuf, PathBuf)]) -> Result<(), AppError> {
+    let transaction = connection.transaction().map_err(database_error)?;
+    for (from, to) in moved {
+        transaction
+            .execute(
+                "UPDATE jobs SET path = ?2 WHERE path = ?1",
+                params![from.display().to_string(), to.display().to_string()],
+            )
+            .map_err(database_error)?;
+    }
+    transaction.commit().map_err(database_error)
+}
+
+pub fn run(command: HistoryCommand) -> Result<(), AppError> {
+    let mut connection = open()?;
+    if let HistoryCommand::Import { file } = &command {
+        return import(&mut connection, file);
+    }
+    let query = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> Result<Vec<Entry>, AppError> {
+        let mut statement = connection.prepare(sql).map_err(database_error)?;
+        let rows = statement
+            .query_map(params, Entry::from_row)
+            .map_err(database_error)?;
+        rows.collect::<rusqlite::Result<_>>()
+            .map_err(database_error)
+    };
+    match command {
+        HistoryCommand::List { limit, failed, tag } => {
+            let status = failed.then_some(Status::Failed.as_str());
+            let entries = query(
+                &format!(
+                    "SELECT {COLUMNS} FROM jobs WHERE (?1 IS NULL OR status = ?1)
+                        AND (?3 IS NULL OR id IN (SELECT job_id FROM tags WHERE tag = ?3))
+                    ORDER BY id DESC LIMIT ?2"
+                ),
+                &[&status, &limit, &tag],
+            )?;
+            print_list(&entries);
+        }
+        HistoryCommand::Search { text, limit } => {
+            // LIKE's wildcards in the text match themselves
+            let pattern = format!(
+                "%{}%",
+                text.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            let entries = query(
+                &format!(
+                    "SELECT {COLUMNS} FROM jobs
+                    WHERE url LIKE ?1 ESCAPE '\\' OR title LIKE ?1 ESCAPE '\\'
+                        OR path LIKE ?1 ESCAPE '\\'
+                    ORDER BY id DESC LIMIT ?2"
+                ),
+                &[&pattern, &limit],
+            )?;
+            print_list(&entries);
+        }
+        HistoryCommand::Show { id } => {
+            let entry = connection
+                .query_row(
+                    &format!("SELECT {COLUMNS} FROM jobs WHERE id = ?1"),
+                    [id],
+                    Entry::from_row,
+                )
+                .optional()
+                .map_err(database_error)?
+                .ok_or(AppError::UnknownRecord(id))?;
+            print_entry(&entry);
+        }
+        HistoryCommand::Tag { id, tags, remove } => {
+            let transaction = connection.transaction().map_err(database_error)?;
+            let exists: bool = transaction
+                .query_row(
+                    "SELECT EXISTS (SELECT 1 FROM jobs WHERE id = ?1)",
+                    [id],
+                    |row| row.get(0),
+                )
+                .map_err(database_error)?;
+            if !exists {
+                return Err(AppError::UnknownRecord(id));
+            }
+            let sql = if remove {
+                "DELETE FROM tags WHERE job_id = ?1 AND tag = ?2"
+            } else {
+                "INSERT OR IGNORE INTO tags (job_id, tag) VALUES (?1, ?2)"
+            };
+            for tag in &tags {
+                transaction
+                    .execute(sql, params![id, tag])
+                    .map_err(database_error)?;
+            }
+            transaction.commit().map_err(database_error)?;
+        }
+        HistoryCommand::Collect {
+            dir,
+            tag,
+            move_files,
+        } => {
+            let entries = query(
+                &format!(
+                    "SELECT {COLUMNS} FROM jobs
+                    WHERE status IN ('completed', 'skipped') AND path IS NOT NULL
+                        AND id IN (SELECT job_id FROM tags WHERE ?1 IS NULL OR tag = ?1)
+                    ORDER BY id"
+                ),
+                &[&tag],
+            )?;
+            let moved = collection::collect(&entries, &dir, tag.as_deref(), move_files);
+            set_paths(&mut connection, &moved)?;
+        }
+        HistoryCommand::Export { format } => {
+            let entries = query(&format!("SELECT {COLUMNS} FROM jobs ORDER BY id"), &[])?;
+            match format {
+                ExportFormat::Csv => print_csv(&entries),
+                ExportFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&entries)
+                        .map_err(|e| AppError::InvalidJson(e.to_string()))?
+                ),
+            }
+        }
+        HistoryCommand::Import { .. } => unreachable!("imported above"),
+    }
+    Ok(())
+}
+
+/// Downloads that completed or failed, summed up over a site, a month, an
+/// uploader or the whole history
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    // `None` for the whole history, and for jobs that didn't record it
+    name: Option<String>,
+    completed: i64,
+    failed: i64,
+    /// The share of jobs that failed, from 0 to 1
+    failure_rate: Option<f64>,
+    bytes: i64,
+    /// Bytes per second over the time spent downloading completed jobs
+    average_speed: Option<f64>,
+}
+
+/// What `stats` prints
+#[derive(Debug, Serialize)]
+struct Summary {
+    total: Totals,
+    sites: Vec<Totals>,
+    months: Vec<Totals>,
+    uploaders: Vec<Totals>,
+}
+
+/// Sums up completed and failed jobs grouped by the `key` expression
+fn totals(
+    connection: &Connection,
+    key: &str,
+    order: &str,
+    limit: Option<u32>,
+) -> Result<Vec<Totals>, AppError> {
+    let mut statement = connection
+        .prepare(&format!(
+            "SELECT {key} AS name,
+                SUM(status = 'completed'),
+                SUM(status = 'failed'),
+                TOTAL(CASE WHEN status = 'completed' THEN size END),
+                TOTAL(CASE WHEN status = 'completed' AND size IS NOT NULL
+                    THEN finished_at - started_at END)
+            FROM jobs WHERE status IN ('completed', 'failed')
+            GROUP BY name ORDER BY {order} LIMIT ?1"
+        ))
+        .map_err(database_error)?;
+    // SQLite takes a negative limit as no limit
+    let limit = limit.map_or(-1, i64::from);
+    let rows = statement
+        .query_map([limit], |row| {
+            let (completed, failed): (i64, i64) = (row.get(1)?, row.get(2)?);
+            let (bytes, seconds): (f64, f64) = (row.get(3)?, row.get(4)?);
+            Ok(Totals {
+                name: row.get(0)?,
+                completed,
+                failed,
+                failure_rate: (completed + failed > 0)
+                    .then(|| failed as f64 / (completed + failed) as f64),
+                bytes: bytes as i64,
+                average_speed: (seconds > 0.0).then(|| bytes / seconds),
+            })
+        })
+        .map_err(database_error)?;
+    rows.collect::<rusqlite::Result<_>>()
+        .map_err(database_error)
+}
+
+fn print_totals(heading: &str, groups: &[Totals]) {
+    if groups.is_empty() {
+        return;
+    }
+    println!(
+        "\n{heading:<24}  {:>9}  {:>6}  {:>7}  {:>10}  {:>12}",
+        "Completed", "Failed", "Failing", "Size", "Speed"
+    );
+    for group in groups {
+        println!(
+            "{:<24}  {:>9}  {:>6}  {:>7}  {:>10}  {:>12}",
+            group.name.as_deref().unwrap_or("(unknown)"),
+            group.completed,
+            group.failed,
+            group
+                .failure_rate
+                .map(|rate| format!("{:.1}%", rate * 100.0))
+                .unwrap_or_default(),
+            FileSize::new(group.bytes as f64).to_string(),
+            group
+                .average_speed
+                .map(|speed| format!("{}/s", FileSize::new(speed)))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Prints what the history adds up to: sizes and failure rates per site
+/// and per month, average speeds, and who the most downloads came from
+pub fn stats(args: &StatsArgs) -> Result<(), AppError> {
+    let connection = open()?;
+    let mut summary = Summary {
+        total: totals(&connection, "NULL", "name", None)?
+            .pop()
+            .unwrap_or_default(),
+        sites: totals(&connection, "extractor", "4 DESC", None)?,
+        months: totals(
+            &connection,
+            "strftime('%Y-%m', finished_at, 'unixepoch', 'localtime')",
+            "name",
+            None,
+        )?,
+        uploaders: totals(
+            &connection,
+            "uploader",
+            "name IS NULL, 2 DESC, 4 DESC",
+            Some(args.top),
+        )?,
+    };
+    // Jobs that didn't record an uploader sort last, as one group
+    summary.uploaders.retain(|uploader| uploader.name.is_some());
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&summary)
+                .map_err(|e| AppError::InvalidJson(e.to_string()))?
+        );
+        return Ok(());
+    }
+
+    let totals = &summary.total;
+    if totals.completed + totals.failed == 0 {
+        println!("Nothing in the history");
+        return Ok(());
+    }
+    print!("{} completed, {} failed", totals.completed, totals.failed);
+    if let Some(rate) = totals.failure_rate {
+        print!(" ({:.1}% failing)", rate * 100.0);
+    }
+    print!(", {}", FileSize::new(totals.bytes as f64));
+    if let Some(speed) = totals.average_speed {
+        print!(" at {}/s on average", FileSize::new(speed));
+    }
+    println!();
+    print_totals("Site", &summary.sites);
+    print_totals("Month", &summary.months);
+    print_totals("Uploader", &summary.uploaders);
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use crate::download::{self, FetchOptions, Progress};
+use crate::fragments::{self, Fragment, FragmentedStream, Key};
+use crate::{AppError, http};
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::LazyLock;
+
+/// `NAME=value` and `NAME="quoted, value"` pairs of a tag's attribute list
+static ATTRIBUTE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([A-Z0-9-]+)=("[^"]*"|[^,]*)"#).unwrap());
+
+fn attributes(list: &str) -> HashMap<&str, &str> {
+    ATTRIBUTE_REGEX
+        .captures_iter(list)
+        .filter_map(|captures| {
+            let name = captures.get(1)?.as_str();
+            let value = captures.get(2)?.as_str();
+            Some((name, value.trim_matches('"')))
+        })
+        .collect()
+}
+
+fn resolve(base: &Url, reference: &str) -> Result<String, AppError> {
+    base.join(reference)
+        .map(String::from)
+        .map_err(|e| AppError::InvalidFeed(format!("bad URL `{reference}` in {base}: {e}")))
+}
+
+/// Playlist features the engine doesn't handle, which yt-dlp is left to
+fn unsupported(url: &str, feature: &str) -> AppError {
+    AppError::UnsupportedUrl(format!("{url} (HLS {feature})"))
+}
+
+fn parse_iv(value: &str) -> Option<[u8; 16]> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))?;
+    let number = u128::from_str_radix(hex, 16).ok()?;
+    Some(number.to_be_bytes())
+}
+
+/// The URI of the highest-bandwidth variant of a master playlist
+fn best_variant(base: &Url, text: &str) -> Result<String, AppError> {
+    let mut best: Option<(u64, &str)> = None;
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(list) = line.strip_prefix("#EXT-X-STREAM-INF:") else {
+            continue;
+        };
+        let bandwidth = attributes(list)
+            .get("BANDWIDTH")
+            .and_then(|bandwidth| bandwidth.parse().ok())
+            .unwrap_or(0);
+        let Some(uri) = lines.find(|line| !line.is_empty() && !line.starts_with('#')) else {
+            break;
+        };
+        if best.is_none_or(|(best_bandwidth, _)| bandwidth > best_bandwidth) {
+            best = Some((bandwidth, uri));
+        }
+    }
+    let (_, uri) = best.ok_or_else(|| AppError::InvalidFeed(format!("no variants in {base}")))?;
+    resolve(base, uri)
+}
+
+fn parse_media_playlist(base: &Url, text: &str) -> Result<FragmentedStream, AppError> {
+    let url = base.as_str();
+    let mut stream = FragmentedStream {
+        init_url: None,
+        fragments: vec![],
+    };
+    let mut sequence = 0;
+    let mut key = None;
+    let mut ended = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = value.trim().parse().unwrap_or(0);
+        } else if let Some(list) = line.strip_prefix("#EXT-X-KEY:") {
+            let attributes = attributes(list);
+            key = match attributes.get("METHOD").copied() {
+                Some("NONE") => None,
+                Some("AES-128") => {
+                    let uri = attributes.get("URI").ok_or_else(|| {
+                        AppError::InvalidFeed(format!("key without URI in {url}"))
+                    })?;
+                    Some(Key {
+                        url: resolve(base, uri)?,
+                        iv: attributes.get("IV").and_then(|iv| parse_iv(iv)),
+                    })
+                }
+                method => {
+                    return Err(unsupported(
+                        url,
+                        &format!("{} encryption", method.unwrap_or("unknown")),
+                    ));
+                }
+            };
+        } else if let Some(list) = line.strip_prefix("#EXT-X-MAP:") {
+            let attributes = attributes(list);
+            if attributes.contains_key("BYTERANGE") {
+                return Err(unsupported(url, "byte ranges"));
+            }
+            if let Some(uri) = attributes.get("URI") {
+                stream.init_url = Some(resolve(base, uri)?);
+            }
+        } else if line.starts_with("#EXT-X-BYTERANGE") {
+            return Err(unsupported(url, "byte ranges"));
+        } else if line == "#EXT-X-ENDLIST" {
+            ended = true;
+        } else if !line.is_empty() && !line.starts_with('#') {
+            stream.fragments.push(Fragment {
+                url: resolve(base, line)?,
+                sequence,
+                key: key.clone(),
+            });
+            sequence += 1;
+        }
+    }
+
+    // Live playlists keep growing, which is what the record command is for
+    if !ended {
+        return Err(unsupported(url, "live playlist"));
+    }
+    if stream.fragments.is_empty() {
+        return Err(AppError::InvalidFeed(format!("no fragments in {url}")));
+    }
+    Ok(stream)
+}
+
+/// Fetches the playlist at `url`, following a master playlist to its best
+/// variant.
+fn fetch_playlist(url: &str, headers: &[(String, String)]) -> Result<FragmentedStream, AppError> {
+    let mut url = Url::parse(url).map_err(|_| AppError::UnsupportedUrl(url.to_string()))?;
+    let mut text = String::from_utf8_lossy(&http::fetch_bytes(url.as_str(), headers)?).into_owned();
+    if text.contains("#EXT-X-STREAM-INF") {
+        url = Url::parse(&best_variant(&url, &text)?)
+            .map_err(|e| AppError::InvalidFeed(e.to_string()))?;
+        text = String::from_utf8_lossy(&http::fetch_bytes(url.as_str(), headers)?).into_owned();
+    }
+    if !text.trim_start().starts_with("#EXTM3U") {
+        return Err(AppError::InvalidFeed(format!(
+            "{url} isn't an HLS playlist"
+        )));
+    }
+    parse_media_playlist(&url, &text)
+}
+
+/// Downloads the HLS stream at `url` to `path`, fetching `options.connections`
+/// fragments at a time and decrypting AES-128 ones, and returns where the
+/// file was saved.
+///
+/// Fragments are joined in order. MPEG-TS streams are remuxed into `path`'s
+/// container with ffmpeg, or kept as `.ts` when it isn't installed. Playlists
+/// the engine can't handle fail with `UnsupportedUrl`, so the caller can hand
+/// them to yt-dlp instead.
+pub fn download(
+    url: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    options: &FetchOptions,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<PathBuf, AppError> {
+    let stream = fetch_playlist(url, headers)?;
+    fragments::download(&stream, headers, path, options, on_progress)?;
+    finish(&stream, path)
+}
+
+/// Moves the joined stream into place, remuxing MPEG-TS when `path` asks for
+/// another container
+fn finish(stream: &FragmentedStream, path: &Path) -> Result<PathBuf, AppError> {
+    let part_path = &download::part_path(path);
+    let is_transport_stream = stream.init_url.is_none();
+    let wants_transport_stream = path.extension().is_some_and(|ext| ext == "ts");
+    if !is_transport_stream || wants_transport_stream {
+        std::fs::rename(part_path, path).map_err(|e| AppError::Io(e.to_string()))?;
+        return Ok(path.to_path_buf());
+    }
+
+    // ffmpeg picks the container from the extension, so the remux gets one
+    // after the part file's name
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let remux_path = part_path.with_extension(format!("tmp.{extension}"));
+    let remuxed = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-f", "mpegts", "-i"])
+        .arg(part_path)
+        .args(["-c", "copy"])
+        .arg(&remux_path)
+        .stdin(Stdio::null())
+        .status();
+    match remuxed {
+        Ok(status) if status.success() => {
+            std::fs::rename(&remux_path, path).map_err(|e| AppError::Io(e.to_string()))?;
+            let _ = std::fs::remove_file(part_path);
+            Ok(path.to_path_buf())
+        }
+        Ok(status) => {
+            let _ = std::fs::remove_file(&remux_path);
+            Err(AppError::CommandFailed(format!(
+                "ffmpeg exited with {status} remuxing {}",
+                part_path.display()
+            )))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("ffmpeg isn't installed, keeping the stream as MPEG-TS");
+            let path = path.with_extension("ts");
+            std::fs::rename(part_path, &path).map_err(|e| AppError::Io(e.to_string()))?;
+            Ok(path)
+        }
+        Err(e) => Err(AppError::CommandFailed(format!(
+            "failed to execute ffmpeg: {e}"
+        ))),
+    }
+}
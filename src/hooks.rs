@@ -0,0 +1,142 @@
+use crate::cli::HookArgs;
+use crate::download::{DownloadEvent, DownloadObserver};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// What a hook is told about the job that ran it, as `DOWNLOADER_*`
+/// environment variables and as JSON on its stdin.
+#[derive(Serialize, Default)]
+struct Payload<'a> {
+    event: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    selection: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+impl Payload<'_> {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        [
+            ("DOWNLOADER_EVENT", Some(self.event.to_string())),
+            ("DOWNLOADER_URL", Some(self.url.to_string())),
+            ("DOWNLOADER_TITLE", self.title.map(str::to_string)),
+            ("DOWNLOADER_SELECTION", self.selection.map(str::to_string)),
+            ("DOWNLOADER_PATH", self.path.clone()),
+            ("DOWNLOADER_ERROR", self.error.map(str::to_string)),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+    }
+}
+
+/// Passes events on to another observer, running the user's hook commands
+/// when an item's details are fetched, when a download completes, and when
+/// one fails. The job waits for its hook, so a hook run on metadata can
+/// prepare for the download. Hooks that fail are reported and otherwise
+/// ignored.
+pub struct Hooked<'a, O> {
+    observer: O,
+    hooks: &'a HookArgs,
+    // Titles of the items being downloaded, for the hooks after metadata
+    titles: Mutex<HashMap<String, String>>,
+}
+
+impl<'a, O> Hooked<'a, O> {
+    pub fn new(observer: O, hooks: &'a HookArgs) -> Hooked<'a, O> {
+        Hooked {
+            observer,
+            hooks,
+            titles: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<O: DownloadObserver> DownloadObserver for Hooked<'_, O> {
+    fn on_event(&self, url: &str, event: &DownloadEvent) {
+        self.observer.on_event(url, event);
+        let title = self.titles.lock().unwrap().get(url).cloned();
+        let (command, payload) = match event {
+            DownloadEvent::MetadataFetched {
+                title, selection, ..
+            } => {
+                self.titles
+                    .lock()
+                    .unwrap()
+                    .insert(url.to_string(), title.clone());
+                let payload = Payload {
+                    event: "metadata",
+                    url,
+                    title: Some(title),
+                    selection: Some(selection),
+                    ..Payload::default()
+                };
+                (&self.hooks.on_metadata, payload)
+            }
+            DownloadEvent::Completed { path, .. } => {
+                let payload = Payload {
+                    event: "download_complete",
+                    url,
+                    title: title.as_deref(),
+                    path: Some(path.display().to_string()),
+                    ..Payload::default()
+                };
+                (&self.hooks.on_download_complete, payload)
+            }
+            DownloadEvent::Failed { error } => {
+                self.titles.lock().unwrap().remove(url);
+                let payload = Payload {
+                    event: "error",
+                    url,
+                    title: title.as_deref(),
+                    error: Some(error),
+                    ..Payload::default()
+                };
+                (&self.hooks.on_error, payload)
+            }
+            _ => return,
+        };
+        if let Some(command) = command
+            && let Err(error) = run(command, &payload)
+        {
+            eprintln!("\r\x1b[2KThe {} hook failed: {error}", payload.event);
+        }
+    }
+}
+
+/// Runs `command` in the shell with `payload`, waiting for it to finish
+fn run(command: &str, payload: &Payload) -> Result<(), String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .envs(payload.env())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to execute `{command}`: {e}"))?;
+    let json = serde_json::to_string(payload).unwrap_or_default();
+    if let Some(mut stdin) = child.stdin.take() {
+        // Hooks that don't read their stdin close it early
+        let _ = writeln!(stdin, "{json}");
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("`{command}` exited with {status}"));
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::timeout::Timeouts;
+use crate::{AppError, download, retry};
+use reqwest::blocking::Client;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::LazyLock;
+
+static CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    let timeouts = Timeouts::current();
+    Client::builder()
+        .user_agent(concat!("downloader/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(timeouts.connect)
+        // Applies to each read, so a transfer only times out once it stalls
+        .timeout(timeouts.stall)
+        .build()
+        .expect("Failed to build HTTP client")
+});
+
+pub fn client() -> &'static Client {
+    &CLIENT
+}
+
+/// Maps a failed request to an error, keeping the status of error responses
+/// so they can be told apart from network failures.
+pub fn error(e: reqwest::Error) -> AppError {
+    match e.status() {
+        Some(status) => AppError::HttpStatus(status.as_u16(), e.to_string()),
+        None if e.is_timeout() => AppError::TimedOut(e.to_string()),
+        None => AppError::Http(e.to_string()),
+    }
+}
+
+/// Maps a failed read of a response body to an error
+pub fn read_error(e: io::Error) -> AppError {
+    if e.kind() == io::ErrorKind::TimedOut {
+        AppError::TimedOut(format!(
+            "no data for {}s",
+            Timeouts::current().stall.as_secs()
+        ))
+    } else {
+        AppError::Http(e.to_string())
+    }
+}
+
+pub fn fetch_text(url: &str) -> Result<String, AppError> {
+    retry::with_retries(|| {
+        client()
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(error)
+    })
+}
+
+/// Fetches the whole body of `url`, sending `headers` with the request.
+pub fn fetch_bytes(url: &str, headers: &[(String, String)]) -> Result<Vec<u8>, AppError> {
+    retry::with_retries(|| {
+        let mut request = client().get(url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        request
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.bytes())
+            .map(|bytes| bytes.to_vec())
+            .map_err(error)
+    })
+}
+
+/// Follows redirects from `url` and returns where they end up.
+pub fn resolve_redirects(url: &str) -> Result<String, AppError> {
+    client()
+        .head(url)
+        .send()
+        .map(|response| response.url().to_string())
+        .map_err(error)
+}
+
+/// Streams the body of `url` into a new file at `path`, returning the bytes
+/// written. The file only appears at `path` once it's complete.
+pub fn download_to(url: &str, path: &Path) -> Result<u64, AppError> {
+    let part_path = download::TempFile::new(download::part_path(path));
+    let part_path = part_path.path();
+    let written = retry::with_retries(|| {
+        let mut response = client()
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(error)?;
+        let mut file = File::create(part_path).map_err(|e| AppError::Io(e.to_string()))?;
+        response.copy_to(&mut file).map_err(error)
+    })?;
+    std::fs::rename(part_path, path).map_err(|e| AppError::Io(e.to_string()))?;
+    Ok(written)
+}
+
+/// The extension of the file `url` points at, falling back to one implied by
+/// its MIME type.
+pub fn file_extension(url: &str, mime_type: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    if let Some((_, extension)) = file_name.rsplit_once('.')
+        && !extension.is_empty()
+        && extension.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return extension.to_lowercase();
+    }
+    match mime_type {
+        "audio/mpeg" => "mp3",
+        "audio/mp4" | "audio/x-m4a" => "m4a",
+        "audio/ogg" => "ogg",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/webp" => "webp",
+        _ => "bin",
+    }
+    .to_string()
+}
@@ -0,0 +1,150 @@
+use crate::recode::VideoContainer;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+// The render node VAAPI encodes on, which is the first GPU's on Linux
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// Hardware video encoders to transcode with.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HwAccel {
+    /// Whichever of the others works here
+    Auto,
+    /// NVIDIA's NVENC
+    Nvenc,
+    /// VA-API, for Intel and AMD GPUs on Linux
+    Vaapi,
+    /// Apple's VideoToolbox
+    Videotoolbox,
+}
+
+/// A hardware encoder ffmpeg can use on this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Nvenc,
+    Vaapi,
+    VideoToolbox,
+}
+
+impl Device {
+    const ALL: [Device; 3] = [Device::Nvenc, Device::Vaapi, Device::VideoToolbox];
+
+    /// ffmpeg's arguments before the input, to open the device
+    pub fn input_args(self) -> &'static [&'static str] {
+        match self {
+            Device::Vaapi => &["-vaapi_device", VAAPI_DEVICE],
+            Device::Nvenc | Device::VideoToolbox => &[],
+        }
+    }
+
+    /// The video filter handing frames to the device, for encoders that
+    /// can't take them from memory
+    pub fn upload_filter(self) -> Option<&'static str> {
+        match self {
+            Device::Vaapi => Some("format=nv12,hwupload"),
+            Device::Nvenc | Device::VideoToolbox => None,
+        }
+    }
+
+    /// ffmpeg's arguments for encoding video for `container` on the
+    /// device, or `None` when it has no encoder the container holds
+    pub fn encoder_args(self, container: VideoContainer) -> Option<&'static [&'static str]> {
+        Some(match (self, container) {
+            (Device::Nvenc, VideoContainer::Mp4 | VideoContainer::Mkv) => &[
+                "-c:v",
+                "h264_nvenc",
+                "-preset",
+                "p5",
+                "-cq",
+                "23",
+                "-b:v",
+                "0",
+            ],
+            (Device::Vaapi, VideoContainer::Mp4 | VideoContainer::Mkv) => {
+                &["-c:v", "h264_vaapi", "-qp", "23"]
+            }
+            (Device::Vaapi, VideoContainer::Webm) => {
+                &["-c:v", "vp9_vaapi", "-global_quality", "80"]
+            }
+            (Device::VideoToolbox, VideoContainer::Mp4 | VideoContainer::Mkv) => {
+                &["-c:v", "h264_videotoolbox", "-q:v", "65"]
+            }
+            (Device::Nvenc | Device::VideoToolbox, VideoContainer::Webm) => return None,
+        })
+    }
+
+    /// Whether the device encodes a frame, which its encoder being built
+    /// into ffmpeg doesn't promise without the hardware and drivers
+    fn works(self) -> bool {
+        let encoder = self.encoder_args(VideoContainer::Mp4).unwrap_or_default();
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-hide_banner", "-loglevel", "error"])
+            .args(self.input_args())
+            .args(["-f", "lavfi", "-i", "color=black:size=256x256:rate=1"])
+            .args(["-frames:v", "1"]);
+        if let Some(filter) = self.upload_filter() {
+            command.args(["-vf", filter]);
+        }
+        command
+            .args(encoder)
+            .args(["-f", "null", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Device::Nvenc => "NVENC",
+            Device::Vaapi => "VA-API",
+            Device::VideoToolbox => "VideoToolbox",
+        })
+    }
+}
+
+/// The devices that encode on this machine, tried once
+fn working() -> &'static [Device] {
+    static WORKING: OnceLock<Vec<Device>> = OnceLock::new();
+    WORKING.get_or_init(|| {
+        Device::ALL
+            .into_iter()
+            .filter(|device| device.works())
+            .collect()
+    })
+}
+
+/// The device `accel` asks for, when it works here. Otherwise warns, once,
+/// that videos are transcoded in software.
+pub fn device(accel: HwAccel) -> Option<Device> {
+    let wanted = |device: &Device| match accel {
+        HwAccel::Auto => true,
+        HwAccel::Nvenc => *device == Device::Nvenc,
+        HwAccel::Vaapi => *device == Device::Vaapi,
+        HwAccel::Videotoolbox => *device == Device::VideoToolbox,
+    };
+    let device = working().iter().copied().find(wanted);
+    if device.is_none() {
+        static WARNED: OnceLock<()> = OnceLock::new();
+        WARNED.get_or_init(|| {
+            eprintln!(
+                "\r\x1b[2KNo {} hardware encoder works here, so videos are transcoded in software",
+                match accel {
+                    HwAccel::Auto => "NVENC, VA-API or VideoToolbox".to_string(),
+                    HwAccel::Nvenc => Device::Nvenc.to_string(),
+                    HwAccel::Vaapi => Device::Vaapi.to_string(),
+                    HwAccel::Videotoolbox => Device::VideoToolbox.to_string(),
+                }
+            );
+        });
+    }
+    device
+}
@@ -0,0 +1,46 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::{AppError, FileDetails, download};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Saves the parsed `details` of a download next to `media_path` as
+/// `<file>.info.json`, replacing its extension as yt-dlp does, and
+/// yt-dlp's own `raw` description of it as `<file>.raw.info.json` when
+/// given, so the item can be looked up or processed again without fetching
+/// it. Returns the files written, leaving out existing ones `on_conflict`
+/// kept.
+pub fn write_next_to(
+    details: &FileDetails,
+    raw: Option<&Value>,
+    media_path: &Path,
+    on_conflict: ConflictPolicy,
+) -> Result<Vec<PathBuf>, AppError> {
+    let mut paths = vec![];
+    let files = [
+        (
+            media_path.with_extension("info.json"),
+            Some(to_json(details)?),
+        ),
+        (
+            media_path.with_extension("raw.info.json"),
+            raw.map(to_json).transpose()?,
+        ),
+    ];
+    for (path, json) in files {
+        let Some(json) = json else {
+            continue;
+        };
+        if !conflict::should_write_sidecar(&path, on_conflict) {
+            println!("Skipped {}, which already exists", path.display());
+            continue;
+        }
+        download::write_file(&path, json)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, AppError> {
+    serde_json::to_string_pretty(value).map_err(|e| AppError::InvalidJson(e.to_string()))
+}
@@ -0,0 +1,80 @@
+use crate::AppError;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the first Ctrl-C, after which no new work starts
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Process IDs of the yt-dlp and aria2c children still running
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Handles Ctrl-C by letting the run wind down: jobs that haven't started
+/// are dropped, children are asked to exit, and transfers stop where they
+/// are, so the queue and any resumable parts are saved on the way out.
+/// A second Ctrl-C quits at once.
+pub fn install() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("\r\x1b[2KQuitting");
+            std::process::exit(130);
+        }
+        eprintln!("\r\x1b[2KStopping; press Ctrl-C again to quit at once");
+        stop();
+    });
+    if let Err(error) = result {
+        eprintln!("Warning: Ctrl-C will quit without cleaning up: {error}");
+    }
+}
+
+/// Winds the run down as the first Ctrl-C does, e.g. for `daemon --stop`
+pub fn stop() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    for pid in CHILDREN.lock().unwrap().iter() {
+        terminate(*pid);
+    }
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Fails with `AppError::Interrupted` once Ctrl-C was pressed
+pub fn check() -> Result<(), AppError> {
+    if is_interrupted() {
+        return Err(AppError::Interrupted);
+    }
+    Ok(())
+}
+
+/// Asks a child to exit with SIGTERM, continuing it in case it's paused so
+/// it can
+fn terminate(pid: u32) {
+    for signal in ["-TERM", "-CONT"] {
+        let _ = Command::new("kill")
+            .args([signal, &pid.to_string()])
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+/// Keeps a child on the list to terminate on Ctrl-C until dropped.
+pub struct ChildGuard(u32);
+
+impl ChildGuard {
+    pub fn new(child: &Child) -> ChildGuard {
+        let pid = child.id();
+        CHILDREN.lock().unwrap().push(pid);
+        // Started just as Ctrl-C was pressed
+        if is_interrupted() {
+            terminate(pid);
+        }
+        ChildGuard(pid)
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILDREN.lock().unwrap().retain(|pid| *pid != self.0);
+    }
+}
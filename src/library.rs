@@ -0,0 +1,130 @@
+use crate::cli::OrganizeArgs;
+use crate::history::Entry;
+use crate::{AppError, collection, filename, history};
+use chrono::{Datelike, Local, NaiveDate, TimeZone};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Where `organize` puts downloads under its folder, unless told otherwise
+pub const DEFAULT_LAYOUT: &str = "%(extractor)s/%(uploader)s/%(year)s";
+
+/// The fields a layout can use
+const FIELDS: [&str; 6] = ["extractor", "uploader", "year", "month", "id", "title"];
+
+/// Checks a layout only uses known fields and stays inside the library
+pub fn parse_layout(value: &str) -> Result<String, String> {
+    let fields: Vec<(&str, Option<&str>)> = FIELDS.iter().map(|field| (*field, None)).collect();
+    if filename::render_template(value, &fields).is_none() {
+        let known: Vec<String> = FIELDS.iter().map(|field| format!("%({field})s")).collect();
+        return Err(format!(
+            "invalid layout `{value}`, which can only use {}",
+            known.join(", ")
+        ));
+    }
+    if !Path::new(value)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(format!(
+            "invalid layout `{value}`, which has to be a relative path without `..`"
+        ));
+    }
+    Ok(value.to_string())
+}
+
+/// The folder `layout` puts the download of `entry` in, relative to the
+/// library. Years and months are when the item was published, or when it
+/// was downloaded for items the history doesn't have the date of.
+fn folder(layout: &str, entry: &Entry) -> Option<String> {
+    let date = entry
+        .upload_date
+        .as_deref()
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+        .or_else(|| {
+            Local
+                .timestamp_opt(entry.finished_at, 0)
+                .single()
+                .map(|time| time.date_naive())
+        });
+    let year = date.map(|date| date.year().to_string());
+    let month = date.map(|date| format!("{:02}", date.month()));
+    let fields = [
+        ("extractor", entry.extractor.as_deref()),
+        ("uploader", entry.uploader.as_deref()),
+        ("year", year.as_deref()),
+        ("month", month.as_deref()),
+        ("id", entry.item_id.as_deref()),
+        ("title", entry.title.as_deref()),
+    ];
+    filename::render_template(layout, &fields)
+}
+
+/// Moves completed downloads, with the files saved next to them, into the
+/// folders their layout gives under the library, recording where they went
+/// in the history
+pub fn organize(args: &OrganizeArgs) -> Result<(), AppError> {
+    let library = std::path::absolute(&args.dir).unwrap_or_else(|_| args.dir.clone());
+    let mut seen = HashSet::new();
+    let mut moved = vec![];
+    let mut failed = 0;
+    for entry in history::downloads()? {
+        let Some(path) = entry.path.as_deref().map(PathBuf::from) else {
+            continue;
+        };
+        // Jobs that downloaded the same file again share its path
+        if !seen.insert(path.clone()) || !path.is_file() {
+            continue;
+        }
+        let Some(folder) = folder(&args.layout, &entry) else {
+            continue;
+        };
+        let dir = library.join(folder);
+        if path.parent() == Some(dir.as_path()) {
+            continue;
+        }
+        if args.dry_run {
+            println!("Would move {} to {}", path.display(), dir.display());
+            continue;
+        }
+        let new_path = match collection::move_into(&path, &dir) {
+            Ok(new_path) => new_path,
+            Err(error) => {
+                eprintln!("Couldn't move {}: {error}", path.display());
+                failed += 1;
+                continue;
+            }
+        };
+        println!("Moved {} to {}", path.display(), dir.display());
+        // Folders of the library's old layout that are left empty go too
+        if !args.link {
+            for old_dir in path.ancestors().skip(1) {
+                if old_dir == library
+                    || !old_dir.starts_with(&library)
+                    || std::fs::remove_dir(old_dir).is_err()
+                {
+                    break;
+                }
+            }
+        }
+        if args.link
+            && let Err(error) = collection::symlink(&new_path, &path)
+        {
+            eprintln!("Couldn't link to {}: {error}", new_path.display());
+        }
+        moved.push((path, new_path));
+    }
+    history::record_moves(&moved)?;
+    if !args.dry_run {
+        println!(
+            "Moved {} download(s) into {}{}",
+            moved.len(),
+            library.display(),
+            if failed > 0 {
+                format!(", {failed} failed")
+            } else {
+                String::new()
+            }
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use crate::AppError;
+use crate::audio::{AudioFormat, AudioQuality};
+use crate::download::{self, TempFile};
+use serde_json::Value;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The EBU R128 true peak and loudness range targets, in dBTP and LU
+const TRUE_PEAK: f64 = -1.5;
+const LOUDNESS_RANGE: f64 = 11f64;
+
+/// What the first pass of `loudnorm` measured, in its own field names
+struct Measurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Measures the loudness of the audio at `path` with a first `loudnorm`
+/// pass, which prints its findings as JSON at the end of ffmpeg's log
+fn measure(path: &Path, target: f64) -> Result<Measurement, AppError> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-af"])
+        .arg(format!(
+            "loudnorm=I={target}:TP={TRUE_PEAK}:LRA={LOUDNESS_RANGE}:print_format=json"
+        ))
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {} measuring the loudness of {}",
+            output.status,
+            path.display()
+        )));
+    }
+    let log = String::from_utf8_lossy(&output.stderr);
+    let json = log
+        .rfind('{')
+        .zip(log.rfind('}'))
+        .and_then(|(start, end)| log.get(start..=end))
+        .and_then(|json| serde_json::from_str::<Value>(json).ok())
+        .ok_or_else(|| {
+            AppError::CommandFailed(format!("no loudness measured for {}", path.display()))
+        })?;
+    let field = |name: &str| {
+        json.get(name)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| AppError::InvalidJson(format!("loudnorm measured no {name}")))
+    };
+    Ok(Measurement {
+        input_i: field("input_i")?,
+        input_tp: field("input_tp")?,
+        input_lra: field("input_lra")?,
+        input_thresh: field("input_thresh")?,
+        target_offset: field("target_offset")?,
+    })
+}
+
+/// Normalizes the loudness of the audio at `path` in place to `target`
+/// LUFS, measuring it in a first pass so the second can apply one linear
+/// gain rather than compressing it. The audio is re-encoded at `quality`.
+/// Silent audio, which has no loudness to measure, is left as it is.
+pub fn normalize(path: &Path, target: f64, quality: AudioQuality) -> Result<(), AppError> {
+    let measured = measure(path, target)?;
+    if !measured.input_i.parse::<f64>().is_ok_and(f64::is_finite) {
+        eprintln!("\r\x1b[2KNo loudness to normalize in {}", path.display());
+        return Ok(());
+    }
+
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let staged =
+        TempFile::new(download::part_path(path).with_extension(format!("tmp.{extension}")));
+    let filter = format!(
+        "loudnorm=I={target}:TP={TRUE_PEAK}:LRA={LOUDNESS_RANGE}:measured_I={}:\
+        measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        measured.input_i,
+        measured.input_tp,
+        measured.input_lra,
+        measured.input_thresh,
+        measured.target_offset
+    );
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(path)
+        // Cover art is kept as it is
+        .args(["-map", "0:a:0", "-map", "0:v?", "-c:v", "copy", "-af"])
+        .arg(filter)
+        // loudnorm works at 192 kHz, which most formats can't take
+        .args(["-ar", "48000"]);
+    // Other containers get ffmpeg's usual encoder for them
+    if let Some(format) = AudioFormat::of(path) {
+        command.args(format.encoder_args(quality));
+    }
+    let status = command
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} normalizing the loudness of {}",
+            path.display()
+        )));
+    }
+    std::fs::rename(staged.path(), path).map_err(|e| AppError::Io(e.to_string()))
+}
@@ -1,28 +1,243 @@
+mod animation;
+mod archive;
+mod audio;
+mod backend;
+mod cache;
+mod channel;
+mod chapter;
+mod checksum;
+mod cleanup;
+mod cli;
+mod collection;
+mod config;
+mod conflict;
+#[cfg(unix)]
+mod daemon;
+mod dash;
+mod direct;
+mod disk;
+mod download;
+mod embed;
+mod failed;
+mod filename;
+mod fit;
+mod fragments;
+mod history;
+mod hls;
+mod hooks;
+mod http;
+mod hwaccel;
+mod infojson;
+mod interrupt;
+mod library;
+mod loudness;
+mod merge;
+mod music;
+mod nfo;
+mod playlist;
+mod podcast;
+mod postprocess;
+mod preview;
+mod profile;
+mod queue;
+mod recode;
+mod remux;
+mod retry;
+mod schedule;
+mod scheduler;
+mod schema;
+mod section;
+mod speed;
+mod sponsorblock;
+mod subscription;
+mod subtitle;
+mod thumbnail;
+mod timeout;
+mod verify;
+#[cfg(unix)]
+mod watch;
+mod ytdlp;
+
 use crate::FileSizeUnit::{Bytes, Gigabytes, Kilobytes, Megabytes};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Commands};
+use config::Config;
+use conflict::ConflictPolicy;
+use download::{DownloadEvent, DownloadObserver};
+use recode::VideoContainer;
 use regex::Regex;
+use section::Section;
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{LazyLock, OnceLock};
+use std::thread;
+use std::time::Duration;
 
+#[derive(Debug)]
 enum AppError {
     InvalidResolution(u16, u16),
     MissingField(&'static str),
+    CommandFailed(String),
+    InvalidJson(String),
+    NewerFormat(String),
+    UnsupportedUrl(String),
+    NotLive(String),
+    Http(String),
+    HttpStatus(u16, String),
+    Io(String),
+    InvalidFeed(String),
+    NoMatchingFormat(String),
+    InvalidConfig(String),
+    DrmProtected(String),
+    BatchFailed {
+        failed: usize,
+        total: usize,
+    },
+    UnknownJob(u64),
+    UnknownRecord(i64),
+    UnknownSubscription(u64),
+    UnknownFailedJob(u64),
+    Database(String),
+    ChecksumMismatch(String),
+    Interrupted,
+    TimedOut(String),
+    CorruptFragment(String),
+    CorruptMedia(String),
+    NeedsTranscode(String),
+    InsufficientSpace {
+        needed: u64,
+        available: u64,
+        dir: String,
+    },
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AppError::InvalidResolution(width, height) => {
+                write!(f, "invalid resolution {width}x{height}")
+            }
+            AppError::MissingField(field) => write!(f, "missing {field} field"),
+            AppError::CommandFailed(message) => write!(f, "{message}"),
+            AppError::InvalidJson(message) => write!(f, "invalid metadata: {message}"),
+            AppError::NewerFormat(message) => {
+                write!(f, "{message}, so downloader has to be updated to use it")
+            }
+            AppError::UnsupportedUrl(url) => write!(f, "unsupported URL: {url}"),
+            AppError::NotLive(url) => write!(f, "{url} is not a live stream"),
+            AppError::Http(message) | AppError::HttpStatus(_, message) => {
+                write!(f, "request failed: {message}")
+            }
+            AppError::Io(message) => write!(f, "file error: {message}"),
+            AppError::InvalidFeed(message) => write!(f, "invalid feed: {message}"),
+            AppError::NoMatchingFormat(message) => write!(f, "no matching format: {message}"),
+            AppError::InvalidConfig(message) => write!(f, "invalid config: {message}"),
+            AppError::BatchFailed { failed, total } => {
+                write!(f, "{failed} of {total} downloads failed")
+            }
+            AppError::UnknownJob(id) => write!(f, "no queued job with ID {id}"),
+            AppError::UnknownRecord(id) => write!(f, "no download with ID {id} in the history"),
+            AppError::UnknownSubscription(id) => write!(f, "no subscription with ID {id}"),
+            AppError::UnknownFailedJob(id) => write!(f, "no failed job with ID {id}"),
+            AppError::Database(message) => write!(f, "history error: {message}"),
+            AppError::ChecksumMismatch(message) => write!(f, "checksum mismatch: {message}"),
+            AppError::Interrupted => write!(f, "interrupted"),
+            AppError::TimedOut(message) => write!(f, "timed out: {message}"),
+            AppError::CorruptFragment(message) => write!(f, "corrupt {message}"),
+            AppError::CorruptMedia(message) => write!(f, "corrupt download: {message}"),
+            AppError::NeedsTranscode(message) => write!(f, "can't remux: {message}"),
+            AppError::InsufficientSpace {
+                needed,
+                available,
+                dir,
+            } => write!(
+                f,
+                "the download needs {} but only {} is free in {dir}",
+                FileSize::new(*needed as f64),
+                FileSize::new(*available as f64)
+            ),
+            AppError::DrmProtected(url) => {
+                write!(
+                    f,
+                    "every format of {url} is DRM-protected and can't be downloaded"
+                )
+            }
+        }
+    }
+}
+
+impl AppError {
+    /// Whether the failure may go away on its own: network errors, timeouts,
+    /// and server-side or rate-limit statuses, but not missing pages, DRM,
+    /// or URLs nothing can download.
+    fn is_retryable(&self) -> bool {
+        match self {
+            AppError::Http(_)
+            | AppError::TimedOut(_)
+            | AppError::CorruptFragment(_)
+            | AppError::CorruptMedia(_) => true,
+            AppError::HttpStatus(status, _) => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+
+    /// What kind of failure this is, for `failed retry --only`
+    fn failure_kind(&self) -> failed::FailureKind {
+        match self {
+            error if error.is_retryable() => failed::FailureKind::Network,
+            AppError::HttpStatus(..)
+            | AppError::UnsupportedUrl(_)
+            | AppError::NotLive(_)
+            | AppError::DrmProtected(_)
+            | AppError::NoMatchingFormat(_) => failed::FailureKind::Unavailable,
+            AppError::Io(_) | AppError::InsufficientSpace { .. } => failed::FailureKind::Storage,
+            _ => failed::FailureKind::Other,
+        }
+    }
+
+    /// Whether another format of the same item may succeed where this one
+    /// failed: the transfer itself went wrong, rather than the item, the
+    /// disk, or the user.
+    fn is_format_failure(&self) -> bool {
+        matches!(
+            self,
+            AppError::Http(_)
+                | AppError::HttpStatus(..)
+                | AppError::TimedOut(_)
+                | AppError::CommandFailed(_)
+                | AppError::ChecksumMismatch(_)
+                | AppError::CorruptFragment(_)
+                | AppError::CorruptMedia(_)
+        )
+    }
 }
 
 fn round_down_to_2_decimal_places(value: f32) -> f32 {
     (value * 100.0).ceil() / 100.0
 }
 
+/// Seconds as `H:MM:SS`
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0f64).round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    format!("{hours}:{minutes:02}:{seconds:02}")
+}
+
 #[derive(Deserialize, Debug)]
 struct RawFileFormat {
     format_id: String,
     ext: String,
     // If not provided, can be approximated with (tbr x duration in seconds x 125)
     filesize: Option<f64>,
+    filesize_approx: Option<f64>,
     #[serde(default = "default_codec")]
     acodec: String,
     #[serde(default = "default_codec")]
@@ -30,8 +245,46 @@ struct RawFileFormat {
     height: Option<u16>,
     width: Option<u16>,
     tbr: Option<f64>,
+    fps: Option<f64>,
+    dynamic_range: Option<String>,
+    abr: Option<f64>,
+    language: Option<String>,
+    protocol: Option<String>,
+    format_note: Option<String>,
+    container: Option<String>,
+    // true, false, or "maybe" when yt-dlp can't tell
+    #[serde(default)]
+    has_drm: Value,
+    url: Option<String>,
+    manifest_url: Option<String>,
+    // Headers the site expects when fetching `url`, e.g. User-Agent
+    #[serde(default)]
+    http_headers: HashMap<String, String>,
+}
+
+impl RawFileFormat {
+    /// Storyboards come as MHTML (YouTube) or are labelled as such in the note
+    fn is_storyboard(&self) -> bool {
+        self.ext == "mhtml"
+            || self.protocol.as_deref() == Some("mhtml")
+            || self
+                .format_note
+                .as_deref()
+                .is_some_and(|note| note.to_lowercase().contains("storyboard"))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum UnitSystem {
+    /// Powers of 1024: KiB, MiB, GiB
+    Binary,
+    /// Powers of 1000: KB, MB, GB, as browsers and most download managers show
+    Si,
 }
 
+/// Chosen once at startup from `--si` or the config file
+static UNIT_SYSTEM: OnceLock<UnitSystem> = OnceLock::new();
+
 #[derive(Debug, PartialOrd, PartialEq, Clone)]
 enum FileSizeUnit {
     Bytes,
@@ -53,107 +306,274 @@ enum Resolution {
     P4320,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum DynamicRange {
+    Sdr,
+    Hdr10,
+    Hlg,
+    Dv,
+}
+
+/// How a format is delivered, which decides whether it can be fetched with a
+/// plain HTTP request or needs a segmented downloader
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Protocol {
+    Http,
+    Https,
+    /// HLS playlists (`m3u8`, `m3u8_native`)
+    Hls,
+    /// DASH manifests (`http_dash_segments`)
+    Dash,
+    Other(String),
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+enum AudioQuality {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
+// Read from text like `500M` in the config
+#[serde(try_from = "String")]
 struct FileSize {
-    size: f32,
-    unit: FileSizeUnit,
+    bytes: u64,
+    // Approximated rather than reported by the site
+    estimated: bool,
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
 enum FileEncoding {
     VideoAndAudio,
     VideoOnly,
     AudioOnly,
     Image,
+    /// Thumbnail grids used for seek previews (YouTube's `sb*` formats)
+    Storyboard,
     Unknown,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct FileFormat {
     id: String,
     extension: String,
     resolution: Option<Resolution>,
-    file_size: FileSize,
+    width: Option<u16>,
+    height: Option<u16>,
+    fps: Option<f64>,
+    vcodec: String,
+    acodec: String,
+    dynamic_range: Option<DynamicRange>,
+    // Total and audio bitrate in kbps
+    tbr: Option<f64>,
+    abr: Option<f64>,
+    // Language of the audio track, e.g. `en` or `de-DE`
+    language: Option<String>,
+    protocol: Option<Protocol>,
+    // Site-provided description, e.g. "Premium", "DASH video" or "storyboard"
+    format_note: Option<String>,
+    // Container when it differs from the extension, e.g. `webm_dash`
+    container: Option<String>,
+    // None when the size can't be known upfront, e.g. for live streams
+    file_size: Option<FileSize>,
     file_encoding: FileEncoding,
+    // Includes formats yt-dlp only suspects are protected
+    has_drm: bool,
+    // Where the media itself is served, for formats fetched without yt-dlp
+    url: Option<String>,
+    // The HLS playlist or DASH manifest listing the format's fragments
+    manifest_url: Option<String>,
+    http_headers: Vec<(String, String)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct FileDetails {
+    // Stable per site, unlike the URL the item was requested with
+    id: String,
     title: String,
-    duration: f64,
+    // Live streams have no duration until they end
+    duration: Option<f64>,
+    is_live: bool,
     ext: String,
     extractor: String,
     extractor_key: String,
+    uploader: Option<String>,
+    channel: Option<String>,
+    // YYYYMMDD
+    upload_date: Option<String>,
+    view_count: Option<u64>,
+    like_count: Option<u64>,
+    description: Option<String>,
+    webpage_url: Option<String>,
+    // The URL as given, before redirects and playlist expansion
+    original_url: Option<String>,
+    // public, unlisted, private, premium_only, subscriber_only or needs_auth
+    availability: Option<String>,
+    thumbnails: Vec<thumbnail::Thumbnail>,
+    subtitles: Vec<subtitle::SubtitleTrack>,
+    chapters: Vec<chapter::Chapter>,
     formats: Vec<FileFormat>,
 }
 
 impl FileSize {
     fn new(size_in_bytes: f64) -> FileSize {
-        let size_in_kilobytes = (size_in_bytes / 1024f64) as f32;
-        if size_in_kilobytes < 1f32 {
-            return FileSize {
-                size: round_down_to_2_decimal_places(size_in_bytes as f32),
-                unit: Bytes,
-            };
+        FileSize {
+            bytes: size_in_bytes.max(0f64).round() as u64,
+            estimated: false,
         }
+    }
 
-        let size_in_megabytes = size_in_kilobytes / 1024f32;
-        if size_in_megabytes < 1f32 {
-            return FileSize {
-                size: round_down_to_2_decimal_places(size_in_kilobytes),
-                unit: Kilobytes,
-            };
+    fn estimated(size_in_bytes: f64) -> FileSize {
+        FileSize {
+            estimated: true,
+            ..FileSize::new(size_in_bytes)
         }
+    }
 
-        let size_in_gigabytes = size_in_megabytes / 1024f32;
-        if size_in_gigabytes < 1f32 {
-            return FileSize {
-                size: round_down_to_2_decimal_places(size_in_megabytes),
-                unit: Megabytes,
-            };
+    /// The largest unit the size is at least one of, for display
+    fn unit(&self, unit_system: UnitSystem) -> FileSizeUnit {
+        let base = unit_system.base();
+        match self.bytes as f64 {
+            bytes if bytes < base => Bytes,
+            bytes if bytes < base.powi(2) => Kilobytes,
+            bytes if bytes < base.powi(3) => Megabytes,
+            _ => Gigabytes,
         }
+    }
 
-        FileSize {
-            size: round_down_to_2_decimal_places(size_in_gigabytes),
-            unit: Gigabytes,
-        }
+    /// The size expressed in `unit`
+    fn scaled(&self, unit: &FileSizeUnit, unit_system: UnitSystem) -> f32 {
+        let exponent = match unit {
+            Bytes => 0,
+            Kilobytes => 1,
+            Megabytes => 2,
+            Gigabytes => 3,
+        };
+        let divisor = unit_system.base().powi(exponent);
+        round_down_to_2_decimal_places((self.bytes as f64 / divisor) as f32)
     }
 }
 
-impl Display for FileSize {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self.unit {
-            Bytes => write!(f, "{}B", self.size),
-            Kilobytes => write!(f, "{}KB", self.size),
-            Megabytes => write!(f, "{}MB", self.size),
-            Gigabytes => write!(f, "{}GB", self.size),
+impl FileSize {
+    /// Parses sizes like `500K`, `200MB` or `1.5GiB`. Single-letter and `iB`
+    /// suffixes are binary (as in yt-dlp), `KB`/`MB`/`GB` are SI.
+    fn parse(value: &str) -> Result<FileSize, String> {
+        let value = value.trim();
+        let split = value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len());
+        let (number, suffix) = value.split_at(split);
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("invalid size `{value}`"))?;
+        let multiplier = match suffix.trim().to_uppercase().as_str() {
+            "" | "B" => 1f64,
+            "K" | "KIB" => 1024f64,
+            "M" | "MIB" => 1024f64.powi(2),
+            "G" | "GIB" => 1024f64.powi(3),
+            "KB" => 1000f64,
+            "MB" => 1000f64.powi(2),
+            "GB" => 1000f64.powi(3),
+            _ => return Err(format!("invalid size unit in `{value}`")),
+        };
+        Ok(FileSize::new(number * multiplier))
+    }
+}
+
+impl TryFrom<String> for FileSize {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<FileSize, String> {
+        FileSize::parse(&value)
+    }
+}
+
+impl UnitSystem {
+    fn current() -> UnitSystem {
+        *UNIT_SYSTEM.get().unwrap_or(&UnitSystem::Binary)
+    }
+
+    fn base(self) -> f64 {
+        match self {
+            UnitSystem::Binary => 1024f64,
+            UnitSystem::Si => 1000f64,
         }
     }
 }
 
-impl PartialOrd for FileSize {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.unit.eq(&other.unit) {
-            return Some(self.size.partial_cmp(&other.size).unwrap());
+impl Display for FileSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let unit_system = UnitSystem::current();
+        let unit = self.unit(unit_system);
+        let size = self.scaled(&unit, unit_system);
+        if self.estimated {
+            write!(f, "~")?;
+        }
+        match (unit, unit_system) {
+            (Bytes, _) => write!(f, "{}B", size),
+            (Kilobytes, UnitSystem::Binary) => write!(f, "{}KiB", size),
+            (Megabytes, UnitSystem::Binary) => write!(f, "{}MiB", size),
+            (Gigabytes, UnitSystem::Binary) => write!(f, "{}GiB", size),
+            (Kilobytes, UnitSystem::Si) => write!(f, "{}KB", size),
+            (Megabytes, UnitSystem::Si) => write!(f, "{}MB", size),
+            (Gigabytes, UnitSystem::Si) => write!(f, "{}GB", size),
         }
-        Some(self.unit.partial_cmp(&other.unit).unwrap())
     }
 }
 
 impl Resolution {
+    const BUCKETS: [(u16, Resolution); 9] = [
+        (144, Resolution::P144),
+        (240, Resolution::P240),
+        (360, Resolution::P360),
+        (480, Resolution::P480),
+        (720, Resolution::P720),
+        (1080, Resolution::P1080),
+        (1440, Resolution::P1440),
+        (2160, Resolution::P2160),
+        (4320, Resolution::P4320),
+    ];
+
+    /// Parses a standard resolution such as `720` or `1080p`
+    fn parse(value: &str) -> Result<Resolution, String> {
+        let pixels: u16 = value
+            .trim()
+            .trim_end_matches(['p', 'P'])
+            .parse()
+            .map_err(|_| format!("invalid resolution `{value}`"))?;
+        Resolution::BUCKETS
+            .iter()
+            .find(|(bucket, _)| *bucket == pixels)
+            .map(|(_, resolution)| resolution.clone())
+            .ok_or_else(|| format!("`{value}` is not a standard resolution"))
+    }
+
+    /// Classifies a frame by its shorter side, so landscape and portrait
+    /// videos of the same quality land in the same bucket. Sizes between
+    /// buckets (e.g. Instagram's 640x1136) go to the nearest one, with ties
+    /// going to the lower.
     fn try_new(width: u16, height: u16) -> Result<Resolution, AppError> {
-        match (width, height) {
-            (4320, _) | (_, 4320) => Ok(Resolution::P4320),
-            (2160, _) | (_, 2160) => Ok(Resolution::P2160),
-            (1440, _) | (_, 1440) => Ok(Resolution::P1440),
-            (1080, _) | (_, 1080) => Ok(Resolution::P1080),
-            (720, _) | (_, 720) => Ok(Resolution::P720),
-            (480, _) | (_, 480) => Ok(Resolution::P480),
-            (360, _) | (_, 360) => Ok(Resolution::P360),
-            (240, _) | (_, 240) => Ok(Resolution::P240),
-            (144, _) | (_, 144) => Ok(Resolution::P144),
-            _ => Err(AppError::InvalidResolution(width, height)),
+        let shorter_side = width.min(height);
+        if shorter_side == 0 {
+            return Err(AppError::InvalidResolution(width, height));
+        }
+
+        let mut nearest = &Resolution::BUCKETS[0];
+        for bucket in &Resolution::BUCKETS[1..] {
+            if bucket.0.abs_diff(shorter_side) < nearest.0.abs_diff(shorter_side) {
+                nearest = bucket;
+            }
         }
+        Ok(nearest.1.clone())
     }
 }
 
@@ -173,6 +593,136 @@ impl Display for Resolution {
     }
 }
 
+// Written as shown, e.g. `720p`, like the protocol and dynamic range
+impl Serialize for Resolution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Protocol {
+    fn parse(value: &str) -> Protocol {
+        match value {
+            "http" => Protocol::Http,
+            "https" => Protocol::Https,
+            "m3u8" | "m3u8_native" => Protocol::Hls,
+            "dash" | "http_dash_segments" | "http_dash_segments_generator" => Protocol::Dash,
+            other => Protocol::Other(other.to_string()),
+        }
+    }
+
+    /// The protocol of a plain file URL
+    fn from_url(url: &str) -> Protocol {
+        if url.starts_with("https://") {
+            Protocol::Https
+        } else {
+            Protocol::Http
+        }
+    }
+
+    /// Plain file downloads, as opposed to streaming manifests
+    fn is_direct(&self) -> bool {
+        matches!(self, Protocol::Http | Protocol::Https)
+    }
+
+    /// Protocols the native engine can download
+    fn is_native(&self) -> bool {
+        self.is_direct() || matches!(self, Protocol::Hls | Protocol::Dash)
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Protocol::Http => write!(f, "http"),
+            Protocol::Https => write!(f, "https"),
+            Protocol::Hls => write!(f, "hls"),
+            Protocol::Dash => write!(f, "dash"),
+            Protocol::Other(protocol) => write!(f, "{protocol}"),
+        }
+    }
+}
+
+impl Serialize for Protocol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl AudioQuality {
+    fn from_bitrate(abr: f64) -> AudioQuality {
+        match abr {
+            abr if abr < 96f64 => AudioQuality::Low,
+            abr if abr < 160f64 => AudioQuality::Medium,
+            _ => AudioQuality::High,
+        }
+    }
+}
+
+impl Display for AudioQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AudioQuality::Low => write!(f, "low"),
+            AudioQuality::Medium => write!(f, "medium"),
+            AudioQuality::High => write!(f, "high"),
+        }
+    }
+}
+
+impl DynamicRange {
+    fn parse(value: &str) -> Option<DynamicRange> {
+        match value {
+            "SDR" => Some(DynamicRange::Sdr),
+            // HDR10+ and 12-bit HDR are HDR10 extensions, so they're grouped with it
+            "HDR10" | "HDR10+" | "HDR12" => Some(DynamicRange::Hdr10),
+            "HLG" => Some(DynamicRange::Hlg),
+            "DV" => Some(DynamicRange::Dv),
+            _ => None,
+        }
+    }
+}
+
+impl Orientation {
+    /// Frames within 5% of square count as square, since encoders round
+    /// dimensions to even numbers
+    fn from_aspect_ratio(aspect_ratio: f64) -> Orientation {
+        if (aspect_ratio - 1f64).abs() <= 0.05 {
+            Orientation::Square
+        } else if aspect_ratio > 1f64 {
+            Orientation::Landscape
+        } else {
+            Orientation::Portrait
+        }
+    }
+}
+
+impl Display for Orientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Orientation::Landscape => write!(f, "landscape"),
+            Orientation::Portrait => write!(f, "portrait"),
+            Orientation::Square => write!(f, "square"),
+        }
+    }
+}
+
+impl Display for DynamicRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DynamicRange::Sdr => write!(f, "SDR"),
+            DynamicRange::Hdr10 => write!(f, "HDR10"),
+            DynamicRange::Hlg => write!(f, "HLG"),
+            DynamicRange::Dv => write!(f, "DV"),
+        }
+    }
+}
+
+impl Serialize for DynamicRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 fn default_codec() -> String {
     "unknown".to_string()
 }
@@ -180,15 +730,22 @@ fn default_codec() -> String {
 impl<'de> Deserialize<'de> for FileDetails {
     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let value = Value::deserialize(d)?;
+        let id = value
+            .get("id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| D::Error::custom("missing id field"))?
+            .to_string();
         let title = value
             .get("title")
             .and_then(|v| v.as_str())
             .ok_or_else(|| D::Error::custom("missing title field"))?
             .to_string();
-        let duration = value
-            .get("duration")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| D::Error::custom("missing duration field"))?;
+        let is_live = value
+            .get("is_live")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        // Absent for images, stories and live streams
+        let duration = value.get("duration").and_then(|v| v.as_f64());
         let ext = value
             .get("ext")
             .and_then(|v| v.as_str())
@@ -204,15 +761,26 @@ impl<'de> Deserialize<'de> for FileDetails {
             .and_then(|v| v.as_str())
             .ok_or_else(|| D::Error::custom("missing extractor_key field"))?
             .to_string();
-        let json_formats = value
-            .get("formats")
-            .and_then(|v| v.as_array())
-            .ok_or_else(|| D::Error::custom("missing formats field"))?;
+        let optional_string =
+            |name: &str| value.get(name).and_then(Value::as_str).map(str::to_string);
+        let optional_count = |name: &str| value.get(name).and_then(Value::as_u64);
+        let json_formats = match value.get("formats").and_then(|v| v.as_array()) {
+            Some(json_formats) => json_formats.clone(),
+            // Single-file items such as images are described by the item itself
+            None if value.get("url").is_some() => {
+                let mut format = value.clone();
+                if format.get("format_id").is_none() {
+                    format["format_id"] = Value::from("0");
+                }
+                vec![format]
+            }
+            None => return Err(D::Error::custom("missing formats field")),
+        };
         let mut formats = vec![];
 
         for format in json_formats {
-            let raw_file_format: RawFileFormat = serde_json::from_value(format.clone())
-                .map_err(|e| D::Error::custom(e.to_string()))?;
+            let raw_file_format: RawFileFormat =
+                serde_json::from_value(format).map_err(|e| D::Error::custom(e.to_string()))?;
             let file_format = match FileFormat::try_new(raw_file_format, duration) {
                 Ok(file_format) => file_format,
                 _ => continue,
@@ -221,16 +789,93 @@ impl<'de> Deserialize<'de> for FileDetails {
         }
 
         Ok(Self {
+            id,
             title,
             duration,
+            is_live,
             ext,
             extractor,
             extractor_key,
+            uploader: optional_string("uploader"),
+            channel: optional_string("channel"),
+            upload_date: optional_string("upload_date"),
+            view_count: optional_count("view_count"),
+            like_count: optional_count("like_count"),
+            description: optional_string("description"),
+            webpage_url: optional_string("webpage_url"),
+            original_url: optional_string("original_url"),
+            availability: optional_string("availability"),
+            thumbnails: thumbnail::from_json(&value),
+            subtitles: subtitle::from_json(&value),
+            chapters: chapter::from_json(&value),
             formats,
         })
     }
 }
 
+/// Longest description shown in listings before it's cut off
+const DESCRIPTION_SUMMARY_LENGTH: usize = 80;
+
+impl FileDetails {
+    /// Whether the duration is within `--min-duration`/`--max-duration`.
+    /// Items without one (images, live streams) aren't ruled out.
+    fn matches_duration(&self, listing: &cli::ListingArgs) -> bool {
+        let Some(duration) = self.duration else {
+            return true;
+        };
+        listing.min_duration.is_none_or(|min| duration >= min)
+            && listing.max_duration.is_none_or(|max| duration <= max)
+    }
+
+    /// Who uploaded the item, falling back to the channel it's on
+    fn uploader_name(&self) -> Option<String> {
+        self.uploader.clone().or_else(|| self.channel.clone())
+    }
+
+    /// Fails when there are formats but none can be downloaded because of DRM,
+    /// which would otherwise surface as a confusing "no matching format"
+    fn ensure_not_drm_only(&self, url: &str) -> Result<(), AppError> {
+        if !self.formats.is_empty() && self.formats.iter().all(|format| format.has_drm) {
+            return Err(AppError::DrmProtected(url.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether the item is a still image, such as an Instagram photo post
+    fn is_image(&self) -> bool {
+        !self.formats.is_empty()
+            && self
+                .formats
+                .iter()
+                .all(|format| format.file_encoding == FileEncoding::Image)
+    }
+
+    /// The upload date as ISO 8601, which tags and media servers expect,
+    /// rather than yt-dlp's YYYYMMDD
+    fn iso_upload_date(&self) -> Option<String> {
+        self.upload_date.as_ref().map(|date| match date.len() {
+            8 => format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]),
+            _ => date.clone(),
+        })
+    }
+
+    /// First line of the description, shortened for listings
+    fn description_summary(&self) -> Option<String> {
+        let first_line = self.description.as_deref()?.lines().next()?.trim();
+        if first_line.is_empty() {
+            return None;
+        }
+        if first_line.chars().count() <= DESCRIPTION_SUMMARY_LENGTH {
+            return Some(first_line.to_string());
+        }
+        let summary: String = first_line
+            .chars()
+            .take(DESCRIPTION_SUMMARY_LENGTH)
+            .collect();
+        Some(format!("{}…", summary.trim_end()))
+    }
+}
+
 impl Display for FileDetails {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let formats = self
@@ -239,191 +884,827 @@ impl Display for FileDetails {
             .map(|f| f.to_string())
             .collect::<Vec<_>>()
             .join("\n\t");
+        let duration = match (self.duration, self.is_live) {
+            (_, true) => "live".to_string(),
+            (Some(duration), false) => format_duration(duration),
+            (None, false) if self.is_image() => "None (image)".to_string(),
+            (None, false) => "None".to_string(),
+        };
         write!(
             f,
-            "FileDetails (\ntitle: {},\nduration: {},\n\
-            ext: {},\nextractor: {},\nextractor_key: {},\nformats: {}\n)",
-            self.title, self.duration, self.ext, self.extractor, self.extractor_key, formats
-        )
+            "FileDetails (\nid: {},\ntitle: {},\nduration: {},\n\
+            ext: {},\nextractor: {},\nextractor_key: {},\n",
+            self.id, self.title, duration, self.ext, self.extractor, self.extractor_key
+        )?;
+        let optional_fields = [
+            ("uploader", self.uploader.clone()),
+            ("channel", self.channel.clone()),
+            ("upload_date", self.upload_date.clone()),
+            ("view_count", self.view_count.map(|n| n.to_string())),
+            ("like_count", self.like_count.map(|n| n.to_string())),
+            ("description", self.description_summary()),
+            ("webpage_url", self.webpage_url.clone()),
+            ("availability", self.availability.clone()),
+            (
+                "original_url",
+                self.original_url
+                    .clone()
+                    .filter(|url| Some(url) != self.webpage_url.as_ref()),
+            ),
+        ];
+        for (name, value) in optional_fields {
+            if let Some(value) = value {
+                writeln!(f, "{name}: {value},")?;
+            }
+        }
+        write!(f, "formats: {formats}\n)")
     }
 }
 
 impl Display for FileFormat {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let resolution = if let Some(resolution) = &self.resolution {
-            resolution.to_string()
+        let fps = self
+            .fps
+            .map(|fps| fps.round().to_string())
+            .unwrap_or_default();
+        let dynamic_range = match &self.dynamic_range {
+            Some(dynamic_range) if self.is_hdr() => format!(" {dynamic_range}"),
+            _ => String::new(),
+        };
+        let resolution = match (&self.resolution, self.width, self.height) {
+            (Some(resolution), Some(width), Some(height)) => match self.orientation() {
+                Some(Orientation::Landscape) | None => {
+                    format!("{resolution}{fps}{dynamic_range} ({width}x{height})")
+                }
+                Some(orientation) => {
+                    format!("{resolution}{fps}{dynamic_range} ({width}x{height}, {orientation})")
+                }
+            },
+            (Some(resolution), _, _) => format!("{resolution}{fps}{dynamic_range}"),
+            (None, _, _) => "None".to_string(),
+        };
+        let file_size = if let Some(file_size) = &self.file_size {
+            file_size.to_string()
         } else {
-            "None".to_string()
+            "unknown".to_string()
         };
         write!(
             f,
-            r#"FileFormat (id: {}, extension: {}, resolution: {}, file size: {}, file encoding: {})"#,
-            self.id, self.extension, resolution, self.file_size, self.file_encoding
-        )
+            r#"FileFormat (id: {}, extension: {}, resolution: {}, file size: {}, file encoding: {}, codecs: {}/{}"#,
+            self.id,
+            self.extension,
+            resolution,
+            file_size,
+            self.file_encoding,
+            self.vcodec,
+            self.acodec
+        )?;
+        if let (Some(abr), Some(audio_quality)) = (self.abr, self.audio_quality()) {
+            write!(f, ", audio: {}kbps ({audio_quality})", abr.round())?;
+        }
+        if let Some(language) = &self.language {
+            write!(f, ", language: {language}")?;
+        }
+        if let Some(protocol) = &self.protocol {
+            write!(f, ", protocol: {protocol}")?;
+        }
+        if let Some(container) = &self.container {
+            write!(f, ", container: {container}")?;
+        }
+        if let Some(format_note) = &self.format_note {
+            write!(f, ", note: {format_note}")?;
+        }
+        if self.has_drm {
+            write!(f, ", DRM")?;
+        }
+        write!(f, ")")
     }
 }
 
 impl FileFormat {
-    fn try_new(raw: RawFileFormat, duration: f64) -> Result<FileFormat, AppError> {
+    fn is_hdr(&self) -> bool {
+        self.dynamic_range
+            .as_ref()
+            .is_some_and(|dynamic_range| *dynamic_range != DynamicRange::Sdr)
+    }
+
+    /// Storyboards and preview images, which are never what anyone wants to
+    /// download as "the video"
+    fn is_preview(&self) -> bool {
+        self.file_encoding == FileEncoding::Storyboard
+            || self
+                .format_note
+                .as_deref()
+                .is_some_and(|note| note.to_lowercase().contains("preview"))
+    }
+
+    /// Whether the format can be picked automatically: not a preview, and
+    /// not DRM-protected since those can't be downloaded
+    fn is_selectable(&self) -> bool {
+        !self.is_preview() && !self.has_drm
+    }
+
+    /// Width over height
+    fn aspect_ratio(&self) -> Option<f64> {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) if height > 0 => Some(f64::from(width) / f64::from(height)),
+            _ => None,
+        }
+    }
+
+    fn orientation(&self) -> Option<Orientation> {
+        self.aspect_ratio().map(Orientation::from_aspect_ratio)
+    }
+
+    /// Whether two streams can be merged without changing container, i.e.
+    /// both are MP4 family or both WebM
+    fn is_compatible_with(&self, other: &FileFormat) -> bool {
+        let container = |format: &FileFormat| {
+            match format.extension.as_str() {
+                "mp4" | "m4a" | "m4v" | "mov" => "mp4",
+                extension => extension,
+            }
+            .to_string()
+        };
+        container(self) == container(other)
+    }
+
+    /// Matches `de` against both `de` and regional tags like `de-DE`
+    fn is_in_language(&self, language: &str) -> bool {
+        self.language.as_deref().is_some_and(|own| {
+            own.eq_ignore_ascii_case(language)
+                || own
+                    .split_once('-')
+                    .is_some_and(|(base, _)| base.eq_ignore_ascii_case(language))
+        })
+    }
+
+    fn audio_quality(&self) -> Option<AudioQuality> {
+        self.abr.map(AudioQuality::from_bitrate)
+    }
+
+    /// Rough perceived quality: resolution dominates, then frame rate, then
+    /// bitrate weighted by codec efficiency, so a 2 Mbps AV1 stream outranks
+    /// a 2.5 Mbps H.264 one
+    fn quality_score(&self) -> f64 {
+        let pixels = self
+            .width
+            .zip(self.height)
+            .map_or(1f64, |(width, height)| f64::from(width) * f64::from(height));
+        let fps = self.fps.unwrap_or(30f64).clamp(1f64, 120f64);
+        let efficiency = if self.vcodec != "none" && self.vcodec != "unknown" {
+            FormatPreference::efficiency(&self.vcodec)
+        } else {
+            FormatPreference::efficiency(&self.acodec)
+        };
+        let bitrate = self.tbr.or(self.abr).unwrap_or(1f64) * efficiency;
+        10f64 * pixels.max(1f64).log2() + 5f64 * fps.log2() + bitrate.max(1f64).log2()
+    }
+
+    /// Which of two formats of the same resolution to keep, per `--prefer`:
+    /// the highest `quality_score` for `best`, the smallest file for
+    /// `smallest`, and otherwise the tie-breakers below.
+    ///
+    /// HDR wins when preferred, then higher frame rate, then the better audio
+    /// tier, then the preferred codec, then the higher audio bitrate, and
+    /// finally the larger file. Audio is judged by tier before codec so an
+    /// efficient codec isn't passed over for a bigger file of the same
+    /// perceived quality.
+    fn is_better_than(&self, other: &FileFormat, preference: &FormatPreference) -> bool {
+        let preferred = match preference.prefer {
+            QualityPreference::Best => self
+                .quality_score()
+                .partial_cmp(&other.quality_score())
+                .unwrap_or(Ordering::Equal),
+            // Unknown sizes can't be shown to be small, so they lose
+            QualityPreference::Smallest => match (&self.file_size, &other.file_size) {
+                (Some(own), Some(other)) => other.bytes.cmp(&own.bytes),
+                (Some(_), None) => Ordering::Greater,
+                (None, Some(_)) => Ordering::Less,
+                (None, None) => Ordering::Equal,
+            },
+            QualityPreference::Balanced => Ordering::Equal,
+        };
+        preferred
+            .then_with(|| preference.compare_dynamic_range(self, other))
+            .then_with(|| self.fps.partial_cmp(&other.fps).unwrap_or(Ordering::Equal))
+            .then_with(|| self.audio_quality().cmp(&other.audio_quality()))
+            .then_with(|| preference.compare_codecs(self, other))
+            .then_with(|| self.abr.partial_cmp(&other.abr).unwrap_or(Ordering::Equal))
+            .then_with(|| self.file_size.cmp(&other.file_size))
+            == Ordering::Greater
+    }
+
+    /// Whether the format satisfies the filter. Constraints on video
+    /// properties don't apply to audio-only formats.
+    fn matches(&self, filter: &FormatFilter) -> bool {
+        if !filter.extensions.is_empty()
+            && !filter
+                .extensions
+                .iter()
+                .any(|extension| extension.eq_ignore_ascii_case(&self.extension))
+        {
+            return false;
+        }
+        if self.file_encoding == FileEncoding::Storyboard && !filter.include_storyboards {
+            return false;
+        }
+        if !filter.encodings.is_empty() && !filter.encodings.contains(&self.file_encoding) {
+            return false;
+        }
+        // Formats of unknown size can't be ruled out either way
+        if let Some(file_size) = &self.file_size
+            && (filter
+                .min_size
+                .as_ref()
+                .is_some_and(|min| file_size.bytes < min.bytes)
+                || filter
+                    .max_size
+                    .as_ref()
+                    .is_some_and(|max| file_size.bytes > max.bytes))
+        {
+            return false;
+        }
+        if filter.no_hls && self.protocol == Some(Protocol::Hls) {
+            return false;
+        }
+        if filter.direct_only && !self.protocol.as_ref().is_some_and(Protocol::is_direct) {
+            return false;
+        }
+        if self.file_encoding == FileEncoding::AudioOnly {
+            return filter.audio_languages.is_empty()
+                || filter
+                    .audio_languages
+                    .iter()
+                    .any(|language| self.is_in_language(language));
+        }
+        if filter.no_hdr && self.is_hdr() {
+            return false;
+        }
+        if let Some(orientation) = &filter.orientation
+            && self.orientation().as_ref() != Some(orientation)
+        {
+            return false;
+        }
+        if let Some(min_resolution) = &filter.min_resolution
+            && !self
+                .resolution
+                .as_ref()
+                .is_some_and(|r| r >= min_resolution)
+        {
+            return false;
+        }
+        if let Some(max_resolution) = &filter.max_resolution
+            && !self
+                .resolution
+                .as_ref()
+                .is_some_and(|r| r <= max_resolution)
+        {
+            return false;
+        }
+        match filter.min_fps {
+            Some(min_fps) => self.fps.is_some_and(|fps| fps >= min_fps),
+            None => true,
+        }
+    }
+
+    fn try_new(raw: RawFileFormat, duration: Option<f64>) -> Result<FileFormat, AppError> {
         let resolution = match (raw.width, raw.height) {
             (Some(width), Some(height)) => Some(Resolution::try_new(width, height)?),
             _ => None,
         };
 
-        let file_size = match raw.filesize {
-            Some(filesize) => FileSize::new(filesize),
-            None => {
-                let Some(tbr) = raw.tbr else {
-                    return Err(AppError::MissingField("tbr"));
-                };
-                let file_size = duration * tbr * 125f64;
-                FileSize::new(file_size)
+        // Storyboards have no meaningful bitrate to estimate a size from
+        let duration = duration.filter(|_| !raw.is_storyboard());
+        let file_size = match (raw.filesize, raw.filesize_approx, raw.tbr, duration) {
+            (Some(filesize), _, _, _) => Some(FileSize::new(filesize)),
+            (None, Some(filesize_approx), _, _) => Some(FileSize::estimated(filesize_approx)),
+            (None, None, Some(tbr), Some(duration)) => {
+                Some(FileSize::estimated(duration * tbr * 125f64))
             }
+            (None, None, None, Some(_)) => return Err(AppError::MissingField("tbr")),
+            // Without a duration (images, live streams) there's nothing to estimate from
+            (None, None, _, None) => None,
         };
 
         Ok(FileFormat {
             id: raw.format_id.clone(),
             extension: raw.ext.clone(),
             resolution,
+            width: raw.width,
+            height: raw.height,
+            fps: raw.fps,
+            vcodec: raw.vcodec.clone(),
+            acodec: raw.acodec.clone(),
+            dynamic_range: raw.dynamic_range.as_deref().and_then(DynamicRange::parse),
+            tbr: raw.tbr,
+            abr: raw.abr,
+            language: raw.language.clone(),
+            protocol: raw.protocol.as_deref().map(Protocol::parse),
+            format_note: raw.format_note.clone(),
+            container: raw.container.clone(),
             file_size,
+            has_drm: matches!(&raw.has_drm, Value::Bool(true))
+                || raw.has_drm.as_str() == Some("maybe"),
+            url: raw.url.clone(),
+            manifest_url: raw.manifest_url.clone(),
+            http_headers: raw
+                .http_headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
             file_encoding: FileEncoding::from(raw),
         })
     }
 }
 
-impl From<RawFileFormat> for FileEncoding {
-    fn from(value: RawFileFormat) -> Self {
-        match (
-            value.acodec.as_str(),
-            value.vcodec.as_str(),
-            value.width,
-            value.height,
-        ) {
-            (acodec, vcodec, Some(_), Some(_)) if acodec != "none" && vcodec != "none" => {
-                FileEncoding::VideoAndAudio
+/// A property formats can be sorted by with `--sort-by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Resolution,
+    Fps,
+    Size,
+    AudioBitrate,
+    Extension,
+}
+
+/// One key of a multi-key sort, e.g. `res` or `+size`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SortField {
+    key: SortKey,
+    descending: bool,
+}
+
+impl SortField {
+    /// Parses `key`, `-key` (both descending) or `+key` (ascending), as in
+    /// yt-dlp's `--format-sort`
+    fn parse(value: &str) -> Result<SortField, String> {
+        let value = value.trim();
+        let (name, descending) = match value.strip_prefix('+') {
+            Some(name) => (name, false),
+            None => (value.strip_prefix('-').unwrap_or(value), true),
+        };
+        let key = match name {
+            "res" => SortKey::Resolution,
+            "fps" => SortKey::Fps,
+            "size" => SortKey::Size,
+            "abr" => SortKey::AudioBitrate,
+            "ext" => SortKey::Extension,
+            _ => {
+                return Err(format!(
+                    "unknown sort key `{name}`, expected res, fps, size, abr or ext"
+                ));
             }
-            ("none", vcodec, Some(_), Some(_)) if vcodec != "none" => FileEncoding::VideoOnly,
-            (acodec, "none", None, None) if acodec != "none" => FileEncoding::AudioOnly,
-            ("none", vcodec, Some(_), Some(_)) if vcodec != "none" => FileEncoding::Image,
-            _ => FileEncoding::Unknown,
+        };
+        Ok(SortField { key, descending })
+    }
+
+    fn compare(&self, a: &FileFormat, b: &FileFormat) -> Ordering {
+        let pixels = |format: &FileFormat| {
+            format
+                .width
+                .zip(format.height)
+                .map(|(width, height)| u32::from(width) * u32::from(height))
+        };
+        let bytes = |format: &FileFormat| format.file_size.as_ref().map(|size| size.bytes);
+        match self.key {
+            SortKey::Resolution => self.compare_known(pixels(a), pixels(b)),
+            SortKey::Fps => self.compare_known(a.fps, b.fps),
+            SortKey::Size => self.compare_known(bytes(a), bytes(b)),
+            SortKey::AudioBitrate => self.compare_known(a.abr, b.abr),
+            SortKey::Extension => self.compare_known(Some(&a.extension), Some(&b.extension)),
         }
     }
-}
 
-impl Display for FileEncoding {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            FileEncoding::VideoAndAudio => write!(f, "Video and Audio"),
-            FileEncoding::VideoOnly => write!(f, "Video Only"),
-            FileEncoding::AudioOnly => write!(f, "Audio Only"),
-            FileEncoding::Image => write!(f, "Image"),
-            FileEncoding::Unknown => write!(f, "Unknown"),
+    /// Unknown values sort last whichever the direction
+    fn compare_known<T: PartialOrd>(&self, a: Option<T>, b: Option<T>) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                let ordering = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+                if self.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
         }
     }
 }
 
-const YOUTUBE_REGEX: Regex = Regex::new(r"https?://(www\.)?(youtube\.com|youtu\.be)/.+").unwrap();
-const INSTAGRAM_REGEX: Regex =
-    Regex::new(r"https?://(www\.)?instagram\.com/(p|reel|stories)/[A-Za-z0-9_.-]+(/[\w-]+)?/?")
-        .unwrap();
-
+impl FileDetails {
+    /// Sorts the formats by each field in turn, keeping the extractor's
+    /// order between formats that tie on all of them
+    fn sort_formats(&mut self, fields: &[SortField]) {
+        self.formats.sort_by(|a, b| {
+            fields.iter().fold(Ordering::Equal, |ordering, field| {
+                ordering.then_with(|| field.compare(a, b))
+            })
+        });
+    }
+}
+
+/// Constraints a format must satisfy to be listed or selected. Empty lists
+/// and `None` bounds don't constrain anything.
+#[derive(Debug, Clone, Default)]
+struct FormatFilter {
+    extensions: Vec<String>,
+    encodings: Vec<FileEncoding>,
+    min_size: Option<FileSize>,
+    max_size: Option<FileSize>,
+    min_resolution: Option<Resolution>,
+    max_resolution: Option<Resolution>,
+    min_fps: Option<f64>,
+    no_hdr: bool,
+    // Only applies to formats with a picture
+    orientation: Option<Orientation>,
+    audio_languages: Vec<String>,
+    no_hls: bool,
+    direct_only: bool,
+    include_storyboards: bool,
+}
+
+impl From<&cli::FormatArgs> for FormatFilter {
+    fn from(format_args: &cli::FormatArgs) -> Self {
+        Self {
+            extensions: format_args.extensions.clone(),
+            encodings: format_args.encodings.clone(),
+            min_size: format_args.min_size.clone(),
+            max_size: format_args.max_size.clone(),
+            min_resolution: format_args.min_resolution.clone(),
+            max_resolution: format_args.max_resolution.clone(),
+            min_fps: format_args.min_fps,
+            no_hdr: format_args.no_hdr,
+            orientation: if format_args.portrait_only {
+                Some(Orientation::Portrait)
+            } else if format_args.landscape_only {
+                Some(Orientation::Landscape)
+            } else {
+                None
+            },
+            audio_languages: format_args.audio_langs.clone(),
+            no_hls: format_args.no_hls,
+            direct_only: format_args.direct_only,
+            include_storyboards: format_args.include_storyboards,
+        }
+    }
+}
+
+impl FileDetails {
+    /// Keeps only the formats that match `filter`
+    fn filter(mut self, filter: &FormatFilter) -> FileDetails {
+        self.formats.retain(|format| format.matches(filter));
+        self
+    }
+}
+
+/// What to favour when picking between formats of the same resolution
+#[derive(Debug, Clone)]
+struct FormatPreference {
+    /// Codec families, most preferred first
+    video_codecs: Vec<String>,
+    audio_codecs: Vec<String>,
+    prefer_hdr: bool,
+    prefer: QualityPreference,
+}
+
+/// How to trade quality against size when picking between formats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum QualityPreference {
+    /// The smallest file
+    Smallest,
+    /// Efficient codecs and good audio over sheer file size
+    #[default]
+    Balanced,
+    /// The highest quality score, whatever the size
+    Best,
+}
+
+impl FormatPreference {
+    fn new(format_args: &cli::FormatArgs) -> Self {
+        Self {
+            video_codecs: format_args.video_codecs.clone(),
+            audio_codecs: format_args.audio_codecs.clone(),
+            prefer_hdr: format_args.prefer_hdr,
+            prefer: format_args.prefer,
+        }
+    }
+
+    /// How much quality a codec gets out of each bit, relative to H.264/AAC
+    fn efficiency(codec: &str) -> f64 {
+        match FormatPreference::family(codec) {
+            "av01" => 2f64,
+            "vp9" | "h265" | "opus" => 1.5f64,
+            "h264" | "aac" => 1f64,
+            _ => 0.8f64,
+        }
+    }
+
+    /// Maps codec strings like `avc1.640028` or `vp09.00.40.08` to the family
+    /// names used in preferences
+    fn family(codec: &str) -> &str {
+        let prefix = codec.split('.').next().unwrap_or(codec);
+        match prefix {
+            "avc1" | "avc3" | "h264" => "h264",
+            "hev1" | "hvc1" | "h265" | "hevc" => "h265",
+            "vp09" | "vp9" => "vp9",
+            "vp08" | "vp8" => "vp8",
+            "av01" | "av1" => "av01",
+            "mp4a" | "aac" => "aac",
+            prefix => prefix,
+        }
+    }
+
+    /// Lower is better; codecs missing from the list rank after all listed ones
+    fn rank(codec: &str, preference: &[String]) -> usize {
+        let family = FormatPreference::family(codec);
+        preference
+            .iter()
+            .position(|preferred| preferred == family)
+            .unwrap_or(preference.len())
+    }
+
+    fn compare_codecs(&self, a: &FileFormat, b: &FileFormat) -> Ordering {
+        let video = FormatPreference::rank(&b.vcodec, &self.video_codecs)
+            .cmp(&FormatPreference::rank(&a.vcodec, &self.video_codecs));
+        let audio = FormatPreference::rank(&b.acodec, &self.audio_codecs)
+            .cmp(&FormatPreference::rank(&a.acodec, &self.audio_codecs));
+        video.then(audio)
+    }
+
+    /// Orders formats across resolutions: higher resolution wins (so any
+    /// video beats audio alone), and formats of the same resolution fall back
+    /// to `FileFormat::is_better_than`
+    fn compare_quality(&self, a: &FileFormat, b: &FileFormat) -> Ordering {
+        a.resolution
+            .partial_cmp(&b.resolution)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| {
+                if a.is_better_than(b, self) {
+                    Ordering::Greater
+                } else if b.is_better_than(a, self) {
+                    Ordering::Less
+                } else {
+                    Ordering::Equal
+                }
+            })
+    }
+
+    fn compare_dynamic_range(&self, a: &FileFormat, b: &FileFormat) -> Ordering {
+        if self.prefer_hdr {
+            a.is_hdr().cmp(&b.is_hdr())
+        } else {
+            Ordering::Equal
+        }
+    }
+}
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "heic", "avif"];
+
+impl From<RawFileFormat> for FileEncoding {
+    fn from(value: RawFileFormat) -> Self {
+        if value.is_storyboard() {
+            return FileEncoding::Storyboard;
+        }
+        // Images carry no codecs, so they're told apart by extension
+        if IMAGE_EXTENSIONS.contains(&value.ext.to_lowercase().as_str()) {
+            return FileEncoding::Image;
+        }
+        match (
+            value.acodec.as_str(),
+            value.vcodec.as_str(),
+            value.width,
+            value.height,
+        ) {
+            ("none", "none", Some(_), Some(_)) => FileEncoding::Image,
+            (acodec, vcodec, Some(_), Some(_)) if acodec != "none" && vcodec != "none" => {
+                FileEncoding::VideoAndAudio
+            }
+            ("none", vcodec, Some(_), Some(_)) if vcodec != "none" => FileEncoding::VideoOnly,
+            (acodec, "none", None, None) if acodec != "none" => FileEncoding::AudioOnly,
+            _ => FileEncoding::Unknown,
+        }
+    }
+}
+
+impl FileEncoding {
+    fn from_mime_type(mime_type: &str) -> Self {
+        match mime_type.split('/').next() {
+            Some("audio") => FileEncoding::AudioOnly,
+            Some("video") => FileEncoding::VideoAndAudio,
+            Some("image") => FileEncoding::Image,
+            _ => FileEncoding::Unknown,
+        }
+    }
+}
+
+impl Display for FileEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FileEncoding::VideoAndAudio => write!(f, "Video and Audio"),
+            FileEncoding::VideoOnly => write!(f, "Video Only"),
+            FileEncoding::AudioOnly => write!(f, "Audio Only"),
+            FileEncoding::Image => write!(f, "Image"),
+            FileEncoding::Storyboard => write!(f, "Storyboard"),
+            FileEncoding::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+static YOUTUBE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"https?://(www\.|music\.|m\.)?(youtube\.com|youtube-nocookie\.com|youtu\.be)/.+")
+        .unwrap()
+});
+static YOUTUBE_MUSIC_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"https?://music\.youtube\.com/.+").unwrap());
+static YOUTUBE_CHANNEL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"https?://(www\.|m\.)?youtube\.com/(@[\w.-]+|channel/[\w-]+|c/[\w.-]+|user/[\w.-]+)(/(videos|shorts|streams))?/?$",
+    )
+    .unwrap()
+});
+static INSTAGRAM_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"https?://(www\.)?instagram\.com/(?:share/)?(p|reel|tv|stories|share)/([A-Za-z0-9_.-]+)(/[\w-]+)?/?")
+        .unwrap()
+});
+static INSTAGRAM_PROFILE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^https?://(www\.)?instagram\.com/([A-Za-z0-9_.]+)/?(\?.*)?$").unwrap()
+});
+
+/// First path segments on instagram.com that aren't usernames
+const INSTAGRAM_RESERVED_PATHS: [&str; 7] =
+    ["p", "reel", "reels", "stories", "tv", "explore", "share"];
+static SHORTENED_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^https?://((www\.)?(t\.co|bit\.ly|tinyurl\.com|goo\.gl|ow\.ly|buff\.ly|is\.gd)/|[^/]+/share/)",
+    )
+    .unwrap()
+});
+static PODCAST_FEED_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^https?://([^/]*\bfeeds?\.[^/]+/.+|.+(\.rss|\.xml|/feed/?|/rss/?))(\?.*)?$")
+        .unwrap()
+});
+
 enum Extractor {
     Instagram(InstagramContentType),
-    Youtube,
+    Youtube(YoutubeContentType),
+    Podcast,
+    Direct,
 }
 
 enum InstagramContentType {
     Story,
     Post,
     Reel,
+    Highlights,
+    Profile,
+    Tv,
+}
+
+enum YoutubeContentType {
+    Video,
+    Channel,
+    Music,
 }
 
 fn get_extractor(url: &str) -> Option<Extractor> {
     if YOUTUBE_REGEX.is_match(url) {
-        Some(Extractor::Youtube)
-    } else if INSTAGRAM_REGEX.is_match(url) {
-        get_instagram_content_type(url).map(|content_type| Extractor::Instagram(content_type))
+        Some(Extractor::Youtube(get_youtube_content_type(url)))
+    } else if INSTAGRAM_REGEX.is_match(url) || INSTAGRAM_PROFILE_REGEX.is_match(url) {
+        get_instagram_content_type(url).map(Extractor::Instagram)
+    } else if PODCAST_FEED_REGEX.is_match(url) {
+        Some(Extractor::Podcast)
+    } else if direct::DIRECT_MEDIA_REGEX.is_match(url) {
+        Some(Extractor::Direct)
     } else {
         None
     }
 }
 
+/// Expands shortened and app share links (t.co, bit.ly, `/share/` links) to
+/// the URL they redirect to, so they reach the right extractor. Other URLs,
+/// and links that fail to resolve, are returned unchanged.
+fn resolve_url(url: &str) -> String {
+    if !SHORTENED_URL_REGEX.is_match(url) {
+        return url.to_string();
+    }
+    http::resolve_redirects(url).unwrap_or_else(|_| url.to_string())
+}
+
+fn get_youtube_content_type(url: &str) -> YoutubeContentType {
+    if YOUTUBE_CHANNEL_REGEX.is_match(url) {
+        YoutubeContentType::Channel
+    } else if YOUTUBE_MUSIC_REGEX.is_match(url) {
+        YoutubeContentType::Music
+    } else {
+        YoutubeContentType::Video
+    }
+}
+
 fn get_instagram_content_type(url: &str) -> Option<InstagramContentType> {
     if let Some(captures) = INSTAGRAM_REGEX.captures(url) {
         match captures.get(2).map(|m| m.as_str()) {
             Some("p") => Some(InstagramContentType::Post),
             Some("reel") => Some(InstagramContentType::Reel),
-            Some("stories") => Some(InstagramContentType::Story),
+            Some("tv") => Some(InstagramContentType::Tv),
+            // Bare share links don't say what they point at; yt-dlp follows them
+            Some("share") => Some(InstagramContentType::Post),
+            Some("stories") => match (captures.get(3).map(|m| m.as_str()), captures.get(4)) {
+                (Some("highlights"), _) => Some(InstagramContentType::Highlights),
+                (_, Some(_)) => Some(InstagramContentType::Story),
+                (_, None) => Some(InstagramContentType::Profile),
+            },
             _ => None,
         }
+    } else if let Some(captures) = INSTAGRAM_PROFILE_REGEX.captures(url) {
+        let username = captures.get(2)?.as_str();
+        (!INSTAGRAM_RESERVED_PATHS.contains(&username)).then_some(InstagramContentType::Profile)
     } else {
         None
     }
 }
 
-fn get_file_formats() -> () {
-    let output = Command::new("yt-dlp")
-        .arg("-q")
-        .args([
-            "-J",
-            "https://www.instagram.com/stories/pretty._wendy/3593101623165471287?igsh=YjJxbnoycDA2ZHNk",
-        ])
-        .stderr(Stdio::null()) // Suppresses unnecessary download messages
-        .output()
-        .expect("Failed to execute yt-dlp");
+/// Profile pages aren't reliably supported by yt-dlp, but the profile's story
+/// reel is, and that is what a profile download means here.
+fn get_instagram_profile_stories_url(url: &str) -> String {
+    match INSTAGRAM_PROFILE_REGEX.captures(url) {
+        Some(captures) => format!("https://www.instagram.com/stories/{}/", &captures[2]),
+        None => url.to_string(),
+    }
+}
 
-    // Check if the command was successful
-    if !output.status.success() {
-        eprintln!("yt-dlp command failed. {output:#?}");
+fn print_thumbnails(file_details: &FileDetails) {
+    if file_details.thumbnails.is_empty() {
+        println!("{}\nThumbnails: None", file_details.title);
         return;
     }
+    println!("{}\nThumbnails:", file_details.title);
+    for thumbnail in &file_details.thumbnails {
+        println!("\t{thumbnail}");
+    }
+}
 
-    // Print the output as a string
-    let result = String::from_utf8_lossy(&output.stdout);
-    let json: Value = serde_json::from_str(&result).unwrap();
-    let file_details: FileDetails = serde_json::from_value(json).unwrap();
-    println!("{}", file_details);
-
-    let mut best_formats = BestFormats::new();
-
-    // for format in file_details.formats {
-    //     match format.file_encoding {
-    //         FileEncoding::VideoAndAudio => {
-    //             let Some(resolution) = format.resolution.clone() else {
-    //                 continue;
-    //             };
-    //             best_formats.video_and_audio.entry(resolution).and_modify(|best_format| {
-    //                 if format.file_size > best_format.file_size {
-    //                     *best_format = format.clone();
-    //                 }
-    //             }).or_insert(format);
-    //         },
-    //         FileEncoding::VideoOnly => {
-    //             let Some(resolution) = format.resolution.clone() else {
-    //                 continue;
-    //             };
-    //             best_formats.video_only.entry(resolution).and_modify(|best_format| {
-    //                 if format.file_size > best_format.file_size {
-    //                     *best_format = format.clone();
-    //                 }
-    //             }).or_insert(format);
-    //         },
-    //         FileEncoding::AudioOnly => {
-    //             if let Some(best_format) = best_formats.audio_only.clone() {
-    //                 if format.file_size > best_format.file_size {
-    //                     best_formats.audio_only = Some(format);
-    //                 }
-    //             }
-    //         },
-    //         _ => continue,
-    //     }
-    // }
+fn print_subtitles(file_details: &FileDetails) {
+    if file_details.subtitles.is_empty() {
+        println!("{}\nSubtitles: None", file_details.title);
+        return;
+    }
+    println!("{}\nSubtitles:", file_details.title);
+    for track in &file_details.subtitles {
+        println!("\t{track}");
+    }
 }
 
+fn print_file_details(file_details: FileDetails, args: &cli::InfoArgs) {
+    if args.list_thumbnails || args.list_subs {
+        if args.list_thumbnails {
+            print_thumbnails(&file_details);
+        }
+        if args.list_subs {
+            print_subtitles(&file_details);
+        }
+        return;
+    }
+    let format_args = &args.formats;
+    let mut file_details = file_details.filter(&FormatFilter::from(format_args));
+    file_details.sort_formats(&format_args.sort_by);
+    if let Some(budget) = &format_args.max_total_size {
+        let preference = FormatPreference::new(format_args);
+        match file_details.select_within(budget, &preference) {
+            Some(selection) => println!("{}\n{selection}", file_details.title),
+            None => println!("{}\nNothing fits in {budget}", file_details.title),
+        }
+    } else if format_args.best {
+        let preference = FormatPreference::new(format_args);
+        println!(
+            "{}\n{}",
+            file_details.title,
+            file_details.best_formats(&preference)
+        );
+        if let Some(selection) = file_details.select(&preference) {
+            println!("Selected:\n{selection}");
+        }
+    } else {
+        println!("{}", file_details);
+    }
+    if !file_details.chapters.is_empty() {
+        println!("Chapters:");
+        for (number, chapter) in file_details.chapters.iter().enumerate() {
+            println!("\t{:>2}. {chapter}", number + 1);
+        }
+    }
+}
+
+/// Key for audio tracks without a language, as in ISO 639-2
+const UNDETERMINED_LANGUAGE: &str = "und";
+
 struct BestFormats {
     video_and_audio: HashMap<Resolution, FileFormat>,
     video_only: HashMap<Resolution, FileFormat>,
     audio_only: Option<FileFormat>,
+    audio_by_language: HashMap<String, FileFormat>,
 }
 
 impl BestFormats {
@@ -432,10 +1713,1790 @@ impl BestFormats {
             video_and_audio: HashMap::new(),
             video_only: HashMap::new(),
             audio_only: None,
+            audio_by_language: HashMap::new(),
+        }
+    }
+
+    /// The highest resolution video stream, preferring ones without audio so
+    /// that audio tracks can be chosen separately
+    fn best_video(&self) -> Option<&FileFormat> {
+        BestFormats::highest_resolution(&self.video_only)
+            .or_else(|| BestFormats::highest_resolution(&self.video_and_audio))
+    }
+
+    fn highest_resolution(formats: &HashMap<Resolution, FileFormat>) -> Option<&FileFormat> {
+        formats
+            .iter()
+            .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, format)| format)
+    }
+
+    fn audio_in_language(&self, language: &str) -> Option<&FileFormat> {
+        self.audio_by_language
+            .values()
+            .find(|format| format.is_in_language(language))
+    }
+
+    fn insert_if_better(
+        formats: &mut HashMap<Resolution, FileFormat>,
+        resolution: Resolution,
+        format: &FileFormat,
+        preference: &FormatPreference,
+    ) {
+        formats
+            .entry(resolution)
+            .and_modify(|best_format| {
+                if format.is_better_than(best_format, preference) {
+                    *best_format = format.clone();
+                }
+            })
+            .or_insert_with(|| format.clone());
+    }
+}
+
+impl FileDetails {
+    /// The largest format of each resolution for video, and overall for audio
+    fn best_formats(&self, preference: &FormatPreference) -> BestFormats {
+        let mut best_formats = BestFormats::new();
+
+        for format in self.formats.iter().filter(|format| format.is_selectable()) {
+            match format.file_encoding {
+                FileEncoding::VideoAndAudio => {
+                    let Some(resolution) = format.resolution.clone() else {
+                        continue;
+                    };
+                    BestFormats::insert_if_better(
+                        &mut best_formats.video_and_audio,
+                        resolution,
+                        format,
+                        preference,
+                    );
+                }
+                FileEncoding::VideoOnly => {
+                    let Some(resolution) = format.resolution.clone() else {
+                        continue;
+                    };
+                    BestFormats::insert_if_better(
+                        &mut best_formats.video_only,
+                        resolution,
+                        format,
+                        preference,
+                    );
+                }
+                FileEncoding::AudioOnly => {
+                    match &best_formats.audio_only {
+                        Some(best_format) if !format.is_better_than(best_format, preference) => {}
+                        _ => best_formats.audio_only = Some(format.clone()),
+                    }
+                    let language = format
+                        .language
+                        .clone()
+                        .unwrap_or_else(|| UNDETERMINED_LANGUAGE.to_string());
+                    best_formats
+                        .audio_by_language
+                        .entry(language)
+                        .and_modify(|best_format| {
+                            if format.is_better_than(best_format, preference) {
+                                *best_format = format.clone();
+                            }
+                        })
+                        .or_insert_with(|| format.clone());
+                }
+                _ => continue,
+            }
+        }
+
+        best_formats
+    }
+}
+
+/// What to download for an item: one format, or a video stream plus a
+/// separate audio stream to merge into it
+#[derive(Debug, Clone)]
+enum FormatSelection {
+    Single(FileFormat),
+    // Audio is boxed to keep the variants a similar size
+    Pair(FileFormat, Box<FileFormat>),
+}
+
+impl FormatSelection {
+    /// The format spec yt-dlp takes with `-f`
+    fn spec(&self) -> String {
+        match self {
+            FormatSelection::Single(format) => format.id.clone(),
+            FormatSelection::Pair(video, audio) => format!("{}+{}", video.id, audio.id),
+        }
+    }
+
+    /// The format that decides the quality, i.e. the video of a pair
+    fn primary(&self) -> &FileFormat {
+        match self {
+            FormatSelection::Single(format) | FormatSelection::Pair(format, _) => format,
+        }
+    }
+
+    /// Container to merge a pair into, chosen by what its codecs fit in
+    fn merge_format(&self) -> Option<&'static str> {
+        match self {
+            FormatSelection::Pair(video, audio) => Some(merge::container(video, audio)),
+            FormatSelection::Single(_) => None,
+        }
+    }
+
+    /// The codecs of the video and audio the download ends up with
+    fn codecs(&self) -> (&str, &str) {
+        match self {
+            FormatSelection::Single(format) => (&format.vcodec, &format.acodec),
+            FormatSelection::Pair(video, audio) => (&video.vcodec, &audio.acodec),
+        }
+    }
+
+    fn formats(&self) -> Vec<&FileFormat> {
+        match self {
+            FormatSelection::Single(format) => vec![format],
+            FormatSelection::Pair(video, audio) => vec![video, audio],
+        }
+    }
+
+    /// Combined size of all streams, estimated if any of them is
+    fn total_size(&self) -> Option<FileSize> {
+        match self {
+            FormatSelection::Single(format) => format.file_size.clone(),
+            FormatSelection::Pair(video, audio) => {
+                let (video, audio) = (video.file_size.as_ref()?, audio.file_size.as_ref()?);
+                Some(FileSize {
+                    bytes: video.bytes + audio.bytes,
+                    estimated: video.estimated || audio.estimated,
+                })
+            }
+        }
+    }
+}
+
+impl Display for FormatSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FormatSelection::Single(format) => write!(f, "\t{format}")?,
+            FormatSelection::Pair(video, audio) => write!(f, "\t{video}\n\t+ {audio}")?,
+        }
+        match self.total_size() {
+            Some(total_size) => write!(f, "\nTotal size: {total_size}"),
+            None => write!(f, "\nTotal size: None"),
+        }
+    }
+}
+
+impl FileDetails {
+    /// The best video with audio: a muxed format at the highest resolution
+    /// when there is one, and otherwise the best video-only format there
+    /// paired with the best audio that merges into the same container. Items
+    /// without video get the best audio alone, or failing that the largest
+    /// image.
+    fn select(&self, preference: &FormatPreference) -> Option<FormatSelection> {
+        let best_formats = self.best_formats(preference);
+        let Some(resolution) = best_formats
+            .video_and_audio
+            .keys()
+            .chain(best_formats.video_only.keys())
+            .max_by(|a, b| a.partial_cmp(b).unwrap())
+        else {
+            return best_formats
+                .audio_only
+                .or_else(|| self.best_image().cloned())
+                .map(FormatSelection::Single);
+        };
+
+        if let Some(format) = best_formats.video_and_audio.get(resolution) {
+            return Some(FormatSelection::Single(format.clone()));
+        }
+        let video = best_formats.video_only.get(resolution)?;
+        match self.best_audio_for(video, preference) {
+            Some(audio) => Some(FormatSelection::Pair(
+                video.clone(),
+                Box::new(audio.clone()),
+            )),
+            None => Some(FormatSelection::Single(video.clone())),
+        }
+    }
+
+    /// The best audio to extract: an audio-only format when there is one,
+    /// and otherwise the muxed format with the best audio, the smallest of
+    /// those
+    fn select_audio(&self, preference: &FormatPreference) -> Option<FormatSelection> {
+        let best_formats = self.best_formats(preference);
+        best_formats
+            .audio_only
+            .or_else(|| {
+                best_formats
+                    .video_and_audio
+                    .into_iter()
+                    .max_by(|(a_resolution, a), (b_resolution, b)| {
+                        let abr = |format: &FileFormat| format.abr.unwrap_or_default();
+                        abr(a)
+                            .total_cmp(&abr(b))
+                            .then_with(|| b_resolution.partial_cmp(a_resolution).unwrap())
+                    })
+                    .map(|(_, format)| format)
+            })
+            .map(FormatSelection::Single)
+    }
+
+    /// What to try when `selected` fails, best first: the selection `select`
+    /// would make at its resolution and each lower one, muxed or as a pair,
+    /// leaving out `selected` itself and anything over `budget`
+    fn fallbacks(
+        &self,
+        selected: &FormatSelection,
+        preference: &FormatPreference,
+        budget: Option<&FileSize>,
+    ) -> Vec<FormatSelection> {
+        let Some(current) = &selected.primary().resolution else {
+            return vec![];
+        };
+        let best_formats = self.best_formats(preference);
+        let mut resolutions: Vec<&Resolution> = best_formats
+            .video_and_audio
+            .keys()
+            .chain(best_formats.video_only.keys())
+            .filter(|resolution| *resolution <= current)
+            .collect();
+        resolutions.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        resolutions.dedup();
+
+        let mut fallbacks = vec![];
+        for resolution in resolutions {
+            if let Some(format) = best_formats.video_and_audio.get(resolution) {
+                fallbacks.push(FormatSelection::Single(format.clone()));
+            }
+            if let Some(video) = best_formats.video_only.get(resolution) {
+                fallbacks.push(match self.best_audio_for(video, preference) {
+                    Some(audio) => FormatSelection::Pair(video.clone(), Box::new(audio.clone())),
+                    None => FormatSelection::Single(video.clone()),
+                });
+            }
+        }
+        fallbacks.retain(|fallback| {
+            fallback.spec() != selected.spec()
+                && budget.is_none_or(|budget| {
+                    fallback
+                        .total_size()
+                        .is_some_and(|size| size.bytes <= budget.bytes)
+                })
+        });
+        fallbacks
+    }
+
+    /// The largest image, for photo posts
+    fn best_image(&self) -> Option<&FileFormat> {
+        self.formats
+            .iter()
+            .filter(|format| format.file_encoding == FileEncoding::Image && format.is_selectable())
+            .max_by_key(|format| {
+                format
+                    .width
+                    .zip(format.height)
+                    .map(|(width, height)| u32::from(width) * u32::from(height))
+            })
+    }
+
+    /// The best audio to pair with `video`, favouring tracks that share its
+    /// container so no remuxing to Matroska is needed
+    fn best_audio_for(
+        &self,
+        video: &FileFormat,
+        preference: &FormatPreference,
+    ) -> Option<&FileFormat> {
+        self.formats
+            .iter()
+            .filter(|format| {
+                format.file_encoding == FileEncoding::AudioOnly && format.is_selectable()
+            })
+            .max_by(|a, b| {
+                video
+                    .is_compatible_with(a)
+                    .cmp(&video.is_compatible_with(b))
+                    .then_with(|| preference.compare_quality(a, b))
+            })
+    }
+
+    /// The best format, or video-only format paired with the best audio that
+    /// still fits, whose combined size is within `budget`. Formats of unknown
+    /// size are passed over since they can't be shown to fit.
+    fn select_within(
+        &self,
+        budget: &FileSize,
+        preference: &FormatPreference,
+    ) -> Option<FormatSelection> {
+        let fits = |selection: &FormatSelection| {
+            selection
+                .total_size()
+                .is_some_and(|size| size.bytes <= budget.bytes)
+        };
+        let formats: Vec<&FileFormat> = self
+            .formats
+            .iter()
+            .filter(|format| format.is_selectable())
+            .collect();
+        let mut audio_formats: Vec<&FileFormat> = formats
+            .iter()
+            .copied()
+            .filter(|format| format.file_encoding == FileEncoding::AudioOnly)
+            .collect();
+        // Best first, so the first audio that fits is the one to pair
+        audio_formats.sort_by(|a, b| preference.compare_quality(b, a));
+
+        formats
+            .iter()
+            .filter_map(|format| match format.file_encoding {
+                FileEncoding::VideoAndAudio | FileEncoding::AudioOnly => {
+                    Some(FormatSelection::Single((*format).clone()))
+                }
+                FileEncoding::VideoOnly => audio_formats
+                    .iter()
+                    .map(|audio| {
+                        FormatSelection::Pair((*format).clone(), Box::new((*audio).clone()))
+                    })
+                    .find(fits),
+                _ => None,
+            })
+            .filter(fits)
+            .max_by(|a, b| preference.compare_quality(a.primary(), b.primary()))
+    }
+}
+
+impl Display for BestFormats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sections = [
+            ("Video and Audio", &self.video_and_audio),
+            ("Video Only", &self.video_only),
+        ];
+        for (name, formats) in sections {
+            let mut formats = formats.iter().collect::<Vec<_>>();
+            // Highest resolution first
+            formats.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap());
+            writeln!(f, "{name}:")?;
+            for (resolution, format) in formats {
+                writeln!(f, "\t{resolution}: {format}")?;
+            }
+        }
+        if self.audio_by_language.len() > 1 {
+            let mut languages = self.audio_by_language.iter().collect::<Vec<_>>();
+            languages.sort_by_key(|(language, _)| *language);
+            writeln!(f, "Audio by Language:")?;
+            for (language, format) in languages {
+                writeln!(f, "\t{language}: {format}")?;
+            }
+        }
+        match &self.audio_only {
+            Some(format) => write!(f, "Audio Only:\n\t{format}"),
+            None => write!(f, "Audio Only: None"),
+        }
+    }
+}
+
+fn info(args: cli::InfoArgs) -> Result<(), AppError> {
+    match get_extractor(&args.url) {
+        Some(Extractor::Youtube(YoutubeContentType::Channel)) => {
+            for entry in channel::fetch_uploads(&args.url, &args.listing)? {
+                print_file_details(entry.file_details, &args);
+            }
+        }
+        Some(
+            Extractor::Youtube(YoutubeContentType::Video | YoutubeContentType::Music)
+            | Extractor::Instagram(
+                InstagramContentType::Post
+                | InstagramContentType::Reel
+                | InstagramContentType::Tv
+                | InstagramContentType::Story
+                | InstagramContentType::Highlights,
+            ),
+        ) => print_entries(&args.url, &args)?,
+        Some(Extractor::Instagram(InstagramContentType::Profile)) => {
+            print_entries(&get_instagram_profile_stories_url(&args.url), &args)?
+        }
+        Some(Extractor::Podcast) => {
+            let mut episodes = podcast::fetch_episodes(&args.url)?;
+            if let Some(latest) = args.listing.latest {
+                episodes.truncate(latest);
+            }
+            for episode in episodes {
+                if !episode.file_details.matches_duration(&args.listing) {
+                    continue;
+                }
+                if let Some(published) = &episode.published {
+                    println!("Published {published}");
+                }
+                print_file_details(episode.file_details, &args);
+            }
+        }
+        Some(Extractor::Direct) => {
+            print_file_details(direct::probe(&args.url)?.file_details, &args)
+        }
+        None => match direct::probe(&args.url) {
+            Ok(media) => print_file_details(media.file_details, &args),
+            Err(_) => return Err(AppError::UnsupportedUrl(args.url)),
+        },
+    }
+    Ok(())
+}
+
+fn download(mut args: cli::DownloadArgs) -> Result<(), AppError> {
+    let queued = if args.from_queue {
+        // Jobs paused before they started wait for a later run
+        let jobs: Vec<queue::Job> = queue::Queue::load()?
+            .pending()
+            .into_iter()
+            .filter(|job| !job.paused)
+            .collect();
+        if jobs.is_empty() {
+            println!("Nothing is queued");
+            return Ok(());
+        }
+        args.urls = jobs.iter().map(|job| job.url.clone()).collect();
+        Some(jobs)
+    } else {
+        None
+    };
+    start_run(&args)?;
+
+    let total = args.urls.len();
+    let observer = observers(
+        TerminalObserver {
+            live_progress: total == 1 || args.jobs <= 1,
+        },
+        &args,
+    );
+    // Jobs are told apart by their place in the run, as the same URL may
+    // be queued more than once
+    let run_job = |index: &usize| {
+        let url = &args.urls[*index];
+        let job = queued.as_ref().map(|jobs| &jobs[*index]);
+        // Queued jobs may be held back until a set time
+        if let Some(start) = job.and_then(|job| job.start_at) {
+            schedule::wait_until_timestamp(start, url);
+        }
+        if let Some(window) = &args.schedule {
+            schedule::wait_for(window);
+        }
+        interrupt::check()?;
+        match job {
+            Some(job) => {
+                queue::Queue::start(&[job.id])?;
+                download::as_job(job.id, || download_url(url, &args, &observer))
+            }
+            None => download_url(url, &args, &observer),
+        }
+    };
+    let done = AtomicBool::new(false);
+    let results = thread::scope(|scope| {
+        if let Some(window) = &args.schedule {
+            scope.spawn(|| schedule::hold_outside(window, &done));
+        }
+        if let Some(jobs) = &queued {
+            scope.spawn(|| {
+                queue::watch_pauses(jobs, &done, |job| {
+                    download::pause_for(job.id).set(job.paused);
+                    let event = if job.paused {
+                        DownloadEvent::Paused
+                    } else {
+                        DownloadEvent::Resumed
+                    };
+                    observer.on_event(&job.url, &event);
+                })
+            });
+        }
+        let results = if total == 1 {
+            vec![run_job(&0)]
+        } else {
+            let indices: Vec<usize> = (0..total).collect();
+            scheduler::run(&indices, args.jobs, run_job, |index, status| {
+                if *status != scheduler::JobStatus::Queued {
+                    eprintln!(
+                        "\r\x1b[2K[{}/{total}] {status}: {}",
+                        index + 1,
+                        args.urls[index]
+                    );
+                }
+            })
+        };
+        done.store(true, atomic::Ordering::Relaxed);
+        results
+    });
+
+    let settled: Vec<(&str, &Result<_, AppError>)> =
+        args.urls.iter().map(String::as_str).zip(&results).collect();
+    // Like the history, the failed list is kept when it can be
+    let recorded = match failed::settle(&settled) {
+        Ok(()) => true,
+        Err(error) => {
+            eprintln!(
+                "Failed downloads won't be kept in the failed list, and stay queued: {error}"
+            );
+            false
+        }
+    };
+    // Failed jobs move to the failed list, and interrupted ones, or failed
+    // ones the list couldn't take, stay queued for the next run
+    if let Some(jobs) = queued {
+        let done: Vec<u64> = jobs
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| match result {
+                Ok(_) => true,
+                Err(AppError::Interrupted) => false,
+                Err(_) => recorded,
+            })
+            .map(|(job, _)| job.id)
+            .collect();
+        queue::Queue::complete(&done)?;
+    }
+
+    if total == 1 {
+        return results.into_iter().next().unwrap().map(|_| ());
+    }
+    println!("Summary:");
+    let mut failed = 0;
+    for (url, result) in args.urls.iter().zip(&results) {
+        match result {
+            Ok(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                println!("\tDone {url}: {}", paths.join(", "));
+            }
+            Err(error) => {
+                failed += 1;
+                println!("\tFailed {url}: {error}");
+            }
         }
     }
+    interrupt::check()?;
+    if failed > 0 {
+        return Err(AppError::BatchFailed { failed, total });
+    }
+    Ok(())
+}
+
+/// Sets up what every job of a run shares
+fn start_run(args: &cli::DownloadArgs) -> Result<(), AppError> {
+    if let Some(rate) = &args.limit_rate {
+        download::limit_rate(rate.bytes);
+    }
+    if let Some(path) = &args.download_archive {
+        archive::open(path)?;
+    }
+    if args.restrict_filenames {
+        filename::restrict();
+    }
+    Ok(())
+}
+
+/// Downloads one URL, returning where its files were saved and reporting
+/// what happens to `observer`
+fn download_url(
+    url: &str,
+    args: &cli::DownloadArgs,
+    observer: &dyn DownloadObserver,
+) -> Result<Vec<PathBuf>, AppError> {
+    observer.on_event(url, &DownloadEvent::Started);
+    let result = match get_extractor(url) {
+        Some(Extractor::Direct) | None => {
+            download_direct(url, args, observer).map(|path| path.into_iter().collect())
+        }
+        Some(_) if args.multi_audio => {
+            download_multi_audio(url, args, observer).map(|path| path.into_iter().collect())
+        }
+        Some(Extractor::Instagram(InstagramContentType::Profile)) => {
+            download_selected(&get_instagram_profile_stories_url(url), args, observer)
+        }
+        Some(Extractor::Podcast) => Err(AppError::UnsupportedUrl(format!(
+            "{url} (use the podcast command for feeds)"
+        ))),
+        Some(_) => download_selected(url, args, observer),
+    };
+    if let Err(error) = &result {
+        observer.on_event(
+            url,
+            &DownloadEvent::Failed {
+                error: error.to_string(),
+            },
+        );
+    }
+    result
+}
+
+/// Downloads a URL that serves a media file itself, returning where it was
+/// saved, or `None` when an existing file was kept
+fn download_direct(
+    url: &str,
+    args: &cli::DownloadArgs,
+    observer: &dyn DownloadObserver,
+) -> Result<Option<PathBuf>, AppError> {
+    // Unrecognized URLs may still serve media directly, which the probe's
+    // Content-Type check decides
+    let media = direct::probe(url).map_err(|_| AppError::UnsupportedUrl(url.to_string()))?;
+    if is_duplicate(url, args, observer, &media.file_details) {
+        return Ok(None);
+    }
+    if let Some(budget) = &args.formats.max_total_size {
+        let preference = FormatPreference::new(&args.formats);
+        if media
+            .file_details
+            .select_within(budget, &preference)
+            .is_none()
+        {
+            return Err(AppError::NoMatchingFormat(format!(
+                "{url} doesn't fit in {budget}"
+            )));
+        }
+    }
+    let selection = media
+        .file_details
+        .formats
+        .iter()
+        .map(|format| FormatSelection::Single(format.clone()).to_string())
+        .collect();
+    observer.on_event(
+        url,
+        &DownloadEvent::MetadataFetched {
+            title: media.file_details.title.clone(),
+            extractor: media.file_details.extractor_key.clone(),
+            id: media.file_details.id.clone(),
+            uploader: media.file_details.uploader_name(),
+            upload_date: media.file_details.upload_date.clone(),
+            format: media
+                .file_details
+                .formats
+                .iter()
+                .map(|format| format.id.as_str())
+                .collect::<Vec<_>>()
+                .join("+"),
+            selection,
+        },
+    );
+    let path = Path::new(&args.output_dir).join(media.file_name());
+    let Some(path) = conflict::resolve(&path, args.on_conflict) else {
+        observer.on_event(url, &DownloadEvent::Skipped { path });
+        return Ok(None);
+    };
+    let formats: Vec<&FileFormat> = media.file_details.formats.iter().collect();
+    check_space(args, &formats)?;
+
+    direct::download(&media, &path, &fetch_options(args), &mut |progress| {
+        observer.on_event(url, &DownloadEvent::Progress(progress.clone()))
+    })?;
+    finish(url, args, observer, &media.file_details, None, &path)?;
+    Ok(Some(path))
+}
+
+fn download_options<'a>(
+    args: &'a cli::DownloadArgs,
+    playlist_item: Option<usize>,
+) -> ytdlp::DownloadOptions<'a> {
+    ytdlp::DownloadOptions {
+        output_dir: &args.output_dir,
+        template: &args.template,
+        playlist_item,
+        rate_limit: job_rate_limit(args),
+        downloader: args.downloader.unwrap_or_default(),
+        connections: args.connections,
+        pause: download::current_pause(),
+        on_conflict: args.on_conflict,
+        section: None,
+    }
+}
+
+fn fetch_options(args: &cli::DownloadArgs) -> download::FetchOptions {
+    download::FetchOptions {
+        connections: args.connections,
+        rate_limit: job_rate_limit(args),
+        downloader: args.downloader.unwrap_or_default(),
+        pause: download::current_pause(),
+        skip_unavailable_fragments: args.skip_unavailable_fragments,
+    }
+}
+
+/// `--limit-rate` for a single job. External programs can't share the
+/// native downloader's limiter, so concurrent ones split the cap evenly.
+fn job_rate_limit(args: &cli::DownloadArgs) -> Option<u64> {
+    args.limit_rate.as_ref().map(|rate| {
+        // The daemon starts without URLs and may run `--jobs` at once
+        let concurrent = match args.urls.len() {
+            0 => args.jobs,
+            urls => args.jobs.min(urls),
+        };
+        rate.bytes / concurrent.max(1) as u64
+    })
+}
+
+/// The URL and output path for fetching `selection` over plain HTTP(S)
+/// instead of through yt-dlp, which avoids a second yt-dlp run per file.
+/// Only single formats served as ordinary files qualify, and only when the
+/// file name template is simple enough to render without yt-dlp.
+fn native_download_path<'a>(
+    args: &cli::DownloadArgs,
+    file_details: &FileDetails,
+    selection: &'a FormatSelection,
+) -> Option<(&'a str, PathBuf)> {
+    let FormatSelection::Single(format) = selection else {
+        return None;
+    };
+    Some((
+        native_url(format)?,
+        output_path(args, file_details, selection)?,
+    ))
+}
+
+/// The URL the native engine fetches `format` from, if it can
+fn native_url(format: &FileFormat) -> Option<&str> {
+    if !format.protocol.as_ref().is_some_and(Protocol::is_native) {
+        return None;
+    }
+    // yt-dlp's URL for DASH formats is the manifest when it has one, but
+    // only `manifest_url` is sure to be
+    match format.protocol {
+        Some(Protocol::Dash) => format.manifest_url.as_deref().or(format.url.as_deref()),
+        _ => format.url.as_deref(),
+    }
+}
+
+/// Where `selection` is saved, when the file name template is simple enough
+/// to render without yt-dlp
+fn output_path(
+    args: &cli::DownloadArgs,
+    file_details: &FileDetails,
+    selection: &FormatSelection,
+) -> Option<PathBuf> {
+    let format_id = selection.spec();
+    let extension = selection
+        .merge_format()
+        .unwrap_or(&selection.primary().extension);
+    let fields = [
+        ("id", Some(file_details.id.as_str())),
+        ("title", Some(file_details.title.as_str())),
+        ("ext", Some(extension)),
+        ("uploader", file_details.uploader.as_deref()),
+        ("channel", file_details.channel.as_deref()),
+        ("upload_date", file_details.upload_date.as_deref()),
+        ("extractor", Some(file_details.extractor.as_str())),
+        ("format_id", Some(format_id.as_str())),
+    ];
+    let file_name = filename::render_template(&args.template, &fields)?;
+    Some(Path::new(&args.output_dir).join(file_name))
+}
+
+/// Renders download events on the terminal.
+struct TerminalObserver {
+    // Concurrent downloads would garble a live progress line, so they share
+    // one for their combined progress and otherwise only report their status
+    live_progress: bool,
 }
 
-fn main() {
-    get_file_formats();
+impl DownloadObserver for TerminalObserver {
+    fn on_event(&self, url: &str, event: &DownloadEvent) {
+        if !self.live_progress && !matches!(event, DownloadEvent::Progress(_)) {
+            // Clears the combined progress line before anything else
+            eprint!("\r\x1b[2K");
+        }
+        match event {
+            DownloadEvent::MetadataFetched {
+                title, selection, ..
+            } => {
+                println!("Downloading {title}\n{selection}")
+            }
+            DownloadEvent::Progress(progress) if self.live_progress => {
+                eprint!("\r\x1b[2K{}", progress_line(progress))
+            }
+            DownloadEvent::Progress(_) => {
+                let (jobs, progress) = speed::overall();
+                eprint!("\r\x1b[2K{jobs} downloading: {}", progress_line(&progress));
+            }
+            DownloadEvent::FallingBack {
+                failed,
+                error,
+                selection,
+                ..
+            } => {
+                // Clears any progress line first
+                eprintln!("\r\x1b[2K{url}: format {failed} failed: {error}");
+                println!("Falling back to\n{selection}");
+            }
+            DownloadEvent::DownloadingSection { section } if self.live_progress => {
+                eprint!("\r\x1b[2KDownloading {section}")
+            }
+            DownloadEvent::Merging if self.live_progress => eprint!("\r\x1b[2KMerging formats"),
+            DownloadEvent::ExtractingAudio if self.live_progress => {
+                eprint!("\r\x1b[2KExtracting audio")
+            }
+            DownloadEvent::NormalizingAudio if self.live_progress => {
+                eprint!("\r\x1b[2KNormalizing loudness")
+            }
+            DownloadEvent::Embedding { what } if self.live_progress => {
+                eprint!("\r\x1b[2KEmbedding {what}")
+            }
+            DownloadEvent::RemovingSegments { count } if self.live_progress => {
+                eprint!("\r\x1b[2KRemoving {count} SponsorBlock segment(s)")
+            }
+            DownloadEvent::Remuxing { container } if self.live_progress => {
+                eprint!("\r\x1b[2KRemuxing into {container}")
+            }
+            DownloadEvent::Transcoding {
+                container,
+                fraction,
+            } if self.live_progress => match fraction {
+                Some(fraction) => eprint!(
+                    "\r\x1b[2KTranscoding into {container}: {:5.1}%",
+                    fraction * 100f64
+                ),
+                None => eprint!("\r\x1b[2KTranscoding into {container}"),
+            },
+            DownloadEvent::Paused if self.live_progress => eprint!("\r\x1b[2KPaused"),
+            DownloadEvent::Paused => eprintln!("Paused {url}"),
+            DownloadEvent::Resumed if !self.live_progress => eprintln!("Resumed {url}"),
+            DownloadEvent::Completed { path, .. } => {
+                if self.live_progress {
+                    eprintln!();
+                }
+                println!("Saved {}", path.display());
+            }
+            DownloadEvent::Skipped { path } => {
+                println!("Skipped {}, which already exists", path.display())
+            }
+            DownloadEvent::Archived { title } => {
+                println!("Skipped {title}, which is in the download archive")
+            }
+            DownloadEvent::Duplicate {
+                title,
+                previous,
+                path,
+            } => match path {
+                Some(path) => println!(
+                    "Skipped {title}, downloaded before as {} (history {previous})",
+                    path.display()
+                ),
+                None => println!("Skipped {title}, downloaded before (history {previous})"),
+            },
+            // Clears any progress line before the error is reported
+            DownloadEvent::Failed { .. } if self.live_progress => eprint!("\r\x1b[2K"),
+            DownloadEvent::Failed { error } => eprintln!("{url}: {error}"),
+            _ => {}
+        }
+    }
+}
+
+/// The observers every download of a run reports to: speeds are measured
+/// for the terminal, and items are then recorded in the history and passed
+/// to the user's hooks
+fn observers(terminal: TerminalObserver, args: &cli::DownloadArgs) -> impl DownloadObserver + '_ {
+    let tracked = speed::Tracked(terminal);
+    hooks::Hooked::new(history::Recorded::new(tracked, &args.tags), &args.hooks)
+}
+
+/// A one-line progress report
+fn progress_line(progress: &download::Progress) -> String {
+    let downloaded = FileSize::new(progress.downloaded_bytes as f64);
+    let mut line = match (progress.fraction(), progress.total_bytes) {
+        (Some(fraction), Some(total)) => format!(
+            "{:5.1}% of {}",
+            fraction * 100f64,
+            FileSize::new(total as f64)
+        ),
+        _ => downloaded.to_string(),
+    };
+    if let Some(speed) = progress.speed {
+        line.push_str(&format!(" at {}/s", FileSize::new(speed)));
+    }
+    if let Some(average) = progress.average_speed {
+        line.push_str(&format!(" (average {}/s)", FileSize::new(average)));
+    }
+    if let Some(eta) = progress.eta {
+        line.push_str(&format!(" ETA {}", format_duration(eta)));
+    }
+    line
+}
+
+/// Downloads the best video with the best audio track for each of
+/// `--audio-lang`, muxed into a single file. Returns `None` when an
+/// existing file was kept.
+fn download_multi_audio(
+    url: &str,
+    args: &cli::DownloadArgs,
+    observer: &dyn DownloadObserver,
+) -> Result<Option<PathBuf>, AppError> {
+    if args.formats.audio_langs.is_empty() {
+        return Err(AppError::NoMatchingFormat(
+            "--multi-audio needs --audio-lang".to_string(),
+        ));
+    }
+
+    let entries = playlist::fetch_entries(url, &[])?;
+    let Some(entry) = entries.into_iter().next() else {
+        return Err(AppError::MissingField("entries"));
+    };
+    if archive::contains(&entry.file_details) {
+        observer.on_event(
+            url,
+            &DownloadEvent::Archived {
+                title: entry.file_details.title,
+            },
+        );
+        return Ok(None);
+    }
+    if is_duplicate(url, args, observer, &entry.file_details) {
+        return Ok(None);
+    }
+    entry.file_details.ensure_not_drm_only(url)?;
+    let json = entry.json;
+    let file_details = entry
+        .file_details
+        .filter(&FormatFilter::from(&args.formats));
+    let best_formats = file_details.best_formats(&FormatPreference::new(&args.formats));
+
+    let video = best_formats
+        .best_video()
+        .ok_or_else(|| AppError::NoMatchingFormat("no video format".to_string()))?;
+    let mut format_ids = vec![video.id.as_str()];
+    let mut audios = vec![];
+    for language in &args.formats.audio_langs {
+        let audio = best_formats.audio_in_language(language).ok_or_else(|| {
+            AppError::NoMatchingFormat(format!("no audio track in language `{language}`"))
+        })?;
+        format_ids.push(audio.id.as_str());
+        audios.push(audio);
+    }
+    observer.on_event(
+        url,
+        &DownloadEvent::MetadataFetched {
+            title: file_details.title.clone(),
+            extractor: file_details.extractor_key.clone(),
+            id: file_details.id.clone(),
+            uploader: file_details.uploader_name(),
+            upload_date: file_details.upload_date.clone(),
+            format: format_ids.join("+"),
+            selection: format_ids.join("+"),
+        },
+    );
+    check_space(args, &[&[video], audios.as_slice()].concat())?;
+
+    let Some(path) = ytdlp::download_multi_audio(
+        url,
+        &format_ids.join("+"),
+        &download_options(args, None),
+        &mut |event| observer.on_event(url, event),
+    )?
+    else {
+        archive::record(&file_details)?;
+        return Ok(None);
+    };
+    finish(url, args, observer, &file_details, Some(&json), &path)?;
+    archive::record(&file_details)?;
+    Ok(Some(path))
+}
+
+/// The items behind `url` that `listing` keeps: a channel's uploads,
+/// listed flat so only the ones wanted are fetched, or the chosen `items`
+/// of anything else, like slides of a carousel
+fn listed_entries(
+    url: &str,
+    listing: &cli::ListingArgs,
+) -> Result<Vec<playlist::PlaylistEntry>, AppError> {
+    if let Some(Extractor::Youtube(YoutubeContentType::Channel)) = get_extractor(url) {
+        return channel::fetch_uploads(url, listing);
+    }
+    let mut entries = playlist::fetch_entries(url, &listing.items)?;
+    entries.retain(|entry| entry.file_details.matches_duration(listing));
+    Ok(entries)
+}
+
+/// Whether `args` ask for something of the video, like a resolution or a
+/// recode, which keeps music from being downloaded as audio only
+fn wants_video(args: &cli::DownloadArgs) -> bool {
+    let formats = &args.formats;
+    formats.max_total_size.is_some()
+        || formats.min_resolution.is_some()
+        || formats.max_resolution.is_some()
+        || formats.min_fps.is_some()
+        || args.recode_video.is_some()
+        || args.burn_subs.is_some()
+        || args.fit.is_some()
+        || args.to_gif
+}
+
+/// Downloads the best format, or video and audio pair, of each item behind
+/// `url`, or the best that fits in `--max-total-size` when given, and returns
+/// where they were saved. A selection that keeps failing is replaced by the
+/// next best, up to `--max-fallbacks` times. YouTube Music is downloaded as
+/// audio, as with `--extract-audio`, unless the video is asked for.
+fn download_selected(
+    url: &str,
+    args: &cli::DownloadArgs,
+    observer: &dyn DownloadObserver,
+) -> Result<Vec<PathBuf>, AppError> {
+    let is_music = matches!(
+        get_extractor(url),
+        Some(Extractor::Youtube(YoutubeContentType::Music))
+    );
+    let music_args =
+        (is_music && !args.extract_audio && !wants_video(args)).then(|| cli::DownloadArgs {
+            extract_audio: true,
+            ..args.clone()
+        });
+    let args = music_args.as_ref().unwrap_or(args);
+    let entries = listed_entries(url, &args.listing)?;
+    // Chosen items keep their numbers, even when only one is left
+    let is_playlist = entries.len() > 1 || !args.listing.items.is_empty();
+    let filter = FormatFilter::from(&args.formats);
+    let preference = FormatPreference::new(&args.formats);
+
+    let mut paths = vec![];
+    for entry in entries {
+        if archive::contains(&entry.file_details) {
+            observer.on_event(
+                url,
+                &DownloadEvent::Archived {
+                    title: entry.file_details.title,
+                },
+            );
+            continue;
+        }
+        if is_duplicate(url, args, observer, &entry.file_details) {
+            continue;
+        }
+        entry.file_details.ensure_not_drm_only(url)?;
+        let mut file_details = entry.file_details.filter(&filter);
+        let selection = match &args.formats.max_total_size {
+            _ if args.extract_audio => file_details.select_audio(&preference).ok_or_else(|| {
+                AppError::NoMatchingFormat(format!("no audio to extract for {url}"))
+            })?,
+            Some(budget) => file_details
+                .select_within(budget, &preference)
+                .ok_or_else(|| AppError::NoMatchingFormat(format!("nothing fits in {budget}")))?,
+            None => file_details.select(&preference).ok_or_else(|| {
+                AppError::NoMatchingFormat(format!("nothing to download for {url}"))
+            })?,
+        };
+
+        observer.on_event(
+            url,
+            &DownloadEvent::MetadataFetched {
+                title: file_details.title.clone(),
+                extractor: file_details.extractor_key.clone(),
+                id: file_details.id.clone(),
+                uploader: file_details.uploader_name(),
+                upload_date: file_details.upload_date.clone(),
+                format: selection.spec(),
+                selection: selection.to_string(),
+            },
+        );
+
+        let playlist_item = is_playlist.then_some(entry.index);
+        let sections: Vec<Option<Section>> = match args.sections.as_slice() {
+            [] => vec![None],
+            sections => sections.iter().copied().map(Some).collect(),
+        };
+        for section in sections {
+            // Each section is saved to a file of its own
+            let section_args = section.map(|section| cli::DownloadArgs {
+                template: section.template(&args.template),
+                ..args.clone()
+            });
+            let args = section_args.as_ref().unwrap_or(args);
+            let mut fallbacks = file_details
+                .fallbacks(
+                    &selection,
+                    &preference,
+                    args.formats.max_total_size.as_ref(),
+                )
+                .into_iter()
+                .take(args.max_fallbacks);
+            let mut selection = selection.clone();
+            let downloaded = loop {
+                match download_selection(
+                    url,
+                    args,
+                    &file_details,
+                    &selection,
+                    playlist_item,
+                    section.as_ref(),
+                    observer,
+                )
+                .and_then(|downloaded| {
+                    verify_download(
+                        args,
+                        &file_details,
+                        &selection,
+                        section.as_ref(),
+                        downloaded,
+                    )
+                }) {
+                    Err(error) if error.is_format_failure() => {
+                        let Some(fallback) = fallbacks.next() else {
+                            break Err(error);
+                        };
+                        observer.on_event(
+                            url,
+                            &DownloadEvent::FallingBack {
+                                failed: selection.spec(),
+                                error: error.to_string(),
+                                format: fallback.spec(),
+                                selection: fallback.to_string(),
+                            },
+                        );
+                        selection = fallback;
+                    }
+                    result => break result,
+                }
+            }?;
+            if let Some(section) = section {
+                file_details.duration = Some(section.duration());
+            }
+            // Kept files count as downloaded, as they do for yt-dlp
+            let Some(path) = downloaded else {
+                archive::record(&file_details)?;
+                continue;
+            };
+            let Some(path) =
+                post_process(url, args, &mut file_details, &selection, path, observer)?
+            else {
+                archive::record(&file_details)?;
+                continue;
+            };
+            finish(url, args, observer, &file_details, Some(&entry.json), &path)?;
+            archive::record(&file_details)?;
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Runs the post-processing pipeline on a download saved at `path`,
+/// updating `file_details` to match. Returns where it ends up, or `None`
+/// when an existing file was kept instead.
+fn post_process(
+    url: &str,
+    args: &cli::DownloadArgs,
+    file_details: &mut FileDetails,
+    selection: &FormatSelection,
+    path: PathBuf,
+    observer: &dyn DownloadObserver,
+) -> Result<Option<PathBuf>, AppError> {
+    let pipeline = args
+        .post_processing
+        .as_deref()
+        .unwrap_or(postprocess::DEFAULT_PIPELINE);
+    let mut job = postprocess::Job {
+        url,
+        args,
+        details: file_details,
+        selection,
+        observer,
+    };
+    let processed = postprocess::run(pipeline, &mut job, path.clone())?;
+    if processed.is_none() {
+        let path = processed_path(args, path, selection);
+        observer.on_event(url, &DownloadEvent::Skipped { path });
+    }
+    Ok(processed)
+}
+
+/// Downloads one selection for an item, returning where it was saved, or
+/// `None` when an existing file was kept
+fn download_selection(
+    url: &str,
+    args: &cli::DownloadArgs,
+    file_details: &FileDetails,
+    selection: &FormatSelection,
+    playlist_item: Option<usize>,
+    section: Option<&Section>,
+    observer: &dyn DownloadObserver,
+) -> Result<Option<PathBuf>, AppError> {
+    // yt-dlp only names the file once it's downloaded, so catch files
+    // to skip beforehand where the name is known
+    if args.on_conflict == ConflictPolicy::Skip
+        && let Some(path) = output_path(args, file_details, selection)
+        && let path = processed_path(args, path, selection)
+        && path.exists()
+    {
+        observer.on_event(url, &DownloadEvent::Skipped { path });
+        return Ok(None);
+    }
+    check_space(args, &selection.formats())?;
+
+    // ffmpeg fetches just the section from streams it can read directly
+    if let Some(section) = section
+        && merge::is_available()
+        && let Some(inputs) = section_inputs(selection)
+        && let Some(path) = output_path(args, file_details, selection)
+    {
+        let Some(path) = conflict::resolve(&path, args.on_conflict) else {
+            observer.on_event(url, &DownloadEvent::Skipped { path });
+            return Ok(None);
+        };
+        observer.on_event(
+            url,
+            &DownloadEvent::DownloadingSection {
+                section: section.to_string(),
+            },
+        );
+        section::download(section, &inputs, &path)?;
+        return Ok(Some(path));
+    }
+    if let Some((format_url, path)) = native_download_path(args, file_details, selection) {
+        let FormatSelection::Single(format) = selection else {
+            unreachable!("only single formats are downloaded natively")
+        };
+        let Some(path) = conflict::resolve(&path, args.on_conflict) else {
+            observer.on_event(url, &DownloadEvent::Skipped { path });
+            return Ok(None);
+        };
+        if let Some(path) = download_natively(args, url, format_url, format, &path, observer)? {
+            return Ok(Some(path));
+        }
+    }
+    // Pairs fetched natively are merged here, as yt-dlp would
+    if let FormatSelection::Pair(video, audio) = selection
+        && merge::is_available()
+        && let Some(streams) = native_url(video).zip(native_url(audio))
+        && let Some(path) = output_path(args, file_details, selection)
+    {
+        let Some(path) = conflict::resolve(&path, args.on_conflict) else {
+            observer.on_event(url, &DownloadEvent::Skipped { path });
+            return Ok(None);
+        };
+        let (video_url, audio_url) = streams;
+        if let Some(video_path) = download_stream(args, url, video_url, video, &path, observer)?
+            && let Some(audio_path) = download_stream(args, url, audio_url, audio, &path, observer)?
+        {
+            observer.on_event(url, &DownloadEvent::Merging);
+            merge::merge(video_path, audio_path, &path)?;
+            return Ok(Some(path));
+        }
+    }
+    let options = ytdlp::DownloadOptions {
+        section: section.copied(),
+        ..download_options(args, playlist_item)
+    };
+    ytdlp::download_format(url, selection, &options, &mut |event| {
+        observer.on_event(url, event)
+    })
+}
+
+/// Checks a fresh download with ffprobe, deleting it when it's broken so
+/// it's fetched again, in the next format or on a later run
+fn verify_download(
+    args: &cli::DownloadArgs,
+    file_details: &FileDetails,
+    selection: &FormatSelection,
+    section: Option<&Section>,
+    downloaded: Option<PathBuf>,
+) -> Result<Option<PathBuf>, AppError> {
+    let Some(path) = downloaded else {
+        return Ok(None);
+    };
+    if args.no_verify || !verify::is_available() {
+        return Ok(Some(path));
+    }
+    // Codecs extractors don't know are left unchecked
+    let (vcodec, acodec) = selection.codecs();
+    let known = |codec: &str| !matches!(codec, "" | "none" | "unknown");
+    let expected = verify::Expected {
+        video: known(vcodec),
+        audio: known(acodec),
+        duration: section.map(Section::duration).or(file_details.duration),
+    };
+    if let Err(error) = verify::verify(&path, expected) {
+        let _ = std::fs::remove_file(&path);
+        return Err(error);
+    }
+    Ok(Some(path))
+}
+
+/// The URLs and headers of the streams of `selection`, when ffmpeg can read
+/// them all directly
+fn section_inputs(selection: &FormatSelection) -> Option<Vec<section::Input<'_>>> {
+    selection
+        .formats()
+        .into_iter()
+        .map(|format| Some((native_url(format)?, format.http_headers.as_slice())))
+        .collect()
+}
+
+/// The container a download saved at `path` is recoded into, when it's
+/// recoded, has subtitles burned in or is fitted to a shape
+fn recode_container(args: &cli::DownloadArgs, path: &Path) -> Option<VideoContainer> {
+    args.recode_video.or_else(|| {
+        (args.burn_subs.is_some() || args.fit.is_some()).then(|| VideoContainer::of(path))
+    })
+}
+
+/// Where a download saved at `path` ends up once its audio is extracted or
+/// it's recoded or turned into an animation
+fn processed_path(args: &cli::DownloadArgs, path: PathBuf, selection: &FormatSelection) -> PathBuf {
+    match recode_container(args, &path) {
+        _ if args.extract_audio => {
+            audio::extracted_path(&path, args.audio_format, selection.primary())
+        }
+        _ if args.to_gif && selection.codecs().0 != "none" => {
+            path.with_extension(args.gif_format.to_string())
+        }
+        Some(container) if selection.codecs().0 != "none" => {
+            path.with_extension(container.to_string())
+        }
+        _ => path,
+    }
+}
+
+/// Downloads one stream of a pair natively next to `path`, named after its
+/// format like yt-dlp's intermediate files, e.g. `Title.f137.mp4`. The file
+/// is deleted once dropped.
+fn download_stream(
+    args: &cli::DownloadArgs,
+    url: &str,
+    format_url: &str,
+    format: &FileFormat,
+    path: &Path,
+    observer: &dyn DownloadObserver,
+) -> Result<Option<download::TempFile>, AppError> {
+    let stream_path = path.with_extension(format!("f{}.{}", format.id, format.extension));
+    let downloaded = download_natively(args, url, format_url, format, &stream_path, observer)?;
+    Ok(downloaded.map(download::TempFile::new))
+}
+
+/// Fails early when `formats` won't fit in the output directory. Several
+/// streams need as much room again while they're merged into a new file.
+fn check_space(args: &cli::DownloadArgs, formats: &[&FileFormat]) -> Result<(), AppError> {
+    let mut needed = FileSize::new(0f64);
+    for format in formats {
+        let Some(size) = &format.file_size else {
+            return Ok(());
+        };
+        needed.bytes += size.bytes;
+        needed.estimated |= size.estimated;
+    }
+    if formats.len() > 1 {
+        needed.bytes *= 2;
+    }
+    disk::check_space(&needed, Path::new(&args.output_dir))
+}
+
+/// Downloads `format` to `path` with the native engine for its protocol,
+/// returning where it was saved, or `None` when the engine can't handle this
+/// stream and yt-dlp should download it instead.
+fn download_natively(
+    args: &cli::DownloadArgs,
+    url: &str,
+    format_url: &str,
+    format: &FileFormat,
+    path: &Path,
+    observer: &dyn DownloadObserver,
+) -> Result<Option<PathBuf>, AppError> {
+    std::fs::create_dir_all(&args.output_dir).map_err(|e| AppError::Io(e.to_string()))?;
+    let mut on_progress = |progress: &download::Progress| {
+        observer.on_event(url, &DownloadEvent::Progress(progress.clone()))
+    };
+    let fragmented = match format.protocol {
+        Some(Protocol::Hls) => hls::download(
+            format_url,
+            &format.http_headers,
+            path,
+            &fetch_options(args),
+            &mut on_progress,
+        ),
+        Some(Protocol::Dash) => dash::download(
+            format_url,
+            &format.id,
+            &format.http_headers,
+            path,
+            &fetch_options(args),
+            &mut on_progress,
+        ),
+        _ => {
+            let options = fetch_options(args);
+            options.downloader.backend().fetch(
+                format_url,
+                &format.http_headers,
+                path,
+                &options,
+                &mut on_progress,
+            )?;
+            return Ok(Some(path.to_path_buf()));
+        }
+    };
+    match fragmented {
+        Ok(path) => Ok(Some(path)),
+        Err(AppError::UnsupportedUrl(reason)) => {
+            eprintln!("Downloading with yt-dlp instead: {reason}");
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Whether the history has the item as downloaded before, in which case
+/// it's reported as a duplicate to pass over. `--allow-duplicates` turns
+/// this off.
+fn is_duplicate(
+    url: &str,
+    args: &cli::DownloadArgs,
+    observer: &dyn DownloadObserver,
+    file_details: &FileDetails,
+) -> bool {
+    if args.allow_duplicates {
+        return false;
+    }
+    let Some(earlier) = history::previous_download(&file_details.extractor_key, &file_details.id)
+    else {
+        return false;
+    };
+    observer.on_event(
+        url,
+        &DownloadEvent::Duplicate {
+            title: file_details.title.clone(),
+            previous: earlier.id,
+            path: earlier.path.map(PathBuf::from),
+        },
+    );
+    true
+}
+
+/// Saves a checksum next to a finished download, warning when the history
+/// has an earlier file with the same contents, and reports it complete,
+/// then saves the extras asked for next to it. `json` is yt-dlp's
+/// description of the item, when it came from yt-dlp.
+fn finish(
+    url: &str,
+    args: &cli::DownloadArgs,
+    observer: &dyn DownloadObserver,
+    file_details: &FileDetails,
+    json: Option<&Value>,
+    path: &Path,
+) -> Result<(), AppError> {
+    let sha256 = checksum::write_sidecar(path)?;
+    // Looked up before this download is recorded
+    let earlier = (!args.allow_duplicates)
+        .then(|| history::same_contents(&sha256, path))
+        .flatten();
+    observer.on_event(
+        url,
+        &DownloadEvent::Completed {
+            path: path.to_path_buf(),
+            sha256,
+        },
+    );
+    if let Some(earlier) = earlier {
+        eprintln!(
+            "{} has the same contents as {}, downloaded {} (history {})",
+            path.display(),
+            earlier.path.as_deref().unwrap_or("an earlier download"),
+            history::format_time(earlier.finished_at),
+            earlier.id
+        );
+    }
+    write_extras(args, file_details, json, path)
+}
+
+/// Saves the thumbnail, chapters, subtitles, details, NFO and preview asked
+/// for next to a downloaded file. `json` is yt-dlp's description of the
+/// item, when it came from yt-dlp.
+fn write_extras(
+    args: &cli::DownloadArgs,
+    file_details: &FileDetails,
+    json: Option<&Value>,
+    media_path: &Path,
+) -> Result<(), AppError> {
+    let mut thumbnail_path = None;
+    if args.write_thumbnail {
+        if file_details.thumbnails.is_empty() {
+            eprintln!("No thumbnail for {}", file_details.title);
+        } else if let Some(path) =
+            thumbnail::write_next_to(&file_details.thumbnails, media_path, args.on_conflict)?
+        {
+            println!("Saved {}", path.display());
+            thumbnail_path = Some(path);
+        }
+    }
+    if args.split_chapters {
+        if file_details.chapters.is_empty() {
+            eprintln!("No chapters in {}", file_details.title);
+        }
+        let paths = chapter::split(
+            &file_details.chapters,
+            media_path,
+            &file_details.title,
+            args.on_conflict,
+        )?;
+        for path in paths {
+            println!("Saved {}", path.display());
+        }
+    }
+    if args.write_subs {
+        let paths = subtitle::write_next_to(
+            &file_details.subtitles,
+            &args.sub_langs,
+            args.convert_subs,
+            media_path,
+            args.on_conflict,
+        )?;
+        for path in paths {
+            println!("Saved {}", path.display());
+        }
+    }
+    if args.write_info_json {
+        let json = json.filter(|_| args.raw_info_json);
+        for path in infojson::write_next_to(file_details, json, media_path, args.on_conflict)? {
+            println!("Saved {}", path.display());
+        }
+    }
+    if args.write_nfo
+        && let Some(path) = nfo::write_next_to(
+            file_details,
+            thumbnail_path.as_deref(),
+            media_path,
+            args.on_conflict,
+        )?
+    {
+        println!("Saved {}", path.display());
+    }
+    if args.write_preview
+        && let Some(path) = preview::write_next_to(media_path, args.preview_grid, args.on_conflict)?
+    {
+        println!("Saved {}", path.display());
+    }
+    Ok(())
+}
+
+fn print_entries(url: &str, args: &cli::InfoArgs) -> Result<(), AppError> {
+    let entries = playlist::fetch_entries(url, &args.listing.items)?;
+    let numbered = entries.len() > 1 || !args.listing.items.is_empty();
+    for entry in entries {
+        if !entry.file_details.matches_duration(&args.listing) {
+            continue;
+        }
+        if numbered {
+            println!("Item {}", entry.index);
+        }
+        print_file_details(entry.file_details, args);
+    }
+    Ok(())
+}
+
+fn record(args: cli::RecordArgs) -> Result<(), AppError> {
+    if get_extractor(&args.url).is_none() {
+        return Err(AppError::UnsupportedUrl(args.url));
+    }
+
+    // Scheduled streams have no metadata until they start, so only check
+    // upfront when we aren't going to wait for one
+    if args.wait.is_none() {
+        let entries = playlist::fetch_entries(&args.url, &[])?;
+        let Some(entry) = entries.first() else {
+            return Err(AppError::MissingField("entries"));
+        };
+        if !entry.file_details.is_live {
+            return Err(AppError::NotLive(args.url));
+        }
+        println!("Recording {}", entry.file_details.title);
+    }
+
+    ytdlp::record_live(&args.url, &args)
+}
+
+/// Fills in the download options left out on the command line from the
+/// config file
+fn apply_config(args: &mut cli::DownloadArgs, config: &Config) {
+    args.downloader = args.downloader.or(config.downloader);
+    args.schedule = args.schedule.or(config.schedule);
+    args.download_archive = args
+        .download_archive
+        .take()
+        .or_else(|| config.download_archive.clone());
+    let hooks = &mut args.hooks;
+    hooks.on_metadata = hooks
+        .on_metadata
+        .take()
+        .or_else(|| config.on_metadata.clone());
+    hooks.on_download_complete = hooks
+        .on_download_complete
+        .take()
+        .or_else(|| config.on_download_complete.clone());
+    hooks.on_error = hooks.on_error.take().or_else(|| config.on_error.clone());
+    args.post_processing = args
+        .post_processing
+        .take()
+        .or_else(|| config.post_processing.clone());
+    args.hwaccel = args.hwaccel.or(config.hwaccel);
+}
+
+fn run(cli: Cli, config: Config) -> Result<(), AppError> {
+    let unit_system = if cli.si || config.si_units {
+        UnitSystem::Si
+    } else {
+        UnitSystem::Binary
+    };
+    UNIT_SYSTEM.get_or_init(|| unit_system);
+    retry::RetryPolicy {
+        retries: cli.retries,
+        delay: Duration::from_secs_f64(cli.retry_delay),
+    }
+    .install();
+    timeout::Timeouts {
+        metadata: Duration::from_secs_f64(cli.metadata_timeout),
+        connect: Duration::from_secs_f64(cli.connect_timeout),
+        stall: Duration::from_secs_f64(cli.stall_timeout),
+    }
+    .install();
+    cache::CachePolicy {
+        mode: if cli.no_cache {
+            cache::CacheMode::Off
+        } else if cli.refresh {
+            cache::CacheMode::Refresh
+        } else {
+            cache::CacheMode::Use
+        },
+        ttl: cli
+            .cache_ttl
+            .or(config.metadata_cache_ttl)
+            .map_or(cache::DEFAULT_TTL, Duration::from_secs_f64),
+    }
+    .install();
+
+    let Some(command) = cli.command else {
+        unreachable!("main only runs a command that was given")
+    };
+    match command {
+        Commands::Info(mut args) => {
+            args.url = resolve_url(&args.url);
+            info(args)
+        }
+        Commands::Download(mut args) => {
+            args.urls = args.urls.iter().map(|url| resolve_url(url)).collect();
+            apply_config(&mut args, &config);
+            download(args)
+        }
+        #[cfg(unix)]
+        Commands::Daemon(mut args) => {
+            let download = &mut args.download;
+            download.urls = download.urls.iter().map(|url| resolve_url(url)).collect();
+            apply_config(download, &config);
+            daemon::run(args, &config)
+        }
+        Commands::Record(mut args) => {
+            args.url = resolve_url(&args.url);
+            record(args)
+        }
+        Commands::Music(mut args) => {
+            let download = &mut args.download;
+            download.urls = download.urls.iter().map(|url| resolve_url(url)).collect();
+            if let Some(url) = download
+                .urls
+                .iter()
+                .find(|url| !matches!(get_extractor(url), Some(Extractor::Youtube(_))))
+            {
+                return Err(AppError::UnsupportedUrl(url.clone()));
+            }
+            apply_config(download, &config);
+            music::download(args)
+        }
+        Commands::Podcast(args) => podcast::download(&args),
+        Commands::Queue(command) => queue::run(command),
+        Commands::History(command) => history::run(command),
+        Commands::Stats(args) => history::stats(&args),
+        Commands::Cleanup(args) => cleanup::run(&args, &config),
+        Commands::Failed(command) => failed::run(command, &config),
+        Commands::Organize(args) => library::organize(&args),
+        Commands::Subscriptions(command) => subscription::run(command, &config),
+        Commands::Verify(args) => checksum::verify(&args.paths),
+        Commands::Remux(args) => remux::run(&args),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match (cli.check_config, &cli.command) {
+        (false, None) => Cli::command()
+            .error(ErrorKind::MissingSubcommand, "a command is required")
+            .exit(),
+        (true, Some(_)) => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--check-config can't be given with a command",
+            )
+            .exit(),
+        _ => {}
+    }
+    interrupt::install();
+    let result = if cli.check_config {
+        schema::check()
+    } else {
+        // State left by older versions is brought up to date first
+        Config::load().and_then(|config| {
+            schema::migrate();
+            profile::apply(cli, &config).and_then(|cli| run(cli, config))
+        })
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(AppError::Interrupted) => {
+            eprintln!("Interrupted");
+            ExitCode::from(130)
+        }
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn youtube_variant_urls_are_videos() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+            "https://www.youtube.com/live/jfKfPfyJRdk",
+            "https://m.youtube.com/live/jfKfPfyJRdk?feature=share",
+        ] {
+            assert!(
+                matches!(
+                    get_extractor(url),
+                    Some(Extractor::Youtube(YoutubeContentType::Video))
+                ),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn mobile_youtube_channel_urls_are_channels() {
+        assert!(matches!(
+            get_extractor("https://m.youtube.com/@handle/videos"),
+            Some(Extractor::Youtube(YoutubeContentType::Channel))
+        ));
+    }
+
+    #[test]
+    fn instagram_share_urls_are_accepted() {
+        assert!(matches!(
+            get_extractor("https://www.instagram.com/share/reel/BAabc123/"),
+            Some(Extractor::Instagram(InstagramContentType::Reel))
+        ));
+        assert!(matches!(
+            get_extractor("https://www.instagram.com/share/p/BAabc123/"),
+            Some(Extractor::Instagram(InstagramContentType::Post))
+        ));
+        assert!(matches!(
+            get_extractor("https://www.instagram.com/share/BAabc123"),
+            Some(Extractor::Instagram(InstagramContentType::Post))
+        ));
+    }
+
+    #[test]
+    fn unrelated_urls_are_rejected() {
+        assert!(get_extractor("https://www.youtube.evil.com/watch?v=x").is_none());
+        assert!(get_extractor("https://example.com/share/abc").is_none());
+    }
 }
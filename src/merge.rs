@@ -0,0 +1,99 @@
+use crate::download::{self, TempFile};
+use crate::{AppError, FileFormat};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// Codecs each container holds, by the part of the codec string before the
+/// first dot, e.g. `avc1` of `avc1.640028`, or by ffprobe's names for them
+const MP4_VIDEO: &[&str] = &[
+    "avc1", "avc3", "h264", "hev1", "hvc1", "h265", "hevc", "av01", "av1", "mp4v", "mpeg4",
+];
+const MP4_AUDIO: &[&str] = &["mp4a", "aac", "mp3", "ac-3", "ac3", "ec-3", "eac3", "alac"];
+const WEBM_VIDEO: &[&str] = &["vp8", "vp9", "vp09", "av01", "av1"];
+const WEBM_AUDIO: &[&str] = &["opus", "vorbis"];
+
+fn codec_in(codec: &str, codecs: &[&str]) -> bool {
+    let family = codec.split('.').next().unwrap_or_default();
+    codecs
+        .iter()
+        .any(|known| family.eq_ignore_ascii_case(known))
+}
+
+/// The container to merge a video and an audio stream into: WebM or MP4
+/// when it can hold both codecs, and Matroska, which holds anything,
+/// otherwise. Streams with unknown codecs go by their extensions.
+pub fn container(video: &FileFormat, audio: &FileFormat) -> &'static str {
+    let known = |codec: &str| !matches!(codec, "" | "none" | "unknown");
+    if !known(&video.vcodec) || !known(&audio.acodec) {
+        return match video.extension.as_str() {
+            _ if !video.is_compatible_with(audio) => "mkv",
+            "webm" => "webm",
+            "mp4" | "m4v" | "mov" => "mp4",
+            _ => "mkv",
+        };
+    }
+    ["webm", "mp4"]
+        .into_iter()
+        .find(|container| holds(container, &video.vcodec) && holds(container, &audio.acodec))
+        .unwrap_or("mkv")
+}
+
+/// Whether `container` can hold a video or audio stream in `codec`
+pub fn holds(container: &str, codec: &str) -> bool {
+    let (video, audio) = match container {
+        "mp4" => (MP4_VIDEO, MP4_AUDIO),
+        "webm" => (WEBM_VIDEO, WEBM_AUDIO),
+        _ => return true,
+    };
+    codec_in(codec, video) || codec_in(codec, audio)
+}
+
+/// Whether ffmpeg can be run, checked once
+pub fn is_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ffmpeg")
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+/// Muxes the streams at `video` and `audio` into `path` without re-encoding
+/// them, in the container `path`'s extension names. The streams are deleted
+/// once they're merged, or the merge fails.
+pub fn merge(video: TempFile, audio: TempFile, path: &Path) -> Result<(), AppError> {
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let merged =
+        TempFile::new(download::part_path(path).with_extension(format!("tmp.{extension}")));
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(video.path())
+        .arg("-i")
+        .arg(audio.path())
+        .args(["-map", "0:v:0", "-map", "1:a:0", "-c", "copy"]);
+    if extension == "mp4" {
+        // Lets players start before the whole file has loaded
+        command.args(["-movflags", "+faststart"]);
+    }
+    let status = command
+        .arg(merged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} merging {} and {}",
+            video.path().display(),
+            audio.path().display()
+        )));
+    }
+    std::fs::rename(merged.path(), path).map_err(|e| AppError::Io(e.to_string()))
+}
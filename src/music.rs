@@ -0,0 +1,17 @@
+use crate::cli::MusicArgs;
+use crate::{AppError, wants_video};
+
+/// Downloads the best audio of each URL like `download --extract-audio`,
+/// cutting albums uploaded as a single video into a file per track by their
+/// chapters, each tagged with its title and track number.
+pub fn download(mut args: MusicArgs) -> Result<(), AppError> {
+    let download = &mut args.download;
+    if download.multi_audio || wants_video(download) {
+        return Err(AppError::CommandFailed(
+            "music is downloaded as audio only, so video options can't be given".to_string(),
+        ));
+    }
+    download.extract_audio = true;
+    download.split_chapters = !args.no_split && download.sections.is_empty();
+    crate::download(args.download)
+}
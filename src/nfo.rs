@@ -0,0 +1,75 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::{AppError, FileDetails, download, thumbnail};
+use std::path::{Path, PathBuf};
+
+/// `text` with the characters XML reserves escaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A Kodi `<movie>` NFO for `details`, which Jellyfin, Emby and Plex's
+/// agents read too. `thumbnail` is a saved thumbnail to point to, or else
+/// the best one online is.
+fn render(details: &FileDetails, thumbnail: Option<&Path>) -> String {
+    let date = details.iso_upload_date();
+    // Media servers count runtime in whole minutes
+    let runtime = details
+        .duration
+        .map(|duration| ((duration / 60f64).round() as u64).max(1).to_string());
+    let thumb = match thumbnail {
+        // Saved next to the NFO, so its name is all the path it needs
+        Some(path) => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        None => thumbnail::best(&details.thumbnails).map(|best| best.url.clone()),
+    };
+    let elements = [
+        ("title", Some(details.title.clone())),
+        ("plot", details.description.clone()),
+        ("premiered", date.clone()),
+        ("year", date.map(|date| date.chars().take(4).collect())),
+        (
+            "studio",
+            details.uploader.clone().or(details.channel.clone()),
+        ),
+        ("runtime", runtime),
+        ("thumb", thumb),
+    ];
+
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    nfo.push_str("<movie>\n");
+    for (name, value) in elements {
+        if let Some(value) = value {
+            nfo.push_str(&format!("  <{name}>{}</{name}>\n", escape(&value)));
+        }
+    }
+    nfo.push_str(&format!(
+        "  <uniqueid type=\"{}\" default=\"true\">{}</uniqueid>\n",
+        escape(&details.extractor_key.to_lowercase()),
+        escape(&details.id)
+    ));
+    nfo.push_str("</movie>\n");
+    nfo
+}
+
+/// Saves an NFO for `details` next to `media_path`, sharing its file stem,
+/// so media servers list the download with its title, description, date and
+/// uploader. Returns where it was saved, or `None` when `on_conflict` kept
+/// an existing one.
+pub fn write_next_to(
+    details: &FileDetails,
+    thumbnail: Option<&Path>,
+    media_path: &Path,
+    on_conflict: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    let path = media_path.with_extension("nfo");
+    if !conflict::should_write_sidecar(&path, on_conflict) {
+        println!("Skipped {}, which already exists", path.display());
+        return Ok(None);
+    }
+    download::write_file(&path, render(details, thumbnail))?;
+    Ok(Some(path))
+}
@@ -0,0 +1,60 @@
+use crate::{AppError, FileDetails, ytdlp};
+use serde_json::Value;
+
+/// A numbered item of a multi-item URL, such as one slide of an Instagram carousel.
+#[derive(Debug)]
+pub struct PlaylistEntry {
+    /// 1-based position of the item in the original playlist
+    pub index: usize,
+    pub file_details: FileDetails,
+    /// The item as yt-dlp described it, before it was parsed
+    pub json: Value,
+}
+
+fn is_playlist(json: &Value) -> bool {
+    json.get("_type").and_then(|v| v.as_str()) == Some("playlist")
+}
+
+/// Splits a yt-dlp JSON dump into its entries. Single items come back as a
+/// playlist of one, so callers can treat every URL the same way.
+///
+/// `items` restricts the result to the given 1-based indices; an empty slice
+/// keeps everything. Entries that fail to parse are reported and skipped so
+/// the numbering of the remaining ones still matches the source.
+pub fn entries_from_json(json: Value, items: &[usize]) -> Result<Vec<PlaylistEntry>, AppError> {
+    if !is_playlist(&json) {
+        let file_details = serde_json::from_value(json.clone())
+            .map_err(|e| AppError::InvalidJson(e.to_string()))?;
+        return Ok(vec![PlaylistEntry {
+            index: 1,
+            file_details,
+            json,
+        }]);
+    }
+
+    let Some(Value::Array(json_entries)) = json.get("entries").cloned() else {
+        return Err(AppError::MissingField("entries"));
+    };
+
+    let mut entries = vec![];
+    for (position, entry) in json_entries.into_iter().enumerate() {
+        let index = position + 1;
+        if !items.is_empty() && !items.contains(&index) {
+            continue;
+        }
+        match serde_json::from_value(entry.clone()) {
+            Ok(file_details) => entries.push(PlaylistEntry {
+                index,
+                file_details,
+                json: entry,
+            }),
+            Err(error) => eprintln!("Skipping item {index}: {error}"),
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn fetch_entries(url: &str, items: &[usize]) -> Result<Vec<PlaylistEntry>, AppError> {
+    entries_from_json(ytdlp::fetch_json(url, &[])?, items)
+}
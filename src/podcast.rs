@@ -0,0 +1,268 @@
+use crate::cli::PodcastArgs;
+use crate::thumbnail::Thumbnail;
+use crate::{
+    AppError, FileDetails, FileEncoding, FileFormat, FileSize, Protocol, default_codec, filename,
+    http,
+};
+use roxmltree::{Document, Node};
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// A feed item with a media enclosure.
+#[derive(Debug)]
+pub struct Episode {
+    pub file_details: FileDetails,
+    pub url: String,
+    /// Publication date as YYYYMMDD, matching yt-dlp's `upload_date`
+    pub published: Option<String>,
+}
+
+impl Episode {
+    /// `YYYY-MM-DD - Title.ext`, so episodes sort chronologically on disk
+    pub fn file_name(&self) -> String {
+        let title = filename::sanitize(&self.file_details.title);
+        let file_name = match &self.published {
+            Some(date) => format!(
+                "{}-{}-{} - {title}.{}",
+                &date[..4],
+                &date[4..6],
+                &date[6..],
+                self.file_details.ext
+            ),
+            None => format!("{title}.{}", self.file_details.ext),
+        };
+        filename::fit(&file_name)
+    }
+
+    /// Midnight UTC of the publication date
+    fn published_time(&self) -> Option<SystemTime> {
+        let date = self.published.as_ref()?;
+        let year: i64 = date[..4].parse().ok()?;
+        let month: i64 = date[4..6].parse().ok()?;
+        let day: i64 = date[6..].parse().ok()?;
+        let days = days_from_civil(year, month, day);
+        let seconds = u64::try_from(days * 86400).ok()?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn child<'a>(node: Node<'a, 'a>, name: &str) -> Option<Node<'a, 'a>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(|text| text.trim().to_string())
+}
+
+/// Parses `itunes:duration`, which is either plain seconds or `[HH:]MM:SS`.
+fn parse_duration(value: &str) -> Option<f64> {
+    value.split(':').try_fold(0f64, |total, part| {
+        part.trim().parse::<f64>().ok().map(|n| total * 60f64 + n)
+    })
+}
+
+/// Parses RFC 2822 (RSS `pubDate`) or RFC 3339 (Atom) dates into YYYYMMDD.
+fn parse_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.len() >= 10 && value.as_bytes()[4] == b'-' {
+        let date = value[..10].replace('-', "");
+        return date.chars().all(|c| c.is_ascii_digit()).then_some(date);
+    }
+
+    let tokens: Vec<&str> = value.split_whitespace().collect();
+    let month_position = tokens
+        .iter()
+        .position(|token| MONTHS.contains(&token.to_lowercase().get(..3).unwrap_or_default()))?;
+    let month = MONTHS
+        .iter()
+        .position(|m| tokens[month_position].to_lowercase().starts_with(m))?
+        + 1;
+    let day: u32 = tokens.get(month_position.checked_sub(1)?)?.parse().ok()?;
+    let year: u32 = tokens.get(month_position + 1)?.parse().ok()?;
+    Some(format!("{year:04}{month:02}{day:02}"))
+}
+
+fn parse_episode(item: Node, feed_title: &str) -> Option<Episode> {
+    // RSS uses <enclosure url=..>, Atom uses <link rel="enclosure" href=..>
+    let (url, mime_type, length) = if let Some(enclosure) = child(item, "enclosure") {
+        (
+            enclosure.attribute("url")?,
+            enclosure.attribute("type").unwrap_or_default(),
+            enclosure.attribute("length"),
+        )
+    } else {
+        let link = item
+            .children()
+            .find(|n| n.tag_name().name() == "link" && n.attribute("rel") == Some("enclosure"))?;
+        (
+            link.attribute("href")?,
+            link.attribute("type").unwrap_or_default(),
+            link.attribute("length"),
+        )
+    };
+
+    let extension = http::file_extension(url, mime_type);
+    let file_size = length
+        .and_then(|length| length.parse::<f64>().ok())
+        .filter(|length| *length > 0f64)
+        .map(FileSize::new);
+    let published = ["pubDate", "published", "updated"]
+        .iter()
+        .find_map(|name| child_text(item, name))
+        .and_then(|date| parse_date(&date));
+    // itunes:author on RSS, author/name on Atom
+    let author = child_text(item, "author")
+        .filter(|author| !author.is_empty())
+        .or_else(|| child(item, "author").and_then(|author| child_text(author, "name")));
+
+    // RSS has the episode page as the text of <link>, Atom as a link's href
+    let webpage_url = item
+        .children()
+        .filter(|n| n.tag_name().name() == "link")
+        .find(|link| link.attribute("rel").is_none_or(|rel| rel == "alternate"))
+        .and_then(|link| {
+            link.attribute("href")
+                .map(str::to_string)
+                .or_else(|| link.text().map(|text| text.trim().to_string()))
+        });
+
+    // <itunes:image href=..>, or a plain <image><url> on some feeds
+    let thumbnails = child(item, "image")
+        .and_then(|image| {
+            image
+                .attribute("href")
+                .map(str::to_string)
+                .or_else(|| child_text(image, "url"))
+        })
+        .map(|url| Thumbnail {
+            url,
+            width: None,
+            height: None,
+            preference: None,
+        })
+        .into_iter()
+        .collect();
+
+    let file_details = FileDetails {
+        // Feeds give episodes a guid (RSS) or id (Atom) that survives URL changes
+        id: ["guid", "id"]
+            .iter()
+            .find_map(|name| child_text(item, name))
+            .unwrap_or_else(|| url.to_string()),
+        title: child_text(item, "title").unwrap_or_else(|| feed_title.to_string()),
+        duration: child_text(item, "duration").and_then(|d| parse_duration(&d)),
+        is_live: false,
+        ext: extension.clone(),
+        extractor: "podcast".to_string(),
+        extractor_key: "Podcast".to_string(),
+        uploader: author,
+        channel: Some(feed_title.to_string()),
+        upload_date: published.clone(),
+        view_count: None,
+        like_count: None,
+        description: ["description", "summary"]
+            .iter()
+            .find_map(|name| child_text(item, name)),
+        webpage_url,
+        original_url: None,
+        availability: None,
+        thumbnails,
+        subtitles: vec![],
+        chapters: vec![],
+        formats: vec![FileFormat {
+            id: "enclosure".to_string(),
+            extension,
+            resolution: None,
+            width: None,
+            height: None,
+            fps: None,
+            vcodec: default_codec(),
+            acodec: default_codec(),
+            dynamic_range: None,
+            tbr: None,
+            abr: None,
+            language: None,
+            protocol: Some(Protocol::from_url(url)),
+            format_note: None,
+            container: None,
+            file_size,
+            has_drm: false,
+            url: Some(url.to_string()),
+            manifest_url: None,
+            http_headers: vec![],
+            file_encoding: FileEncoding::from_mime_type(mime_type),
+        }],
+    };
+
+    Some(Episode {
+        file_details,
+        url: url.to_string(),
+        published,
+    })
+}
+
+/// Parses an RSS or Atom feed into its episodes, newest first.
+pub fn parse_feed(xml: &str) -> Result<Vec<Episode>, AppError> {
+    let document = Document::parse(xml).map_err(|e| AppError::InvalidFeed(e.to_string()))?;
+    let root = document.root_element();
+    let feed_title = root
+        .descendants()
+        .find(|n| n.tag_name().name() == "title")
+        .and_then(|n| n.text())
+        .unwrap_or_default();
+
+    let mut episodes: Vec<Episode> = root
+        .descendants()
+        .filter(|n| matches!(n.tag_name().name(), "item" | "entry"))
+        .filter_map(|item| parse_episode(item, feed_title))
+        .collect();
+    episodes.sort_by(|a, b| b.published.cmp(&a.published));
+    Ok(episodes)
+}
+
+pub fn fetch_episodes(url: &str) -> Result<Vec<Episode>, AppError> {
+    parse_feed(&http::fetch_text(url)?)
+}
+
+/// Downloads the latest episodes of a feed into the output directory.
+pub fn download(args: &PodcastArgs) -> Result<(), AppError> {
+    let mut episodes = fetch_episodes(&args.url)?;
+    if let Some(latest) = args.latest {
+        episodes.truncate(latest);
+    }
+
+    let output_dir = Path::new(&args.output_dir);
+    std::fs::create_dir_all(output_dir).map_err(|e| AppError::Io(e.to_string()))?;
+    for episode in episodes {
+        let path = output_dir.join(episode.file_name());
+        println!("Downloading {}", path.display());
+        http::download_to(&episode.url, &path)?;
+
+        // Date the file by publication rather than download time
+        if let Some(published) = episode.published_time() {
+            File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(published))
+                .map_err(|e| AppError::Io(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,319 @@
+use crate::download::{DownloadEvent, DownloadObserver};
+use crate::fit::Fit;
+use crate::sponsorblock::SponsorBlockMode;
+use crate::{
+    AppError, FileDetails, FormatSelection, animation, audio, cli, embed, loudness, recode,
+    recode_container, sponsorblock, subtitle,
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Steps run on a download once it's saved and its streams merged, in the
+/// order they run unless the config file or `--post-processing` says
+/// otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    ExtractAudio,
+    Recode,
+    ToGif,
+    NormalizeAudio,
+    Sponsorblock,
+    EmbedSubs,
+    EmbedMetadata,
+    EmbedThumbnail,
+}
+
+pub const DEFAULT_PIPELINE: &[Step] = &[
+    Step::ExtractAudio,
+    Step::Recode,
+    Step::ToGif,
+    Step::NormalizeAudio,
+    Step::Sponsorblock,
+    Step::EmbedSubs,
+    Step::EmbedMetadata,
+    Step::EmbedThumbnail,
+];
+
+impl Step {
+    pub fn processor(self) -> &'static dyn PostProcessor {
+        match self {
+            Step::ExtractAudio => &ExtractAudio,
+            Step::Recode => &Recode,
+            Step::ToGif => &ToGif,
+            Step::NormalizeAudio => &NormalizeAudio,
+            Step::Sponsorblock => &SponsorBlock,
+            Step::EmbedSubs => &EmbedSubs,
+            Step::EmbedMetadata => &EmbedMetadata,
+            Step::EmbedThumbnail => &EmbedThumbnail,
+        }
+    }
+}
+
+/// A download being post-processed, and what was asked of it.
+pub struct Job<'a> {
+    pub url: &'a str,
+    pub args: &'a cli::DownloadArgs,
+    /// Steps update it to match the file, like chapters moved by cuts
+    pub details: &'a mut FileDetails,
+    pub selection: &'a FormatSelection,
+    pub observer: &'a dyn DownloadObserver,
+}
+
+impl Job<'_> {
+    fn has_video(&self) -> bool {
+        self.selection.codecs().0 != "none"
+    }
+
+    fn embedding(&self, what: &str) {
+        self.observer.on_event(
+            self.url,
+            &DownloadEvent::Embedding {
+                what: what.to_string(),
+            },
+        );
+    }
+}
+
+/// Something done to a saved download.
+pub trait PostProcessor: Sync {
+    /// Whether `job` asks for the step
+    fn wanted(&self, job: &Job) -> bool;
+
+    /// Runs the step on the file at `path` and returns where the file is
+    /// now, or `None` when an existing file was kept instead, which ends
+    /// the pipeline.
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError>;
+}
+
+/// Runs the steps of `pipeline` that `job` asks for on the file at `path`,
+/// in order. Returns where the file ends up, or `None` when a step kept an
+/// existing file instead.
+pub fn run(pipeline: &[Step], job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+    for step in Step::value_variants() {
+        if !pipeline.contains(step) && step.processor().wanted(job) {
+            eprintln!(
+                "\r\x1b[2K{} is left out of the post-processing pipeline, so it won't run",
+                step.to_possible_value().unwrap_or_default().get_name()
+            );
+        }
+    }
+    let mut path = path;
+    for step in pipeline {
+        let processor = step.processor();
+        if !processor.wanted(job) {
+            continue;
+        }
+        let Some(processed) = processor.run(job, path)? else {
+            return Ok(None);
+        };
+        path = processed;
+    }
+    Ok(Some(path))
+}
+
+struct ExtractAudio;
+
+impl PostProcessor for ExtractAudio {
+    fn wanted(&self, job: &Job) -> bool {
+        job.args.extract_audio
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        job.observer
+            .on_event(job.url, &DownloadEvent::ExtractingAudio);
+        audio::extract(
+            &path,
+            job.selection.primary(),
+            job.args.audio_format,
+            job.args.audio_quality,
+            job.args.on_conflict,
+        )
+    }
+}
+
+struct Recode;
+
+impl PostProcessor for Recode {
+    fn wanted(&self, job: &Job) -> bool {
+        let args = job.args;
+        !args.extract_audio
+            && (args.recode_video.is_some() || args.burn_subs.is_some() || args.fit.is_some())
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        let Some(container) = recode_container(job.args, &path) else {
+            return Ok(Some(path));
+        };
+        let subtitles = match &job.args.burn_subs {
+            Some(language) => subtitle::fetch_for_ffmpeg(
+                &job.details.subtitles,
+                language,
+                subtitle::FFMPEG_FORMATS,
+                &path,
+            )?,
+            None => None,
+        };
+        let target = recode::Target {
+            container,
+            burn_subtitles: subtitles.as_ref().map(|(_, file)| file.path()),
+            fit: job.args.fit.map(|ratio| Fit {
+                ratio,
+                mode: job.args.fit_mode,
+            }),
+            hwaccel: job.args.hwaccel,
+        };
+        let (url, observer) = (job.url, job.observer);
+        recode::recode(
+            &path,
+            job.selection.codecs(),
+            target,
+            job.details.duration,
+            job.args.keep_video,
+            job.args.on_conflict,
+            &mut |event| observer.on_event(url, event),
+        )
+    }
+}
+
+struct ToGif;
+
+impl PostProcessor for ToGif {
+    fn wanted(&self, job: &Job) -> bool {
+        job.args.to_gif && job.has_video()
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        let animation = animation::Animation {
+            format: job.args.gif_format,
+            fps: job.args.gif_fps,
+            width: job.args.gif_width,
+            colors: job.args.gif_colors,
+        };
+        job.observer.on_event(
+            job.url,
+            &DownloadEvent::Transcoding {
+                container: animation.format.to_string(),
+                fraction: None,
+            },
+        );
+        animation::convert(
+            &path,
+            animation,
+            job.details.duration,
+            job.args.keep_video,
+            job.args.on_conflict,
+        )
+    }
+}
+
+struct NormalizeAudio;
+
+impl PostProcessor for NormalizeAudio {
+    fn wanted(&self, job: &Job) -> bool {
+        job.args.normalize_audio
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        job.observer
+            .on_event(job.url, &DownloadEvent::NormalizingAudio);
+        loudness::normalize(&path, job.args.loudness_target, job.args.audio_quality)?;
+        Ok(Some(path))
+    }
+}
+
+/// Marks the SponsorBlock segments of a YouTube video as chapters, or cuts
+/// them out.
+struct SponsorBlock;
+
+impl PostProcessor for SponsorBlock {
+    fn wanted(&self, job: &Job) -> bool {
+        job.args.sponsorblock.is_some()
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        let Some(mode) = job.args.sponsorblock else {
+            return Ok(Some(path));
+        };
+        // SponsorBlock's segments are keyed by YouTube video ID
+        if job.details.extractor_key != "Youtube" {
+            eprintln!(
+                "\r\x1b[2KSponsorBlock only covers YouTube, not {}",
+                job.details.extractor
+            );
+            return Ok(Some(path));
+        }
+        let segments = sponsorblock::fetch(&job.details.id, &job.args.sb_categories)?;
+        if segments.is_empty() {
+            return Ok(Some(path));
+        }
+        match mode {
+            SponsorBlockMode::Mark => {
+                job.embedding("SponsorBlock chapters");
+                job.details.chapters = sponsorblock::mark(&job.details.chapters, &segments);
+                embed::chapters(&job.details.chapters, &path)?;
+            }
+            SponsorBlockMode::Remove => {
+                job.observer.on_event(
+                    job.url,
+                    &DownloadEvent::RemovingSegments {
+                        count: segments.len(),
+                    },
+                );
+                sponsorblock::remove(&path, &segments, job.details)?;
+                if !job.details.chapters.is_empty() {
+                    embed::chapters(&job.details.chapters, &path)?;
+                }
+            }
+        }
+        Ok(Some(path))
+    }
+}
+
+struct EmbedSubs;
+
+impl PostProcessor for EmbedSubs {
+    fn wanted(&self, job: &Job) -> bool {
+        job.args.embed_subs
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        job.embedding("subtitles");
+        embed::subtitles(&job.details.subtitles, &job.args.sub_langs, &path)?;
+        Ok(Some(path))
+    }
+}
+
+struct EmbedMetadata;
+
+impl PostProcessor for EmbedMetadata {
+    fn wanted(&self, job: &Job) -> bool {
+        job.args.embed_metadata
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        job.embedding("metadata");
+        embed::metadata(job.details, &path)?;
+        Ok(Some(path))
+    }
+}
+
+struct EmbedThumbnail;
+
+impl PostProcessor for EmbedThumbnail {
+    // Cover art is what audio files usually show
+    fn wanted(&self, job: &Job) -> bool {
+        !job.args.no_embed_thumbnail && (job.args.embed_thumbnail || job.args.extract_audio)
+    }
+
+    fn run(&self, job: &mut Job, path: PathBuf) -> Result<Option<PathBuf>, AppError> {
+        job.embedding("thumbnail");
+        embed::thumbnail(
+            &job.details.thumbnails,
+            &path,
+            job.has_video() && !job.args.extract_audio,
+        )?;
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,153 @@
+use crate::AppError;
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, TempFile};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Width of each frame in the sheet, in pixels
+const TILE_WIDTH: u32 = 320;
+/// Tiles a side of the sheet can have
+const MAX_TILES: u32 = 20;
+
+/// How many frames a preview lays out, as columns by rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl Grid {
+    /// Parses `COLUMNSxROWS`, like `4x4`
+    pub fn parse(value: &str) -> Result<Grid, String> {
+        let invalid = || format!("`{value}` isn't a grid like 4x4");
+        let (columns, rows) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (columns, rows) = (
+            columns.trim().parse::<u32>().map_err(|_| invalid())?,
+            rows.trim().parse::<u32>().map_err(|_| invalid())?,
+        );
+        if !(1..=MAX_TILES).contains(&columns) || !(1..=MAX_TILES).contains(&rows) {
+            return Err(format!("`{value}` needs 1 to {MAX_TILES} columns and rows"));
+        }
+        Ok(Grid { columns, rows })
+    }
+
+    fn frames(self) -> u32 {
+        self.columns * self.rows
+    }
+}
+
+impl Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.columns, self.rows)
+    }
+}
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<Stream>,
+    format: Option<Format>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    #[serde(default)]
+    codec_type: String,
+    disposition: Option<Disposition>,
+}
+
+#[derive(Deserialize)]
+struct Disposition {
+    // Cover art, which audio files carry as a one-frame video stream
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+#[derive(Deserialize)]
+struct Format {
+    // ffprobe gives numbers as strings
+    duration: Option<String>,
+}
+
+/// How many seconds long the video at `path` is, or `None` when it has no
+/// moving pictures to preview
+fn video_duration(path: &Path) -> Result<Option<f64>, AppError> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-of", "json", "-show_entries"])
+        .arg("stream=codec_type:stream_disposition=attached_pic:format=duration")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffprobe: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffprobe exited with {} reading {}: {}",
+            output.status,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let probe: Probe = serde_json::from_slice(&output.stdout)
+        .map_err(|e| AppError::InvalidJson(format!("ffprobe output: {e}")))?;
+    let has_video = probe.streams.iter().any(|stream| {
+        stream.codec_type == "video"
+            && stream
+                .disposition
+                .as_ref()
+                .is_none_or(|disposition| disposition.attached_pic == 0)
+    });
+    Ok(probe
+        .format
+        .and_then(|format| format.duration?.parse::<f64>().ok())
+        .filter(|duration| has_video && *duration > 0f64))
+}
+
+/// Saves a contact sheet of frames spread evenly through the video at
+/// `media_path` next to it as `<file>.preview.jpg`, laid out in `grid`, so
+/// a long run of downloads can be looked over without playing them.
+/// Returns where it was saved, or `None` when the file isn't a video or
+/// `on_conflict` kept an existing sheet.
+pub fn write_next_to(
+    media_path: &Path,
+    grid: Grid,
+    on_conflict: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    let Some(duration) = video_duration(media_path)? else {
+        eprintln!("No video in {} to preview", media_path.display());
+        return Ok(None);
+    };
+    let path = media_path.with_extension("preview.jpg");
+    if !conflict::should_write_sidecar(&path, on_conflict) {
+        println!("Skipped {}, which already exists", path.display());
+        return Ok(None);
+    }
+
+    // Each frame is from the middle of its share of the video, which keeps
+    // black opening and closing frames out
+    let interval = duration / f64::from(grid.frames());
+    let filter =
+        format!("fps=1/{interval:.3},scale={TILE_WIDTH}:-2,tile={grid}:padding=4:margin=4");
+    // ffmpeg picks the format from the extension, so the part file gets one
+    // after the final name
+    let staged = TempFile::new(download::part_path(&path).with_extension("tmp.jpg"));
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-ss"])
+        .arg(format!("{:.3}", interval / 2f64))
+        .arg("-i")
+        .arg(media_path)
+        .args(["-map", "0:v:0", "-an", "-vf", &filter])
+        .args(["-frames:v", "1", "-q:v", "3", "-update", "1"])
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} making a preview of {}",
+            media_path.display()
+        )));
+    }
+    conflict::place(staged.path(), &path, ConflictPolicy::Overwrite)
+}
@@ -0,0 +1,71 @@
+use crate::AppError;
+use crate::cli::{Cli, Commands, FailedCommand};
+use crate::config::Config;
+use clap::Parser;
+use std::ffi::OsString;
+use toml::Value;
+
+/// The download options a profile in the config stands for. Its keys are
+/// option names without their dashes: `max-res = "720p"` is `--max-res
+/// 720p`, `extract-audio = true` is `--extract-audio`, and a list gives the
+/// option once for each of its values.
+pub fn options(config: &Config, name: &str) -> Result<Vec<String>, AppError> {
+    let profile = config
+        .profile
+        .get(name)
+        .ok_or_else(|| AppError::InvalidConfig(format!("no profile named `{name}`")))?;
+    let mut options = vec![];
+    for (key, value) in profile {
+        let values = match value {
+            Value::Boolean(true) => {
+                options.push(format!("--{key}"));
+                continue;
+            }
+            Value::Boolean(false) => continue,
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(text) => text.clone(),
+                Value::Integer(number) => number.to_string(),
+                Value::Float(number) => number.to_string(),
+                _ => {
+                    return Err(AppError::InvalidConfig(format!(
+                        "profile.{name}.{key} isn't text, a number or a list of them"
+                    )));
+                }
+            };
+            options.extend([format!("--{key}"), value]);
+        }
+    }
+    Ok(options)
+}
+
+/// Puts the options of the profile chosen with `--profile` in front of the
+/// ones given on the command line, which override them, and parses the
+/// command line again.
+pub fn apply(cli: Cli, config: &Config) -> Result<Cli, AppError> {
+    let (command, name) = match &cli.command {
+        Some(Commands::Download(args)) => ("download", &args.profile),
+        #[cfg(unix)]
+        Some(Commands::Daemon(args)) => ("daemon", &args.download.profile),
+        Some(Commands::Music(args)) => ("music", &args.download.profile),
+        Some(Commands::Failed(FailedCommand::Retry(args))) => ("retry", &args.download.profile),
+        _ => return Ok(cli),
+    };
+    let Some(name) = name else {
+        return Ok(cli);
+    };
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    // Only global options, which take numbers, come before the command
+    let Some(position) = argv.iter().skip(1).position(|arg| arg == command) else {
+        return Ok(cli);
+    };
+    let options = options(config, name)?;
+    argv.splice(
+        position + 2..position + 2,
+        options.into_iter().map(OsString::from),
+    );
+    Ok(Cli::parse_from(argv))
+}
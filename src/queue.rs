@@ -0,0 +1,348 @@
+use crate::cli::QueueCommand;
+use crate::config::data_dir;
+use crate::schema::Schema;
+use crate::{AppError, download, resolve_url};
+use chrono::{Local, TimeZone};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(
+    Serialize, Deserialize, ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // pad() so the name can be aligned in the job list
+        f.pad(match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        })
+    }
+}
+
+/// A URL waiting to be downloaded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub url: String,
+    pub priority: Priority,
+    /// Paused jobs are suspended mid-transfer, or skipped if they haven't started
+    #[serde(default)]
+    pub paused: bool,
+    /// Unix timestamp the job waits for before starting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_at: Option<i64>,
+    /// A run started the job and hasn't finished it, having been stopped or
+    /// killed
+    #[serde(default)]
+    pub started: bool,
+}
+
+/// Pending downloads, kept in `queue.json` in the user's data directory.
+///
+/// Jobs are stored in the order they were queued, which is the order they
+/// run in within each priority. Jobs leave the file only once they're done,
+/// and it's synced to disk on every change, so a daemon that's killed, or
+/// loses power, picks up where it was on its next start.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Queue {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+/// The versions of the queue's format, which the file is brought through
+pub const SCHEMA: Schema = Schema { migrations: &[] };
+
+pub fn queue_path() -> Result<PathBuf, AppError> {
+    data_dir()
+        .map(|dir| dir.join("queue.json"))
+        .ok_or_else(|| AppError::Io("no data directory to keep the queue in".to_string()))
+}
+
+/// Waits for other processes to finish changing the queue, and keeps them
+/// from changing it until the returned file is dropped
+fn lock() -> Result<File, AppError> {
+    download::lock(&queue_path()?.with_extension("lock"))
+}
+
+impl Queue {
+    /// Loads the saved queue, or an empty one when nothing was queued yet.
+    pub fn load() -> Result<Queue, AppError> {
+        let path = queue_path()?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Queue::default()),
+            Err(e) => return Err(AppError::Io(format!("{}: {e}", path.display()))),
+        };
+        SCHEMA.parse(&path, &contents)
+    }
+
+    pub fn save(&self) -> Result<(), AppError> {
+        let path = queue_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+        }
+        let json = SCHEMA.to_json(self)?;
+        // Written whole so a running download watching it never reads half
+        // of it, and synced so a crash doesn't either
+        download::write_file_synced(&path, json)
+    }
+
+    /// Loads the queue, has `change` make changes to it and saves them, with
+    /// other processes held off in between so none of their changes are lost
+    pub fn update<T>(
+        change: impl FnOnce(&mut Queue) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let _lock = lock()?;
+        let mut queue = Queue::load()?;
+        let value = change(&mut queue)?;
+        queue.save()?;
+        Ok(value)
+    }
+
+    /// Queues `url` behind the jobs already waiting at the same priority.
+    pub fn add(&mut self, url: &str, priority: Priority, start_at: Option<i64>) -> &Job {
+        self.next_id += 1;
+        self.jobs.push(Job {
+            id: self.next_id,
+            url: url.to_string(),
+            priority,
+            paused: false,
+            start_at,
+            started: false,
+        });
+        self.jobs.last().unwrap()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Result<Job, AppError> {
+        let index = self.position(id)?;
+        Ok(self.jobs.remove(index))
+    }
+
+    /// Changes a job's priority, and with `first` moves it ahead of the other
+    /// jobs at that priority. A job moved to another priority without `first`
+    /// goes to the back of it, as if it were queued again.
+    pub fn reorder(
+        &mut self,
+        id: u64,
+        priority: Option<Priority>,
+        first: bool,
+    ) -> Result<&Job, AppError> {
+        let index = self.position(id)?;
+        let priority = priority.unwrap_or(self.jobs[index].priority);
+        if !first && priority == self.jobs[index].priority {
+            return Ok(&self.jobs[index]);
+        }
+
+        let mut job = self.jobs.remove(index);
+        job.priority = priority;
+        if first {
+            self.jobs.insert(0, job);
+            Ok(&self.jobs[0])
+        } else {
+            self.jobs.push(job);
+            Ok(self.jobs.last().unwrap())
+        }
+    }
+
+    /// Pending jobs in the order they'll run: ones a run started and didn't
+    /// finish first, as they've downloaded part of their files, then higher
+    /// priorities first, and in queue order within a priority. Jobs waiting
+    /// for a start time come after the rest, soonest first.
+    pub fn pending(&self) -> Vec<Job> {
+        let now = Local::now().timestamp();
+        let mut jobs = self.jobs.clone();
+        jobs.sort_by_key(|job| {
+            (
+                job.start_at.filter(|start| *start > now),
+                !job.started,
+                Reverse(job.priority),
+            )
+        });
+        jobs
+    }
+
+    pub fn set_paused(&mut self, id: u64, paused: bool) -> Result<&Job, AppError> {
+        let index = self.position(id)?;
+        self.jobs[index].paused = paused;
+        Ok(&self.jobs[index])
+    }
+
+    /// Marks jobs started in the saved queue, for a later run to resume them
+    /// first if this one doesn't finish them.
+    pub fn start(ids: &[u64]) -> Result<(), AppError> {
+        Queue::update(|queue| {
+            for job in queue.jobs.iter_mut().filter(|job| ids.contains(&job.id)) {
+                job.started = true;
+            }
+            Ok(())
+        })
+    }
+
+    /// Removes finished jobs from the saved queue, keeping any queued or
+    /// changed while they downloaded.
+    pub fn complete(ids: &[u64]) -> Result<(), AppError> {
+        Queue::update(|queue| {
+            queue.jobs.retain(|job| !ids.contains(&job.id));
+            Ok(())
+        })
+    }
+
+    fn position(&self, id: u64) -> Result<usize, AppError> {
+        self.jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or(AppError::UnknownJob(id))
+    }
+}
+
+/// Calls `on_change` with each of `jobs` whose paused state is changed in the
+/// saved queue, until `done` is set. Lets `queue pause` and `queue resume`
+/// from another terminal reach a running download.
+pub fn watch_pauses(jobs: &[Job], done: &AtomicBool, on_change: impl Fn(&Job)) {
+    let mut paused: Vec<bool> = jobs.iter().map(|job| job.paused).collect();
+    while !done.load(Ordering::Relaxed) {
+        thread::sleep(WATCH_INTERVAL);
+        // The file may be mid-write; the next look will catch up
+        let Ok(queue) = Queue::load() else {
+            continue;
+        };
+        for (job, was_paused) in jobs.iter().zip(&mut paused) {
+            let Some(saved) = queue.jobs.iter().find(|saved| saved.id == job.id) else {
+                continue;
+            };
+            if saved.paused != *was_paused {
+                *was_paused = saved.paused;
+                on_change(saved);
+            }
+        }
+    }
+}
+
+/// Queues each of `urls` and describes what was queued
+pub fn add_all(
+    queue: &mut Queue,
+    urls: &[String],
+    priority: Priority,
+    start_at: Option<i64>,
+) -> String {
+    let mut report = String::new();
+    for url in urls {
+        let job = queue.add(url, priority, start_at);
+        report.push_str(&format!(
+            "Queued {} at {} priority: {}\n",
+            job.id, job.priority, job.url
+        ));
+    }
+    if let Some(start) = start_at.and_then(|start| Local.timestamp_opt(start, 0).single()) {
+        report.push_str(&format!(
+            "Starting no earlier than {}\n",
+            start.format("%Y-%m-%d %H:%M")
+        ));
+    }
+    report
+}
+
+pub fn run(command: QueueCommand) -> Result<(), AppError> {
+    let command = match command {
+        QueueCommand::Add { urls, priority, at } => QueueCommand::Add {
+            urls: urls.iter().map(|url| resolve_url(url)).collect(),
+            priority,
+            at,
+        },
+        command => command,
+    };
+    // A running daemon picks added jobs up at once. It changes the queue
+    // itself, so the lock isn't taken until it's known there's none.
+    #[cfg(unix)]
+    if let QueueCommand::Add { urls, priority, at } = &command
+        && let Some(reply) = crate::daemon::send(&crate::daemon::Request::Add {
+            urls: urls.clone(),
+            priority: *priority,
+            start_at: at.map(|at| at.timestamp()),
+        })?
+    {
+        print!("{reply}");
+        return Ok(());
+    }
+    let _lock = lock()?;
+    let mut queue = Queue::load()?;
+    match command {
+        QueueCommand::Add { urls, priority, at } => {
+            let start_at = at.map(|at| at.timestamp());
+            print!("{}", add_all(&mut queue, &urls, priority, start_at));
+        }
+        QueueCommand::List => {
+            let jobs = queue.pending();
+            if jobs.is_empty() {
+                println!("Nothing is queued");
+            }
+            for job in jobs {
+                let mut notes = String::new();
+                if job.paused {
+                    notes.push_str("  (paused)");
+                }
+                if job.started {
+                    notes.push_str("  (started)");
+                }
+                if let Some(start) = job
+                    .start_at
+                    .and_then(|start| Local.timestamp_opt(start, 0).single())
+                    .filter(|start| *start > Local::now())
+                {
+                    notes.push_str(&format!("  (at {})", start.format("%Y-%m-%d %H:%M")));
+                }
+                println!("{:>4}  {:<6}  {}{notes}", job.id, job.priority, job.url);
+            }
+            return Ok(());
+        }
+        QueueCommand::Move {
+            id,
+            priority,
+            first,
+        } => {
+            let job = queue.reorder(id, priority, first)?;
+            if first {
+                println!("Moved {} to the front of {} priority", job.id, job.priority);
+            } else {
+                println!("Moved {} to {} priority", job.id, job.priority);
+            }
+        }
+        QueueCommand::Pause { ids } => {
+            for id in ids {
+                let job = queue.set_paused(id, true)?;
+                println!("Paused {}: {}", job.id, job.url);
+            }
+        }
+        QueueCommand::Resume { ids } => {
+            for id in ids {
+                let job = queue.set_paused(id, false)?;
+                println!("Resumed {}: {}", job.id, job.url);
+            }
+        }
+        QueueCommand::Remove { ids } => {
+            for id in ids {
+                let job = queue.remove(id)?;
+                println!("Removed {}: {}", job.id, job.url);
+            }
+        }
+    }
+    queue.save()
+}
@@ -0,0 +1,317 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, DownloadEvent, TempFile};
+use crate::fit::Fit;
+use crate::hwaccel::{self, Device, HwAccel};
+use crate::interrupt::{self, ChildGuard};
+use crate::{AppError, merge};
+use clap::ValueEnum;
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Containers downloads can be recoded into.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoContainer {
+    Mp4,
+    Mkv,
+    Webm,
+}
+
+impl VideoContainer {
+    /// The container of a file at `path`, or Matroska, which holds anything,
+    /// for extensions that aren't one
+    pub fn of(path: &Path) -> VideoContainer {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        VideoContainer::from_str(&extension, true).unwrap_or(VideoContainer::Mkv)
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            VideoContainer::Mp4 => "mp4",
+            VideoContainer::Mkv => "mkv",
+            VideoContainer::Webm => "webm",
+        }
+    }
+
+    /// Whether a stream in `codec` can go in as it is. A missing stream
+    /// (`none`) always can.
+    fn holds(self, codec: &str) -> bool {
+        codec == "none" || merge::holds(self.extension(), codec)
+    }
+
+    /// ffmpeg's arguments for encoding video that doesn't fit
+    fn video_encoder(self) -> &'static [&'static str] {
+        match self {
+            VideoContainer::Webm => &["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"],
+            VideoContainer::Mp4 | VideoContainer::Mkv => {
+                &["-c:v", "libx264", "-preset", "medium", "-crf", "23"]
+            }
+        }
+    }
+
+    /// ffmpeg's arguments for encoding audio that doesn't fit
+    fn audio_encoder(self) -> &'static [&'static str] {
+        match self {
+            VideoContainer::Webm => &["-c:a", "libopus", "-b:a", "128k"],
+            VideoContainer::Mp4 | VideoContainer::Mkv => &["-c:a", "aac", "-b:a", "192k"],
+        }
+    }
+}
+
+impl Display for VideoContainer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Codecs yt-dlp couldn't tell, which have to be found out by trying
+fn is_unknown(codec: &str) -> bool {
+    matches!(codec, "" | "unknown")
+}
+
+/// What to recode a video into.
+#[derive(Debug, Clone, Copy)]
+pub struct Target<'a> {
+    pub container: VideoContainer,
+    // Rendered into the frames, so the video is always transcoded
+    pub burn_subtitles: Option<&'a Path>,
+    // Videos narrower than its ratio are padded or cropped, and transcoded
+    pub fit: Option<Fit>,
+    // Tried first for video that's transcoded, falling back to software
+    pub hwaccel: Option<HwAccel>,
+}
+
+/// Recodes the video at `path`, whose streams are in `vcodec` and
+/// `acodec`, into `target`. Streams the container can hold are copied
+/// and the rest transcoded; when a codec isn't known, copying is tried
+/// first, and video is transcoded on a hardware encoder when `target` asks
+/// for one that works, or in software when it fails. The original is
+/// deleted unless `keep_original` is set. Returns where the result was
+/// saved, or `None` when an existing file was kept under `policy`.
+pub fn recode(
+    path: &Path,
+    (vcodec, acodec): (&str, &str),
+    target: Target,
+    duration: Option<f64>,
+    keep_original: bool,
+    policy: ConflictPolicy,
+    on_event: &mut dyn FnMut(&DownloadEvent),
+) -> Result<Option<PathBuf>, AppError> {
+    let Target {
+        container,
+        burn_subtitles,
+        fit,
+        hwaccel,
+    } = target;
+    // Audio alone isn't a video to recode
+    if vcodec == "none" {
+        return Ok(Some(path.to_path_buf()));
+    }
+    let extension = container.extension();
+    let target = path.with_extension(extension);
+    // Cropping comes first so no subtitles are cut off
+    let filters: Vec<String> = [
+        fit.map(|fit| fit.filter(path)).transpose()?.flatten(),
+        burn_subtitles.map(subtitles_filter),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let fits = |codec: &str| !is_unknown(codec) && container.holds(codec);
+    if target == path && filters.is_empty() && fits(vcodec) && fits(acodec) {
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let staged =
+        TempFile::new(download::part_path(&target).with_extension(format!("tmp.{extension}")));
+    let codecs = (vcodec, acodec);
+    let filter = (!filters.is_empty()).then(|| filters.join(","));
+    // Only looked for when the video is transcoded, which a known codec the
+    // container holds isn't without a filter
+    let device = hwaccel
+        .filter(|_| filter.is_some() || !fits(vcodec))
+        .and_then(hwaccel::device)
+        .filter(|device| device.encoder_args(container).is_some());
+    let mut attempt = |device: Option<Device>, transcode_all: bool| {
+        let (args, transcoding) =
+            stream_args(container, codecs, filter.as_deref(), device, transcode_all);
+        let input_args = device.map_or(&[][..], Device::input_args);
+        run(
+            (input_args, path),
+            &args,
+            staged.path(),
+            container,
+            transcoding,
+            duration,
+            on_event,
+        )
+    };
+    let unknown = is_unknown(vcodec) || is_unknown(acodec);
+    let mut result = attempt(device, false);
+    if result.is_err() && unknown && !interrupt::is_interrupted() {
+        // The unknown codec didn't fit after all
+        result = attempt(device, true);
+    }
+    if let Some(device) = device
+        && result.is_err()
+        && !interrupt::is_interrupted()
+    {
+        eprintln!(
+            "\r\x1b[2K{device} couldn't transcode {}, so it's transcoded in software",
+            path.display()
+        );
+        result = attempt(None, unknown);
+    }
+    result?;
+
+    if target == path {
+        return conflict::place(staged.path(), &target, ConflictPolicy::Overwrite);
+    }
+    let placed = conflict::place(staged.path(), &target, policy)?;
+    if !keep_original {
+        std::fs::remove_file(path).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    }
+    Ok(placed)
+}
+
+/// The video filter rendering the subtitles at `path` into the frames
+fn subtitles_filter(path: &Path) -> String {
+    // The path is escaped once as the filter's option, then again for the
+    // filter graph around it
+    let option = path
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace(':', "\\:");
+    let mut filter = "subtitles=".to_string();
+    for c in option.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            filter.push('\\');
+        }
+        filter.push(c);
+    }
+    filter
+}
+
+/// ffmpeg's arguments for the streams of a file in `codecs`: each copied
+/// when `container` holds it, or its codec is unknown, and transcoded
+/// otherwise, or always with `transcode_all`. Video is always transcoded
+/// through `filter` when there is one, on `device` when given. Also returns
+/// whether anything is transcoded.
+fn stream_args(
+    container: VideoContainer,
+    (vcodec, acodec): (&str, &str),
+    filter: Option<&str>,
+    device: Option<Device>,
+    transcode_all: bool,
+) -> (Vec<String>, bool) {
+    let copy = |codec: &str| !transcode_all && (is_unknown(codec) || container.holds(codec));
+    let (copy_video, copy_audio) = (filter.is_none() && copy(vcodec), copy(acodec));
+    let device = device.filter(|_| !copy_video);
+    let mut args = vec!["-map", "0:v:0", "-map", "0:a:0?"];
+    let filters: Vec<&str> = filter
+        .into_iter()
+        .chain(device.and_then(Device::upload_filter))
+        .collect();
+    let filters = filters.join(",");
+    if !filters.is_empty() {
+        args.extend(["-vf", &filters]);
+    }
+    args.extend(if copy_video {
+        &["-c:v", "copy"][..]
+    } else {
+        device
+            .and_then(|device| device.encoder_args(container))
+            .unwrap_or(container.video_encoder())
+    });
+    args.extend(if copy_audio {
+        &["-c:a", "copy"][..]
+    } else {
+        container.audio_encoder()
+    });
+    let args = args.into_iter().map(str::to_string).collect();
+    (args, !(copy_video && copy_audio))
+}
+
+/// Runs ffmpeg on `input`, opened with the arguments given with it, with
+/// `args`, reporting how far a transcode has got through `duration` seconds
+/// of it
+fn run(
+    (input_args, input): (&[&str], &Path),
+    args: &[String],
+    output: &Path,
+    container: VideoContainer,
+    transcoding: bool,
+    duration: Option<f64>,
+    on_event: &mut dyn FnMut(&DownloadEvent),
+) -> Result<(), AppError> {
+    let container_name = container.to_string();
+    on_event(&if transcoding {
+        DownloadEvent::Transcoding {
+            container: container_name.clone(),
+            fraction: duration.map(|_| 0f64),
+        }
+    } else {
+        DownloadEvent::Remuxing {
+            container: container_name.clone(),
+        }
+    });
+    let mut command = Command::new("ffmpeg");
+    command
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-nostats",
+            "-progress",
+            "pipe:1",
+        ])
+        .args(input_args)
+        .arg("-i")
+        .arg(input)
+        .args(args);
+    if container == VideoContainer::Mp4 {
+        // Lets players start before the whole file has loaded
+        command.args(["-movflags", "+faststart"]);
+    }
+    let mut child = command
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    let _guard = ChildGuard::new(&child);
+
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            // Microseconds, like the older `out_time_ms` despite its name
+            let Some(micros) = line
+                .strip_prefix("out_time_us=")
+                .and_then(|micros| micros.trim().parse::<f64>().ok())
+            else {
+                continue;
+            };
+            if transcoding && let Some(duration) = duration.filter(|duration| *duration > 0f64) {
+                on_event(&DownloadEvent::Transcoding {
+                    container: container_name.clone(),
+                    fraction: Some((micros / 1e6 / duration).clamp(0f64, 1f64)),
+                });
+            }
+        }
+    }
+
+    let status = child
+        .wait()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    interrupt::check()?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} recoding {} into {container}",
+            input.display()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use crate::cli::RemuxArgs;
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, TempFile};
+use crate::recode::VideoContainer;
+use crate::{AppError, merge};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<Stream>,
+}
+
+/// A stream of a media file, as ffprobe reports it
+#[derive(Deserialize)]
+struct Stream {
+    index: usize,
+    #[serde(default)]
+    codec_type: String,
+    #[serde(default)]
+    codec_name: String,
+    #[serde(default)]
+    disposition: Disposition,
+}
+
+#[derive(Deserialize, Default)]
+struct Disposition {
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+impl Stream {
+    /// What the stream is, for messages, e.g. `vp9 video`
+    fn describe(&self) -> String {
+        match self.disposition.attached_pic {
+            0 => format!("{} {}", self.codec_name, self.codec_type),
+            _ => format!("{} cover art", self.codec_name),
+        }
+    }
+
+    /// Whether `container` can hold the stream as it is
+    fn fits(&self, container: VideoContainer) -> bool {
+        let extension = container.to_string();
+        match (self.codec_type.as_str(), container) {
+            // Data streams, like timecodes, are left out
+            ("data", _) => true,
+            ("video", VideoContainer::Webm) if self.disposition.attached_pic != 0 => false,
+            ("video" | "audio", _) => merge::holds(&extension, &self.codec_name),
+            ("subtitle", VideoContainer::Mp4) => self.codec_name == "mov_text",
+            ("subtitle", VideoContainer::Webm) => self.codec_name == "webvtt",
+            ("subtitle", VideoContainer::Mkv) => self.codec_name != "mov_text",
+            ("attachment", container) => container == VideoContainer::Mkv,
+            _ => false,
+        }
+    }
+}
+
+/// The streams of the file at `path`, read with ffprobe
+fn probe(path: &Path) -> Result<Vec<Stream>, AppError> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-of", "json", "-show_entries"])
+        .arg("stream=index,codec_type,codec_name:stream_disposition=attached_pic")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffprobe: {e}")))?;
+    if !output.status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffprobe exited with {} reading {}: {}",
+            output.status,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let probe: Probe = serde_json::from_slice(&output.stdout)
+        .map_err(|e| AppError::InvalidJson(format!("ffprobe output: {e}")))?;
+    Ok(probe.streams)
+}
+
+/// Copies the streams of the file at `path` into `container` next to it,
+/// without re-encoding anything, and deletes the original unless
+/// `keep_original` is set. Fails, naming the streams, when the container
+/// can't hold them as they are. Returns where the result was saved, or
+/// `None` when an existing file was kept under `policy`.
+pub fn remux(
+    path: &Path,
+    container: VideoContainer,
+    keep_original: bool,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    let extension = container.to_string();
+    let target = path.with_extension(&extension);
+    if target == path {
+        println!("{} is already {extension}", path.display());
+        return Ok(Some(target));
+    }
+
+    let streams = probe(path)?;
+    let misfits: Vec<String> = streams
+        .iter()
+        .filter(|stream| !stream.fits(container))
+        .map(Stream::describe)
+        .collect();
+    if !misfits.is_empty() {
+        let fits_mkv = streams
+            .iter()
+            .all(|stream| stream.fits(VideoContainer::Mkv));
+        let hint = if fits_mkv {
+            "; --to mkv would keep them as they are"
+        } else {
+            ""
+        };
+        return Err(AppError::NeedsTranscode(format!(
+            "{} has {}, which {extension} can't hold without re-encoding{hint}",
+            path.display(),
+            misfits.join(" and ")
+        )));
+    }
+
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let staged =
+        TempFile::new(download::part_path(&target).with_extension(format!("tmp.{extension}")));
+    let mut command = Command::new("ffmpeg");
+    command.args(["-y", "-loglevel", "error", "-i"]).arg(path);
+    for stream in streams.iter().filter(|stream| stream.codec_type != "data") {
+        command.arg("-map").arg(format!("0:{}", stream.index));
+    }
+    command.args(["-c", "copy"]);
+    if container == VideoContainer::Mp4 {
+        // Lets players start before the whole file has loaded
+        command.args(["-movflags", "+faststart"]);
+    }
+    let status = command
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} remuxing {} into {extension}",
+            path.display()
+        )));
+    }
+
+    let placed = conflict::place(staged.path(), &target, policy)?;
+    if placed.is_some() && !keep_original {
+        std::fs::remove_file(path).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    }
+    Ok(placed)
+}
+
+/// Remuxes each of the files given on the command line
+pub fn run(args: &RemuxArgs) -> Result<(), AppError> {
+    for path in &args.paths {
+        match remux(path, args.to, args.keep_original, args.on_conflict)? {
+            Some(placed) if placed != *path => println!("Saved {}", placed.display()),
+            Some(_) => {}
+            None => println!(
+                "Skipped {}, which already exists",
+                path.with_extension(args.to.to_string()).display()
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::{AppError, interrupt};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+// Backoff stops growing here, so a long outage is still polled now and then
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often and how patiently failed requests are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    // Before the first retry, doubling for each one after it
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sets the policy used by every retried request.
+    pub fn install(self) {
+        POLICY.get_or_init(|| self);
+    }
+
+    pub fn current() -> RetryPolicy {
+        POLICY.get().copied().unwrap_or_default()
+    }
+
+    /// Exponential backoff with jitter between half and all of it, so
+    /// concurrent jobs that failed together don't retry in lockstep
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_DELAY);
+        let jitter = (RandomState::new().build_hasher().finish() % 1000) as f64 / 2000f64;
+        backoff.mul_f64(0.5 + jitter)
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails with an error that retrying won't
+/// fix, or runs out of retries, reporting each retry on stderr.
+pub fn with_retries<T>(mut attempt: impl FnMut() -> Result<T, AppError>) -> Result<T, AppError> {
+    let policy = RetryPolicy::current();
+    let mut retry = 0;
+    loop {
+        match attempt() {
+            Err(error)
+                if error.is_retryable()
+                    && retry < policy.retries
+                    && !interrupt::is_interrupted() =>
+            {
+                let delay = policy.backoff(retry);
+                retry += 1;
+                eprintln!(
+                    // Clears any progress line the failed attempt left
+                    "\r\x1b[2K{error}; retrying in {:.1}s ({retry}/{})",
+                    delay.as_secs_f64(),
+                    policy.retries
+                );
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
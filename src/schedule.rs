@@ -0,0 +1,252 @@
+use crate::{download, interrupt};
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The most a recurring task's runs are spread out by
+const MAX_JITTER: Duration = Duration::from_secs(10 * 60);
+
+/// The longest a recurring task can wait between runs, in days, which
+/// keeps the next run's time within what a date can hold
+const MAX_INTERVAL_DAYS: u64 = 10 * 365;
+
+/// A daily stretch of local time when transfers may run, like `01:00-07:00`.
+/// Windows that end before they start run past midnight.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+fn parse_clock(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .map_err(|_| format!("invalid time `{value}`, expected HH:MM"))
+}
+
+impl Window {
+    pub fn parse(value: &str) -> Result<Window, String> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| format!("invalid window `{value}`, expected HH:MM-HH:MM"))?;
+        Ok(Window {
+            start: parse_clock(start)?,
+            end: parse_clock(end)?,
+        })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.contains(Local::now().time())
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Window, String> {
+        Window::parse(&value)
+    }
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// When a recurring task runs, like `every 6h` or `daily at 03:00`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Recurrence {
+    Every(Duration),
+    DailyAt(NaiveTime),
+}
+
+impl Recurrence {
+    pub fn parse(value: &str) -> Result<Recurrence, String> {
+        let value = value.trim();
+        let invalid =
+            || format!("invalid schedule `{value}`, expected like `every 6h` or `daily at 03:00`");
+        if let Some(time) = value.strip_prefix("daily at ") {
+            return Ok(Recurrence::DailyAt(parse_clock(time)?));
+        }
+        let interval = value.strip_prefix("every ").ok_or_else(invalid)?.trim();
+        let unit = match interval.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let count = interval[..interval.len() - 1]
+            .parse::<u64>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(invalid)?;
+        count
+            .checked_mul(unit)
+            .filter(|secs| *secs <= MAX_INTERVAL_DAYS * 24 * 60 * 60)
+            .map(|secs| Recurrence::Every(Duration::from_secs(secs)))
+            .ok_or_else(|| {
+                format!("schedule `{value}` is too long, at most `every {MAX_INTERVAL_DAYS}d`")
+            })
+    }
+
+    /// When the task runs next after running at `now`. Runs are pushed back
+    /// by up to a twentieth of the time between them, so tasks set for the
+    /// same time don't all hit a site at once.
+    pub fn next_after(&self, now: DateTime<Local>) -> DateTime<Local> {
+        let (next, period) = match self {
+            Recurrence::Every(interval) => (now + *interval, *interval),
+            Recurrence::DailyAt(time) => (
+                next_occurrence(*time, now).unwrap_or(now + Days::new(1)),
+                Duration::from_secs(24 * 60 * 60),
+            ),
+        };
+        next + jitter((period / 20).min(MAX_JITTER))
+    }
+}
+
+/// A duration of up to `max`, which needn't be truly random
+fn jitter(max: Duration) -> Duration {
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    Duration::from_secs(u64::from(seed) % (max.as_secs() + 1))
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Recurrence, String> {
+        Recurrence::parse(&value)
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> String {
+        recurrence.to_string()
+    }
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recurrence::Every(interval) => {
+                let seconds = interval.as_secs();
+                let (count, unit) = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")]
+                    .into_iter()
+                    .find(|(unit, _)| seconds % unit == 0)
+                    .map(|(unit, name)| (seconds / unit, name))
+                    .unwrap_or((seconds, "s"));
+                write!(f, "every {count}{unit}")
+            }
+            Recurrence::DailyAt(time) => write!(f, "daily at {}", time.format("%H:%M")),
+        }
+    }
+}
+
+/// The next time the clock reads `time` after `now`
+fn next_occurrence(time: NaiveTime, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    let today = now.date_naive();
+    [Some(today), today.checked_add_days(Days::new(1))]
+        .into_iter()
+        .flatten()
+        .filter_map(|date: NaiveDate| local(date.and_time(time)))
+        .find(|next| *next > now)
+}
+
+/// A local date and time, taking the earlier one when a clock change makes
+/// it ambiguous
+fn local(time: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&time).earliest()
+}
+
+/// Parses when to start a job: `HH:MM` for its next occurrence, or
+/// `YYYY-MM-DD HH:MM`.
+pub fn parse_start(value: &str) -> Result<DateTime<Local>, String> {
+    let value = value.trim();
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M") {
+        return local(time).ok_or_else(|| format!("{value} doesn't exist in the local time zone"));
+    }
+    let time = NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| format!("invalid time `{value}`, expected HH:MM or YYYY-MM-DD HH:MM"))?;
+    next_occurrence(time, Local::now())
+        .ok_or_else(|| format!("{value} doesn't exist in the local time zone"))
+}
+
+/// Sleeps until `time`, checking the clock now and then so a suspended
+/// machine doesn't oversleep
+pub fn wait_until(time: DateTime<Local>) {
+    while let Ok(remaining) = (time - Local::now()).to_std() {
+        if remaining.is_zero() || interrupt::is_interrupted() {
+            break;
+        }
+        thread::sleep(remaining.min(CHECK_INTERVAL));
+    }
+}
+
+/// Holds a queued job until its start time, a Unix timestamp
+pub fn wait_until_timestamp(timestamp: i64, url: &str) {
+    let Some(start) = Local.timestamp_opt(timestamp, 0).single() else {
+        return;
+    };
+    if start > Local::now() {
+        eprintln!(
+            "Waiting until {} to start {url}",
+            start.format("%Y-%m-%d %H:%M")
+        );
+        wait_until(start);
+    }
+}
+
+/// Blocks until `window` is open, saying so first if it isn't
+pub fn wait_for(window: &Window) {
+    if window.is_open() {
+        return;
+    }
+    eprintln!("Waiting for the {window} download window to open");
+    while !window.is_open() && !interrupt::is_interrupted() {
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+/// Holds every transfer while `window` is closed, until `done` is set.
+/// Transfers pick up where they stopped when it opens again.
+pub fn hold_outside(window: &Window, done: &AtomicBool) {
+    // Jobs wait for the window themselves before starting
+    let mut held = !window.is_open();
+    download::hold_all(held);
+    while !done.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_secs(1));
+        if window.is_open() == held {
+            held = !held;
+            download::hold_all(held);
+            if held {
+                eprintln!("\r\x1b[2KThe {window} download window closed; holding transfers");
+            } else {
+                eprintln!("\r\x1b[2KThe {window} download window opened; continuing transfers");
+            }
+        }
+    }
+    download::hold_all(false);
+}
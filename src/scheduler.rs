@@ -0,0 +1,83 @@
+use crate::{AppError, interrupt};
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Where a job is in its lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JobStatus::Queued => write!(f, "Queued"),
+            JobStatus::Running => write!(f, "Running"),
+            JobStatus::Succeeded => write!(f, "Done"),
+            JobStatus::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+/// Runs `work` on each input with at most `jobs` running at once, and returns
+/// the results in input order.
+///
+/// Jobs are isolated: an error or even a panic only fails that job's result.
+/// `on_status` is called with the job's index whenever its status changes.
+/// After Ctrl-C, jobs that haven't started fail with `AppError::Interrupted`
+/// without running.
+pub fn run<I, T, F, S>(inputs: &[I], jobs: usize, work: F, on_status: S) -> Vec<Result<T, AppError>>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&I) -> Result<T, AppError> + Sync,
+    S: Fn(usize, &JobStatus) + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<T, AppError>>>> =
+        Mutex::new(inputs.iter().map(|_| None).collect());
+    for index in 0..inputs.len() {
+        on_status(index, &JobStatus::Queued);
+    }
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, inputs.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(input) = inputs.get(index) else {
+                        break;
+                    };
+                    if interrupt::is_interrupted() {
+                        results.lock().unwrap()[index] = Some(Err(AppError::Interrupted));
+                        continue;
+                    }
+                    on_status(index, &JobStatus::Running);
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| work(input)))
+                        .unwrap_or_else(|_| {
+                            Err(AppError::CommandFailed("download job panicked".to_string()))
+                        });
+                    let status = match result {
+                        Ok(_) => JobStatus::Succeeded,
+                        Err(_) => JobStatus::Failed,
+                    };
+                    on_status(index, &status);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every job runs once"))
+        .collect()
+}
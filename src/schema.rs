@@ -0,0 +1,314 @@
+use crate::cli::Cli;
+use crate::config::{self, Config};
+use crate::failed::DeadLetters;
+use crate::queue::Queue;
+use crate::subscription::Subscriptions;
+use crate::{AppError, failed, history, profile, queue, subscription};
+use clap::Parser;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// Brings a file's contents from one version of its format to the next
+pub type Migration<T> = fn(&mut T);
+
+/// The format of one of the JSON files state is kept in, at the version
+/// after the last of its migrations. Files from before there were versions
+/// are version 1, and every file is brought up to the current version as
+/// it's read, so its fields can change without breaking it.
+pub struct Schema {
+    pub migrations: &'static [Migration<Map<String, Value>>],
+}
+
+/// The version of `object` from the file at `path`, taken out of it
+fn take_version(path: &Path, object: &mut Map<String, Value>) -> Result<u64, AppError> {
+    match object.remove("version") {
+        None => Ok(1),
+        Some(version) => version.as_u64().ok_or_else(|| {
+            AppError::InvalidJson(format!("{}: version isn't a number", path.display()))
+        }),
+    }
+}
+
+impl Schema {
+    pub const fn version(&self) -> u64 {
+        self.migrations.len() as u64 + 1
+    }
+
+    /// The version of the file at `path`, or `None` when there's none, or
+    /// it isn't one of these files
+    fn version_of(&self, path: &Path) -> Option<u64> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        match serde_json::from_str(&contents).ok()? {
+            Value::Object(mut object) => take_version(path, &mut object).ok(),
+            _ => None,
+        }
+    }
+
+    /// Reads the contents of the file at `path`, migrating them from
+    /// whichever version they're at
+    pub fn parse<T: DeserializeOwned>(&self, path: &Path, contents: &str) -> Result<T, AppError> {
+        let invalid =
+            |e: serde_json::Error| AppError::InvalidJson(format!("{}: {e}", path.display()));
+        let Value::Object(mut object) = serde_json::from_str(contents).map_err(invalid)? else {
+            return Err(AppError::InvalidJson(format!(
+                "{}: not a JSON object",
+                path.display()
+            )));
+        };
+        let version = take_version(path, &mut object)?;
+        if version > self.version() {
+            return Err(AppError::NewerFormat(format!(
+                "{} is at version {version} of its format, newer than {}",
+                path.display(),
+                self.version()
+            )));
+        }
+        for migration in &self.migrations[version.saturating_sub(1) as usize..] {
+            migration(&mut object);
+        }
+        serde_json::from_value(Value::Object(object)).map_err(invalid)
+    }
+
+    /// `value` as the contents of a file at the current version
+    pub fn to_json<T: Serialize>(&self, value: &T) -> Result<String, AppError> {
+        let mut value =
+            serde_json::to_value(value).map_err(|e| AppError::InvalidJson(e.to_string()))?;
+        if let Value::Object(object) = &mut value {
+            object.insert("version".to_string(), self.version().into());
+        }
+        serde_json::to_string_pretty(&value).map_err(|e| AppError::InvalidJson(e.to_string()))
+    }
+}
+
+/// A state file's schema, where it's kept, and how to load and save it
+type StateFile = (
+    &'static Schema,
+    fn() -> Result<PathBuf, AppError>,
+    fn() -> Result<(), AppError>,
+);
+
+const STATE_FILES: [StateFile; 3] = [
+    (&queue::SCHEMA, queue::queue_path, || {
+        Queue::update(|_| Ok(()))
+    }),
+    (&failed::SCHEMA, failed::dead_letters_path, || {
+        DeadLetters::update(|_| Ok(()))
+    }),
+    (
+        &subscription::SCHEMA,
+        subscription::subscriptions_path,
+        || Subscriptions::update(|_| Ok(())),
+    ),
+];
+
+/// Brings the state files written by older versions up to date, so it's
+/// done once rather than each time they're read. It's done when it can be,
+/// so commands that don't need the files still run: files with nowhere to
+/// be kept are skipped, and ones that can't be updated are reported and
+/// left for the commands that use them, as are files from newer versions.
+pub fn migrate() {
+    for (schema, path, update) in STATE_FILES {
+        let Ok(path) = path() else {
+            continue;
+        };
+        if schema
+            .version_of(&path)
+            .is_none_or(|version| version >= schema.version())
+        {
+            continue;
+        }
+        eprintln!(
+            "Updating {} to version {} of its format",
+            path.display(),
+            schema.version()
+        );
+        if let Err(error) = update() {
+            eprintln!("Couldn't update {}: {error}", path.display());
+        }
+    }
+    if let Ok(path) = history::history_path()
+        && let Err(error) = history::migrate_existing()
+    {
+        eprintln!("Couldn't update {}: {error}", path.display());
+    }
+}
+
+/// What's wrong with the config's settings, other than what reading it
+/// catches
+fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+    let mut profiles: Vec<&String> = config.profile.keys().collect();
+    profiles.sort();
+    for name in profiles {
+        // Profiles hold download options, so they have to parse as them
+        let problem = match profile::options(config, name) {
+            Err(error) => Some(error.to_string()),
+            Ok(options) => {
+                let argv = ["downloader", "download"]
+                    .into_iter()
+                    .map(String::from)
+                    .chain(options)
+                    .chain(["https://example.com".to_string()]);
+                Cli::try_parse_from(argv).err().map(|e| {
+                    let message = e.to_string();
+                    let first_line = message.lines().next().unwrap_or_default();
+                    first_line.trim_start_matches("error: ").to_string()
+                })
+            }
+        };
+        if let Some(problem) = problem {
+            problems.push(format!("profile.{name}: {problem}"));
+        }
+    }
+    for retention in &config.cleanup {
+        if retention.max_size.is_none() && retention.max_age_days.is_none() {
+            problems.push(format!(
+                "cleanup of {}: no max_size or max_age_days",
+                retention.dir.display()
+            ));
+        } else if !retention.dir.is_dir() {
+            problems.push(format!(
+                "cleanup of {}: not a folder",
+                retention.dir.display()
+            ));
+        }
+    }
+    if let Some(dir) = &config.watch_dir
+        && !dir.is_dir()
+    {
+        problems.push(format!("watch_dir {}: not a folder", dir.display()));
+    }
+    problems
+}
+
+/// How a state file at `path` with `schema` stands: whether it's there,
+/// and at which version
+fn state_status(
+    schema: &Schema,
+    path: &Path,
+    loaded: Result<(), AppError>,
+) -> Result<String, AppError> {
+    if !path.exists() {
+        return Ok("none yet".to_string());
+    }
+    loaded?;
+    Ok(version_status(
+        schema.version_of(path).unwrap_or(1) as i64,
+        schema.version() as i64,
+    ))
+}
+
+fn version_status(version: i64, current: i64) -> String {
+    if version < current {
+        format!("ok, version {version}, updated to {current} on the next run")
+    } else {
+        format!("ok, version {version}")
+    }
+}
+
+/// Checks the config file and the state files can be read by this version,
+/// listing what's wrong with them, for `--check-config`
+pub fn check() -> Result<(), AppError> {
+    let mut problems = 0;
+    let mut report = |name: &Path, result: Result<String, AppError>| match result {
+        Ok(status) => println!("{}: {status}", name.display()),
+        // Errors name the file themselves
+        Err(error) => {
+            println!("{error}");
+            problems += 1;
+        }
+    };
+
+    if let Some(path) = config::config_path() {
+        match Config::load() {
+            Ok(_) if !path.exists() => report(&path, Ok("none, using the defaults".to_string())),
+            Ok(config) => {
+                report(&path, Ok("ok".to_string()));
+                for problem in config_problems(&config) {
+                    let problem = format!("{}: {problem}", path.display());
+                    report(&path, Err(AppError::InvalidConfig(problem)));
+                }
+            }
+            Err(error) => report(&path, Err(error)),
+        }
+    }
+    let path = queue::queue_path()?;
+    report(
+        &path,
+        state_status(&queue::SCHEMA, &path, Queue::load().map(|_| ())),
+    );
+    let path = failed::dead_letters_path()?;
+    report(
+        &path,
+        state_status(&failed::SCHEMA, &path, DeadLetters::load().map(|_| ())),
+    );
+    let path = subscription::subscriptions_path()?;
+    report(
+        &path,
+        state_status(
+            &subscription::SCHEMA,
+            &path,
+            Subscriptions::load().map(|_| ()),
+        ),
+    );
+    let path = history::history_path()?;
+    report(
+        &path,
+        history::stored_version().and_then(|version| match version {
+            None => Ok("none yet".to_string()),
+            Some(version) if version > history::SCHEMA_VERSION => {
+                Err(history::newer_version(&path, version))
+            }
+            Some(version) => Ok(version_status(version, history::SCHEMA_VERSION)),
+        }),
+    );
+
+    if problems > 0 {
+        return Err(AppError::CommandFailed(format!(
+            "{problems} problem(s) found"
+        )));
+    }
+    println!("No problems found");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Serialize)]
+    struct Entry {
+        url: String,
+        tags: Vec<String>,
+    }
+
+    /// Version 2 renamed `link` to `url` and added `tags`
+    fn to_version_2(object: &mut Map<String, Value>) {
+        if let Some(link) = object.remove("link") {
+            object.insert("url".to_string(), link);
+        }
+        object.insert("tags".to_string(), Value::Array(vec![]));
+    }
+
+    const SCHEMA: Schema = Schema {
+        migrations: &[to_version_2],
+    };
+
+    #[test]
+    fn version_1_files_are_brought_forward() {
+        let path = Path::new("entry.json");
+        let entry: Entry = SCHEMA
+            .parse(path, r#"{"link": "https://example.com"}"#)
+            .unwrap();
+        assert_eq!(entry.url, "https://example.com");
+        assert!(entry.tags.is_empty());
+
+        let saved: Value = serde_json::from_str(&SCHEMA.to_json(&entry).unwrap()).unwrap();
+        assert_eq!(saved["version"], 2);
+        let reread: Entry = SCHEMA.parse(path, &saved.to_string()).unwrap();
+        assert_eq!(reread.url, "https://example.com");
+    }
+}
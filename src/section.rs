@@ -0,0 +1,136 @@
+use crate::download::{self, TempFile};
+use crate::{AppError, format_duration};
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A stretch of a video to download instead of all of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Section {
+    // Seconds from the start of the video
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Parses `HH:MM:SS`, `MM:SS` or plain seconds, any of them with a fraction
+fn parse_time(time: &str) -> Option<f64> {
+    let mut seconds = 0f64;
+    let parts: Vec<&str> = time.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    for part in parts {
+        let value: f64 = part.parse().ok()?;
+        if !value.is_finite() || value < 0f64 {
+            return None;
+        }
+        seconds = seconds * 60f64 + value;
+    }
+    Some(seconds)
+}
+
+impl Section {
+    /// Parses `START-END`, like `00:05:00-00:12:30`. yt-dlp's leading `*` is
+    /// accepted too.
+    pub fn parse(value: &str) -> Result<Section, String> {
+        let range = value.trim().trim_start_matches('*');
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("`{value}` isn't a range like 00:05:00-00:12:30"))?;
+        let (Some(start), Some(end)) = (parse_time(start), parse_time(end)) else {
+            return Err(format!("`{value}` has an invalid time"));
+        };
+        if end <= start {
+            return Err(format!("`{value}` ends before it starts"));
+        }
+        Ok(Section { start, end })
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+
+    /// yt-dlp's `--download-sections` value for the section
+    pub fn ytdlp_arg(&self) -> String {
+        format!("*{}-{}", self.start, self.end)
+    }
+
+    /// `template` with the section added to the end of the file name, so
+    /// each section of a video gets a file of its own, e.g.
+    /// `Title [id] 0h05m00s-0h12m30s.mp4`
+    pub fn template(&self, template: &str) -> String {
+        let label = format!("{}-{}", file_time(self.start), file_time(self.end));
+        match template.strip_suffix(".%(ext)s") {
+            Some(stem) => format!("{stem} {label}.%(ext)s"),
+            None => format!("{template} {label}"),
+        }
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            format_duration(self.start),
+            format_duration(self.end)
+        )
+    }
+}
+
+/// A time for file names, which can't all take colons
+fn file_time(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    format!("{hours}h{minutes:02}m{seconds:02}s")
+}
+
+/// A stream for ffmpeg to read: its URL and the headers to fetch it with
+pub type Input<'a> = (&'a str, &'a [(String, String)]);
+
+/// Saves `section` of the streams at `inputs` to `path`. ffmpeg seeks into
+/// them and copies just the section, starting at the keyframe before it.
+/// With two inputs, the first gives the video and the second the audio.
+pub fn download(section: &Section, inputs: &[Input], path: &Path) -> Result<(), AppError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+    }
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let staged =
+        TempFile::new(download::part_path(path).with_extension(format!("tmp.{extension}")));
+    let mut command = Command::new("ffmpeg");
+    command.args(["-y", "-loglevel", "error"]);
+    for (url, headers) in inputs {
+        if !headers.is_empty() {
+            let headers: String = headers
+                .iter()
+                .map(|(name, value)| format!("{name}: {value}\r\n"))
+                .collect();
+            command.arg("-headers").arg(headers);
+        }
+        command
+            .arg("-ss")
+            .arg(section.start.to_string())
+            .arg("-t")
+            .arg(section.duration().to_string())
+            .arg("-i")
+            .arg(url);
+    }
+    if inputs.len() > 1 {
+        command.args(["-map", "0:v:0", "-map", "1:a:0"]);
+    }
+    let status = command
+        .args(["-c", "copy"])
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} downloading {section}"
+        )));
+    }
+    std::fs::rename(staged.path(), path).map_err(|e| AppError::Io(e.to_string()))
+}
@@ -0,0 +1,128 @@
+use crate::download::{DownloadEvent, DownloadObserver, Progress};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// How far back the current speed looks
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// Throughput of each running job, by the URL it was started with
+static JOBS: LazyLock<Mutex<HashMap<String, Throughput>>> = LazyLock::new(Default::default);
+
+/// Bytes a download had at recent points in time, for its current and
+/// average speed.
+#[derive(Debug)]
+struct Throughput {
+    started: Instant,
+    first_bytes: u64,
+    samples: VecDeque<(Instant, u64)>,
+    latest: Progress,
+}
+
+impl Throughput {
+    fn new(now: Instant, bytes: u64) -> Throughput {
+        Throughput {
+            started: now,
+            first_bytes: bytes,
+            samples: VecDeque::new(),
+            latest: Progress::default(),
+        }
+    }
+
+    /// Records `progress` and fills in its speeds and ETA. Speeds the
+    /// backend reported are kept until there's enough to measure.
+    fn update(&mut self, progress: &mut Progress) {
+        let now = Instant::now();
+        let bytes = progress.downloaded_bytes;
+        // A new stream of the same job, like the audio after the video
+        if self.samples.back().is_some_and(|(_, last)| bytes < *last) {
+            *self = Throughput::new(now, bytes);
+        }
+        self.samples.push_back((now, bytes));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > SPEED_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let rate = |since: Instant, from: u64| {
+            let elapsed = now.duration_since(since).as_secs_f64();
+            (elapsed > 0f64).then(|| (bytes - from) as f64 / elapsed)
+        };
+        if let Some((since, from)) = self.samples.front()
+            && let Some(speed) = rate(*since, *from)
+        {
+            progress.speed = Some(speed);
+        }
+        if let Some(average) = rate(self.started, self.first_bytes) {
+            progress.average_speed = Some(average);
+        }
+        let speed = progress
+            .speed
+            .or(progress.average_speed)
+            .filter(|speed| *speed > 0f64);
+        if let Some((total, speed)) = progress.total_bytes.zip(speed) {
+            progress.eta = Some(total.saturating_sub(bytes) as f64 / speed);
+        }
+        self.latest = progress.clone();
+    }
+}
+
+/// Passes events on to another observer, with the speeds and ETA of each
+/// progress update measured over a rolling window. Also keeps the totals
+/// `overall` reports across concurrent jobs.
+pub struct Tracked<O>(pub O);
+
+impl<O: DownloadObserver> DownloadObserver for Tracked<O> {
+    fn on_event(&self, url: &str, event: &DownloadEvent) {
+        match event {
+            DownloadEvent::Progress(progress) => {
+                let mut progress = progress.clone();
+                JOBS.lock()
+                    .unwrap()
+                    .entry(url.to_string())
+                    .or_insert_with(|| Throughput::new(Instant::now(), progress.downloaded_bytes))
+                    .update(&mut progress);
+                self.0.on_event(url, &DownloadEvent::Progress(progress));
+            }
+            DownloadEvent::Completed { .. }
+            | DownloadEvent::Skipped { .. }
+            | DownloadEvent::Failed { .. } => {
+                JOBS.lock().unwrap().remove(url);
+                self.0.on_event(url, event);
+            }
+            _ => self.0.on_event(url, event),
+        }
+    }
+}
+
+/// The combined progress of every job that is transferring, and how many
+/// there are. The total and ETA are only known when every job's total is.
+pub fn overall() -> (usize, Progress) {
+    let jobs = JOBS.lock().unwrap();
+    let sum = |speed: fn(&Progress) -> Option<f64>| {
+        jobs.values()
+            .filter_map(|job| speed(&job.latest))
+            .reduce(|a, b| a + b)
+    };
+    let downloaded_bytes = jobs.values().map(|job| job.latest.downloaded_bytes).sum();
+    let total_bytes = jobs
+        .values()
+        .map(|job| job.latest.total_bytes)
+        .sum::<Option<u64>>();
+    let speed = sum(|progress| progress.speed);
+    let eta = total_bytes
+        .zip(speed)
+        .filter(|(_, speed)| *speed > 0f64)
+        .map(|(total, speed)| total.saturating_sub(downloaded_bytes) as f64 / speed);
+    let progress = Progress {
+        downloaded_bytes,
+        total_bytes,
+        speed,
+        average_speed: sum(|progress| progress.average_speed),
+        eta,
+    };
+    (jobs.len(), progress)
+}
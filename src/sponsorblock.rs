@@ -0,0 +1,271 @@
+use crate::chapter::Chapter;
+use crate::download::{self, TempFile};
+use crate::{AppError, FileDetails, http};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const API: &str = "https://sponsor.ajay.app/api/skipSegments";
+
+/// What to do with the SponsorBlock segments of a video.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SponsorBlockMode {
+    /// Add them as chapters
+    Mark,
+    /// Cut them out
+    Remove,
+}
+
+/// Kinds of segments SponsorBlock users submit.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentCategory {
+    Sponsor,
+    Intro,
+    Outro,
+    Selfpromo,
+    Preview,
+    Filler,
+    Interaction,
+    MusicOfftopic,
+}
+
+impl SegmentCategory {
+    fn api_name(self) -> &'static str {
+        match self {
+            SegmentCategory::Sponsor => "sponsor",
+            SegmentCategory::Intro => "intro",
+            SegmentCategory::Outro => "outro",
+            SegmentCategory::Selfpromo => "selfpromo",
+            SegmentCategory::Preview => "preview",
+            SegmentCategory::Filler => "filler",
+            SegmentCategory::Interaction => "interaction",
+            SegmentCategory::MusicOfftopic => "music_offtopic",
+        }
+    }
+
+    fn from_api_name(name: &str) -> Option<SegmentCategory> {
+        SegmentCategory::value_variants()
+            .iter()
+            .copied()
+            .find(|category| category.api_name() == name)
+    }
+
+    /// The title of a chapter marking a segment
+    fn title(self) -> &'static str {
+        match self {
+            SegmentCategory::Sponsor => "Sponsor",
+            SegmentCategory::Intro => "Intro",
+            SegmentCategory::Outro => "Outro",
+            SegmentCategory::Selfpromo => "Self-promotion",
+            SegmentCategory::Preview => "Preview",
+            SegmentCategory::Filler => "Filler",
+            SegmentCategory::Interaction => "Interaction reminder",
+            SegmentCategory::MusicOfftopic => "Non-music section",
+        }
+    }
+}
+
+/// A stretch of a video SponsorBlock users marked as one of the categories.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub category: SegmentCategory,
+    // Seconds from the start of the video
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiSegment {
+    category: String,
+    action_type: String,
+    segment: (f64, f64),
+}
+
+/// Fetches the segments of the YouTube video `video_id` in `categories`,
+/// earliest first. Videos nobody has submitted any for have none.
+pub fn fetch(video_id: &str, categories: &[SegmentCategory]) -> Result<Vec<Segment>, AppError> {
+    let categories: Vec<&str> = categories
+        .iter()
+        .map(|category| category.api_name())
+        .collect();
+    let categories = serde_json::to_string(&categories).unwrap_or_default();
+    let url = reqwest::Url::parse_with_params(
+        API,
+        [("videoID", video_id), ("categories", categories.as_str())],
+    )
+    .map_err(|e| AppError::Http(e.to_string()))?;
+    let body = match http::fetch_text(url.as_str()) {
+        Err(AppError::HttpStatus(404, _)) => return Ok(vec![]),
+        result => result?,
+    };
+    let segments: Vec<ApiSegment> =
+        serde_json::from_str(&body).map_err(|e| AppError::InvalidJson(e.to_string()))?;
+    let mut segments: Vec<Segment> = segments
+        .into_iter()
+        // Other actions mute the segment or label the whole video
+        .filter(|segment| segment.action_type == "skip")
+        .filter_map(|segment| {
+            Some(Segment {
+                category: SegmentCategory::from_api_name(&segment.category)?,
+                start: segment.segment.0,
+                end: segment.segment.1,
+            })
+        })
+        .filter(|segment| segment.end > segment.start)
+        .collect();
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(segments)
+}
+
+/// `segments` with overlapping ones joined, as stretches of time to remove
+fn merged(segments: &[Segment]) -> Vec<(f64, f64)> {
+    let mut ranges: Vec<(f64, f64)> = vec![];
+    for segment in segments {
+        match ranges.last_mut() {
+            Some((_, end)) if segment.start <= *end => *end = end.max(segment.end),
+            _ => ranges.push((segment.start, segment.end)),
+        }
+    }
+    ranges
+}
+
+/// `chapters` with `segments` cut out of them and added as chapters of
+/// their own, in order
+pub fn mark(chapters: &[Chapter], segments: &[Segment]) -> Vec<Chapter> {
+    let removed = merged(segments);
+    let mut marked: Vec<Chapter> = chapters
+        .iter()
+        .flat_map(|chapter| {
+            let mut pieces = vec![];
+            let mut start = chapter.start_time;
+            for &(cut_start, cut_end) in &removed {
+                if cut_end <= start || cut_start >= chapter.end_time {
+                    continue;
+                }
+                if cut_start > start {
+                    pieces.push((start, cut_start));
+                }
+                start = cut_end;
+            }
+            if start < chapter.end_time {
+                pieces.push((start, chapter.end_time));
+            }
+            pieces.into_iter().map(|(start_time, end_time)| Chapter {
+                title: chapter.title.clone(),
+                start_time,
+                end_time,
+            })
+        })
+        .collect();
+    marked.extend(segments.iter().map(|segment| Chapter {
+        title: format!("[SponsorBlock]: {}", segment.category.title()),
+        start_time: segment.start,
+        end_time: segment.end,
+    }));
+    marked.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+    marked
+}
+
+/// Where `time` in the video ends up once `removed` is cut out of it
+fn shifted(time: f64, removed: &[(f64, f64)]) -> f64 {
+    let cut: f64 = removed
+        .iter()
+        .filter(|(start, _)| *start < time)
+        .map(|(start, end)| end.min(time) - start)
+        .sum();
+    time - cut
+}
+
+/// Cuts `segments` out of the file at `media` in place, copying the streams
+/// between them, which cuts at the nearest keyframes. The chapters and
+/// duration in `details` are moved to match, leaving out emptied chapters.
+pub fn remove(
+    media: &Path,
+    segments: &[Segment],
+    details: &mut FileDetails,
+) -> Result<(), AppError> {
+    let removed = merged(segments);
+    if removed.is_empty() {
+        return Ok(());
+    }
+
+    // ffmpeg's concat demuxer resolves paths from the list's directory and
+    // takes them quoted, with quotes closed and escaped
+    let absolute = std::path::absolute(media).map_err(|e| AppError::Io(e.to_string()))?;
+    let file_line = format!(
+        "file '{}'\n",
+        absolute.to_string_lossy().replace('\'', r"'\''")
+    );
+    let mut list = String::new();
+    let mut start = 0f64;
+    for &(cut_start, cut_end) in &removed {
+        if cut_start > start {
+            list.push_str(&file_line);
+            list.push_str(&format!("inpoint {start}\noutpoint {cut_start}\n"));
+        }
+        start = cut_end;
+    }
+    // The last piece runs to the end, however long the video turns out to be
+    if details.duration.is_none_or(|duration| start < duration) {
+        list.push_str(&file_line);
+        list.push_str(&format!("inpoint {start}\n"));
+    }
+    let list_file = TempFile::new(download::part_path(media).with_extension("concat.txt"));
+    std::fs::write(list_file.path(), list).map_err(|e| AppError::Io(e.to_string()))?;
+
+    // ffmpeg picks the container from the extension, so the part file gets
+    // one after the final name
+    let extension = media.extension().unwrap_or_default().to_string_lossy();
+    let staged =
+        TempFile::new(download::part_path(media).with_extension(format!("tmp.{extension}")));
+    let status = Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "concat",
+            "-safe",
+            "0",
+            "-i",
+        ])
+        .arg(list_file.path())
+        .arg("-i")
+        .arg(media)
+        .args([
+            "-map",
+            "0",
+            "-map_metadata",
+            "1",
+            "-map_chapters",
+            "-1",
+            "-c",
+            "copy",
+        ])
+        .arg(staged.path())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffmpeg: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "ffmpeg exited with {status} removing segments from {}",
+            media.display()
+        )));
+    }
+    std::fs::rename(staged.path(), media).map_err(|e| AppError::Io(e.to_string()))?;
+
+    details.chapters = details
+        .chapters
+        .iter()
+        .map(|chapter| Chapter {
+            title: chapter.title.clone(),
+            start_time: shifted(chapter.start_time, &removed),
+            end_time: shifted(chapter.end_time, &removed),
+        })
+        .filter(|chapter| chapter.end_time > chapter.start_time)
+        .collect();
+    details.duration = details.duration.map(|duration| shifted(duration, &removed));
+    Ok(())
+}
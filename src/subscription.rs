@@ -0,0 +1,473 @@
+use crate::channel::{self, ChannelEntry};
+use crate::cli::{DownloadArgs, SubscriptionCommand};
+use crate::config::{Config, data_dir};
+use crate::schedule::Recurrence;
+use crate::schema::Schema;
+use crate::{
+    AppError, Extractor, TerminalObserver, YoutubeContentType, apply_config, download,
+    download_url, get_extractor, interrupt, observers, profile, resolve_url, start_run,
+};
+use chrono::{Local, TimeZone};
+use clap::builder::Resettable;
+use clap::{Args, FromArgMatches};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::path::PathBuf;
+
+/// How many of a channel's newest uploads each sync looks through
+const CHANNEL_DEPTH: usize = 100;
+
+/// Where a subscription's items are listed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// A channel, profile or playlist
+    Url(String),
+    /// A file of URLs, one per line, that other tools add to
+    BatchFile(PathBuf),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Url(url) => write!(f, "{url}"),
+            Source::BatchFile(path) => write!(f, "batch file {}", path.display()),
+        }
+    }
+}
+
+/// A source whose new items are downloaded on sync.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subscription {
+    pub id: u64,
+    #[serde(flatten)]
+    pub source: Source,
+    /// Download options for its items, as given on the command line
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// IDs of the items downloaded, or there when it was subscribed to
+    #[serde(default)]
+    pub seen: Vec<String>,
+    /// IDs of items seen before that the playlist no longer lists, or lists
+    /// as private or deleted, as of the last sync
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gone: Vec<String>,
+    /// When the daemon, or `sync --due`, syncs it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Recurrence>,
+    /// Unix timestamp of the last sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<i64>,
+    /// Unix timestamp of the next scheduled sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<i64>,
+}
+
+impl Subscription {
+    /// Whether its scheduled sync time has come
+    fn is_due(&self) -> bool {
+        self.schedule.is_some()
+            && self
+                .next_run
+                .is_none_or(|next| next <= Local::now().timestamp())
+    }
+}
+
+/// Subscriptions, kept in `subscriptions.json` in the user's data directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    subscriptions: Vec<Subscription>,
+}
+
+/// The versions of the subscriptions' format, which the file is brought through
+pub const SCHEMA: Schema = Schema { migrations: &[] };
+
+pub fn subscriptions_path() -> Result<PathBuf, AppError> {
+    data_dir()
+        .map(|dir| dir.join("subscriptions.json"))
+        .ok_or_else(|| AppError::Io("no data directory to keep subscriptions in".to_string()))
+}
+
+/// Waits for other processes to finish changing the subscriptions, and
+/// keeps them from changing them until the returned file is dropped
+fn lock() -> Result<File, AppError> {
+    download::lock(&subscriptions_path()?.with_extension("lock"))
+}
+
+impl Subscriptions {
+    /// Loads the saved subscriptions, or none when there aren't any yet.
+    pub fn load() -> Result<Subscriptions, AppError> {
+        let path = subscriptions_path()?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Subscriptions::default());
+            }
+            Err(e) => return Err(AppError::Io(format!("{}: {e}", path.display()))),
+        };
+        SCHEMA.parse(&path, &contents)
+    }
+
+    fn save(&self) -> Result<(), AppError> {
+        let path = subscriptions_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| AppError::Io(e.to_string()))?;
+        }
+        let json = SCHEMA.to_json(self)?;
+        download::write_file_synced(&path, json)
+    }
+
+    /// Loads the subscriptions, has `change` make changes to them and saves
+    /// them, with other processes held off in between
+    pub fn update<T>(
+        change: impl FnOnce(&mut Subscriptions) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let _lock = lock()?;
+        let mut subscriptions = Subscriptions::load()?;
+        let value = change(&mut subscriptions)?;
+        subscriptions.save()?;
+        Ok(value)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Result<&mut Subscription, AppError> {
+        self.subscriptions
+            .iter_mut()
+            .find(|subscription| subscription.id == id)
+            .ok_or(AppError::UnknownSubscription(id))
+    }
+}
+
+/// The download options saved with a subscription, parsed as the download
+/// command would, with `urls` to download
+fn download_args(options: &[String], urls: Vec<String>) -> Result<DownloadArgs, clap::Error> {
+    let command = DownloadArgs::augment_args(clap::Command::new("download").no_binary_name(true))
+        .args_override_self(true)
+        // Options are checked before there are any URLs
+        .mut_arg("urls", |arg| arg.required_unless_present(Resettable::Reset));
+    let matches = command.try_get_matches_from(options.iter().chain(&urls))?;
+    DownloadArgs::from_arg_matches(&matches)
+}
+
+/// The items of a subscription, and whether they're all of them. Channels
+/// list their newest uploads first, so only the newest are looked through;
+/// playlists and profiles are listed whole. Each URL of a batch file is an
+/// item, known by the URL itself.
+fn list(source: &Source) -> Result<(Vec<ChannelEntry>, bool), AppError> {
+    match source {
+        Source::Url(url) => match get_extractor(url) {
+            Some(Extractor::Youtube(YoutubeContentType::Channel)) => {
+                Ok((channel::list_uploads(url, Some(CHANNEL_DEPTH))?, false))
+            }
+            _ => Ok((channel::list_entries(url, None)?, true)),
+        },
+        Source::BatchFile(path) => {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+            let entries = contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| {
+                    let url = resolve_url(line);
+                    ChannelEntry {
+                        id: url.clone(),
+                        url,
+                        unavailable: false,
+                    }
+                })
+                .collect();
+            // Lines taken out of a batch file are done with, not gone
+            Ok((entries, false))
+        }
+    }
+}
+
+/// Reports the items of `subscription` that are gone since the last sync,
+/// keeping note of them so each is reported once
+fn report_gone(
+    subscription: &Subscription,
+    entries: &[ChannelEntry],
+    complete: bool,
+) -> Result<(), AppError> {
+    let listed: HashSet<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+    // Only a whole listing shows what's no longer in it
+    let removed: Vec<&str> = subscription
+        .seen
+        .iter()
+        .map(String::as_str)
+        .filter(|id| complete && !listed.contains(id))
+        .collect();
+    let unavailable: Vec<&str> = entries
+        .iter()
+        .filter(|entry| entry.unavailable)
+        .map(|entry| entry.id.as_str())
+        .collect();
+    let reported: HashSet<&str> = subscription.gone.iter().map(String::as_str).collect();
+    for (ids, description) in [
+        (&removed, "no longer listed"),
+        (&unavailable, "private or deleted"),
+    ] {
+        let new: Vec<&str> = ids
+            .iter()
+            .copied()
+            .filter(|id| !reported.contains(id))
+            .collect();
+        if !new.is_empty() {
+            println!("{} item(s) {description}: {}", new.len(), new.join(", "));
+        }
+    }
+    let gone: Vec<String> = [removed, unavailable]
+        .concat()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    if gone != subscription.gone {
+        Subscriptions::update(|subscriptions| {
+            subscriptions.get_mut(subscription.id)?.gone = gone;
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Downloads what `subscription` added since it was last synced, returning
+/// how many items were new and how many of them failed. Only the listing is
+/// fetched for items seen before. Each new one is marked seen as soon as
+/// it's downloaded, so an interrupted sync doesn't fetch it again.
+fn sync(
+    subscription: &Subscription,
+    config: &Config,
+    live_progress: bool,
+) -> Result<(usize, usize), AppError> {
+    let (entries, complete) = list(&subscription.source)?;
+    report_gone(subscription, &entries, complete)?;
+    let seen: HashSet<&str> = subscription.seen.iter().map(String::as_str).collect();
+    let new: Vec<ChannelEntry> = entries
+        .into_iter()
+        .filter(|entry| !entry.unavailable && !seen.contains(entry.id.as_str()))
+        .collect();
+    if new.is_empty() {
+        println!("Nothing new");
+        return mark_synced(subscription.id).map(|_| (0, 0));
+    }
+    let urls: Vec<String> = new.iter().map(|entry| entry.url.clone()).collect();
+    let invalid = |e: clap::Error| {
+        AppError::InvalidConfig(format!("subscription {}'s options: {e}", subscription.id))
+    };
+    let mut args = download_args(&subscription.options, urls.clone()).map_err(invalid)?;
+    if let Some(name) = &args.profile {
+        let options = [
+            profile::options(config, name)?,
+            subscription.options.clone(),
+        ]
+        .concat();
+        args = download_args(&options, urls).map_err(invalid)?;
+    }
+    apply_config(&mut args, config);
+    // What's set up for the whole run, like the download archive, is set
+    // up by the first subscription that asks for it
+    start_run(&args)?;
+
+    let observer = observers(TerminalObserver { live_progress }, &args);
+    let mut failed = 0;
+    for entry in &new {
+        interrupt::check()?;
+        if download_url(&entry.url, &args, &observer).is_err() {
+            // Left unseen to be tried again on the next sync
+            failed += 1;
+            continue;
+        }
+        Subscriptions::update(|subscriptions| {
+            subscriptions
+                .get_mut(subscription.id)?
+                .seen
+                .push(entry.id.clone());
+            Ok(())
+        })?;
+    }
+    mark_synced(subscription.id)?;
+    Ok((new.len(), failed))
+}
+
+fn mark_synced(id: u64) -> Result<(), AppError> {
+    Subscriptions::update(|subscriptions| {
+        let now = Local::now();
+        let subscription = subscriptions.get_mut(id)?;
+        subscription.last_sync = Some(now.timestamp());
+        subscription.next_run = subscription
+            .schedule
+            .map(|schedule| schedule.next_after(now).timestamp());
+        Ok(())
+    })
+}
+
+/// Moves a subscription's next scheduled sync on without it having synced,
+/// so one that keeps failing isn't tried over and over
+fn reschedule(id: u64) -> Result<(), AppError> {
+    Subscriptions::update(|subscriptions| {
+        let subscription = subscriptions.get_mut(id)?;
+        subscription.next_run = subscription
+            .schedule
+            .map(|schedule| schedule.next_after(Local::now()).timestamp());
+        Ok(())
+    })
+}
+
+/// Syncs each of `subscriptions`, one failing to list leaving the others
+/// to sync
+fn sync_all(
+    subscriptions: &[Subscription],
+    config: &Config,
+    live_progress: bool,
+) -> Result<(), AppError> {
+    let (mut total, mut failed) = (0, 0);
+    for subscription in subscriptions {
+        println!("Syncing {}: {}", subscription.id, subscription.source);
+        match sync(subscription, config, live_progress) {
+            Ok((new, failures)) => {
+                total += new;
+                failed += failures;
+            }
+            Err(AppError::Interrupted) => return Err(AppError::Interrupted),
+            Err(error) => {
+                eprintln!("Couldn't sync {}: {error}", subscription.id);
+                reschedule(subscription.id)?;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(AppError::BatchFailed { failed, total });
+    }
+    Ok(())
+}
+
+/// Whether any subscription's scheduled sync time has come. A file that
+/// can't be read has none.
+pub fn any_due() -> bool {
+    Subscriptions::load()
+        .is_ok_and(|subscriptions| subscriptions.subscriptions.iter().any(Subscription::is_due))
+}
+
+/// Syncs the subscriptions whose scheduled time has come, for the daemon
+pub fn sync_due(config: &Config) -> Result<(), AppError> {
+    let due: Vec<Subscription> = Subscriptions::load()?
+        .subscriptions
+        .into_iter()
+        .filter(Subscription::is_due)
+        .collect();
+    sync_all(&due, config, false)
+}
+
+pub fn run(command: SubscriptionCommand, config: &Config) -> Result<(), AppError> {
+    match command {
+        SubscriptionCommand::Add {
+            url,
+            batch_file,
+            include_existing,
+            schedule,
+            options,
+        } => {
+            let source = match (url, batch_file) {
+                (_, Some(path)) => Source::BatchFile(
+                    std::path::absolute(&path)
+                        .map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?,
+                ),
+                (Some(url), None) => Source::Url(resolve_url(&url)),
+                (None, None) => unreachable!("clap requires a URL or batch file"),
+            };
+            // Checked now rather than on the first sync
+            download_args(&options, vec![]).unwrap_or_else(|e| e.exit());
+            // A batch file is there to have what's in it downloaded
+            let seen: Vec<String> = if include_existing || matches!(source, Source::BatchFile(_)) {
+                vec![]
+            } else {
+                list(&source)?.0.into_iter().map(|entry| entry.id).collect()
+            };
+            let count = seen.len();
+            let subscription = Subscriptions::update(|subscriptions| {
+                subscriptions.next_id += 1;
+                let subscription = Subscription {
+                    id: subscriptions.next_id,
+                    source,
+                    options,
+                    seen,
+                    gone: vec![],
+                    schedule,
+                    last_sync: None,
+                    next_run: schedule
+                        .map(|schedule| schedule.next_after(Local::now()).timestamp()),
+                };
+                subscriptions.subscriptions.push(subscription.clone());
+                Ok(subscription)
+            })?;
+            println!("Subscribed {}: {}", subscription.id, subscription.source);
+            if count > 0 {
+                println!("Its {count} item(s) there now won't be downloaded");
+            }
+        }
+        SubscriptionCommand::List => {
+            let subscriptions = Subscriptions::load()?.subscriptions;
+            if subscriptions.is_empty() {
+                println!("No subscriptions");
+            }
+            let format_time = |time: i64| {
+                Local
+                    .timestamp_opt(time, 0)
+                    .single()
+                    .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            };
+            for subscription in subscriptions {
+                let mut state = match subscription.last_sync.and_then(format_time) {
+                    Some(time) => format!("synced {time}"),
+                    None => "never synced".to_string(),
+                };
+                if let Some(schedule) = subscription.schedule {
+                    state.push_str(&format!(", {schedule}"));
+                }
+                if !subscription.gone.is_empty() {
+                    state.push_str(&format!(", {} gone", subscription.gone.len()));
+                }
+                if let Some(next) = subscription.next_run.and_then(format_time) {
+                    state.push_str(&format!(", next {next}"));
+                }
+                let line = format!(
+                    "{:>4}  {}  ({state}) {}",
+                    subscription.id,
+                    subscription.source,
+                    subscription.options.join(" ")
+                );
+                println!("{}", line.trim_end());
+            }
+        }
+        SubscriptionCommand::Remove { id } => {
+            let subscription = Subscriptions::update(|subscriptions| {
+                let index = subscriptions
+                    .subscriptions
+                    .iter()
+                    .position(|subscription| subscription.id == id)
+                    .ok_or(AppError::UnknownSubscription(id))?;
+                Ok(subscriptions.subscriptions.remove(index))
+            })?;
+            println!("Unsubscribed {id}: {}", subscription.source);
+        }
+        SubscriptionCommand::Sync { ids, due } => {
+            let subscriptions: Vec<Subscription> = Subscriptions::load()?
+                .subscriptions
+                .into_iter()
+                .filter(|subscription| ids.is_empty() || ids.contains(&subscription.id))
+                .filter(|subscription| !due || subscription.is_due())
+                .collect();
+            if let Some(id) = ids
+                .iter()
+                .find(|id| !subscriptions.iter().any(|s| s.id == **id))
+            {
+                return Err(AppError::UnknownSubscription(*id));
+            }
+            sync_all(&subscriptions, config, true)?;
+        }
+    }
+    Ok(())
+}
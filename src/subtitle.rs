@@ -0,0 +1,238 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{self, TempFile};
+use crate::{AppError, http};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Subtitle formats ffmpeg can read
+pub const FFMPEG_FORMATS: &[&str] = &["vtt", "srt", "ass", "ssa"];
+
+/// Formats subtitles can be converted into after download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SubtitleFormat {
+    Srt,
+}
+
+/// One file of a subtitle or caption track, from yt-dlp's `subtitles` or
+/// `automatic_captions`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubtitleTrack {
+    pub language: String,
+    pub ext: String,
+    pub url: String,
+    pub name: Option<String>,
+    // Generated by speech recognition rather than uploaded
+    pub automatic: bool,
+}
+
+impl SubtitleTrack {
+    /// Matches `en` against both `en` and regional tags like `en-US`
+    fn is_in_language(&self, language: &str) -> bool {
+        self.language.eq_ignore_ascii_case(language)
+            || self
+                .language
+                .split_once('-')
+                .is_some_and(|(base, _)| base.eq_ignore_ascii_case(language))
+    }
+}
+
+impl Display for SubtitleTrack {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.language, self.ext)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name}")?;
+        }
+        if self.automatic {
+            write!(f, " [automatic]")?;
+        }
+        Ok(())
+    }
+}
+
+fn tracks_from(value: &Value, field: &str, automatic: bool) -> Vec<SubtitleTrack> {
+    let Some(languages) = value.get(field).and_then(Value::as_object) else {
+        return vec![];
+    };
+    languages
+        .iter()
+        .flat_map(|(language, files)| {
+            files
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(move |file| {
+                    Some(SubtitleTrack {
+                        language: language.clone(),
+                        ext: file.get("ext")?.as_str()?.to_string(),
+                        url: file.get("url")?.as_str()?.to_string(),
+                        name: file.get("name").and_then(Value::as_str).map(str::to_string),
+                        automatic,
+                    })
+                })
+        })
+        .collect()
+}
+
+/// Parses `subtitles` and `automatic_captions`, uploaded tracks first.
+pub fn from_json(value: &Value) -> Vec<SubtitleTrack> {
+    let mut tracks = tracks_from(value, "subtitles", false);
+    tracks.extend(tracks_from(value, "automatic_captions", true));
+    tracks
+}
+
+/// The track to fetch for `language`: uploaded subtitles over automatic
+/// captions, and text formats that can be converted over the rest.
+fn choose<'a>(tracks: &'a [SubtitleTrack], language: &str) -> Option<&'a SubtitleTrack> {
+    tracks
+        .iter()
+        .filter(|track| track.is_in_language(language))
+        .min_by_key(|track| {
+            (
+                track.automatic,
+                !matches!(track.ext.as_str(), "vtt" | "srt"),
+            )
+        })
+}
+
+/// Converts WebVTT to SubRip, dropping the header, comment and style blocks,
+/// cue settings, and inline tags such as karaoke timestamps.
+fn vtt_to_srt(vtt: &str) -> String {
+    let vtt = vtt.replace("\r\n", "\n");
+    let mut srt = String::new();
+    let mut number = 0;
+    for block in vtt.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let mut times = timing.split("-->").map(|time| {
+            time.split_whitespace()
+                .next()
+                .map(srt_timestamp)
+                .unwrap_or_default()
+        });
+        let (Some(start), Some(end)) = (times.next(), times.next()) else {
+            continue;
+        };
+        let text: Vec<String> = lines
+            .map(strip_tags)
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        if text.is_empty() {
+            continue;
+        }
+
+        number += 1;
+        srt.push_str(&format!(
+            "{number}\n{start} --> {end}\n{}\n\n",
+            text.join("\n")
+        ));
+    }
+    srt
+}
+
+/// `mm:ss.ttt` or `hh:mm:ss.ttt` to SubRip's `hh:mm:ss,ttt`
+fn srt_timestamp(time: &str) -> String {
+    let time = time.replace('.', ",");
+    if time.matches(':').count() == 1 {
+        format!("00:{time}")
+    } else {
+        time
+    }
+}
+
+fn strip_tags(line: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+}
+
+/// Downloads a track for each of `languages` next to `media_path`, named like
+/// `Title.en.vtt`, converting them when `convert` is given. Languages without
+/// a track are reported and skipped, as are existing files `on_conflict`
+/// keeps.
+pub fn write_next_to(
+    tracks: &[SubtitleTrack],
+    languages: &[String],
+    convert: Option<SubtitleFormat>,
+    media_path: &Path,
+    on_conflict: ConflictPolicy,
+) -> Result<Vec<PathBuf>, AppError> {
+    let mut paths = vec![];
+    for language in languages {
+        let Some(track) = choose(tracks, language) else {
+            eprintln!("No subtitles in language `{language}`");
+            continue;
+        };
+
+        let convert_to_srt = convert == Some(SubtitleFormat::Srt) && track.ext != "srt";
+        let to_srt = convert_to_srt && track.ext == "vtt";
+        if convert_to_srt && !to_srt {
+            eprintln!(
+                "Can't convert {} subtitles to SRT, keeping them as is",
+                track.ext
+            );
+        }
+        let extension = if to_srt { "srt" } else { &track.ext };
+        let path = media_path.with_extension(format!("{language}.{extension}"));
+        if !conflict::should_write_sidecar(&path, on_conflict) {
+            println!("Skipped {}, which already exists", path.display());
+            continue;
+        }
+        if to_srt {
+            download::write_file(&path, vtt_to_srt(&http::fetch_text(&track.url)?))?;
+        } else {
+            http::download_to(&track.url, &path)?;
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Downloads the track for `language` into a temporary file next to `media`
+/// for ffmpeg to read, when it's in one of `formats`. WebVTT is converted to
+/// SubRip when that's one of them, as ffmpeg keeps WebVTT's inline tags.
+/// Languages without such a track are reported and give `None`.
+pub fn fetch_for_ffmpeg<'a>(
+    tracks: &'a [SubtitleTrack],
+    language: &str,
+    formats: &[&str],
+    media: &Path,
+) -> Result<Option<(&'a SubtitleTrack, TempFile)>, AppError> {
+    let Some(track) = choose(tracks, language) else {
+        eprintln!("\r\x1b[2KNo subtitles in language `{language}`");
+        return Ok(None);
+    };
+    if !formats.contains(&track.ext.as_str()) {
+        eprintln!(
+            "\r\x1b[2KCan't use {} subtitles here, only {}",
+            track.ext,
+            formats.join(", ")
+        );
+        return Ok(None);
+    }
+
+    let to_srt = track.ext == "vtt" && formats.contains(&"srt");
+    let extension = if to_srt { "srt" } else { &track.ext };
+    let file =
+        TempFile::new(download::part_path(media).with_extension(format!("{language}.{extension}")));
+    if to_srt {
+        download::write_file(file.path(), vtt_to_srt(&http::fetch_text(&track.url)?))?;
+    } else {
+        http::download_to(&track.url, file.path())?;
+    }
+    Ok(Some((track, file)))
+}
@@ -0,0 +1,79 @@
+use crate::conflict::{self, ConflictPolicy};
+use crate::{AppError, http};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// An entry of yt-dlp's `thumbnails` array.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Thumbnail {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // Higher is better, as ranked by the extractor
+    pub preference: Option<i64>,
+}
+
+impl Thumbnail {
+    fn area(&self) -> Option<u32> {
+        self.width
+            .zip(self.height)
+            .map(|(width, height)| width * height)
+    }
+}
+
+impl Display for Thumbnail {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => write!(f, "{width}x{height}")?,
+            _ => write!(f, "unknown size")?,
+        }
+        if let Some(preference) = self.preference {
+            write!(f, ", preference {preference}")?;
+        }
+        write!(f, ": {}", self.url)
+    }
+}
+
+/// Parses the `thumbnails` array, skipping entries without a URL.
+pub fn from_json(value: &Value) -> Vec<Thumbnail> {
+    value
+        .get("thumbnails")
+        .and_then(Value::as_array)
+        .map(|thumbnails| {
+            thumbnails
+                .iter()
+                .filter_map(|thumbnail| serde_json::from_value(thumbnail.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The highest-preference thumbnail, then the largest. yt-dlp lists them worst
+/// first, so later entries win ties.
+pub fn best(thumbnails: &[Thumbnail]) -> Option<&Thumbnail> {
+    thumbnails
+        .iter()
+        .max_by_key(|thumbnail| (thumbnail.preference, thumbnail.area()))
+}
+
+/// Downloads the best thumbnail next to `media_path`, sharing its file stem.
+/// Returns where it was saved, or `None` when there are no thumbnails or
+/// `on_conflict` kept an existing file.
+pub fn write_next_to(
+    thumbnails: &[Thumbnail],
+    media_path: &Path,
+    on_conflict: ConflictPolicy,
+) -> Result<Option<PathBuf>, AppError> {
+    let Some(thumbnail) = best(thumbnails) else {
+        return Ok(None);
+    };
+    let path = media_path.with_extension(http::file_extension(&thumbnail.url, "image/jpeg"));
+    if !conflict::should_write_sidecar(&path, on_conflict) {
+        println!("Skipped {}, which already exists", path.display());
+        return Ok(None);
+    }
+    http::download_to(&thumbnail.url, &path)?;
+    Ok(Some(path))
+}
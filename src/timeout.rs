@@ -0,0 +1,122 @@
+use crate::download::{self, Pause};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long each phase of a download may take before it's given up on and
+/// handed to the retry policy.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    // For yt-dlp to fetch an item's details
+    pub metadata: Duration,
+    // To connect to a server
+    pub connect: Duration,
+    // Without receiving anything mid-transfer
+    pub stall: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            metadata: Duration::from_secs(120),
+            connect: Duration::from_secs(30),
+            stall: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Sets the timeouts used by every request and child process.
+    pub fn install(self) {
+        TIMEOUTS.get_or_init(|| self);
+    }
+
+    pub fn current() -> Timeouts {
+        TIMEOUTS.get().copied().unwrap_or_default()
+    }
+}
+
+/// Terminates a child process once it has gone too long without showing
+/// signs of life, until dropped. Time spent paused doesn't count.
+pub struct Watchdog {
+    // When the child was last seen, and how long it has from then
+    deadline: Arc<Mutex<(Instant, Duration)>>,
+    armed: Arc<AtomicBool>,
+    fired: Arc<AtomicBool>,
+    done: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub fn new(child: &Child, limit: Duration, pause: Option<&Pause>) -> Watchdog {
+        let deadline = Arc::new(Mutex::new((Instant::now(), limit)));
+        let armed = Arc::new(AtomicBool::new(true));
+        let fired = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicBool::new(false));
+        let pid = child.id().to_string();
+        let watcher = {
+            let (deadline, armed, fired, done) = (
+                Arc::clone(&deadline),
+                Arc::clone(&armed),
+                Arc::clone(&fired),
+                Arc::clone(&done),
+            );
+            let pause = pause.cloned();
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    thread::sleep(CHECK_INTERVAL);
+                    let mut deadline = deadline.lock().unwrap();
+                    let (last_seen, limit) = *deadline;
+                    if !armed.load(Ordering::Relaxed)
+                        || pause.as_ref().is_some_and(Pause::is_paused)
+                    {
+                        *deadline = (Instant::now(), limit);
+                    } else if last_seen.elapsed() >= limit {
+                        fired.store(true, Ordering::Relaxed);
+                        download::signal(&pid, "-TERM");
+                        break;
+                    }
+                }
+            })
+        };
+        Watchdog {
+            deadline,
+            armed,
+            fired,
+            done,
+            watcher: Some(watcher),
+        }
+    }
+
+    /// Records that the child is still making progress, giving it `limit`
+    /// to show the next sign
+    pub fn touch(&self, limit: Duration) {
+        *self.deadline.lock().unwrap() = (Instant::now(), limit);
+    }
+
+    /// Stops watching for good, e.g. once the child moves on to work that
+    /// prints nothing for a long while
+    pub fn disarm(&self) {
+        self.armed.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether the child was terminated for going quiet
+    pub fn fired(&self) -> bool {
+        self.fired.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
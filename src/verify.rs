@@ -0,0 +1,99 @@
+use crate::AppError;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+// Durations may be off by this many seconds, or this share of the length,
+// whichever is more, since extractors and containers round them differently
+const DURATION_SLACK: f64 = 2f64;
+const DURATION_SHARE: f64 = 0.02;
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<Stream>,
+    format: Option<Format>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    #[serde(default)]
+    codec_type: String,
+}
+
+#[derive(Deserialize)]
+struct Format {
+    // ffprobe gives numbers as strings, and no duration for still images
+    duration: Option<String>,
+}
+
+/// What a finished download should hold.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Expected {
+    pub video: bool,
+    pub audio: bool,
+    // Seconds
+    pub duration: Option<f64>,
+}
+
+/// Whether ffprobe can be run, checked once
+pub fn is_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("ffprobe")
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+/// Fails unless ffprobe reads the file at `path` without errors and finds
+/// the streams and duration in `expected`, as a truncated or badly joined
+/// download wouldn't have.
+pub fn verify(path: &Path, expected: Expected) -> Result<(), AppError> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-of", "json", "-show_entries"])
+        .arg("stream=codec_type:format=duration")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute ffprobe: {e}")))?;
+    let corrupt =
+        |problem: String| AppError::CorruptMedia(format!("{}: {problem}", path.display()));
+    let errors = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !errors.trim().is_empty() {
+        return Err(corrupt(
+            errors
+                .trim()
+                .lines()
+                .next()
+                .unwrap_or("unreadable")
+                .to_string(),
+        ));
+    }
+    let probe: Probe = serde_json::from_slice(&output.stdout)
+        .map_err(|e| AppError::InvalidJson(format!("ffprobe output: {e}")))?;
+
+    let has = |kind: &str| probe.streams.iter().any(|stream| stream.codec_type == kind);
+    for (wanted, kind) in [(expected.video, "video"), (expected.audio, "audio")] {
+        if wanted && !has(kind) {
+            return Err(corrupt(format!("no {kind} stream")));
+        }
+    }
+    let actual = probe
+        .format
+        .and_then(|format| format.duration?.parse::<f64>().ok());
+    if let Some((expected, actual)) = expected.duration.zip(actual) {
+        let tolerance = DURATION_SLACK.max(expected * DURATION_SHARE);
+        if (expected - actual).abs() > tolerance {
+            return Err(corrupt(format!(
+                "{actual:.1}s long instead of {expected:.1}s"
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use crate::queue::{self, Priority, Queue};
+use crate::{AppError, conflict, resolve_url};
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Where files are moved once their links are queued, inside the folder
+pub const DONE_DIR: &str = "done";
+
+/// How long a file has to be left alone before it's read, so one that's
+/// still being written isn't queued half
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// The kinds of files links are taken from
+#[derive(Clone, Copy)]
+enum LinkFile {
+    /// A list of links, one per line
+    Text,
+    /// An internet shortcut, as browsers and Windows save them
+    Shortcut,
+}
+
+impl LinkFile {
+    fn of(path: &Path) -> Option<LinkFile> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "txt" => Some(LinkFile::Text),
+            "url" => Some(LinkFile::Shortcut),
+            _ => None,
+        }
+    }
+
+    /// The links in `contents`: a list's lines that are web addresses,
+    /// skipping titles and notes shared along with them, or a shortcut's
+    /// `URL=`
+    fn links(self, contents: &str) -> Vec<String> {
+        let lines = contents.lines().map(str::trim);
+        let links: Vec<&str> = match self {
+            LinkFile::Text => lines.collect(),
+            LinkFile::Shortcut => lines
+                .filter_map(|line| line.strip_prefix("URL="))
+                .map(str::trim)
+                .collect(),
+        };
+        links
+            .into_iter()
+            .filter(|link| {
+                Url::parse(link).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+            })
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Whether `path` is ready to be read: a file nothing has changed for a
+/// while, and not one of the hidden temporary files syncing tools write to
+fn settled(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if name.starts_with('.') || name.starts_with('~') {
+        return false;
+    }
+    path.metadata().is_ok_and(|metadata| {
+        metadata.is_file()
+            && metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age >= SETTLE_TIME)
+    })
+}
+
+/// Moves a handled file into the `done` folder, numbering its name when
+/// one of the same name was handled before
+fn set_aside(path: &Path, dir: &Path) -> Result<PathBuf, AppError> {
+    let done = dir.join(DONE_DIR);
+    std::fs::create_dir_all(&done).map_err(|e| AppError::Io(format!("{}: {e}", done.display())))?;
+    let mut to = done.join(path.file_name().unwrap_or_default());
+    if to.exists() {
+        to = conflict::numbered(&to);
+    }
+    std::fs::rename(path, &to).map_err(|e| AppError::Io(format!("{}: {e}", path.display())))?;
+    Ok(to)
+}
+
+/// Queues the links of the `.txt` and `.url` files dropped into `dir`, and
+/// moves each file into its `done` folder once they're queued
+pub fn ingest(dir: &Path) -> Result<(), AppError> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| AppError::Io(format!("{}: {e}", dir.display())))?;
+    let mut files: Vec<(PathBuf, LinkFile)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| LinkFile::of(&path).map(|kind| (path, kind)))
+        .filter(|(path, _)| settled(path))
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, kind) in files {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            // Most likely not text, which a later look won't change
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => String::new(),
+            Err(e) => return Err(AppError::Io(format!("{}: {e}", path.display()))),
+        };
+        let links = kind.links(&contents);
+        if links.is_empty() {
+            eprintln!("\r\x1b[2KNo links in {}", path.display());
+        } else {
+            let urls: Vec<String> = links.iter().map(|url| resolve_url(url)).collect();
+            let report =
+                Queue::update(|queue| Ok(queue::add_all(queue, &urls, Priority::Normal, None)))?;
+            eprint!("\r\x1b[2KFrom {}:\n{report}", path.display());
+        }
+        set_aside(&path, dir)?;
+    }
+    Ok(())
+}
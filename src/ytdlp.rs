@@ -0,0 +1,342 @@
+use crate::backend::Downloader;
+use crate::cli::RecordArgs;
+use crate::conflict::{self, ConflictPolicy};
+use crate::download::{ChildPause, DownloadEvent, Pause, Progress};
+use crate::section::Section;
+use crate::timeout::{Timeouts, Watchdog};
+use crate::{AppError, FormatSelection, cache, filename, interrupt, retry};
+use regex::Regex;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::LazyLock;
+use std::thread;
+
+/// yt-dlp errors that may go away on their own, like dropped connections
+/// and server-side or rate-limit statuses
+static TRANSIENT_ERROR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)HTTP Error (408|429|5\d\d)|timed out|connection (reset|refused|aborted)|temporary failure|network is unreachable|incomplete read")
+        .unwrap()
+});
+
+/// The error for a failed yt-dlp run, from the last `ERROR:` line it printed.
+/// Transient failures become HTTP errors so they're retried.
+fn failure(url: &str, status: ExitStatus, stderr: &str) -> AppError {
+    let message = stderr
+        .lines()
+        .rev()
+        .find_map(|line| line.strip_prefix("ERROR:"))
+        .map(str::trim);
+    let description = match message {
+        Some(message) => format!("yt-dlp exited with {status} for {url}: {message}"),
+        None => format!("yt-dlp exited with {status} for {url}"),
+    };
+    if message.is_some_and(|message| TRANSIENT_ERROR.is_match(message)) {
+        AppError::Http(description)
+    } else {
+        AppError::CommandFailed(description)
+    }
+}
+
+/// Runs `yt-dlp -J` against a URL and returns the parsed JSON dump. Without
+/// extra arguments the dump is cached, and one cached recently is returned
+/// instead.
+pub fn fetch_json(url: &str, extra_args: &[&str]) -> Result<Value, AppError> {
+    if extra_args.is_empty()
+        && let Some(json) = cache::get(url)
+    {
+        return Ok(json);
+    }
+    // stderr is captured to classify failures, which also keeps yt-dlp's
+    // messages off the terminal
+    let timeouts = Timeouts::current();
+    let output = retry::with_retries(|| {
+        let child = Command::new("yt-dlp")
+            .arg("-q")
+            .args(["--socket-timeout", &timeouts.connect.as_secs().to_string()])
+            .args(extra_args)
+            .args(["-J", url])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| AppError::CommandFailed(format!("failed to execute yt-dlp: {e}")))?;
+        let watchdog = Watchdog::new(&child, timeouts.metadata, None);
+        let output = child
+            .wait_with_output()
+            .map_err(|e| AppError::CommandFailed(format!("failed to execute yt-dlp: {e}")))?;
+        if watchdog.fired() {
+            return Err(AppError::TimedOut(format!(
+                "yt-dlp took over {}s to fetch {url}",
+                timeouts.metadata.as_secs()
+            )));
+        }
+        if !output.status.success() {
+            return Err(failure(
+                url,
+                output.status,
+                &String::from_utf8_lossy(&output.stderr),
+            ));
+        }
+        Ok(output)
+    })?;
+
+    let result = String::from_utf8_lossy(&output.stdout);
+    let json = serde_json::from_str(&result).map_err(|e| AppError::InvalidJson(e.to_string()))?;
+    if extra_args.is_empty() {
+        cache::put(url, &json);
+    }
+    Ok(json)
+}
+
+/// Records a live stream with yt-dlp, streaming its progress to the terminal.
+///
+/// Segments are written as MPEG-TS without a `.part` file, so whatever was
+/// captured stays playable if the recording is cut short.
+pub fn record_live(url: &str, args: &RecordArgs) -> Result<(), AppError> {
+    let mut command = Command::new("yt-dlp");
+    command.args(["--hls-use-mpegts", "--no-part"]);
+    if args.from_start {
+        command.arg("--live-from-start");
+    }
+    if let Some(wait) = args.wait {
+        command.args(["--wait-for-video", &wait.to_string()]);
+    }
+    if let Some(output) = &args.output {
+        command.args(["-o", output]);
+    }
+
+    let status = command
+        .arg(url)
+        .status()
+        .map_err(|e| AppError::CommandFailed(format!("failed to execute yt-dlp: {e}")))?;
+    if !status.success() {
+        return Err(AppError::CommandFailed(format!(
+            "yt-dlp exited with {status} while recording {url}"
+        )));
+    }
+    Ok(())
+}
+
+/// Where and how yt-dlp saves a download.
+pub struct DownloadOptions<'a> {
+    pub output_dir: &'a str,
+    /// Output file name template, in yt-dlp's format
+    pub template: &'a str,
+    /// 1-based item to download when the URL is a playlist or carousel
+    pub playlist_item: Option<usize>,
+    /// Bytes per second this download may use
+    pub rate_limit: Option<u64>,
+    /// External program for yt-dlp to fetch with, and its connections per file
+    pub downloader: Downloader,
+    pub connections: usize,
+    /// Stops yt-dlp while set
+    pub pause: Pause,
+    /// What to do when the finished file's name is taken
+    pub on_conflict: ConflictPolicy,
+    /// The only part of the video to download
+    pub section: Option<Section>,
+}
+
+/// Where yt-dlp saves downloads, relative to the output directory. Finished
+/// files are only moved out of it, according to the conflict policy, so
+/// nothing half-written ever sits among them.
+const TEMP_DIR: &str = ".part.tmp";
+
+const PROGRESS_PREFIX: &str = "[progress]";
+
+/// One machine-readable line per update, with `NA` for unknown fields
+const PROGRESS_TEMPLATE: &str = "download:[progress] %(progress.downloaded_bytes)s \
+    %(progress.total_bytes)s %(progress.total_bytes_estimate)s %(progress.speed)s %(progress.eta)s";
+
+const POSTPROCESS_PREFIX: &str = "[postprocess]";
+
+/// A line when each post-processor starts and finishes
+const POSTPROCESS_TEMPLATE: &str =
+    "postprocess:[postprocess] %(progress.postprocessor)s %(progress.status)s";
+
+fn parse_progress(line: &str) -> Option<Progress> {
+    let fields: Vec<&str> = line
+        .strip_prefix(PROGRESS_PREFIX)?
+        .split_whitespace()
+        .collect();
+    let number = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse::<f64>().ok())
+    };
+    Some(Progress {
+        downloaded_bytes: number(0)? as u64,
+        total_bytes: number(1).or(number(2)).map(|total| total as u64),
+        speed: number(3),
+        average_speed: None,
+        eta: number(4),
+    })
+}
+
+/// Runs a yt-dlp download with `format_args` and returns the path of the
+/// finished file, retrying transient failures from where they stopped.
+///
+/// yt-dlp writes both its progress lines and the `--print`ed path to
+/// stdout, so it's read line by line: progress and the start of merging go
+/// to `on_event`, and the last other line is the path. Its stderr is passed through to the terminal
+/// and kept to classify failures.
+///
+/// The file is then moved from the temporary directory to the same place
+/// under the output directory, or nowhere when the policy keeps an existing
+/// file, which returns `None`.
+fn run_download(
+    format_args: &[&str],
+    url: &str,
+    options: &DownloadOptions,
+    on_event: &mut dyn FnMut(&DownloadEvent),
+) -> Result<Option<PathBuf>, AppError> {
+    let output_dir = Path::new(options.output_dir);
+    let temp_dir = output_dir.join(TEMP_DIR);
+    let timeouts = Timeouts::current();
+    let staged = retry::with_retries(|| {
+        let mut command = Command::new("yt-dlp");
+        command
+            .args(format_args)
+            .args(["--socket-timeout", &timeouts.connect.as_secs().to_string()])
+            .arg("-P")
+            .arg(&temp_dir)
+            .args(["-o", options.template])
+            .args(filename::ytdlp_args());
+        if let Some(item) = options.playlist_item {
+            command.args(["--playlist-items", &item.to_string()]);
+        }
+        if let Some(rate_limit) = options.rate_limit {
+            command.args(["--limit-rate", &rate_limit.to_string()]);
+        }
+        if let Some(section) = &options.section {
+            command.args(["--download-sections", &section.ytdlp_arg()]);
+        }
+        if options.downloader == Downloader::Aria2c {
+            let connections = options.connections.clamp(1, 16);
+            command.args(["--downloader", "aria2c"]).args([
+                "--downloader-args",
+                &format!("aria2c:-x {connections} -s {connections}"),
+            ]);
+        }
+        let mut child = command
+            // Continue `.part` files left by an interrupted run
+            .args(["--continue", "--part"])
+            .args(["--no-simulate", "--newline", "--progress"])
+            .args(["--progress-template", PROGRESS_TEMPLATE])
+            .args(["--progress-template", POSTPROCESS_TEMPLATE])
+            .args(["--print", "after_move:filepath"])
+            .arg(url)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| AppError::CommandFailed(format!("failed to execute yt-dlp: {e}")))?;
+        let _pause = ChildPause::new(&child, &options.pause);
+        let _guard = interrupt::ChildGuard::new(&child);
+        // yt-dlp fetches the details again before the transfer starts
+        let watchdog = Watchdog::new(&child, timeouts.metadata, Some(&options.pause));
+
+        let stderr = child.stderr.take().map(|stderr| {
+            thread::spawn(move || {
+                let mut errors = String::new();
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    eprintln!("{line}");
+                    if line.starts_with("ERROR:") {
+                        errors.push_str(&line);
+                        errors.push('\n');
+                    }
+                }
+                errors
+            })
+        });
+
+        let mut path = None;
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                watchdog.touch(timeouts.stall);
+                if let Some(postprocess) = line.strip_prefix(POSTPROCESS_PREFIX) {
+                    if postprocess.trim() == "Merger started" {
+                        // Merging prints nothing until it's done
+                        watchdog.disarm();
+                        on_event(&DownloadEvent::Merging);
+                    }
+                    continue;
+                }
+                match parse_progress(&line) {
+                    Some(progress) => on_event(&DownloadEvent::Progress(progress)),
+                    None if !line.trim().is_empty() => path = Some(PathBuf::from(line.trim())),
+                    None => {}
+                }
+            }
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| AppError::CommandFailed(format!("failed to execute yt-dlp: {e}")))?;
+        let errors = stderr
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        if !status.success() {
+            // Its `.part` files are kept for `--continue`
+            interrupt::check()?;
+            if watchdog.fired() {
+                return Err(AppError::TimedOut(format!(
+                    "yt-dlp stopped making progress on {url}"
+                )));
+            }
+            return Err(failure(url, status, &errors));
+        }
+        path.ok_or_else(|| AppError::CommandFailed(format!("yt-dlp saved nothing for {url}")))
+    })?;
+
+    // Absolute templates ignore the output directory, so there's nothing to move
+    let Ok(relative) = staged.strip_prefix(&temp_dir) else {
+        return Ok(Some(staged));
+    };
+    let path = output_dir.join(relative);
+    let placed = conflict::place(&staged, &path, options.on_conflict)?;
+    if placed.is_none() {
+        on_event(&DownloadEvent::Skipped { path });
+    }
+    // Directories only go once no other download is using them
+    for dir in staged.ancestors().skip(1) {
+        if !dir.starts_with(&temp_dir) || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(placed)
+}
+
+/// Downloads the selected formats with yt-dlp, merging a video and audio
+/// pair into one file.
+pub fn download_format(
+    url: &str,
+    selection: &FormatSelection,
+    options: &DownloadOptions,
+    on_event: &mut dyn FnMut(&DownloadEvent),
+) -> Result<Option<PathBuf>, AppError> {
+    let spec = selection.spec();
+    let mut format_args = vec!["-f", &spec];
+    if let Some(merge_format) = selection.merge_format() {
+        format_args.extend(["--merge-output-format", merge_format]);
+    }
+    run_download(&format_args, url, options, on_event)
+}
+
+/// Downloads several streams with yt-dlp and muxes them into one Matroska
+/// file, keeping every audio track rather than just the first.
+pub fn download_multi_audio(
+    url: &str,
+    format_spec: &str,
+    options: &DownloadOptions,
+    on_event: &mut dyn FnMut(&DownloadEvent),
+) -> Result<Option<PathBuf>, AppError> {
+    let format_args = [
+        "-f",
+        format_spec,
+        "--audio-multistreams",
+        "--merge-output-format",
+        "mkv",
+    ];
+    run_download(&format_args, url, options, on_event)
+}